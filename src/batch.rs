@@ -0,0 +1,178 @@
+use crate::json::Json;
+use crate::link::link_files::{LinkReport, link_files_probed};
+use crate::link::link_options::{LinkOptions, options_from_json};
+use crate::link::platform;
+use crate::link::probe::{self, FsCapabilities};
+use crate::link::warning::Warning;
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+
+/// One link operation of a batch.
+#[derive(Debug, Clone)]
+pub struct BatchOp {
+    /// The source pattern to link from
+    pub source: String,
+    /// The destination to link to
+    pub dest: String,
+    /// The options for this operation
+    pub opts: LinkOptions,
+}
+
+/// Parses a batch from a JSON array or an NDJSON stream of operation objects.
+///
+/// Each object has `source` and `dest` strings and an optional `options`
+/// object with the same keys the `serve` methods accept.
+///
+/// # Arguments
+///
+/// * `text` - The batch text
+///
+/// # Returns
+///
+/// * `io::Result<Vec<BatchOp>>` - The operations in order, or an `InvalidData`
+///   error naming the offending operation
+pub fn parse(text: &str) -> io::Result<Vec<BatchOp>> {
+    let invalid = |what: String| io::Error::new(io::ErrorKind::InvalidData, what);
+
+    let values = if text.trim_start().starts_with('[') {
+        match Json::parse(text).map_err(|e| invalid(e.to_string()))? {
+            Json::Array(values) => values,
+            _ => return Err(invalid(String::from("batch must be a JSON array"))),
+        }
+    } else {
+        text.lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| {
+                Json::parse(line).map_err(|e| invalid(format!("line {}: {}", i + 1, e)))
+            })
+            .collect::<io::Result<_>>()?
+    };
+
+    values
+        .iter()
+        .enumerate()
+        .map(|(i, value)| {
+            let field = |name: &str| {
+                value
+                    .get(name)
+                    .and_then(Json::as_str)
+                    .map(str::to_string)
+                    .ok_or_else(|| invalid(format!("operation {}: missing string {}", i + 1, name)))
+            };
+            Ok(BatchOp {
+                source: field("source")?,
+                dest: field("dest")?,
+                opts: options_from_json(value.get("options"))
+                    .map_err(|e| invalid(format!("operation {}: {}", i + 1, e)))?,
+            })
+        })
+        .collect()
+}
+
+/// The outcome of one operation.
+#[derive(Debug)]
+pub struct OpResult {
+    /// The operation's source pattern
+    pub source: String,
+    /// The operation's destination
+    pub dest: String,
+    /// The links created and warnings met, or the error that stopped the operation
+    pub outcome: io::Result<LinkReport>,
+}
+
+/// The combined outcome of a batch.
+#[derive(Debug, Default)]
+pub struct BatchReport {
+    /// The result of every operation, in order
+    pub results: Vec<OpResult>,
+}
+
+impl BatchReport {
+    /// The total number of links created.
+    pub fn linked(&self) -> usize {
+        self.results
+            .iter()
+            .filter_map(|r| r.outcome.as_ref().ok())
+            .map(|report| report.linked.len())
+            .sum()
+    }
+
+    /// The number of operations that failed.
+    pub fn failed(&self) -> usize {
+        self.results.iter().filter(|r| r.outcome.is_err()).count()
+    }
+
+    /// Renders the report as JSON.
+    pub fn to_json(&self) -> Json {
+        let operations = self
+            .results
+            .iter()
+            .map(|result| {
+                let mut fields = vec![
+                    ("source", Json::from(result.source.as_str())),
+                    ("dest", Json::from(result.dest.as_str())),
+                ];
+                match &result.outcome {
+                    Ok(report) => fields.extend([
+                        ("linked", Json::from(report.linked.len())),
+                        (
+                            "warnings",
+                            Json::Array(report.warnings.iter().map(Warning::to_json).collect()),
+                        ),
+                    ]),
+                    Err(e) => fields.push(("error", Json::from(e.to_string()))),
+                }
+                Json::object(fields)
+            })
+            .collect();
+        Json::object([
+            ("operations", Json::Array(operations)),
+            ("linked", Json::from(self.linked())),
+            ("failed", Json::from(self.failed())),
+        ])
+    }
+}
+
+/// Runs every operation of a batch, continuing past failed operations.
+///
+/// Destination filesystems are probed once per device and the result is
+/// shared by every operation linking onto that device. Where device ids are
+/// unavailable, each operation probes its own destination.
+///
+/// # Arguments
+///
+/// * `ops` - The operations to run, in order
+///
+/// # Returns
+///
+/// * `BatchReport` - The outcome of every operation
+pub fn run(ops: &[BatchOp]) -> BatchReport {
+    let mut probes: HashMap<u64, Option<FsCapabilities>> = HashMap::new();
+    let mut report = BatchReport::default();
+
+    for op in ops {
+        let outcome = (|| {
+            let caps = if !op.opts.probe_filesystem {
+                None
+            } else if let Some(dir) = probe::existing_ancestor(Path::new(&op.dest))
+                && let Some(device) = platform::device(&dir.metadata()?)
+            {
+                probes
+                    .entry(device)
+                    .or_insert_with(|| probe::probe(dir).ok())
+                    .clone()
+            } else {
+                probe::probe_destination(Path::new(&op.dest))
+            };
+            link_files_probed(&op.source, &op.dest, &op.opts, caps.as_ref(), |_| {})
+        })();
+        report.results.push(OpResult {
+            source: op.source.clone(),
+            dest: op.dest.clone(),
+            outcome,
+        });
+    }
+    report
+}
@@ -0,0 +1,102 @@
+use crate::inspect::{self, Resolution};
+use crate::link::backups::BACKUP_DIR;
+use crate::link::lexical;
+use crate::link::platform;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// A symlink whose target no longer exists.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BrokenLink {
+    /// The symlink
+    pub path: PathBuf,
+    /// The missing entry its chain ends at
+    pub target: PathBuf,
+}
+
+impl fmt::Display for BrokenLink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} does not exist",
+            self.path.display(),
+            self.target.display()
+        )
+    }
+}
+
+/// Finds the broken symlinks under a directory.
+///
+/// Symlinks are not followed, and the backup directory is left alone, so
+/// backed-up links stay as they were taken. Links whose chain loops or
+/// cannot be read are not counted as broken.
+///
+/// # Arguments
+///
+/// * `dir` - The directory to walk
+/// * `source` - If set, only links whose missing target lies inside this
+///   tree are returned, whether or not the tree itself still exists
+///
+/// # Returns
+///
+/// * `io::Result<Vec<BrokenLink>>` - The broken links, in walk order
+pub fn scan(dir: &Path, source: Option<&Path>) -> io::Result<Vec<BrokenLink>> {
+    let root = match source {
+        Some(source) => Some(match fs::canonicalize(source) {
+            Ok(root) => root,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => env::current_dir()?.join(source),
+            Err(e) => return Err(e),
+        }),
+        None => None,
+    };
+
+    let mut found = Vec::new();
+    let mut walk = WalkDir::new(dir)
+        .min_depth(1)
+        .sort_by_file_name()
+        .into_iter();
+    while let Some(entry) = walk.next() {
+        let entry = entry?;
+        if entry.depth() == 1 && entry.file_name() == BACKUP_DIR {
+            walk.skip_current_dir();
+            continue;
+        }
+        if !entry.path_is_symlink() {
+            continue;
+        }
+        let Resolution::Dangling(target) = inspect::follow(entry.path()).1 else {
+            continue;
+        };
+        if root
+            .as_ref()
+            .is_some_and(|root| !lexical::is_within(&target, root))
+        {
+            continue;
+        }
+        found.push(BrokenLink {
+            path: entry.path().to_path_buf(),
+            target: lexical::normalize(&target),
+        });
+    }
+    Ok(found)
+}
+
+/// Deletes the links found by `scan`.
+///
+/// # Arguments
+///
+/// * `links` - The broken links to delete
+///
+/// # Returns
+///
+/// * `io::Result<()>` - Success if every link was deleted
+pub fn remove(links: &[BrokenLink]) -> io::Result<()> {
+    for link in links {
+        platform::remove_link(&link.path)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,73 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A calendar date and time of day in UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct UtcTime {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+}
+
+impl UtcTime {
+    /// Returns the current time.
+    pub fn now() -> Self {
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        Self::from_unix(secs)
+    }
+
+    /// Converts seconds since the Unix epoch to a calendar time.
+    ///
+    /// # Arguments
+    ///
+    /// * `secs` - Seconds since 1970-01-01T00:00:00Z
+    ///
+    /// # Returns
+    ///
+    /// * `UtcTime` - The calendar time
+    pub fn from_unix(secs: u64) -> Self {
+        let (days, rem) = (secs / 86400, secs % 86400);
+
+        // Civil-from-days, after Howard Hinnant's date algorithms.
+        let z = days as i64 + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + i64::from(month <= 2);
+
+        UtcTime {
+            year,
+            month: month as u32,
+            day: day as u32,
+            hour: (rem / 3600) as u32,
+            minute: (rem / 60 % 60) as u32,
+            second: (rem % 60) as u32,
+        }
+    }
+
+    /// Formats the time as an RFC 3339 timestamp, such as `2024-05-01T12:30:00Z`.
+    pub fn rfc3339(&self) -> String {
+        format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+
+    /// Formats the time without separators, such as `20240501T123000Z`, for
+    /// file names that must sort by age and avoid colons.
+    pub fn compact(&self) -> String {
+        format!(
+            "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
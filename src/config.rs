@@ -0,0 +1,525 @@
+use crate::json::Json;
+use crate::link::home;
+use crate::link::link_options::{LinkOptions, options_from_json};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Settings only the command line reads: how it reports a run, whether it
+/// prompts, and the destination template used when no DEST is given.
+pub const CLI_KEYS: [&str; 9] = [
+    "verbose",
+    "quiet",
+    "color",
+    "progress",
+    "output",
+    "strict",
+    "check_first",
+    "interactive",
+    "dest",
+];
+
+/// Default options read from a TOML file, by default
+/// `~/.config/flnk/config.toml`.
+///
+/// Top-level keys have the names of the `serve` and `batch` options, such as
+/// `symbolic = true`, `backup_suffix = ".orig"`, or `exclude = ["*.part"]`,
+/// plus the settings in `CLI_KEYS`. Named profiles are tables under
+/// `profile`, such as `[profile.media]`, selected with `Config::profile`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Config {
+    /// The file the settings were read from, or `None` if there was none
+    pub path: Option<PathBuf>,
+    /// The top-level keys and tables, in the order they were written
+    pub values: Vec<(String, Json)>,
+}
+
+impl Config {
+    /// Parses the text of a config file.
+    ///
+    /// Supports the parts of TOML a config needs: `key = value` lines with
+    /// bare or quoted keys, `[table]` headers, basic and literal strings,
+    /// integers, floats, booleans, arrays, which may span lines, and inline
+    /// tables. `#` starts a comment.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The text to parse
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Config, String>` - The settings, or the line and problem of
+    ///   the first error
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let mut parser = Parser {
+            chars: text.chars().collect(),
+            pos: 0,
+            line: 1,
+        };
+        parser
+            .document()
+            .map(|values| Config { path: None, values })
+            .map_err(|err| format!("line {}: {}", parser.line, err))
+    }
+
+    /// Returns the value of a top-level key.
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        self.values.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+
+    /// Returns the top-level keys that are settings rather than tables.
+    pub fn settings(&self) -> impl Iterator<Item = (&str, &Json)> {
+        self.values
+            .iter()
+            .filter(|(_, value)| !matches!(value, Json::Object(_)))
+            .map(|(key, value)| (key.as_str(), value))
+    }
+
+    /// Builds the link options the file's settings describe. Of the settings
+    /// in `CLI_KEYS`, only `interactive` is kept.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<LinkOptions, String>` - The options, or a description of the
+    ///   offending key
+    pub fn link_options(&self) -> Result<LinkOptions, String> {
+        let pairs = self
+            .settings()
+            .filter(|(key, _)| !CLI_KEYS.contains(key))
+            .map(|(key, value)| (key, value.clone()));
+        let mut opts = options_from_json(Some(&Json::object(pairs)))?;
+        if let Some(interactive) = self.get("interactive") {
+            opts.interactive = interactive
+                .as_bool()
+                .ok_or_else(|| String::from("interactive must be a boolean"))?;
+        }
+        Ok(opts)
+    }
+
+    /// Returns the settings of a named profile: those of its
+    /// `[profile.NAME]` table, on top of the top-level settings it does not
+    /// set itself.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The profile to select
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Config, String>` - The combined settings, or an error naming
+    ///   the profiles there are if `name` is not one of them
+    pub fn profile(&self, name: &str) -> Result<Config, String> {
+        let profiles = match self.get("profile") {
+            Some(Json::Object(profiles)) => &profiles[..],
+            _ => &[],
+        };
+        let Some((_, Json::Object(settings))) = profiles.iter().find(|(key, _)| key == name) else {
+            let names: Vec<&str> = profiles.iter().map(|(key, _)| key.as_str()).collect();
+            return Err(if names.is_empty() {
+                format!("no profile {}: the config file defines none", name)
+            } else {
+                format!("no profile {} (profiles: {})", name, names.join(", "))
+            });
+        };
+        let mut values: Vec<(String, Json)> = self
+            .settings()
+            .filter(|(key, _)| !settings.iter().any(|(k, _)| k == key))
+            .map(|(key, value)| (key.to_string(), value.clone()))
+            .collect();
+        values.extend(settings.iter().cloned());
+        Ok(Config {
+            path: self.path.clone(),
+            values,
+        })
+    }
+}
+
+/// Fills in a `dest` template for one source: `{name}` becomes the source's
+/// file name, `{stem}` and `{ext}` the name without and after its last `.`,
+/// and `{parent}` the name of the directory holding it. A leading `~` is
+/// the home directory.
+///
+/// # Arguments
+///
+/// * `template` - The template, such as `~/Media/{stem}/`
+/// * `source` - The source being linked
+///
+/// # Returns
+///
+/// * `Result<PathBuf, String>` - The destination, or an error naming an
+///   unknown or unterminated placeholder
+pub fn expand_dest(template: &str, source: &Path) -> Result<PathBuf, String> {
+    let part = |name: Option<&std::ffi::OsStr>| {
+        name.map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    };
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let Some(len) = rest[start..].find('}') else {
+            return Err(format!("unterminated placeholder in {}", template));
+        };
+        out.push_str(&match &rest[start + 1..start + len] {
+            "name" => part(source.file_name()),
+            "stem" => part(source.file_stem()),
+            "ext" => part(source.extension()),
+            "parent" => part(source.parent().and_then(Path::file_name)),
+            other => return Err(format!("unknown placeholder {{{}}} in {}", other, template)),
+        });
+        rest = &rest[start + len + 1..];
+    }
+    out.push_str(rest);
+    Ok(home::expand(Path::new(&out)).unwrap_or_else(|| PathBuf::from(out)))
+}
+
+/// Returns the config file read when none is given: `flnk/config.toml` in
+/// `$XDG_CONFIG_HOME`, or in `~/.config` if that is unset.
+pub fn default_path() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .map(|config| config.join("flnk/config.toml"))
+}
+
+/// Reads a config file.
+///
+/// # Arguments
+///
+/// * `path` - The file to read, or `None` for the default one, which may be missing
+///
+/// # Returns
+///
+/// * `io::Result<Config>` - The settings, empty if the default file does not
+///   exist, or an `InvalidData` error naming the file and line of a syntax error
+pub fn load(path: Option<&Path>) -> io::Result<Config> {
+    let (path, required) = match path {
+        Some(path) => (path.to_path_buf(), true),
+        None => match default_path() {
+            Some(path) => (path, false),
+            None => return Ok(Config::default()),
+        },
+    };
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound && !required => {
+            return Ok(Config::default());
+        }
+        Err(e) => {
+            return Err(io::Error::new(
+                e.kind(),
+                format!("{}: {}", path.display(), e),
+            ));
+        }
+    };
+    let mut config = Config::parse(&text).map_err(|err| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {}", path.display(), err),
+        )
+    })?;
+    config.path = Some(path);
+    Ok(config)
+}
+
+/// A TOML reader over the characters of a file, tracking the line for errors.
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        let matched = self.peek() == Some(c);
+        if matched {
+            self.bump();
+        }
+        matched
+    }
+
+    /// Skips spaces and tabs on the current line.
+    fn skip_spaces(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t')) {
+            self.bump();
+        }
+    }
+
+    /// Skips whitespace, newlines, and comments.
+    fn skip_blank(&mut self) {
+        loop {
+            match self.peek() {
+                Some(' ' | '\t' | '\r' | '\n') => {
+                    self.bump();
+                }
+                Some('#') => self.skip_comment(),
+                _ => return,
+            }
+        }
+    }
+
+    fn skip_comment(&mut self) {
+        while !matches!(self.peek(), None | Some('\n')) {
+            self.bump();
+        }
+    }
+
+    /// Expects nothing but a comment before the end of the line.
+    fn end_of_line(&mut self) -> Result<(), String> {
+        self.skip_spaces();
+        if self.peek() == Some('#') {
+            self.skip_comment();
+        }
+        self.eat('\r');
+        match self.peek() {
+            None => Ok(()),
+            Some('\n') => {
+                self.bump();
+                Ok(())
+            }
+            Some(c) => Err(format!("unexpected {:?} after the value", c)),
+        }
+    }
+
+    fn document(&mut self) -> Result<Vec<(String, Json)>, String> {
+        let mut root = Vec::new();
+        let mut table: Vec<String> = Vec::new();
+        loop {
+            self.skip_blank();
+            match self.peek() {
+                None => return Ok(root),
+                Some('[') => {
+                    self.bump();
+                    if self.peek() == Some('[') {
+                        return Err(String::from("arrays of tables are not supported"));
+                    }
+                    self.skip_spaces();
+                    table = self.key_path()?;
+                    if !self.eat(']') {
+                        return Err(String::from("expected ] to close the table header"));
+                    }
+                    table_mut(&mut root, &table)?;
+                    self.end_of_line()?;
+                }
+                Some(_) => {
+                    let mut path = self.key_path()?;
+                    if !self.eat('=') {
+                        return Err(String::from("expected = after the key"));
+                    }
+                    self.skip_spaces();
+                    let value = self.value()?;
+                    let key = path.pop().unwrap();
+                    path.splice(0..0, table.iter().cloned());
+                    insert(table_mut(&mut root, &path)?, key, value)?;
+                    self.end_of_line()?;
+                }
+            }
+        }
+    }
+
+    /// Reads a dotted key such as `a.b."c d"`, and the spaces after it.
+    fn key_path(&mut self) -> Result<Vec<String>, String> {
+        let mut path = vec![self.key()?];
+        self.skip_spaces();
+        while self.eat('.') {
+            self.skip_spaces();
+            path.push(self.key()?);
+            self.skip_spaces();
+        }
+        Ok(path)
+    }
+
+    fn key(&mut self) -> Result<String, String> {
+        match self.peek() {
+            Some('"') => self.basic_string(),
+            Some('\'') => self.literal_string(),
+            _ => {
+                let start = self.pos;
+                while matches!(self.peek(), Some(c) if c.is_ascii_alphanumeric() || c == '_' || c == '-')
+                {
+                    self.bump();
+                }
+                if self.pos == start {
+                    return Err(String::from("expected a key"));
+                }
+                Ok(self.chars[start..self.pos].iter().collect())
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        match self.peek() {
+            Some('"') => self.basic_string().map(Json::String),
+            Some('\'') => self.literal_string().map(Json::String),
+            Some('[') => self.array(),
+            Some('{') => self.inline_table(),
+            Some('t' | 'f') => {
+                let word = self.word();
+                match word.as_str() {
+                    "true" => Ok(Json::Bool(true)),
+                    "false" => Ok(Json::Bool(false)),
+                    _ => Err(format!("invalid value {}", word)),
+                }
+            }
+            Some(c) if c.is_ascii_digit() || c == '+' || c == '-' => {
+                let word = self.word();
+                word.replace('_', "")
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|n| n.is_finite())
+                    .map(Json::Number)
+                    .ok_or_else(|| format!("invalid or unsupported value {}", word))
+            }
+            _ => Err(String::from("expected a value")),
+        }
+    }
+
+    /// Reads the characters of a bare value, up to a delimiter.
+    fn word(&mut self) -> String {
+        let start = self.pos;
+        while matches!(self.peek(), Some(c) if !matches!(c, ' ' | '\t' | '\r' | '\n' | ',' | ']' | '}' | '#'))
+        {
+            self.bump();
+        }
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    fn basic_string(&mut self) -> Result<String, String> {
+        self.bump();
+        let mut out = String::new();
+        loop {
+            match self.bump() {
+                None | Some('\n') => return Err(String::from("unterminated string")),
+                Some('"') => return Ok(out),
+                Some('\\') => out.push(self.escape()?),
+                Some(c) => out.push(c),
+            }
+        }
+    }
+
+    fn escape(&mut self) -> Result<char, String> {
+        let c = match self.bump() {
+            Some('"') => '"',
+            Some('\\') => '\\',
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('r') => '\r',
+            Some('b') => '\u{8}',
+            Some('f') => '\u{c}',
+            Some(u @ ('u' | 'U')) => {
+                let len = if u == 'u' { 4 } else { 8 };
+                let hex: String = (0..len).filter_map(|_| self.bump()).collect();
+                return u32::from_str_radix(&hex, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| format!("invalid unicode escape \\{}{}", u, hex));
+            }
+            Some(c) => return Err(format!("invalid escape \\{}", c)),
+            None => return Err(String::from("unterminated string")),
+        };
+        Ok(c)
+    }
+
+    fn literal_string(&mut self) -> Result<String, String> {
+        self.bump();
+        let mut out = String::new();
+        loop {
+            match self.bump() {
+                None | Some('\n') => return Err(String::from("unterminated string")),
+                Some('\'') => return Ok(out),
+                Some(c) => out.push(c),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Json, String> {
+        self.bump();
+        let mut items = Vec::new();
+        loop {
+            self.skip_blank();
+            if self.eat(']') {
+                return Ok(Json::Array(items));
+            }
+            items.push(self.value()?);
+            self.skip_blank();
+            if !self.eat(',') {
+                self.skip_blank();
+                if self.eat(']') {
+                    return Ok(Json::Array(items));
+                }
+                return Err(String::from("expected , or ] in the array"));
+            }
+        }
+    }
+
+    fn inline_table(&mut self) -> Result<Json, String> {
+        self.bump();
+        let mut pairs = Vec::new();
+        self.skip_spaces();
+        if self.eat('}') {
+            return Ok(Json::Object(pairs));
+        }
+        loop {
+            self.skip_spaces();
+            let mut path = self.key_path()?;
+            if !self.eat('=') {
+                return Err(String::from("expected = after the key"));
+            }
+            self.skip_spaces();
+            let value = self.value()?;
+            let key = path.pop().unwrap();
+            insert(table_mut(&mut pairs, &path)?, key, value)?;
+            self.skip_spaces();
+            if self.eat('}') {
+                return Ok(Json::Object(pairs));
+            }
+            if !self.eat(',') {
+                return Err(String::from("expected , or } in the inline table"));
+            }
+        }
+    }
+}
+
+/// Finds the table at `path` below `root`, creating missing ones.
+fn table_mut<'a>(
+    mut table: &'a mut Vec<(String, Json)>,
+    path: &[String],
+) -> Result<&'a mut Vec<(String, Json)>, String> {
+    for name in path {
+        let index = match table.iter().position(|(key, _)| key == name) {
+            Some(index) => index,
+            None => {
+                table.push((name.clone(), Json::Object(Vec::new())));
+                table.len() - 1
+            }
+        };
+        table = match &mut table[index].1 {
+            Json::Object(pairs) => pairs,
+            _ => return Err(format!("{} is already a value, not a table", name)),
+        };
+    }
+    Ok(table)
+}
+
+fn insert(table: &mut Vec<(String, Json)>, key: String, value: Json) -> Result<(), String> {
+    if table.iter().any(|(k, _)| *k == key) {
+        return Err(format!("{} is set twice", key));
+    }
+    table.push((key, value));
+    Ok(())
+}
@@ -0,0 +1,287 @@
+use crate::link::backups::BACKUP_DIR;
+use crate::sha256;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process;
+use walkdir::WalkDir;
+
+/// Files under a directory with identical contents.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Duplicates {
+    /// The size of each file in bytes
+    pub size: u64,
+    /// The copy the others are linked to: of the files, the one with the
+    /// most hard links already, or the first found
+    pub keep: PathBuf,
+    /// The paths of the other files, every name of a file that already has
+    /// several listed together
+    pub copies: Vec<PathBuf>,
+    /// The bytes freed by linking the copies to `keep`, which leaves out
+    /// files that still have a name outside the directory
+    pub freed: u64,
+}
+
+/// A regular file found by `scan`, with every name it was found under.
+struct Found {
+    paths: Vec<PathBuf>,
+    nlink: u64,
+}
+
+/// Finds the regular files under a directory whose contents are identical.
+///
+/// Files are grouped by device and size first, and only files sharing both
+/// are hashed. Names of one file are already shared, so a file with
+/// several hard links counts once. Symlinks are not followed, empty files
+/// are left out, and so is the backup directory.
+///
+/// # Arguments
+///
+/// * `dir` - The directory to scan
+///
+/// # Returns
+///
+/// * `io::Result<Vec<Duplicates>>` - Every set of identical files, in the
+///   order their first file was found
+pub fn scan(dir: &Path) -> io::Result<Vec<Duplicates>> {
+    let mut files: Vec<Found> = Vec::new();
+    let mut by_inode: HashMap<(u64, u64), usize> = HashMap::new();
+    let mut by_size: HashMap<(u64, u64), Vec<usize>> = HashMap::new();
+    let mut sizes = Vec::new();
+    let mut walk = WalkDir::new(dir)
+        .min_depth(1)
+        .sort_by_file_name()
+        .into_iter();
+    while let Some(entry) = walk.next() {
+        let entry = entry?;
+        if entry.depth() == 1 && entry.file_name() == BACKUP_DIR {
+            walk.skip_current_dir();
+            continue;
+        }
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let meta = entry.metadata()?;
+        if meta.len() == 0 {
+            continue;
+        }
+        let path = entry.into_path();
+        if let Some(&i) = by_inode.get(&(meta.dev(), meta.ino())) {
+            files[i].paths.push(path);
+            continue;
+        }
+        by_inode.insert((meta.dev(), meta.ino()), files.len());
+        let key = (meta.dev(), meta.len());
+        let same_size = by_size.entry(key).or_default();
+        if same_size.is_empty() {
+            sizes.push(key);
+        }
+        same_size.push(files.len());
+        files.push(Found {
+            paths: vec![path],
+            nlink: meta.nlink(),
+        });
+    }
+
+    let mut found = Vec::new();
+    for key @ (_, size) in sizes {
+        let same_size = &by_size[&key];
+        if same_size.len() < 2 {
+            continue;
+        }
+        let mut digests = Vec::new();
+        let mut by_digest: HashMap<String, Vec<usize>> = HashMap::new();
+        for &i in same_size {
+            let digest = sha256::hash_file(&files[i].paths[0])?;
+            if !by_digest.contains_key(&digest) {
+                digests.push(digest.clone());
+            }
+            by_digest.entry(digest).or_default().push(i);
+        }
+        for digest in digests {
+            let same = &by_digest[&digest];
+            if same.len() < 2 {
+                continue;
+            }
+            let keep = *same.iter().rev().max_by_key(|&&i| files[i].nlink).unwrap();
+            let others = same.iter().filter(|&&i| i != keep).map(|&i| &files[i]);
+            found.push(Duplicates {
+                size,
+                keep: files[keep].paths[0].clone(),
+                copies: others.clone().flat_map(|f| f.paths.clone()).collect(),
+                freed: others.filter(|f| f.nlink == f.paths.len() as u64).count() as u64 * size,
+            });
+        }
+    }
+    Ok(found)
+}
+
+/// Replaces every copy found by `scan` with a hard link to the file kept.
+///
+/// Each link is made under a temporary name next to the copy and renamed
+/// over it, so the copy's path never goes missing. The copies' own
+/// permissions, owners, and timestamps give way to those of the file kept.
+///
+/// # Arguments
+///
+/// * `duplicates` - The sets of identical files
+///
+/// # Returns
+///
+/// * `io::Result<u64>` - The bytes freed, or the first error, leaving the
+///   copies not reached yet as they were
+pub fn link_copies(duplicates: &[Duplicates]) -> io::Result<u64> {
+    for set in duplicates {
+        for copy in &set.copies {
+            let name = copy.file_name().unwrap_or_default().to_string_lossy();
+            let temp = copy.with_file_name(format!(".{}.flnk-dedup-{}", name, process::id()));
+            fs::hard_link(&set.keep, &temp)?;
+            if let Err(e) = fs::rename(&temp, copy) {
+                let _ = fs::remove_file(&temp);
+                return Err(e);
+            }
+        }
+    }
+    Ok(duplicates.iter().map(|set| set.freed).sum())
+}
+
+/// Makes every copy found by `scan` share the extents of the file kept,
+/// leaving each a separate file; see `share_extents`.
+///
+/// # Arguments
+///
+/// * `duplicates` - The sets of identical files
+///
+/// # Returns
+///
+/// * `io::Result<u64>` - The bytes now shared, counting each file once, or
+///   the first error, such as `Unsupported` on filesystems without
+///   block-level deduplication
+pub fn share_copies(duplicates: &[Duplicates]) -> io::Result<u64> {
+    let mut shared = 0;
+    let mut seen = HashSet::new();
+    for set in duplicates {
+        for copy in &set.copies {
+            let meta = fs::metadata(copy)?;
+            if seen.insert((meta.dev(), meta.ino())) {
+                shared += share_extents(&set.keep, copy)?;
+            }
+        }
+    }
+    Ok(shared)
+}
+
+/// The most bytes submitted in one dedupe request; filesystems cap each
+/// request (btrfs at 16 MiB) and report partial progress beyond that.
+#[cfg(target_os = "linux")]
+const DEDUPE_CHUNK: u64 = 16 * 1024 * 1024;
+
+/// `_IOWR(0x94, 54, struct file_dedupe_range)` from `linux/fs.h`.
+#[cfg(target_os = "linux")]
+const FIDEDUPERANGE: libc::Ioctl = 0xC018_9436;
+
+/// The status the kernel reports when the two ranges are not identical.
+#[cfg(target_os = "linux")]
+const FILE_DEDUPE_RANGE_DIFFERS: i32 = 1;
+
+/// `struct file_dedupe_range` with a single destination.
+#[cfg(target_os = "linux")]
+#[repr(C)]
+struct FileDedupeRange {
+    src_offset: u64,
+    src_length: u64,
+    dest_count: u16,
+    reserved1: u16,
+    reserved2: u32,
+    dest_fd: i64,
+    dest_offset: u64,
+    bytes_deduped: u64,
+    status: i32,
+    reserved: u32,
+}
+
+/// Makes `dest` share the extents of `source` on filesystems that support
+/// block-level deduplication (btrfs, XFS).
+///
+/// Unlike replacing `dest` with a hard link, both files stay independent:
+/// writing to one later copies the affected blocks instead of changing the
+/// other. The kernel compares the contents itself and refuses to share
+/// ranges that differ, so this is safe to call on files that are only
+/// believed to be identical.
+///
+/// # Arguments
+///
+/// * `source` - The file whose extents are kept
+/// * `dest` - The duplicate whose extents are replaced by those of `source`
+///
+/// # Returns
+///
+/// * `io::Result<u64>` - The number of bytes now shared, or an `InvalidData`
+///   error if the contents differ and an `Unsupported` error on filesystems
+///   without deduplication
+#[cfg(target_os = "linux")]
+pub fn share_extents(source: &Path, dest: &Path) -> io::Result<u64> {
+    use std::fs::{File, OpenOptions};
+    use std::os::unix::io::AsRawFd;
+
+    let src = File::open(source)?;
+    let dst = OpenOptions::new().write(true).open(dest)?;
+    let len = src.metadata()?.len();
+    if dst.metadata()?.len() != len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} and {} differ in size", source.display(), dest.display()),
+        ));
+    }
+
+    let mut offset = 0;
+    while offset < len {
+        let mut range = FileDedupeRange {
+            src_offset: offset,
+            src_length: (len - offset).min(DEDUPE_CHUNK),
+            dest_count: 1,
+            reserved1: 0,
+            reserved2: 0,
+            dest_fd: dst.as_raw_fd().into(),
+            dest_offset: offset,
+            bytes_deduped: 0,
+            status: 0,
+            reserved: 0,
+        };
+        if unsafe { libc::ioctl(src.as_raw_fd(), FIDEDUPERANGE, &mut range) } != 0 {
+            let err = io::Error::last_os_error();
+            return Err(match err.raw_os_error() {
+                Some(libc::EOPNOTSUPP | libc::ENOTTY | libc::EINVAL) => io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "the filesystem does not support block-level deduplication",
+                ),
+                _ => err,
+            });
+        }
+        if range.status < 0 {
+            return Err(io::Error::from_raw_os_error(-range.status));
+        }
+        if range.status == FILE_DEDUPE_RANGE_DIFFERS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} and {} differ", source.display(), dest.display()),
+            ));
+        }
+        if range.bytes_deduped == 0 {
+            break;
+        }
+        offset += range.bytes_deduped;
+    }
+    Ok(offset)
+}
+
+/// Makes `dest` share the extents of `source`; unsupported outside Linux.
+#[cfg(not(target_os = "linux"))]
+pub fn share_extents(_source: &Path, _dest: &Path) -> io::Result<u64> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "block-level deduplication is only supported on Linux",
+    ))
+}
@@ -0,0 +1,218 @@
+use crate::link::link_files::expand_sources;
+use crate::link::link_options::LinkOptions;
+use crate::link::pipeline::{self, EntryKind, LinkJob};
+use crate::link::platform;
+use crate::link::probe::{self, FsCapabilities};
+use std::fmt;
+use std::fs::{self, File};
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::sync_channel;
+use std::thread;
+
+/// The number of source files opened to check that the source tree is readable.
+const PERMISSION_SAMPLES: usize = 16;
+
+/// Everything worth knowing about a link operation before running it.
+#[derive(Debug, Clone)]
+pub struct DoctorReport {
+    /// The source pattern that was examined
+    pub source: String,
+    /// The destination that was examined
+    pub dest: PathBuf,
+    /// The filesystem type holding the source
+    pub source_fs: String,
+    /// The probed capabilities of the destination filesystem
+    pub dest_caps: Option<FsCapabilities>,
+    /// True if source and destination are on the same device
+    pub same_device: Option<bool>,
+    /// The space available to unprivileged users on the destination, in bytes
+    pub free_bytes: Option<u64>,
+    /// The total size of the files that would be linked, in bytes
+    pub source_bytes: u64,
+    /// The number of entries that would be linked
+    pub entries: usize,
+    /// The number of entries whose destination already exists
+    pub conflicts: usize,
+    /// Problems that would make the run fail
+    pub problems: Vec<String>,
+}
+
+impl DoctorReport {
+    /// True if no problems were found.
+    pub fn is_healthy(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+fn yes_no(value: bool) -> &'static str {
+    if value { "yes" } else { "no" }
+}
+
+impl fmt::Display for DoctorReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "source:         {} ({})", self.source, self.source_fs)?;
+        match &self.dest_caps {
+            Some(caps) => {
+                writeln!(
+                    f,
+                    "destination:    {} ({})",
+                    self.dest.display(),
+                    caps.fs_type
+                )?;
+                writeln!(f, "hard links:     {}", yes_no(caps.hard_links))?;
+                writeln!(f, "symlinks:       {}", yes_no(caps.symlinks))?;
+                writeln!(f, "reflinks:       {}", yes_no(caps.reflinks))?;
+                writeln!(f, "case sensitive: {}", yes_no(caps.case_sensitive))?;
+            }
+            None => writeln!(
+                f,
+                "destination:    {} (could not probe)",
+                self.dest.display()
+            )?,
+        }
+        match self.same_device {
+            Some(same) => writeln!(f, "same device:    {}", yes_no(same))?,
+            None => writeln!(f, "same device:    unknown")?,
+        }
+        match self.free_bytes {
+            Some(free) => writeln!(
+                f,
+                "free space:     {} bytes ({} bytes of source data)",
+                free, self.source_bytes
+            )?,
+            None => writeln!(f, "free space:     unknown")?,
+        }
+        writeln!(f, "entries:        {}", self.entries)?;
+        writeln!(f, "conflicts:      {}", self.conflicts)?;
+        if self.problems.is_empty() {
+            write!(f, "no problems found")
+        } else {
+            write!(f, "problems:")?;
+            for problem in &self.problems {
+                write!(f, "\n  - {}", problem)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Returns the nearest existing directory at or above `path`.
+fn existing_ancestor(path: &Path) -> PathBuf {
+    path.ancestors()
+        .find(|p| !p.as_os_str().is_empty() && p.is_dir())
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
+/// Examines a prospective link operation without creating anything but probe entries.
+///
+/// # Arguments
+///
+/// * `source` - The source path or pattern, as it would be passed to `link_files`
+/// * `dest` - The destination path
+/// * `opts` - The options the run would use
+///
+/// # Returns
+///
+/// * `io::Result<DoctorReport>` - The findings, or an error if the source cannot be read at all
+pub fn diagnose(
+    source: impl AsRef<Path>,
+    dest: impl AsRef<Path>,
+    opts: &LinkOptions,
+) -> io::Result<DoctorReport> {
+    let (source, dest_path) = (source.as_ref(), dest.as_ref());
+    let sources = expand_sources(source)?;
+    let mut problems = Vec::new();
+
+    if sources.is_empty() {
+        problems.push(format!("{} matches nothing", source.display()));
+    }
+
+    let source_dev = sources
+        .first()
+        .and_then(|s| fs::metadata(s).ok())
+        .map(|m| m.dev());
+    let source_fs = sources
+        .first()
+        .and_then(|s| probe::filesystem_type(s).ok())
+        .unwrap_or_else(|| String::from("unknown"));
+
+    let dest_dir = existing_ancestor(dest_path);
+    let dest_caps = probe::probe_destination(dest_path);
+    match &dest_caps {
+        Some(caps) => {
+            if let Err(e) = probe::resolve(caps, opts) {
+                problems.push(e.to_string());
+            }
+        }
+        None if !platform::is_writable(&dest_dir) => {
+            problems.push(format!("{} is not writable", dest_dir.display()));
+        }
+        None => {}
+    }
+
+    let same_device = match (source_dev, &dest_caps) {
+        (Some(src), Some(caps)) => Some(src == caps.device),
+        _ => None,
+    };
+    if same_device == Some(false) && !opts.symbolic {
+        problems.push(String::from(
+            "source and destination are on different devices; hard links are impossible",
+        ));
+    }
+
+    let (tx, rx) = sync_channel::<io::Result<LinkJob>>(pipeline::CHANNEL_CAPACITY);
+    let (mut entries, mut conflicts, mut source_bytes, mut sampled) = (0, 0, 0, 0);
+    let sources_ref = &sources;
+    thread::scope(|scope| {
+        scope.spawn(move || pipeline::discover(sources_ref, dest_path, opts, &tx));
+
+        for job in rx {
+            let job = match job {
+                Ok(job) => job,
+                Err(e) => {
+                    problems.push(format!("cannot read source tree: {}", e));
+                    continue;
+                }
+            };
+            if !job.kind.creates_link() {
+                continue;
+            }
+            entries += 1;
+            if fs::symlink_metadata(&job.dest).is_ok() {
+                conflicts += 1;
+            }
+            if job.kind == EntryKind::File {
+                source_bytes += fs::metadata(&job.source).map(|m| m.len()).unwrap_or(0);
+                if sampled < PERMISSION_SAMPLES {
+                    sampled += 1;
+                    if let Err(e) = File::open(&job.source) {
+                        problems.push(format!("cannot read {}: {}", job.source.display(), e));
+                    }
+                }
+            }
+        }
+    });
+
+    if conflicts > 0 && !opts.force && !opts.backup && !opts.skip_existing {
+        problems.push(format!(
+            "{} destination entries already exist; use -f or -b to replace them, or --skip-existing to keep them",
+            conflicts
+        ));
+    }
+
+    Ok(DoctorReport {
+        source: source.display().to_string(),
+        dest: dest_path.to_path_buf(),
+        source_fs,
+        free_bytes: probe::free_space(&dest_dir).ok(),
+        dest_caps,
+        same_device,
+        source_bytes,
+        entries,
+        conflicts,
+        problems,
+    })
+}
@@ -0,0 +1,261 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The step of linking an entry that failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    /// Creating a hard link
+    Link,
+    /// Creating a symbolic link
+    Symlink,
+    /// Copying a file
+    Copy,
+    /// Making a copy-on-write clone of a file
+    Clone,
+    /// Moving an existing destination entry aside as a backup
+    Backup,
+    /// Removing an existing destination entry to replace it
+    Replace,
+    /// Creating the directories a link goes in
+    CreateDir,
+}
+
+impl Op {
+    /// The operation as a verb phrase, as in "cannot symlink SOURCE at DEST".
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Op::Link => "hard link",
+            Op::Symlink => "symlink",
+            Op::Copy => "copy",
+            Op::Clone => "clone",
+            Op::Backup => "back up the destination to link",
+            Op::Replace => "replace the destination with",
+            Op::CreateDir => "create the directory to link",
+        }
+    }
+}
+
+/// An error linking one entry, naming the entry and what was being done.
+///
+/// Library functions keep returning `io::Result`: a `FlnkError` converts
+/// into an `io::Error` of the matching kind, and `FlnkError::find` gets it
+/// back out.
+#[derive(Debug)]
+pub enum FlnkError {
+    /// The destination exists, and neither `force` nor `backup` allows
+    /// replacing it
+    Exists {
+        op: Op,
+        source: PathBuf,
+        dest: PathBuf,
+    },
+    /// The user chose to quit when asked whether to replace the destination
+    Quit { source: PathBuf, dest: PathBuf },
+    /// Several sources would be linked at the same destination, and `more`
+    /// other destinations are contested too
+    Collision {
+        sources: Vec<PathBuf>,
+        dest: PathBuf,
+        more: usize,
+    },
+    /// The user pressed Ctrl-C; the run stopped before linking this entry,
+    /// after creating `linked` links
+    Interrupted {
+        op: Op,
+        source: PathBuf,
+        dest: PathBuf,
+        linked: usize,
+    },
+    /// A filesystem call failed
+    Io {
+        op: Op,
+        source: PathBuf,
+        dest: PathBuf,
+        error: io::Error,
+    },
+}
+
+impl FlnkError {
+    /// Returns the `FlnkError` an `io::Error` was made from, if any.
+    pub fn find(err: &io::Error) -> Option<&FlnkError> {
+        err.get_ref()?.downcast_ref()
+    }
+
+    /// Returns a `map_err` adapter that gives a bare `io::Error` the entry
+    /// and operation it happened in. Errors that already have them are kept.
+    ///
+    /// # Arguments
+    ///
+    /// * `op` - The operation being carried out
+    /// * `source` - The source entry
+    /// * `dest` - The destination entry
+    ///
+    /// # Returns
+    ///
+    /// * `impl FnOnce(io::Error) -> io::Error` - The adapter
+    pub fn wrap<'a>(
+        op: Op,
+        source: &'a Path,
+        dest: &'a Path,
+    ) -> impl FnOnce(io::Error) -> io::Error + 'a {
+        move |error| {
+            if FlnkError::find(&error).is_some() {
+                return error;
+            }
+            FlnkError::Io {
+                op,
+                source: source.to_path_buf(),
+                dest: dest.to_path_buf(),
+                error,
+            }
+            .into()
+        }
+    }
+
+    /// The operation that failed.
+    pub fn op(&self) -> Op {
+        match self {
+            FlnkError::Exists { op, .. }
+            | FlnkError::Interrupted { op, .. }
+            | FlnkError::Io { op, .. } => *op,
+            FlnkError::Quit { .. } => Op::Replace,
+            FlnkError::Collision { .. } => Op::Link,
+        }
+    }
+
+    /// The source entry; for a collision, the last source listed.
+    pub fn source_path(&self) -> &Path {
+        match self {
+            FlnkError::Exists { source, .. }
+            | FlnkError::Quit { source, .. }
+            | FlnkError::Interrupted { source, .. }
+            | FlnkError::Io { source, .. } => source,
+            FlnkError::Collision { sources, .. } => sources.last().unwrap(),
+        }
+    }
+
+    /// The destination entry.
+    pub fn dest(&self) -> &Path {
+        match self {
+            FlnkError::Exists { dest, .. }
+            | FlnkError::Quit { dest, .. }
+            | FlnkError::Collision { dest, .. }
+            | FlnkError::Interrupted { dest, .. }
+            | FlnkError::Io { dest, .. } => dest,
+        }
+    }
+
+    /// The kind of `io::Error` the error converts into.
+    pub fn kind(&self) -> io::ErrorKind {
+        match self {
+            FlnkError::Exists { .. } | FlnkError::Collision { .. } => io::ErrorKind::AlreadyExists,
+            FlnkError::Quit { .. } | FlnkError::Interrupted { .. } => io::ErrorKind::Interrupted,
+            FlnkError::Io { error, .. } => error.kind(),
+        }
+    }
+
+    /// Why the operation failed, without the entry it failed on.
+    pub fn reason(&self) -> String {
+        match self {
+            FlnkError::Exists { .. } => String::from("the destination exists"),
+            FlnkError::Quit { .. } => String::from("quit when asked to replace it"),
+            FlnkError::Interrupted { linked, .. } => format!(
+                "interrupted after {} link{}",
+                linked,
+                if *linked == 1 { "" } else { "s" }
+            ),
+            FlnkError::Collision { sources, more, .. } => {
+                format!("{} maps there too{}", sources[0].display(), hint(*more))
+            }
+            FlnkError::Io {
+                op: Op::Clone,
+                error,
+                ..
+            } => {
+                let why = match error.kind() {
+                    io::ErrorKind::CrossesDevices => {
+                        "; clones need the source and destination on the same filesystem"
+                    }
+                    io::ErrorKind::Unsupported | io::ErrorKind::InvalidInput => {
+                        "; the filesystem does not support copy-on-write clones"
+                    }
+                    _ => "",
+                };
+                format!("{}{}", error, why)
+            }
+            FlnkError::Io { error, .. } => error.to_string(),
+        }
+    }
+
+    /// The OS error number of the failed filesystem call, if there was one.
+    pub fn errno(&self) -> Option<i32> {
+        match self {
+            FlnkError::Io { error, .. } => error.raw_os_error(),
+            _ => None,
+        }
+    }
+}
+
+/// The end of a collision message: how many other destinations are
+/// contested, and how to link anyway.
+fn hint(more: usize) -> String {
+    let more = match more {
+        0 => String::new(),
+        n => format!(" (and {} more)", n),
+    };
+    format!("{}; use -f or -b to link the last one", more)
+}
+
+impl fmt::Display for FlnkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let FlnkError::Collision {
+            sources,
+            dest,
+            more,
+        } = self
+        {
+            return write!(
+                f,
+                "{} and {} both map to {}{}",
+                sources[0].display(),
+                self.source_path().display(),
+                dest.display(),
+                hint(*more)
+            );
+        }
+        if let FlnkError::Interrupted { .. } = self {
+            return write!(
+                f,
+                "{}; stopped before {} at {}",
+                self.reason(),
+                self.source_path().display(),
+                self.dest().display()
+            );
+        }
+        write!(
+            f,
+            "cannot {} {} at {}: {}",
+            self.op().as_str(),
+            self.source_path().display(),
+            self.dest().display(),
+            self.reason()
+        )
+    }
+}
+
+impl Error for FlnkError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            FlnkError::Io { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<FlnkError> for io::Error {
+    fn from(err: FlnkError) -> Self {
+        io::Error::new(err.kind(), err)
+    }
+}
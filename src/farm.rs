@@ -0,0 +1,296 @@
+use crate::json::Json;
+use crate::link::lexical;
+use crate::link::link_files::Linker;
+use crate::link::link_options::LinkOptions;
+use crate::link::pipeline::{self, LinkJob};
+use crate::link::platform;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The file in the target tree recording the applied packages and resolved conflicts.
+pub const STATE_FILE: &str = ".flnk-farm.json";
+
+/// A package tree to be symlinked into a farm.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Package {
+    /// The root of the package tree
+    pub path: PathBuf,
+    /// Higher priorities win conflicts; the default is 0
+    pub priority: i64,
+}
+
+impl Package {
+    /// Parses a `DIR` or `DIR=PRIORITY` argument.
+    ///
+    /// A `=` suffix is only read as a priority when it is an integer and the
+    /// whole argument does not name an existing path.
+    pub fn parse(arg: &str) -> Package {
+        if !Path::new(arg).exists()
+            && let Some((path, priority)) = arg.rsplit_once('=')
+            && let Ok(priority) = priority.parse()
+        {
+            return Package {
+                path: PathBuf::from(path),
+                priority,
+            };
+        }
+        Package {
+            path: PathBuf::from(arg),
+            priority: 0,
+        }
+    }
+
+    fn to_json(&self) -> Json {
+        Json::object([
+            ("path", Json::from(self.path.as_path())),
+            ("priority", Json::from(self.priority)),
+        ])
+    }
+}
+
+/// An entry provided by more than one package.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    /// The entry's path relative to the farm
+    pub path: PathBuf,
+    /// The package whose entry is linked
+    pub winner: Package,
+    /// The packages whose entries were left out
+    pub losers: Vec<Package>,
+}
+
+impl Conflict {
+    fn to_json(&self) -> Json {
+        Json::object([
+            ("path", Json::from(self.path.as_path())),
+            ("winner", self.winner.to_json()),
+            (
+                "losers",
+                Json::Array(self.losers.iter().map(Package::to_json).collect()),
+            ),
+        ])
+    }
+
+    fn from_json(value: &Json) -> Option<Conflict> {
+        Some(Conflict {
+            path: PathBuf::from(value.get("path")?.as_str()?),
+            winner: package_from_json(value.get("winner")?)?,
+            losers: value
+                .get("losers")?
+                .as_array()?
+                .iter()
+                .map(package_from_json)
+                .collect::<Option<_>>()?,
+        })
+    }
+}
+
+fn package_from_json(value: &Json) -> Option<Package> {
+    Some(Package {
+        path: PathBuf::from(value.get("path")?.as_str()?),
+        priority: value.get("priority")?.as_i64()?,
+    })
+}
+
+/// The outcome of applying packages to a farm.
+#[derive(Debug, Clone, Default)]
+pub struct FarmReport {
+    /// The relative paths of the links created in this run
+    pub linked: Vec<PathBuf>,
+    /// The conflicts resolved in this run
+    pub conflicts: Vec<Conflict>,
+}
+
+/// Returns the package an existing symlink in the farm points into, if any.
+fn owner<'a>(link: &Path, packages: &'a [Package]) -> Option<&'a Package> {
+    let target = fs::read_link(link).ok()?;
+    let target = lexical::normalize(&link.parent().unwrap_or(Path::new("/")).join(target));
+    packages
+        .iter()
+        .filter(|package| target.starts_with(&package.path))
+        .max_by_key(|package| package.path.components().count())
+}
+
+/// Reads the farm's state file, returning its packages and conflicts.
+fn load_state(target: &Path) -> io::Result<(Vec<Package>, Vec<Conflict>)> {
+    let text = match fs::read_to_string(target.join(STATE_FILE)) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok((Vec::new(), Vec::new())),
+        Err(e) => return Err(e),
+    };
+    let invalid = |what: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {}", target.join(STATE_FILE).display(), what),
+        )
+    };
+    let state = Json::parse(&text).map_err(|e| invalid(&e.to_string()))?;
+    let list = |key: &str| state.get(key).and_then(Json::as_array).unwrap_or_default();
+
+    let packages = list("packages")
+        .iter()
+        .map(package_from_json)
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| invalid("malformed package"))?;
+    let conflicts = list("conflicts")
+        .iter()
+        .map(Conflict::from_json)
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| invalid("malformed conflict"))?;
+    Ok((packages, conflicts))
+}
+
+/// Finds the farm an existing symlink belongs to and the package it points into.
+///
+/// # Arguments
+///
+/// * `link` - The symlink to look up
+///
+/// # Returns
+///
+/// * `io::Result<Option<(PathBuf, Package)>>` - The farm directory holding the
+///   nearest `.flnk-farm.json` above the link and the owning package, or `None`
+///   if no farm records it
+pub fn owning_package(link: &Path) -> io::Result<Option<(PathBuf, Package)>> {
+    let link = std::path::absolute(link)?;
+    for dir in link.ancestors().skip(1) {
+        if dir.join(STATE_FILE).is_file() {
+            let (packages, _) = load_state(dir)?;
+            return Ok(owner(&link, &packages).map(|package| (dir.to_path_buf(), package.clone())));
+        }
+    }
+    Ok(None)
+}
+
+/// Symlinks the contents of several packages into one target tree, like a
+/// Nix profile or Homebrew prefix.
+///
+/// Directories are created in the target and only files and symlinks are
+/// linked, so packages sharing a directory such as `bin/` merge into it.
+/// When several packages provide the same entry, the one with the highest
+/// priority wins, ties going to the package listed first. Links from
+/// earlier runs are owned by the package they point into and are only
+/// replaced by a package of higher priority. Every conflict is reported and
+/// recorded, along with the packages and their priorities, in the target's
+/// `.flnk-farm.json`.
+///
+/// # Arguments
+///
+/// * `target` - The farm directory
+/// * `packages` - The packages to apply
+/// * `opts` - The link options; symbolic and files-only linking are always used
+///
+/// # Returns
+///
+/// * `io::Result<FarmReport>` - The links created and the conflicts resolved
+pub fn apply(target: &Path, packages: &[Package], opts: &LinkOptions) -> io::Result<FarmReport> {
+    let target = std::path::absolute(target)?;
+    let packages = packages
+        .iter()
+        .map(|package| {
+            if !package.path.is_dir() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("package {} is not a directory", package.path.display()),
+                ));
+            }
+            Ok(Package {
+                path: lexical::normalize(&std::path::absolute(&package.path)?),
+                priority: package.priority,
+            })
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let opts = LinkOptions {
+        symbolic: true,
+        symlink_files_only: true,
+        ..opts.clone()
+    };
+
+    let (mut known, old_conflicts) = load_state(&target)?;
+    known.retain(|old| !packages.iter().any(|p| p.path == old.path));
+    known.extend(packages.iter().cloned());
+
+    let mut candidates: BTreeMap<PathBuf, Vec<(&Package, LinkJob)>> = BTreeMap::new();
+    for package in &packages {
+        for job in pipeline::collect(std::slice::from_ref(&package.path), &target, &opts)? {
+            candidates
+                .entry(job.rel_path.clone())
+                .or_default()
+                .push((package, job));
+        }
+    }
+
+    let mut report = FarmReport::default();
+    let mut chosen = Vec::new();
+    for (rel_path, mut providers) in candidates {
+        // Stable, so equal priorities keep the command line order.
+        providers.sort_by_key(|(package, _)| Reverse(package.priority));
+        let mut providers = providers.into_iter();
+        let (mut winner, job) = providers.next().unwrap();
+        let mut losers: Vec<Package> = providers.map(|(p, _)| p.clone()).collect();
+        let mut needs_link = true;
+
+        if let Ok(metadata) = fs::symlink_metadata(&job.dest)
+            && metadata.file_type().is_symlink()
+            && let Some(existing) = owner(&job.dest, &known)
+        {
+            if existing.path != winner.path && existing.priority >= winner.priority {
+                losers.insert(0, winner.clone());
+                winner = existing;
+                needs_link = false;
+            } else {
+                if existing.path != winner.path {
+                    losers.push(existing.clone());
+                }
+                if fs::read_link(&job.dest).is_ok_and(|t| t == job.source) {
+                    needs_link = false;
+                } else {
+                    platform::remove_link(&job.dest)?;
+                }
+            }
+        }
+
+        if !losers.is_empty() {
+            report.conflicts.push(Conflict {
+                path: rel_path,
+                winner: winner.clone(),
+                losers,
+            });
+        }
+        if needs_link {
+            chosen.push(job);
+        }
+    }
+
+    let mut linker = Linker::new(&opts)?;
+    for job in chosen {
+        linker.link(job, &mut |_: &LinkJob| {})?;
+    }
+    report.linked = linker.finish()?.linked;
+
+    let mut conflicts: Vec<Conflict> = old_conflicts
+        .into_iter()
+        .filter(|old| !report.conflicts.iter().any(|new| new.path == old.path))
+        .collect();
+    conflicts.extend(report.conflicts.iter().cloned());
+    conflicts.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let state = Json::object([
+        (
+            "packages",
+            Json::Array(known.iter().map(Package::to_json).collect()),
+        ),
+        (
+            "conflicts",
+            Json::Array(conflicts.iter().map(Conflict::to_json).collect()),
+        ),
+    ]);
+    fs::create_dir_all(&target)?;
+    fs::write(target.join(STATE_FILE), format!("{}\n", state))?;
+
+    Ok(report)
+}
@@ -0,0 +1,57 @@
+use std::fs;
+use std::io::{self, Read};
+
+/// Reads the paths listed for `--files-from`: one per line, or separated by
+/// NUL characters with `-0`. Empty entries are left out.
+///
+/// # Arguments
+///
+/// * `file` - The list, or `-` for `stdin`
+/// * `null` - If true, paths are separated by NULs instead of newlines
+/// * `stdin` - Where a list named `-` is read from
+///
+/// # Returns
+///
+/// * `io::Result<Vec<String>>` - The paths, or an error if the list cannot
+///   be read or a path is not valid UTF-8
+pub fn read(file: &str, null: bool, mut stdin: impl Read) -> io::Result<Vec<String>> {
+    if file == "-" {
+        let mut bytes = Vec::new();
+        stdin.read_to_end(&mut bytes)?;
+        return split(&bytes, null, "standard input");
+    }
+    let bytes = fs::read(file).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", file, e)))?;
+    split(&bytes, null, file)
+}
+
+/// Splits a list of paths read from `name` into its entries.
+///
+/// # Arguments
+///
+/// * `bytes` - The contents of the list
+/// * `null` - If true, paths are separated by NULs instead of newlines
+/// * `name` - The list, as named in errors
+///
+/// # Returns
+///
+/// * `io::Result<Vec<String>>` - The non-empty entries, or an
+///   `InvalidData` error naming the first that is not valid UTF-8
+pub fn split(bytes: &[u8], null: bool, name: &str) -> io::Result<Vec<String>> {
+    let separator = if null { b'\0' } else { b'\n' };
+    bytes
+        .split(|b| *b == separator)
+        .filter(|path| !path.is_empty())
+        .map(|path| {
+            String::from_utf8(path.to_vec()).map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{}: {} is not valid UTF-8",
+                        name,
+                        String::from_utf8_lossy(path)
+                    ),
+                )
+            })
+        })
+        .collect()
+}
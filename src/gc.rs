@@ -0,0 +1,125 @@
+use crate::link::backups;
+use crate::link::probe::PROBE_PREFIX;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// What a reclaimable entry is left over from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Leftover {
+    /// A backup generation beyond the ones kept
+    BackupGeneration,
+    /// A filesystem probe entry whose process is gone, left by a run that died
+    StaleProbe,
+}
+
+impl fmt::Display for Leftover {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Leftover::BackupGeneration => write!(f, "expired backup generation"),
+            Leftover::StaleProbe => write!(f, "stale probe entry"),
+        }
+    }
+}
+
+/// An entry `gc` can remove.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reclaimable {
+    /// The file or directory to remove
+    pub path: PathBuf,
+    /// Why it can be removed
+    pub kind: Leftover,
+    /// The bytes its files take up
+    pub bytes: u64,
+}
+
+/// Finds everything under a destination that housekeeping can remove.
+///
+/// # Arguments
+///
+/// * `dest` - The destination directory to clean up
+/// * `keep` - How many of the newest backup generations to keep
+///
+/// # Returns
+///
+/// * `io::Result<Vec<Reclaimable>>` - The expired generations, oldest first,
+///   followed by the stale probe entries
+pub fn scan(dest: &Path, keep: usize) -> io::Result<Vec<Reclaimable>> {
+    let mut found = Vec::new();
+    for path in backups::expired(dest, keep)? {
+        found.push(Reclaimable {
+            bytes: disk_usage(&path)?,
+            path,
+            kind: Leftover::BackupGeneration,
+        });
+    }
+
+    let mut probes = Vec::new();
+    for entry in fs::read_dir(dest)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let Some(pid) = name
+            .to_str()
+            .and_then(|name| name.strip_prefix(PROBE_PREFIX))
+            .and_then(|rest| rest.split('-').next())
+            .and_then(|pid| pid.parse::<i32>().ok())
+        else {
+            continue;
+        };
+        if !process_alive(pid) {
+            probes.push(Reclaimable {
+                bytes: entry.metadata()?.len(),
+                path: entry.path(),
+                kind: Leftover::StaleProbe,
+            });
+        }
+    }
+    probes.sort_by(|a, b| a.path.cmp(&b.path));
+    found.extend(probes);
+    Ok(found)
+}
+
+/// Removes the entries found by `scan`.
+///
+/// # Arguments
+///
+/// * `items` - The entries to remove
+///
+/// # Returns
+///
+/// * `io::Result<u64>` - The bytes reclaimed
+pub fn reclaim(items: &[Reclaimable]) -> io::Result<u64> {
+    let mut total = 0;
+    for item in items {
+        if item.kind == Leftover::BackupGeneration {
+            fs::remove_dir_all(&item.path)?;
+        } else {
+            fs::remove_file(&item.path)?;
+        }
+        total += item.bytes;
+    }
+    Ok(total)
+}
+
+/// Returns true if a process with the given id exists. A process owned by
+/// another user still counts, so its entries are left alone.
+fn process_alive(pid: i32) -> bool {
+    if pid <= 0 {
+        return false;
+    }
+    let rc = unsafe { libc::kill(pid, 0) };
+    rc == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Sums the sizes of the files under `path`, without following symlinks.
+fn disk_usage(path: &Path) -> io::Result<u64> {
+    let mut total = 0;
+    for entry in walkdir::WalkDir::new(path) {
+        let entry = entry.map_err(io::Error::other)?;
+        if entry.file_type().is_file() {
+            total += entry.metadata().map_err(io::Error::other)?.len();
+        }
+    }
+    Ok(total)
+}
@@ -0,0 +1,210 @@
+use crate::clock::UtcTime;
+use crate::link::link_files::link_files;
+use crate::link::link_options::LinkOptions;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// The *arr application that invoked the hook.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arr {
+    Sonarr,
+    Radarr,
+}
+
+impl Arr {
+    /// The prefix of the application's environment variables.
+    fn prefix(self) -> &'static str {
+        match self {
+            Arr::Sonarr => "sonarr",
+            Arr::Radarr => "radarr",
+        }
+    }
+
+    /// The variable name infix for the imported file.
+    fn file(self) -> &'static str {
+        match self {
+            Arr::Sonarr => "episodefile",
+            Arr::Radarr => "moviefile",
+        }
+    }
+
+    /// The variable holding the library folder of the series or movie.
+    fn folder(self) -> &'static str {
+        match self {
+            Arr::Sonarr => "series_path",
+            Arr::Radarr => "movie_path",
+        }
+    }
+}
+
+impl fmt::Display for Arr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Arr::Sonarr => "Sonarr",
+            Arr::Radarr => "Radarr",
+        })
+    }
+}
+
+/// An event passed to a custom-script connection through the environment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportEvent {
+    /// The application that sent the event
+    pub arr: Arr,
+    /// The event type, e.g. `Download` or `Test`
+    pub event_type: String,
+    /// The imported file as it was found in the download folder
+    pub source: Option<PathBuf>,
+    /// The library folder of the series or movie
+    pub dest_folder: Option<PathBuf>,
+    /// The imported file's path relative to `dest_folder`
+    pub relative_path: Option<PathBuf>,
+}
+
+impl ImportEvent {
+    /// Reads the event from Sonarr or Radarr environment variables.
+    ///
+    /// # Arguments
+    ///
+    /// * `var` - Looks up an environment variable, e.g. `|name| std::env::var(name).ok()`
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<ImportEvent>` - The event, or a `NotFound` error when
+    ///   neither `sonarr_eventtype` nor `radarr_eventtype` is set
+    pub fn from_env(var: impl Fn(&str) -> Option<String>) -> io::Result<Self> {
+        let (arr, event_type) = [Arr::Sonarr, Arr::Radarr]
+            .into_iter()
+            .find_map(|arr| Some((arr, var(&format!("{}_eventtype", arr.prefix()))?)))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "no sonarr_eventtype or radarr_eventtype in the environment; run flnk import-hook as a Sonarr or Radarr custom script",
+                )
+            })?;
+        let path = |name: String| var(&name).filter(|v| !v.is_empty()).map(PathBuf::from);
+
+        Ok(ImportEvent {
+            arr,
+            event_type,
+            source: path(format!("{}_{}_sourcepath", arr.prefix(), arr.file())),
+            dest_folder: path(format!("{}_{}", arr.prefix(), arr.folder())),
+            relative_path: path(format!("{}_{}_relativepath", arr.prefix(), arr.file())),
+        })
+    }
+
+    /// Returns where the imported file should be linked: the same folder
+    /// name and relative path under `library`.
+    ///
+    /// # Arguments
+    ///
+    /// * `library` - The library root replacing the one the *arr imported into
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<PathBuf>` - The destination path, or an `InvalidInput`
+    ///   error if the event lacks the variables describing the import
+    pub fn destination(&self, library: &Path) -> io::Result<PathBuf> {
+        let missing = |what: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} {} event has no {}", self.arr, self.event_type, what),
+            )
+        };
+        let folder = self
+            .dest_folder
+            .as_deref()
+            .ok_or_else(|| missing(&format!("{}_{}", self.arr.prefix(), self.arr.folder())))?;
+        let relative = self.relative_path.as_deref().ok_or_else(|| {
+            missing(&format!(
+                "{}_{}_relativepath",
+                self.arr.prefix(),
+                self.arr.file()
+            ))
+        })?;
+
+        let name = folder
+            .file_name()
+            .ok_or_else(|| missing(&format!("folder name in {}", folder.display())))?;
+        Ok(library.join(name).join(relative))
+    }
+}
+
+/// Handles one custom-script event: `Download` events link the imported
+/// file, `Test` events only confirm the hook works, and others are ignored.
+///
+/// Every outcome is printed, which the *arr records in its own log, and
+/// appended to `log` when given.
+///
+/// Without a `library`, the only destination the event names is the file
+/// the *arr just imported, so `Download` and `Test` events fail and the
+/// *arr reports the hook as misconfigured.
+///
+/// # Arguments
+///
+/// * `event` - The event read from the environment
+/// * `library` - The library root replacing the one the *arr imported into
+/// * `opts` - The options for the link operation
+/// * `log` - A file to append a line about the outcome to
+///
+/// # Returns
+///
+/// * `io::Result<()>` - An error if linking failed, so the *arr reports the script as failed
+pub fn run(
+    event: &ImportEvent,
+    library: Option<&Path>,
+    opts: &LinkOptions,
+    log: Option<&Path>,
+) -> io::Result<()> {
+    let result = match (event.event_type.as_str(), library) {
+        ("Test" | "Download", None) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "--library is required: the event only names the file {} just imported",
+                event.arr
+            ),
+        )),
+        ("Test", Some(_)) => Ok(String::from("test event received, hook is working")),
+        ("Download", Some(library)) => link_import(event, library, opts),
+        (other, _) => Ok(format!("ignoring {} event", other)),
+    };
+
+    let message = match &result {
+        Ok(message) => message.clone(),
+        Err(e) => format!("error: {}", e),
+    };
+    let line = format!(
+        "{} {} {}: {}",
+        UtcTime::now().rfc3339(),
+        event.arr,
+        event.event_type,
+        message
+    );
+    println!("{}", line);
+    if let Some(log) = log {
+        let mut file = OpenOptions::new().create(true).append(true).open(log)?;
+        writeln!(file, "{}", line)?;
+    }
+    result.map(|_| ())
+}
+
+/// Links the imported file of a `Download` event, describing what was done.
+fn link_import(event: &ImportEvent, library: &Path, opts: &LinkOptions) -> io::Result<String> {
+    let source = event.source.as_deref().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} Download event has no {}_{}_sourcepath",
+                event.arr,
+                event.arr.prefix(),
+                event.arr.file()
+            ),
+        )
+    })?;
+    let dest = event.destination(library)?;
+
+    link_files(source, &dest, Some(opts))?;
+    Ok(format!("linked {} -> {}", source.display(), dest.display()))
+}
@@ -0,0 +1,95 @@
+use std::ffi::{CString, OsStr, OsString};
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::Path;
+
+pub use libc::{
+    IN_CLOSE_WRITE, IN_CREATE, IN_DELETE, IN_DELETE_SELF, IN_IGNORED, IN_ISDIR, IN_MOVE_SELF,
+    IN_MOVED_FROM, IN_MOVED_TO, IN_Q_OVERFLOW,
+};
+
+/// The size of the fixed part of a raw `struct inotify_event`.
+const EVENT_HEADER: usize = std::mem::size_of::<libc::inotify_event>();
+
+/// A single filesystem event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    /// The watch descriptor the event belongs to
+    pub wd: i32,
+    /// The `IN_*` flags describing what happened
+    pub mask: u32,
+    /// Pairs the `IN_MOVED_FROM` and `IN_MOVED_TO` halves of a rename
+    pub cookie: u32,
+    /// The name of the affected entry inside the watched directory, if any
+    pub name: Option<OsString>,
+}
+
+/// A Linux inotify instance.
+pub struct Inotify {
+    file: File,
+}
+
+impl Inotify {
+    /// Creates a new inotify instance.
+    pub fn new() -> io::Result<Self> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Inotify {
+            file: unsafe { File::from_raw_fd(fd) },
+        })
+    }
+
+    /// Starts watching `path` for the events in `mask`.
+    ///
+    /// Watching the same inode again returns the same descriptor with the mask replaced.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The file or directory to watch
+    /// * `mask` - The `IN_*` events to report
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<i32>` - The watch descriptor reported with the path's events
+    pub fn add_watch(&self, path: &Path, mask: u32) -> io::Result<i32> {
+        let c_path = CString::new(path.as_os_str().as_bytes())?;
+        let wd = unsafe { libc::inotify_add_watch(self.file.as_raw_fd(), c_path.as_ptr(), mask) };
+        if wd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(wd)
+    }
+
+    /// Blocks until at least one event is available and returns every queued event.
+    pub fn read_events(&self) -> io::Result<Vec<Event>> {
+        let mut buf = vec![0u8; 64 * 1024];
+        let len = (&self.file).read(&mut buf)?;
+
+        let mut events = Vec::new();
+        let mut offset = 0;
+        while offset + EVENT_HEADER <= len {
+            let raw = unsafe {
+                std::ptr::read_unaligned(buf[offset..].as_ptr().cast::<libc::inotify_event>())
+            };
+            let name_start = offset + EVENT_HEADER;
+            let name_end = name_start + raw.len as usize;
+            let name = buf[name_start..name_end.min(len)]
+                .split(|b| *b == 0)
+                .next()
+                .filter(|name| !name.is_empty())
+                .map(|name| OsStr::from_bytes(name).to_os_string());
+            events.push(Event {
+                wd: raw.wd,
+                mask: raw.mask,
+                cookie: raw.cookie,
+                name,
+            });
+            offset = name_end;
+        }
+        Ok(events)
+    }
+}
@@ -0,0 +1,251 @@
+use crate::farm::{self, Package};
+use crate::link::home;
+use crate::link::probe::{self, FsCapabilities};
+use crate::link::xattr;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+/// How many symlinks are followed before a chain is reported as a loop,
+/// matching the kernel's `MAXSYMLINKS`.
+const MAX_HOPS: usize = 40;
+
+/// The identity of a filesystem entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Identity {
+    /// The device the entry is on
+    pub device: u64,
+    /// The inode number
+    pub inode: u64,
+    /// The number of hard links to the inode
+    pub links: u64,
+    /// The size in bytes
+    pub size: u64,
+    /// What the entry is: `file`, `directory`, `symlink`, or `special`
+    pub kind: &'static str,
+}
+
+impl Identity {
+    fn of(metadata: &fs::Metadata) -> Self {
+        let file_type = metadata.file_type();
+        Identity {
+            device: metadata.dev(),
+            inode: metadata.ino(),
+            links: metadata.nlink(),
+            size: metadata.len(),
+            kind: if file_type.is_symlink() {
+                "symlink"
+            } else if file_type.is_dir() {
+                "directory"
+            } else if file_type.is_file() {
+                "file"
+            } else {
+                "special"
+            },
+        }
+    }
+}
+
+impl fmt::Display for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}, device {}, inode {}, {} link{}, {} bytes",
+            self.kind,
+            self.device,
+            self.inode,
+            self.links,
+            if self.links == 1 { "" } else { "s" },
+            self.size
+        )
+    }
+}
+
+/// Where following a symlink chain ends.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resolution {
+    /// The chain ends at an existing entry
+    Resolved(PathBuf, Identity),
+    /// The last target does not exist
+    Dangling(PathBuf),
+    /// The chain revisits a link or is longer than the kernel follows
+    Loop,
+    /// A link in the chain could not be read
+    Unreadable(PathBuf, String),
+}
+
+/// Everything flnk knows about a single path.
+#[derive(Debug, Clone)]
+pub struct Inspection {
+    /// The inspected path
+    pub path: PathBuf,
+    /// The entry itself, without following a symlink
+    pub identity: Identity,
+    /// Each symlink followed and its target as stored in the link
+    pub chain: Vec<(PathBuf, PathBuf)>,
+    /// Where the chain ends, if the path is a symlink
+    pub resolution: Option<Resolution>,
+    /// The type of the filesystem holding the path
+    pub fs_type: String,
+    /// The probed capabilities of that filesystem, if its directory is writable
+    pub caps: Option<FsCapabilities>,
+    /// The label of the run that marked the entry, if it carries a flnk mark
+    pub mark: Option<String>,
+    /// The farm directory and package owning the entry, if a farm records it
+    pub farm: Option<(PathBuf, Package)>,
+}
+
+fn yes_no(value: bool) -> &'static str {
+    if value { "yes" } else { "no" }
+}
+
+impl fmt::Display for Inspection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "path:           {}", self.path.display())?;
+        writeln!(f, "entry:          {}", self.identity)?;
+        for (link, target) in &self.chain {
+            writeln!(
+                f,
+                "link:           {} -> {}",
+                link.display(),
+                target.display()
+            )?;
+        }
+        match &self.resolution {
+            Some(Resolution::Resolved(path, identity)) => {
+                writeln!(f, "resolves to:    {}", path.display())?;
+                writeln!(f, "target:         {}", identity)?;
+            }
+            Some(Resolution::Dangling(path)) => {
+                writeln!(f, "resolves to:    {} (missing)", path.display())?
+            }
+            Some(Resolution::Loop) => writeln!(f, "resolves to:    nothing (symlink loop)")?,
+            Some(Resolution::Unreadable(path, err)) => {
+                writeln!(f, "resolves to:    nothing ({}: {})", path.display(), err)?
+            }
+            None => {}
+        }
+        writeln!(f, "filesystem:     {}", self.fs_type)?;
+        match &self.caps {
+            Some(caps) => writeln!(
+                f,
+                "capabilities:   hard links {}, symlinks {}, reflinks {}, case sensitive {}",
+                yes_no(caps.hard_links),
+                yes_no(caps.symlinks),
+                yes_no(caps.reflinks),
+                yes_no(caps.case_sensitive)
+            )?,
+            None => writeln!(f, "capabilities:   could not probe")?,
+        }
+        match (&self.mark, &self.farm) {
+            (Some(label), _) => write!(f, "managed:        yes, marked by run {}", label),
+            (None, Some((farm, package))) => write!(
+                f,
+                "managed:        yes, package {} of farm {}",
+                package.path.display(),
+                farm.display()
+            ),
+            (None, None) => write!(f, "managed:        no"),
+        }
+    }
+}
+
+/// Follows a symlink chain one stored target at a time.
+///
+/// # Arguments
+///
+/// * `path` - The symlink to start from
+///
+/// # Returns
+///
+/// * `(Vec<(PathBuf, PathBuf)>, Resolution)` - Each link with its stored target, and where the chain ends
+pub(crate) fn follow(path: &Path) -> (Vec<(PathBuf, PathBuf)>, Resolution) {
+    let mut chain: Vec<(PathBuf, PathBuf)> = Vec::new();
+    let mut current = path.to_path_buf();
+    loop {
+        let metadata = match fs::symlink_metadata(&current) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return (chain, Resolution::Dangling(current));
+            }
+            Err(e) => return (chain, Resolution::Unreadable(current, e.to_string())),
+        };
+        if !metadata.file_type().is_symlink() {
+            return (
+                chain,
+                Resolution::Resolved(current, Identity::of(&metadata)),
+            );
+        }
+        if chain.len() == MAX_HOPS || chain.iter().any(|(link, _)| *link == current) {
+            return (chain, Resolution::Loop);
+        }
+        let target = match fs::read_link(&current) {
+            Ok(target) => target,
+            Err(e) => return (chain, Resolution::Unreadable(current, e.to_string())),
+        };
+        // Relative targets are resolved against the real directory holding the link.
+        let parent = match current.parent() {
+            Some(parent) if parent.as_os_str().is_empty() => Path::new("."),
+            Some(parent) => parent,
+            None => Path::new("/"),
+        };
+        let base = fs::canonicalize(parent).unwrap_or_else(|_| parent.to_path_buf());
+        let mut next = base.join(&target);
+        // Home-relative targets are followed as the tools that write them
+        // expand them, unless a `~` entry really exists next to the link.
+        if fs::symlink_metadata(&next).is_err()
+            && let Some(expanded) = home::expand(&target)
+        {
+            next = expanded;
+        }
+        chain.push((current, target));
+        current = next;
+    }
+}
+
+/// Gathers the identity, symlink chain, filesystem, and flnk ownership of a path.
+///
+/// The directory holding the path is probed for its link capabilities when it
+/// is writable, which briefly creates probe entries in it.
+///
+/// # Arguments
+///
+/// * `path` - The path to inspect; it need not be anything flnk created
+///
+/// # Returns
+///
+/// * `io::Result<Inspection>` - The findings, or an error if the path does not exist
+pub fn inspect(path: &Path) -> io::Result<Inspection> {
+    let metadata = fs::symlink_metadata(path)?;
+    let identity = Identity::of(&metadata);
+    let (chain, resolution) = if metadata.file_type().is_symlink() {
+        let (chain, resolution) = follow(path);
+        (chain, Some(resolution))
+    } else {
+        (Vec::new(), None)
+    };
+
+    let dir = if metadata.is_dir() {
+        path
+    } else {
+        probe::existing_ancestor(path.parent().unwrap_or(Path::new(""))).unwrap_or(Path::new("."))
+    };
+    let farm = if metadata.file_type().is_symlink() {
+        farm::owning_package(path)?
+    } else {
+        None
+    };
+
+    Ok(Inspection {
+        path: path.to_path_buf(),
+        identity,
+        chain,
+        resolution,
+        fs_type: probe::filesystem_type(dir)?,
+        caps: probe::probe(dir).ok(),
+        mark: xattr::managed_label(path),
+        farm,
+    })
+}
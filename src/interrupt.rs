@@ -0,0 +1,62 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Set by the signal handler when the user presses Ctrl-C.
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Makes Ctrl-C (SIGINT) ask the running link to stop instead of killing
+/// the process, so it can stop between entries and report what it did. A
+/// second Ctrl-C kills the process as usual.
+#[cfg(unix)]
+pub fn install() {
+    let handler: extern "C" fn(libc::c_int) = on_signal;
+    unsafe {
+        libc::signal(libc::SIGINT, handler as libc::sighandler_t);
+    }
+}
+
+/// Ctrl-C is left to kill the process where there are no signals to catch.
+#[cfg(not(unix))]
+pub fn install() {}
+
+#[cfg(unix)]
+extern "C" fn on_signal(_signal: libc::c_int) {
+    REQUESTED.store(true, Ordering::SeqCst);
+    // Only async-signal-safe calls are allowed here; resetting the
+    // disposition is one.
+    unsafe {
+        libc::signal(libc::SIGINT, libc::SIG_DFL);
+    }
+}
+
+/// Returns true once the user has asked the run to stop.
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Asks a run from another thread to stop, as Ctrl-C does for the process.
+///
+/// Clones share one flag: give a clone to the run through
+/// `LinkOptions::cancel` and call `cancel` on another to stop it between
+/// entries with an interrupted error reporting what was linked. The `-u`
+/// interface stops its run with one; `serve` and `watch` do not take one and
+/// run until their process is ended.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    /// Creates a token that has not been cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Asks every run given a clone of this token to stop.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns true once `cancel` has been called on any clone.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
@@ -1,2 +1,44 @@
+#[cfg(all(not(unix), not(windows), not(feature = "wasi")))]
+compile_error!(
+    "targets other than Unix and Windows need the wasi feature: cargo build --target wasm32-wasip2 --no-default-features --features wasi"
+);
+
+pub mod batch;
+#[cfg(unix)]
+pub mod clean;
+pub mod clock;
+#[cfg(unix)]
+pub mod config;
+#[cfg(unix)]
+pub mod dedupe;
+#[cfg(unix)]
+pub mod doctor;
+pub mod error;
+pub mod farm;
+pub mod file_list;
+#[cfg(unix)]
+pub mod gc;
+pub mod import_hook;
+pub mod interrupt;
+#[cfg(unix)]
+pub mod inspect;
+#[cfg(target_os = "linux")]
+pub mod inotify;
+pub mod json;
 pub mod link;
+pub mod response_file;
+#[cfg(target_os = "linux")]
+pub mod sentinel;
+#[cfg(unix)]
+pub mod serve;
+pub mod sha256;
+pub mod stow;
+#[cfg(unix)]
+pub mod systemd;
+pub mod term;
+#[cfg(feature = "tui")]
 pub mod ui;
+#[cfg(unix)]
+pub mod verify;
+#[cfg(target_os = "linux")]
+pub mod watch;
@@ -0,0 +1,134 @@
+use crate::link::lexical;
+use crate::link::platform;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Returns true if the process runs as root and may therefore give away ownership.
+#[cfg(unix)]
+pub fn is_root() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+/// Returns true if the process runs as root and may therefore give away ownership.
+///
+/// WASI has no users, so ownership is never given away.
+#[cfg(not(unix))]
+pub fn is_root() -> bool {
+    false
+}
+
+/// Recreates a source symlink at `dest`, pointing at the same target.
+///
+/// # Arguments
+///
+/// * `source` - The symlink to reproduce
+/// * `dest` - The path where the new symlink should be created
+///
+/// # Returns
+///
+/// * `io::Result<()>` - Success if the symlink was created
+pub fn copy_symlink(source: &Path, dest: &Path) -> io::Result<()> {
+    let target = fs::read_link(source)?;
+    platform::symlink(&target, dest)
+}
+
+/// Works out the target a copy of the `source` symlink at `dest` needs to
+/// point where the source does.
+///
+/// Absolute targets, and relative ones that stay inside `root`, are kept as
+/// they are, so links within a mirrored tree point within the mirror. A
+/// relative target leading out of `root` is rewritten relative to `dest`.
+///
+/// # Arguments
+///
+/// * `source` - The symlink to reproduce
+/// * `dest` - The path where the copy will be created
+/// * `root` - The source tree being linked
+///
+/// # Returns
+///
+/// * `io::Result<PathBuf>` - The target for the copy
+pub fn equivalent_target(source: &Path, dest: &Path, root: &Path) -> io::Result<PathBuf> {
+    let target = fs::read_link(source)?;
+    let parent = source.parent().unwrap_or(Path::new("."));
+    if target.has_root() || lexical::is_within(&parent.join(&target), root) {
+        return Ok(target);
+    }
+    let resolved = lexical::normalize(&std::path::absolute(parent.join(&target))?);
+    let dest_dir = std::path::absolute(dest.parent().unwrap_or(Path::new(".")))?;
+    Ok(pathdiff::diff_paths(&resolved, lexical::normalize(&dest_dir)).unwrap_or(resolved))
+}
+
+/// Sets the access and modification times of `path` without following symlinks.
+#[cfg(unix)]
+fn set_times(path: &Path, metadata: &fs::Metadata) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::MetadataExt;
+
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    let times = [
+        libc::timespec {
+            tv_sec: metadata.atime() as libc::time_t,
+            tv_nsec: metadata.atime_nsec() as _,
+        },
+        libc::timespec {
+            tv_sec: metadata.mtime() as libc::time_t,
+            tv_nsec: metadata.mtime_nsec() as _,
+        },
+    ];
+    let rc = unsafe {
+        libc::utimensat(
+            libc::AT_FDCWD,
+            c_path.as_ptr(),
+            times.as_ptr(),
+            libc::AT_SYMLINK_NOFOLLOW,
+        )
+    };
+    if rc != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Copies the permissions, ownership (when running as root), and timestamps of
+/// `source` onto `dest`, the way `cp -a` does for entries it recreates.
+///
+/// Symlinks are never followed; their own ownership and timestamps are copied.
+///
+/// # Arguments
+///
+/// * `source` - The entry whose metadata should be reproduced
+/// * `dest` - The recreated entry to update
+///
+/// # Returns
+///
+/// * `io::Result<()>` - Success if all metadata was applied
+#[cfg(unix)]
+pub fn preserve_metadata(source: &Path, dest: &Path) -> io::Result<()> {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    let metadata = fs::symlink_metadata(source)?;
+
+    if is_root() {
+        std::os::unix::fs::lchown(dest, Some(metadata.uid()), Some(metadata.gid()))?;
+    }
+    if !metadata.file_type().is_symlink() {
+        fs::set_permissions(dest, fs::Permissions::from_mode(metadata.mode()))?;
+    }
+    set_times(dest, &metadata)
+}
+
+/// Copies the permissions of `source` onto `dest`.
+///
+/// WASI exposes neither ownership nor a way to set timestamps without
+/// following symlinks, so only the read-only flag is reproduced.
+#[cfg(not(unix))]
+pub fn preserve_metadata(source: &Path, dest: &Path) -> io::Result<()> {
+    let metadata = fs::symlink_metadata(source)?;
+    if !metadata.file_type().is_symlink() {
+        fs::set_permissions(dest, metadata.permissions())?;
+    }
+    Ok(())
+}
@@ -0,0 +1,287 @@
+use crate::clock::UtcTime;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// The hidden directory under a destination that holds backup generations.
+pub const BACKUP_DIR: &str = ".flnk-backups";
+
+/// How backups of replaced files are named, as chosen by coreutils'
+/// `--backup=CONTROL` or the `VERSION_CONTROL` environment variable.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BackupControl {
+    /// Always `FILE~`, or the backup suffix
+    Simple,
+    /// Always `FILE.~N~`, one past the highest number already taken
+    Numbered,
+    /// Numbered if the file already has numbered backups, simple otherwise
+    #[default]
+    Existing,
+}
+
+impl BackupControl {
+    /// The coreutils name of the control.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BackupControl::Simple => "simple",
+            BackupControl::Numbered => "numbered",
+            BackupControl::Existing => "existing",
+        }
+    }
+}
+
+/// Parses a control by its coreutils name or alias. `none` and `off`, which
+/// turn backups off, are not controls and are handled by the caller.
+impl FromStr for BackupControl {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "simple" | "never" => Ok(BackupControl::Simple),
+            "numbered" | "t" => Ok(BackupControl::Numbered),
+            "existing" | "nil" => Ok(BackupControl::Existing),
+            _ => Err(format!(
+                "invalid backup control {} (expected none, numbered, existing, or simple)",
+                s
+            )),
+        }
+    }
+}
+
+/// Finds the highest number among the `FILE.~N~` backups next to a file.
+///
+/// # Arguments
+///
+/// * `path` - The file whose numbered backups are looked for
+///
+/// # Returns
+///
+/// * `Option<u64>` - The highest backup number, or `None` if there are none
+pub fn highest_numbered(path: &Path) -> Option<u64> {
+    let name = path.file_name()?.to_string_lossy().into_owned();
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let prefix = format!("{}.~", name);
+    fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| {
+            let entry = entry.ok()?.file_name();
+            let number = entry.to_str()?.strip_prefix(&prefix)?.strip_suffix('~')?;
+            number.parse().ok()
+        })
+        .max()
+}
+
+/// One run's backup generation: the files a run displaced, moved into
+/// `BASE/.flnk-backups/<timestamp>/` at their path relative to `BASE`, or
+/// into a fixed backup directory given with `--backup-dir`.
+///
+/// The generation directory is only created once something is displaced,
+/// so runs that replace nothing leave no empty generation behind.
+#[derive(Debug)]
+pub struct Generation {
+    base: PathBuf,
+    name: String,
+    dir: Option<PathBuf>,
+}
+
+impl Generation {
+    /// Starts a generation for a run linking into `dest`.
+    ///
+    /// # Arguments
+    ///
+    /// * `dest` - The run's destination; backups go under it if it is a
+    ///   directory, or under its parent if it names a single entry
+    ///
+    /// # Returns
+    ///
+    /// * `Generation` - A generation named for the current time
+    pub fn new(dest: &Path) -> Self {
+        let base = if dest.is_dir() {
+            dest
+        } else {
+            dest.parent().unwrap_or(Path::new(""))
+        };
+        Generation {
+            base: base.to_path_buf(),
+            name: UtcTime::now().compact(),
+            dir: None,
+        }
+    }
+
+    /// Starts a run that keeps what it displaces in `dir`, at each entry's
+    /// path relative to the destination, instead of in a new generation.
+    ///
+    /// # Arguments
+    ///
+    /// * `dest` - The run's destination
+    /// * `dir` - The backup directory, created when the first entry is moved
+    ///
+    /// # Returns
+    ///
+    /// * `Generation` - A generation that fills `dir`
+    pub fn in_dir(dest: &Path, dir: &Path) -> Self {
+        Generation {
+            dir: Some(dir.to_path_buf()),
+            ..Generation::new(dest)
+        }
+    }
+
+    /// The directory backups are kept under, whose generations are pruned.
+    pub fn base(&self) -> &Path {
+        &self.base
+    }
+
+    /// Moves a displaced entry into the generation.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The existing destination entry about to be replaced
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<PathBuf>` - Where the entry was moved
+    pub fn stash(&mut self, path: &Path) -> io::Result<PathBuf> {
+        let dir = match &self.dir {
+            Some(dir) => dir.clone(),
+            None => {
+                let dir = self.create_dir()?;
+                self.dir = Some(dir.clone());
+                dir
+            }
+        };
+        let target = unused(dir.join(self.relative(path)));
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(path, &target)?;
+        Ok(target)
+    }
+
+    /// Returns where `stash` would move an entry, without creating anything.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The existing destination entry that would be replaced
+    ///
+    /// # Returns
+    ///
+    /// * `PathBuf` - The entry's path in the generation
+    pub fn planned(&self, path: &Path) -> PathBuf {
+        let dir = match &self.dir {
+            Some(dir) => dir.clone(),
+            None => self.base.join(BACKUP_DIR).join(&self.name),
+        };
+        unused(dir.join(self.relative(path)))
+    }
+
+    /// The path an entry is kept at inside the generation.
+    fn relative<'p>(&self, path: &'p Path) -> &'p Path {
+        match path.strip_prefix(&self.base) {
+            Ok(rel) if !rel.as_os_str().is_empty() => rel,
+            _ => Path::new(path.file_name().unwrap_or(path.as_os_str())),
+        }
+    }
+
+    /// Creates the generation directory, adding a counter to the name if a
+    /// run in the same second already took it.
+    fn create_dir(&self) -> io::Result<PathBuf> {
+        let root = self.base.join(BACKUP_DIR);
+        fs::create_dir_all(&root)?;
+        let mut dir = root.join(&self.name);
+        let mut counter = 1;
+        loop {
+            match fs::create_dir(&dir) {
+                Ok(()) => return Ok(dir),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    dir = root.join(format!("{}-{}", self.name, counter));
+                    counter += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// Returns `path`, or if an earlier backup is already there, the next
+/// numbered name `path.~N~` beside it, so no backup is overwritten.
+fn unused(path: PathBuf) -> PathBuf {
+    if fs::symlink_metadata(&path).is_err() {
+        return path;
+    }
+    let next = highest_numbered(&path).unwrap_or(0) + 1;
+    PathBuf::from(format!("{}.~{}~", path.display(), next))
+}
+
+/// Lists the backup generations under a destination, oldest first.
+///
+/// # Arguments
+///
+/// * `base` - The destination directory holding `.flnk-backups`
+///
+/// # Returns
+///
+/// * `io::Result<Vec<PathBuf>>` - The generation directories, or an empty list if there are none
+pub fn generations(base: &Path) -> io::Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(base.join(BACKUP_DIR)) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut found = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            found.push(entry.path());
+        }
+    }
+    // Names start with a fixed-width timestamp, so they sort by age; a
+    // counter suffix sorts after the plain name of the same second.
+    found.sort_by_key(|dir| generation_key(dir));
+    Ok(found)
+}
+
+fn generation_key(dir: &Path) -> (String, u64) {
+    let name = dir.file_name().unwrap_or_default().to_string_lossy();
+    match name.split_once('-') {
+        Some((stamp, n)) => (stamp.to_string(), n.parse().unwrap_or(0)),
+        None => (name.into_owned(), 0),
+    }
+}
+
+/// Returns the generations beyond the newest `keep`, oldest first.
+///
+/// # Arguments
+///
+/// * `base` - The destination directory holding `.flnk-backups`
+/// * `keep` - How many of the newest generations to keep
+///
+/// # Returns
+///
+/// * `io::Result<Vec<PathBuf>>` - The generation directories that are expired
+pub fn expired(base: &Path, keep: usize) -> io::Result<Vec<PathBuf>> {
+    let mut all = generations(base)?;
+    all.truncate(all.len().saturating_sub(keep));
+    Ok(all)
+}
+
+/// Deletes the generations beyond the newest `keep`.
+///
+/// # Arguments
+///
+/// * `base` - The destination directory holding `.flnk-backups`
+/// * `keep` - How many of the newest generations to keep
+///
+/// # Returns
+///
+/// * `io::Result<Vec<PathBuf>>` - The generation directories that were deleted
+pub fn prune(base: &Path, keep: usize) -> io::Result<Vec<PathBuf>> {
+    let expired = expired(base, keep)?;
+    for dir in &expired {
+        fs::remove_dir_all(dir)?;
+    }
+    Ok(expired)
+}
@@ -0,0 +1,194 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Which entries a chmod clause applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Applies {
+    Dirs,
+    Files,
+    Both,
+}
+
+/// How a chmod clause changes a mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Change {
+    /// Replace the whole mode, e.g. `2775`
+    Set(u32),
+    /// Add, remove, or assign permission bits for some classes, e.g. `g+w`
+    Symbolic {
+        who: u32,
+        op: char,
+        perms: u32,
+        x_if_exec: bool,
+    },
+}
+
+/// One comma-separated item of a chmod spec.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Clause {
+    applies: Applies,
+    change: Change,
+}
+
+/// An rsync-style `--chmod` spec such as `D2775,F664` or `Dg+s,ug+w,Fo-w`.
+///
+/// Items are applied left to right. An item prefixed with `D` applies only to
+/// directories and one prefixed with `F` only to files; each is either an
+/// octal mode or a symbolic change in the form of chmod(1).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChmodSpec {
+    clauses: Vec<Clause>,
+}
+
+impl FromStr for ChmodSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let clauses = s
+            .split(',')
+            .map(|item| parse_clause(item).ok_or_else(|| format!("invalid chmod item {}", item)))
+            .collect::<Result<_, _>>()?;
+        Ok(ChmodSpec { clauses })
+    }
+}
+
+fn parse_clause(item: &str) -> Option<Clause> {
+    let (applies, rest) = match item.as_bytes().first()? {
+        b'D' => (Applies::Dirs, &item[1..]),
+        b'F' => (Applies::Files, &item[1..]),
+        _ => (Applies::Both, item),
+    };
+    if !rest.is_empty() && rest.bytes().all(|b| b.is_ascii_digit()) {
+        let mode = u32::from_str_radix(rest, 8).ok().filter(|m| *m <= 0o7777)?;
+        return Some(Clause {
+            applies,
+            change: Change::Set(mode),
+        });
+    }
+
+    let op_at = rest.find(['+', '-', '='])?;
+    let mut who = 0;
+    for c in rest[..op_at].chars() {
+        who |= match c {
+            'u' => 0o4700,
+            'g' => 0o2070,
+            'o' => 0o1007,
+            'a' => 0o7777,
+            _ => return None,
+        };
+    }
+    if who == 0 {
+        who = 0o7777;
+    }
+    let mut perms = 0;
+    let mut x_if_exec = false;
+    for c in rest[op_at + 1..].chars() {
+        perms |= match c {
+            'r' => 0o444,
+            'w' => 0o222,
+            'x' => 0o111,
+            'X' => {
+                x_if_exec = true;
+                0
+            }
+            's' => 0o6000,
+            't' => 0o1000,
+            _ => return None,
+        };
+    }
+    Some(Clause {
+        applies,
+        change: Change::Symbolic {
+            who,
+            op: rest.as_bytes()[op_at] as char,
+            perms,
+            x_if_exec,
+        },
+    })
+}
+
+impl ChmodSpec {
+    /// Computes the mode an entry gets from its current mode.
+    ///
+    /// # Arguments
+    ///
+    /// * `mode` - The entry's current permission bits
+    /// * `is_dir` - True if the entry is a directory
+    ///
+    /// # Returns
+    ///
+    /// * `u32` - The new permission bits
+    pub fn apply_to(&self, mut mode: u32, is_dir: bool) -> u32 {
+        mode &= 0o7777;
+        for clause in &self.clauses {
+            let applies = match clause.applies {
+                Applies::Dirs => is_dir,
+                Applies::Files => !is_dir,
+                Applies::Both => true,
+            };
+            if !applies {
+                continue;
+            }
+            mode = match clause.change {
+                Change::Set(new) => new,
+                Change::Symbolic {
+                    who,
+                    op,
+                    mut perms,
+                    x_if_exec,
+                } => {
+                    if x_if_exec && (is_dir || mode & 0o111 != 0) {
+                        perms |= 0o111;
+                    }
+                    let bits = perms & who;
+                    match op {
+                        '+' => mode | bits,
+                        '-' => mode & !bits,
+                        _ => (mode & !(who & 0o777)) | bits,
+                    }
+                }
+            };
+        }
+        mode
+    }
+
+    /// Applies the spec to an entry flnk created.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The created directory or copied file
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<()>` - Success if the new mode was set
+    #[cfg(unix)]
+    pub fn apply(&self, path: &Path) -> io::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let metadata = fs::metadata(path)?;
+        let mode = metadata.permissions().mode();
+        let new = self.apply_to(mode, metadata.is_dir());
+        if new != mode & 0o7777 {
+            fs::set_permissions(path, fs::Permissions::from_mode(new))?;
+        }
+        Ok(())
+    }
+
+    /// Applies the spec to an entry flnk created; WASI only knows the
+    /// read-only flag, which follows the owner's write bit.
+    #[cfg(not(unix))]
+    pub fn apply(&self, path: &Path) -> io::Result<()> {
+        let metadata = fs::metadata(path)?;
+        let mode = if metadata.permissions().readonly() {
+            0o555
+        } else {
+            0o755
+        };
+        let new = self.apply_to(mode, metadata.is_dir());
+        let mut permissions = metadata.permissions();
+        permissions.set_readonly(new & 0o200 == 0);
+        fs::set_permissions(path, permissions)
+    }
+}
@@ -0,0 +1,70 @@
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// What a run does when several of its source entries map to the same
+/// destination path.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OnCollision {
+    /// The run is refused before anything is created, unless `force` or
+    /// `backup` lets the entry discovered last win
+    #[default]
+    Error,
+    /// Every entry after the first is linked under a numbered name, such as
+    /// `name (1).ext`
+    Number,
+    /// The entry discovered first is linked and the others are skipped
+    Skip,
+    /// The entry discovered last is linked and the others are skipped, as
+    /// if each had replaced the one before
+    Overwrite,
+}
+
+impl OnCollision {
+    /// The name of the strategy as given on the command line.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            OnCollision::Error => "error",
+            OnCollision::Number => "number",
+            OnCollision::Skip => "skip",
+            OnCollision::Overwrite => "overwrite",
+        }
+    }
+}
+
+impl FromStr for OnCollision {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(OnCollision::Error),
+            "number" => Ok(OnCollision::Number),
+            "skip" => Ok(OnCollision::Skip),
+            "overwrite" => Ok(OnCollision::Overwrite),
+            _ => Err(format!(
+                "invalid collision strategy {} (expected error, skip, number, or overwrite)",
+                s
+            )),
+        }
+    }
+}
+
+/// Returns a destination path with ` (n)` added to its name, before the
+/// extension: `a/movie.mkv` becomes `a/movie (1).mkv`.
+///
+/// # Arguments
+///
+/// * `dest` - The contested destination path
+/// * `n` - The number to add
+///
+/// # Returns
+///
+/// * `PathBuf` - The numbered path
+pub fn numbered(dest: &Path, n: usize) -> PathBuf {
+    let mut name = dest.file_stem().unwrap_or_default().to_os_string();
+    name.push(format!(" ({})", n));
+    if let Some(ext) = dest.extension() {
+        name.push(".");
+        name.push(ext);
+    }
+    dest.with_file_name(name)
+}
@@ -0,0 +1,87 @@
+use crate::json::Json;
+use std::fmt;
+use std::path::PathBuf;
+
+/// A change to the filesystem a dry run found the run would make.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operation {
+    /// Create a directory that does not exist yet
+    CreateDir(PathBuf),
+    /// Move an existing destination entry aside to the given backup path
+    Backup(PathBuf, PathBuf),
+    /// Remove an existing destination entry, with `force`
+    Overwrite(PathBuf),
+    /// Create a link at the destination to the source
+    Link(PathBuf, PathBuf),
+    /// Copy the source to the destination, as a fallback where it cannot be linked
+    Copy(PathBuf, PathBuf),
+    /// Make a copy-on-write clone of the source at the destination
+    Clone(PathBuf, PathBuf),
+    /// Remove a destination entry hidden by an overlay whiteout
+    Remove(PathBuf),
+    /// Empty a destination directory made opaque by an overlay layer
+    Clear(PathBuf),
+}
+
+impl Operation {
+    /// A short stable name for machine-readable output.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Operation::CreateDir(_) => "create_dir",
+            Operation::Backup(..) => "backup",
+            Operation::Overwrite(_) => "overwrite",
+            Operation::Link(..) => "link",
+            Operation::Copy(..) => "copy",
+            Operation::Clone(..) => "clone",
+            Operation::Remove(_) => "remove",
+            Operation::Clear(_) => "clear",
+        }
+    }
+
+    /// Renders the operation as a JSON object with `op`, `dest`, and, where
+    /// it has one, `source` or `backup`.
+    pub fn to_json(&self) -> Json {
+        let op = ("op", Json::from(self.as_str()));
+        match self {
+            Operation::Link(source, dest)
+            | Operation::Copy(source, dest)
+            | Operation::Clone(source, dest) => Json::object([
+                op,
+                ("source", Json::from(source.as_path())),
+                ("dest", Json::from(dest.as_path())),
+            ]),
+            Operation::Backup(dest, backup) => Json::object([
+                op,
+                ("dest", Json::from(dest.as_path())),
+                ("backup", Json::from(backup.as_path())),
+            ]),
+            Operation::CreateDir(dest)
+            | Operation::Overwrite(dest)
+            | Operation::Remove(dest)
+            | Operation::Clear(dest) => Json::object([op, ("dest", Json::from(dest.as_path()))]),
+        }
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operation::CreateDir(dest) => write!(f, "create directory {}", dest.display()),
+            Operation::Backup(dest, backup) => {
+                write!(f, "back up {} to {}", dest.display(), backup.display())
+            }
+            Operation::Overwrite(dest) => write!(f, "overwrite {}", dest.display()),
+            Operation::Link(source, dest) => {
+                write!(f, "link {} -> {}", dest.display(), source.display())
+            }
+            Operation::Copy(source, dest) => {
+                write!(f, "copy {} to {}", source.display(), dest.display())
+            }
+            Operation::Clone(source, dest) => {
+                write!(f, "clone {} to {}", source.display(), dest.display())
+            }
+            Operation::Remove(dest) => write!(f, "remove {}", dest.display()),
+            Operation::Clear(dest) => write!(f, "empty directory {}", dest.display()),
+        }
+    }
+}
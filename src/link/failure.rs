@@ -0,0 +1,99 @@
+use crate::error::{FlnkError, Op};
+use crate::json::Json;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// What a run does when an entry cannot be linked.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// The first failure ends the run
+    #[default]
+    Abort,
+    /// The failure is recorded and the run goes on with the next entry
+    Skip,
+    /// The user is asked on the terminal whether to skip the entry or end the run
+    Ask,
+}
+
+impl ErrorPolicy {
+    /// The name of the policy as given on the command line.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorPolicy::Abort => "abort",
+            ErrorPolicy::Skip => "skip",
+            ErrorPolicy::Ask => "ask",
+        }
+    }
+}
+
+impl FromStr for ErrorPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "abort" => Ok(ErrorPolicy::Abort),
+            "skip" => Ok(ErrorPolicy::Skip),
+            "ask" => Ok(ErrorPolicy::Ask),
+            _ => Err(format!(
+                "invalid error policy {} (expected abort, skip, or ask)",
+                s
+            )),
+        }
+    }
+}
+
+/// An entry a run could not link and went on without, per its error policy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Failure {
+    /// The source entry
+    pub source: PathBuf,
+    /// The destination it was to be linked at
+    pub dest: PathBuf,
+    /// The kind of error met
+    pub kind: io::ErrorKind,
+    /// The error message
+    pub message: String,
+    /// The operation that failed, if known
+    pub op: Option<Op>,
+    /// The OS error number, if a filesystem call failed
+    pub errno: Option<i32>,
+}
+
+impl Failure {
+    /// Records that linking `source` at `dest` failed with `err`. The
+    /// entry named by a `FlnkError` is left out of the message.
+    pub fn new(source: impl Into<PathBuf>, dest: impl Into<PathBuf>, err: &io::Error) -> Self {
+        let found = FlnkError::find(err);
+        Failure {
+            source: source.into(),
+            dest: dest.into(),
+            kind: err.kind(),
+            message: found.map_or_else(|| err.to_string(), FlnkError::reason),
+            op: found.map(FlnkError::op),
+            errno: found.map_or_else(|| err.raw_os_error(), FlnkError::errno),
+        }
+    }
+
+    /// Renders the failure as a JSON object with `source`, `dest`, and `message`.
+    pub fn to_json(&self) -> Json {
+        Json::object([
+            ("source", Json::from(self.source.as_path())),
+            ("dest", Json::from(self.dest.as_path())),
+            ("message", Json::from(self.message.as_str())),
+        ])
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "cannot link {} at {}: {}",
+            self.source.display(),
+            self.dest.display(),
+            self.message
+        )
+    }
+}
@@ -0,0 +1,531 @@
+use crate::link::glob::glob_match;
+use crate::link::platform;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The rules rsync's `-F` adds: the first `-F` reads `.rsync-filter` files
+/// in the tree, and a second also leaves the files themselves out.
+pub const RSYNC_FILTER_RULES: [&str; 2] = ["dir-merge /.rsync-filter", "- .rsync-filter"];
+
+/// Orders the rules of `--filter` and `-F` options as they were given, like
+/// rsync does, since the first rule that matches an entry wins.
+///
+/// # Arguments
+///
+/// * `filters` - Each `--filter` rule with the index of its argument
+/// * `rsync_filters` - The argument index of each `-F`
+///
+/// # Returns
+///
+/// * `Vec<&str>` - The rules to add to the filter, in order
+pub fn command_line_rules<'a>(
+    filters: impl IntoIterator<Item = (usize, &'a str)>,
+    rsync_filters: impl IntoIterator<Item = usize>,
+) -> Vec<&'a str> {
+    let mut rules: Vec<_> = filters.into_iter().collect();
+    rules.extend(rsync_filters.into_iter().zip(RSYNC_FILTER_RULES));
+    rules.sort_by_key(|(index, _)| *index);
+    rules.into_iter().map(|(_, rule)| rule).collect()
+}
+
+/// What a matching filter rule does with an entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterAction {
+    Include,
+    Exclude,
+}
+
+/// A single include or exclude rule in rsync's filter-rule syntax.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilterRule {
+    /// Whether a match includes or excludes the entry
+    pub action: FilterAction,
+    /// The glob pattern, without its anchoring `/` or trailing `/`
+    pub pattern: String,
+    /// True if the pattern started with `/` and only matches from the root
+    pub anchored: bool,
+    /// True if the pattern ended with `/` and only matches directories
+    pub dir_only: bool,
+    /// True if the rule applies to entries the pattern does *not* match
+    pub negated: bool,
+}
+
+impl FilterRule {
+    /// Parses a pattern such as `/build/` into a rule.
+    pub fn new(action: FilterAction, pattern: &str) -> Self {
+        let anchored = pattern.starts_with('/');
+        let dir_only = pattern.len() > 1 && pattern.ends_with('/');
+        let pattern = pattern.trim_start_matches('/').trim_end_matches('/');
+        FilterRule {
+            action,
+            pattern: pattern.to_string(),
+            anchored,
+            dir_only,
+            negated: false,
+        }
+    }
+
+    fn pattern_matches(&self, pattern: &str, path: &str) -> bool {
+        if self.anchored {
+            glob_match(pattern, path)
+        } else if pattern.contains('/') || pattern.contains("**") {
+            glob_match(pattern, path)
+                || path
+                    .match_indices('/')
+                    .any(|(i, _)| glob_match(pattern, &path[i + 1..]))
+        } else {
+            glob_match(pattern, path.rsplit('/').next().unwrap_or(path))
+        }
+    }
+
+    /// Returns the rule's action if it applies to `path`.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The entry's path relative to the root the rule is anchored at
+    /// * `is_dir` - True if the entry is a directory
+    pub fn decide(&self, path: &str, is_dir: bool) -> Option<FilterAction> {
+        if self.dir_only && !is_dir {
+            return None;
+        }
+        let matched = match self.pattern.strip_suffix("/***") {
+            Some(dir) => {
+                self.pattern_matches(dir, path)
+                    || self.pattern_matches(&format!("{}/**", dir), path)
+            }
+            None => self.pattern_matches(&self.pattern, path),
+        };
+        (matched != self.negated).then_some(self.action)
+    }
+}
+
+impl FilterRule {
+    /// The pattern as written in a rule, with its `!`, anchoring `/`, and trailing `/`.
+    fn pattern_text(&self) -> String {
+        format!(
+            "{}{}{}{}",
+            if self.negated { "!" } else { "" },
+            if self.anchored { "/" } else { "" },
+            self.pattern,
+            if self.dir_only { "/" } else { "" }
+        )
+    }
+}
+
+impl fmt::Display for FilterRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = match self.action {
+            FilterAction::Include => '+',
+            FilterAction::Exclude => '-',
+        };
+        write!(f, "{} {}", sign, self.pattern_text())
+    }
+}
+
+/// The per-directory ignore file read by default, in gitignore syntax.
+pub const IGNORE_FILE: &str = ".flnkignore";
+
+/// Git's per-directory ignore file, read with `--respect-gitignore`.
+pub const GITIGNORE_FILE: &str = ".gitignore";
+
+/// How the lines of a per-directory merge file are read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MergeStyle {
+    /// rsync filter rules, first match wins
+    Rules,
+    /// Patterns that exclude, with `!pattern` lines as exceptions; the last match wins
+    Ignore,
+}
+
+/// One parsed line of filter rules.
+enum ParsedLine {
+    Rule(FilterRule),
+    Clear,
+    Merge(PathBuf),
+    DirMerge(String),
+    Nothing,
+}
+
+/// The long rule names, which take their modifiers after a comma.
+const LONG_NAMES: [&str; 8] = [
+    "include",
+    "exclude",
+    "merge",
+    "dir-merge",
+    "hide",
+    "show",
+    "protect",
+    "risk",
+];
+
+/// Parses one rule in rsync's short (`- *.tmp`) or long (`exclude *.tmp`) form.
+fn parse_line(line: &str) -> io::Result<ParsedLine> {
+    let line = line.trim_end_matches(['\r', '\n']);
+    if line.trim().is_empty() || line.starts_with('#') || line.starts_with(';') {
+        return Ok(ParsedLine::Nothing);
+    }
+    if line == "!" || line == "clear" {
+        return Ok(ParsedLine::Clear);
+    }
+
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid filter rule: {}", line),
+        )
+    };
+
+    let (head, arg) = line
+        .split_once([' ', '_'])
+        .filter(|(_, arg)| !arg.is_empty())
+        .ok_or_else(invalid)?;
+    let (name, modifiers) = match head.split_once(',') {
+        Some((name, modifiers)) => (name, modifiers),
+        None if LONG_NAMES.contains(&head) => (head, ""),
+        None => head.split_at(head.chars().next().map_or(0, char::len_utf8)),
+    };
+
+    let action = match name {
+        "+" | "include" | "S" | "show" => FilterAction::Include,
+        "-" | "exclude" | "H" | "hide" => FilterAction::Exclude,
+        "." | "merge" => return Ok(ParsedLine::Merge(PathBuf::from(arg))),
+        ":" | "dir-merge" => {
+            return Ok(ParsedLine::DirMerge(
+                arg.trim_start_matches('/').to_string(),
+            ));
+        }
+        // Protect and risk rules only concern deletion, which does not apply here.
+        "P" | "protect" | "R" | "risk" => return Ok(ParsedLine::Nothing),
+        _ => return Err(invalid()),
+    };
+
+    // `- !pattern` applies to everything the pattern does not match, like
+    // the `,!` modifier; `\!` starts a pattern with a literal `!`.
+    let (negated, arg) = match arg.strip_prefix('!') {
+        Some(pattern) => (true, pattern),
+        None if arg.starts_with("\\!") => (false, &arg[1..]),
+        None => (false, arg),
+    };
+    let mut rule = FilterRule::new(action, arg);
+    rule.negated = negated;
+    for modifier in modifiers.chars() {
+        match modifier {
+            '!' => rule.negated = !rule.negated,
+            _ => return Err(invalid()),
+        }
+    }
+    Ok(ParsedLine::Rule(rule))
+}
+
+/// Parses an ignore file: every line is a pattern to exclude, `!pattern`
+/// re-includes what an earlier line excluded, and `#` starts a comment.
+/// `\!` and `\#` start a pattern with a literal `!` or `#`.
+///
+/// The rules are returned last line first, so evaluating them first match
+/// wins gives the last matching line the final say.
+fn parse_ignore(text: &str) -> Vec<FilterRule> {
+    let mut rules = Vec::new();
+    for line in text.lines() {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (action, pattern) = match line.strip_prefix('!') {
+            Some(pattern) => (FilterAction::Include, pattern),
+            None if line.starts_with("\\!") || line.starts_with("\\#") => {
+                (FilterAction::Exclude, &line[1..])
+            }
+            None => (FilterAction::Exclude, line),
+        };
+        rules.push(FilterRule::new(action, pattern));
+    }
+    rules.reverse();
+    rules
+}
+
+/// An entry in the top-level filter list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum FilterEntry {
+    Rule(FilterRule),
+    /// Rules read from the named file in each traversed directory
+    DirMerge(String, MergeStyle),
+}
+
+/// An ordered list of filter rules, evaluated first match wins.
+///
+/// Entries no rule matches are included, unless include globs are given:
+/// then only files matching one of them are.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Filter {
+    entries: Vec<FilterEntry>,
+    includes: Vec<FilterRule>,
+}
+
+impl Filter {
+    /// True if the filter has no rules and therefore includes everything.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty() && self.includes.is_empty()
+    }
+
+    /// Appends a rule, e.g. `- *.part` or `dir-merge .rsync-filter`.
+    ///
+    /// Merge rules read their file immediately and append its rules in place.
+    ///
+    /// # Arguments
+    ///
+    /// * `rule` - The rule in rsync's filter-rule syntax
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<()>` - An `InvalidInput` error if the rule cannot be parsed
+    pub fn add_rule(&mut self, rule: &str) -> io::Result<()> {
+        match parse_line(rule)? {
+            ParsedLine::Rule(rule) => self.entries.push(FilterEntry::Rule(rule)),
+            ParsedLine::Clear => self.entries.clear(),
+            ParsedLine::Merge(path) => self.merge_file(&path)?,
+            ParsedLine::DirMerge(name) => self
+                .entries
+                .push(FilterEntry::DirMerge(name, MergeStyle::Rules)),
+            ParsedLine::Nothing => {}
+        }
+        Ok(())
+    }
+
+    /// Appends a rule excluding every entry that matches a glob, like
+    /// `- PATTERN` but with no rule syntax to parse.
+    ///
+    /// # Arguments
+    ///
+    /// * `pattern` - The glob, matched against the entry's name, or against
+    ///   its path below the root if it contains a `/`
+    pub fn add_exclude(&mut self, pattern: &str) {
+        self.entries.push(FilterEntry::Rule(FilterRule::new(
+            FilterAction::Exclude,
+            pattern,
+        )));
+    }
+
+    /// Adds a glob to the include list. Once it has any, only files matching
+    /// one of its globs are linked; directories are still descended into, and
+    /// the filter's exclude rules win over it.
+    ///
+    /// # Arguments
+    ///
+    /// * `pattern` - The glob, matched like the pattern of an exclude rule
+    pub fn add_include(&mut self, pattern: &str) {
+        self.includes
+            .push(FilterRule::new(FilterAction::Include, pattern));
+    }
+
+    /// Appends every rule in a filter file, one rule per line.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The file to read
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<()>` - An error if the file cannot be read or holds an invalid rule
+    pub fn merge_file(&mut self, path: &Path) -> io::Result<()> {
+        for line in fs::read_to_string(path)?.lines() {
+            self.add_rule(line)?;
+        }
+        Ok(())
+    }
+
+    /// Appends an ignore file read from each traversed directory, like
+    /// `.gitignore`: each line excludes matching entries, `!pattern` lines
+    /// re-include entries an earlier line excluded, and the last matching
+    /// line wins. Rules added before it take precedence.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The file name to look for, usually `IGNORE_FILE`
+    pub fn add_ignore_file(&mut self, name: &str) {
+        self.entries
+            .push(FilterEntry::DirMerge(name.to_string(), MergeStyle::Ignore));
+    }
+
+    /// Starts evaluating the filter for a walk of one source tree.
+    pub fn walker(&self) -> FilterWalk<'_> {
+        FilterWalk {
+            filter: self,
+            levels: Vec::new(),
+        }
+    }
+}
+
+/// Per-directory rules read from one directory of the walk.
+struct DirLevel {
+    depth: usize,
+    prefix: PathBuf,
+    merged: Vec<(String, Vec<FilterRule>, bool)>,
+}
+
+/// The state of a filter while walking one source tree.
+///
+/// Keeps the rules of the per-directory merge files found in the directories
+/// above the current entry, innermost last.
+pub struct FilterWalk<'a> {
+    filter: &'a Filter,
+    levels: Vec<DirLevel>,
+}
+
+impl FilterWalk<'_> {
+    /// Decides whether a walked entry is excluded.
+    ///
+    /// # Arguments
+    ///
+    /// * `rel` - The entry's path relative to the root of the walk
+    /// * `depth` - The entry's depth in the walk, the root being 0
+    /// * `is_dir` - True if the entry is a directory
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if the first matching rule excludes the entry
+    pub fn is_excluded(&mut self, rel: &Path, depth: usize, is_dir: bool) -> bool {
+        self.excluded_by(rel, depth, is_dir).is_some()
+    }
+
+    /// Decides whether a walked entry is excluded, naming the rule that excluded it.
+    ///
+    /// # Arguments
+    ///
+    /// * `rel` - The entry's path relative to the root of the walk
+    /// * `depth` - The entry's depth in the walk, the root being 0
+    /// * `is_dir` - True if the entry is a directory
+    ///
+    /// # Returns
+    ///
+    /// * `Option<String>` - The excluding rule as written, followed by the
+    ///   merge file it was read from, or `None` if the entry is included
+    pub fn excluded_by(&mut self, rel: &Path, depth: usize, is_dir: bool) -> Option<String> {
+        self.levels.retain(|level| level.depth < depth);
+        let path = platform::slash_path(rel);
+
+        for entry in &self.filter.entries {
+            match entry {
+                FilterEntry::Rule(rule) => {
+                    if let Some(action) = rule.decide(&path, is_dir) {
+                        return (action == FilterAction::Exclude).then(|| rule.to_string());
+                    }
+                }
+                FilterEntry::DirMerge(name, style) => {
+                    'levels: for level in self.levels.iter().rev() {
+                        let local = rel.strip_prefix(&level.prefix).unwrap_or(rel);
+                        let local = platform::slash_path(local);
+                        for (merged_name, rules, cleared) in &level.merged {
+                            if merged_name != name {
+                                continue;
+                            }
+                            for rule in rules {
+                                if let Some(action) = rule.decide(&local, is_dir) {
+                                    return (action == FilterAction::Exclude).then(|| {
+                                        let text = match style {
+                                            MergeStyle::Ignore => rule.pattern_text(),
+                                            MergeStyle::Rules => rule.to_string(),
+                                        };
+                                        format!("{} in {}", text, level.prefix.join(name).display())
+                                    });
+                                }
+                            }
+                            if *cleared {
+                                break 'levels;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Decides whether a walked entry the rules keep is on the include list.
+    ///
+    /// # Arguments
+    ///
+    /// * `rel` - The entry's path relative to the root of the walk
+    /// * `is_dir` - True if the entry is a directory
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if the entry is a directory, there is no include list,
+    ///   or one of its globs matches
+    pub fn is_included(&self, rel: &Path, is_dir: bool) -> bool {
+        let path = platform::slash_path(rel);
+        is_dir
+            || self.filter.includes.is_empty()
+            || self
+                .filter
+                .includes
+                .iter()
+                .any(|rule| rule.decide(&path, false).is_some())
+    }
+
+    /// Reads the per-directory merge files of a directory the walk is about to enter.
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - The directory's path on disk
+    /// * `rel` - The directory's path relative to the root of the walk
+    /// * `depth` - The directory's depth in the walk
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<()>` - An error if a merge file exists but cannot be read or parsed
+    pub fn enter_dir(&mut self, dir: &Path, rel: &Path, depth: usize) -> io::Result<()> {
+        let mut merged = Vec::new();
+        for entry in &self.filter.entries {
+            let FilterEntry::DirMerge(name, style) = entry else {
+                continue;
+            };
+            let text = match fs::read_to_string(dir.join(name)) {
+                Ok(text) => text,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+
+            if *style == MergeStyle::Ignore {
+                merged.push((name.clone(), parse_ignore(&text), false));
+                continue;
+            }
+
+            let (mut rules, mut cleared) = (Vec::new(), false);
+            for line in text.lines() {
+                match parse_line(line)? {
+                    ParsedLine::Rule(rule) => rules.push(rule),
+                    ParsedLine::Clear => {
+                        rules.clear();
+                        cleared = true;
+                    }
+                    ParsedLine::Merge(path) => {
+                        for line in fs::read_to_string(dir.join(path))?.lines() {
+                            if let ParsedLine::Rule(rule) = parse_line(line)? {
+                                rules.push(rule);
+                            }
+                        }
+                    }
+                    ParsedLine::DirMerge(_) => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("nested dir-merge rules are not supported in {}", name),
+                        ));
+                    }
+                    ParsedLine::Nothing => {}
+                }
+            }
+            merged.push((name.clone(), rules, cleared));
+        }
+
+        if !merged.is_empty() {
+            self.levels.push(DirLevel {
+                depth,
+                prefix: rel.to_path_buf(),
+                merged,
+            });
+        }
+        Ok(())
+    }
+}
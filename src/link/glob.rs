@@ -0,0 +1,174 @@
+use std::ffi::OsStr;
+
+/// Matches `text` against a shell-style glob `pattern`.
+///
+/// Supports `*` (any run of characters except `/`), `**` (any run of
+/// characters including `/`), `?` (any single character except `/`),
+/// character classes such as `[a-z]` or `[!0-9]`, and `\` to escape the
+/// next character.
+///
+/// # Arguments
+///
+/// * `pattern` - The glob pattern
+/// * `text` - The text to match, usually a file name or relative path
+///
+/// # Returns
+///
+/// * `bool` - True if the whole of `text` matches the pattern
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    match_from(&pattern, &text)
+}
+
+/// Returns true if `pattern` contains any glob metacharacters. Paths that
+/// are not valid UTF-8 are checked byte by byte.
+pub fn has_glob(pattern: impl AsRef<OsStr>) -> bool {
+    let bytes = pattern.as_ref().as_encoded_bytes();
+    bytes.iter().any(|b| matches!(b, b'*' | b'?' | b'['))
+}
+
+/// Expands `{a,b,c}` alternatives the way shells do, so `*.{mkv,srt}`
+/// becomes `*.mkv` and `*.srt`.
+///
+/// Groups may nest and expand left to right. A brace group without a
+/// top-level comma or without its closing `}` is kept as it is, and `\`
+/// escapes a brace or comma.
+///
+/// # Arguments
+///
+/// * `pattern` - The pattern to expand
+///
+/// # Returns
+///
+/// * `Vec<String>` - The expanded patterns in order, or just `pattern` if it has no groups
+pub fn expand_braces(pattern: &str) -> Vec<String> {
+    let bytes = pattern.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 1,
+            b'{' => {
+                if let Some((commas, close)) = scan_group(bytes, i)
+                    && !commas.is_empty()
+                {
+                    let (prefix, suffix) = (&pattern[..i], &pattern[close + 1..]);
+                    let starts = std::iter::once(i).chain(commas.iter().copied());
+                    let ends = commas.iter().copied().chain(std::iter::once(close));
+                    return starts
+                        .zip(ends)
+                        .flat_map(|(start, end)| {
+                            let alternative = &pattern[start + 1..end];
+                            expand_braces(&format!("{}{}{}", prefix, alternative, suffix))
+                        })
+                        .collect();
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    vec![pattern.to_string()]
+}
+
+/// Finds the `}` closing the group opened at `open`, returning the positions
+/// of the group's top-level commas and of the `}`.
+fn scan_group(bytes: &[u8], open: usize) -> Option<(Vec<usize>, usize)> {
+    let mut commas = Vec::new();
+    let mut depth = 0;
+    let mut i = open + 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 1,
+            b'{' => depth += 1,
+            b'}' if depth == 0 => return Some((commas, i)),
+            b'}' => depth -= 1,
+            b',' if depth == 0 => commas.push(i),
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+fn match_from(pattern: &[char], text: &[char]) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some('*') if pattern.get(1) == Some(&'*') => {
+            let mut rest = &pattern[2..];
+            // `**/` also matches zero directories
+            if rest.first() == Some(&'/') && match_from(&rest[1..], text) {
+                return true;
+            }
+            while rest.first() == Some(&'*') {
+                rest = &rest[1..];
+            }
+            (0..=text.len()).any(|i| match_from(rest, &text[i..]))
+        }
+        Some('*') => {
+            let rest = &pattern[1..];
+            for i in 0..=text.len() {
+                if match_from(rest, &text[i..]) {
+                    return true;
+                }
+                if text.get(i) == Some(&'/') {
+                    break;
+                }
+            }
+            false
+        }
+        Some('?') => match text.first() {
+            Some(c) if *c != '/' => match_from(&pattern[1..], &text[1..]),
+            _ => false,
+        },
+        Some('[') => match (text.first(), parse_class(&pattern[1..])) {
+            (Some(c), Some((matched, len))) if *c != '/' => {
+                matched(*c) && match_from(&pattern[1 + len..], &text[1..])
+            }
+            (Some(c), None) => *c == '[' && match_from(&pattern[1..], &text[1..]),
+            _ => false,
+        },
+        Some('\\') if pattern.len() > 1 => {
+            text.first() == Some(&pattern[1]) && match_from(&pattern[2..], &text[1..])
+        }
+        Some(p) => text.first() == Some(p) && match_from(&pattern[1..], &text[1..]),
+    }
+}
+
+/// Parses a character class body (after the opening `[`).
+///
+/// Returns a predicate for the class and the number of pattern characters
+/// consumed including the closing `]`, or `None` if the class is unterminated.
+fn parse_class(body: &[char]) -> Option<(impl Fn(char) -> bool, usize)> {
+    let mut i = 0;
+    let negated = matches!(body.first(), Some('!' | '^'));
+    if negated {
+        i += 1;
+    }
+    let mut ranges = Vec::new();
+    let mut first = true;
+    while i < body.len() {
+        let c = body[i];
+        if c == ']' && !first {
+            return Some((
+                move |ch: char| ranges.iter().any(|&(lo, hi)| lo <= ch && ch <= hi) != negated,
+                i + 1,
+            ));
+        }
+        first = false;
+        let lo = if c == '\\' && i + 1 < body.len() {
+            i += 1;
+            body[i]
+        } else {
+            c
+        };
+        if body.get(i + 1) == Some(&'-') && body.get(i + 2).is_some_and(|c| *c != ']') {
+            ranges.push((lo, body[i + 2]));
+            i += 3;
+        } else {
+            ranges.push((lo, lo));
+            i += 1;
+        }
+    }
+    None
+}
@@ -0,0 +1,114 @@
+use crate::link::lexical;
+use std::env;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+
+/// How symlink targets under the home directory are written, for dotfile
+/// trees synced between machines whose home directories differ.
+///
+/// The kernel does not expand either form, so such links only resolve for
+/// tools that expand them, as flnk does when inspecting links.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HomeStyle {
+    /// `~/.config/app`, the shell's tilde form
+    Tilde,
+    /// `$HOME/.config/app`, the environment variable form
+    Env,
+}
+
+impl FromStr for HomeStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tilde" => Ok(HomeStyle::Tilde),
+            "env" => Ok(HomeStyle::Env),
+            _ => Err(format!("invalid home style {} (expected tilde or env)", s)),
+        }
+    }
+}
+
+impl HomeStyle {
+    /// The component standing for the home directory.
+    pub fn prefix(self) -> &'static str {
+        match self {
+            HomeStyle::Tilde => "~",
+            HomeStyle::Env => "$HOME",
+        }
+    }
+
+    /// Writes a path below `home` in this style.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - An absolute, normalized path
+    /// * `home` - The home directory
+    ///
+    /// # Returns
+    ///
+    /// * `Option<PathBuf>` - The home-relative form, or `None` if the path is not under `home`
+    pub fn abbreviate(self, path: &Path, home: &Path) -> Option<PathBuf> {
+        let rest = path.strip_prefix(home).ok()?;
+        Some(Path::new(self.prefix()).join(rest))
+    }
+}
+
+/// Returns the home directory named by `$HOME`, or `%USERPROFILE%` on
+/// Windows, if it is set to an absolute path.
+pub fn home_dir() -> Option<PathBuf> {
+    let home = env::var_os("HOME").or_else(|| {
+        if cfg!(windows) {
+            env::var_os("USERPROFILE")
+        } else {
+            None
+        }
+    });
+    let home = PathBuf::from(home?);
+    home.is_absolute().then(|| lexical::normalize(&home))
+}
+
+/// Computes the target of a symlink to `source` written in `style`.
+///
+/// Sources outside the home directory, or any source when `$HOME` is not
+/// set, get their absolute path instead.
+///
+/// # Arguments
+///
+/// * `source` - The source path, taken as written rather than resolved so a
+///   home directory reached through a symlink still matches
+/// * `style` - How the home directory is written
+///
+/// # Returns
+///
+/// * `io::Result<PathBuf>` - The target to store in the link
+pub fn link_target(source: &Path, style: HomeStyle) -> io::Result<PathBuf> {
+    let source = lexical::normalize(&std::path::absolute(source)?);
+    Ok(home_dir()
+        .and_then(|home| style.abbreviate(&source, &home))
+        .unwrap_or(source))
+}
+
+/// Expands a symlink target written in either home style.
+///
+/// # Arguments
+///
+/// * `target` - A target as stored in a link
+///
+/// # Returns
+///
+/// * `Option<PathBuf>` - The target below the home directory, or `None` if
+///   it does not start with `~`, `$HOME`, or `${HOME}` or `$HOME` is not set
+pub fn expand(target: &Path) -> Option<PathBuf> {
+    expand_in(target, &home_dir()?)
+}
+
+/// Expands a home-relative target against the given home directory.
+pub fn expand_in(target: &Path, home: &Path) -> Option<PathBuf> {
+    let mut components = target.components();
+    let Some(Component::Normal(first)) = components.next() else {
+        return None;
+    };
+    matches!(first.to_str(), Some("~" | "$HOME" | "${HOME}"))
+        .then(|| home.join(components.as_path()))
+}
@@ -0,0 +1,239 @@
+use crate::json::Json;
+use std::collections::HashSet;
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// The directory, in the state directory, that holds a journal per run.
+pub const DIR_NAME: &str = "journals";
+
+/// Returns where the journal of a run is kept.
+///
+/// # Arguments
+///
+/// * `state` - The state directory
+/// * `id` - The ID of the run, as from `manifest::new_id`
+///
+/// # Returns
+///
+/// * `PathBuf` - The journal file, named so journals sort by age
+pub fn path(state: &Path, id: &str) -> PathBuf {
+    state.join(DIR_NAME).join(format!("{}.ndjson", id))
+}
+
+/// What the journal of an interrupted run holds.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Interrupted {
+    /// The directory the run was started in, which relative paths are against
+    pub cwd: PathBuf,
+    /// The command-line arguments of the run, without the program name
+    pub args: Vec<OsString>,
+    /// The destination entries the run finished before it was interrupted
+    pub done: HashSet<PathBuf>,
+}
+
+/// Claims a journal for the run that holds it, so no other run resumes it
+/// at the same time. The claim ends when the lock is dropped or the process
+/// exits, however it exits.
+#[derive(Debug)]
+pub struct Lock {
+    _file: File,
+}
+
+impl Lock {
+    /// Takes the lock on a journal without waiting.
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<Option<Lock>>` - The lock, or `None` if a running run
+    ///   holds it. Only Unix can tell: elsewhere the lock is always taken
+    fn try_take(file: File) -> io::Result<Option<Lock>> {
+        #[cfg(unix)]
+        {
+            use std::os::fd::AsRawFd;
+            // SAFETY: flock only reads the descriptor, which `file` owns.
+            if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+                let err = io::Error::last_os_error();
+                return match err.raw_os_error() {
+                    Some(libc::EWOULDBLOCK) => Ok(None),
+                    _ => Err(err),
+                };
+            }
+        }
+        Ok(Some(Lock { _file: file }))
+    }
+}
+
+/// Appends a line for every finished entry of a run to its journal, so an
+/// interrupted run can be finished without starting over.
+#[derive(Debug)]
+pub struct Journal {
+    file: File,
+}
+
+impl Journal {
+    /// Starts the journal of a new run.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The journal file, which must not exist yet
+    /// * `args` - The command-line arguments to record for `--resume`
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<Lock>` - The claim on the journal, to hold until the
+    ///   run ends so it is not resumed while it is still going
+    pub fn start(path: &Path, args: &[OsString]) -> io::Result<Lock> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new().write(true).create_new(true).open(path)?;
+        let lock = Lock::try_take(file.try_clone()?)?;
+        let header = Json::object([
+            ("cwd", os_json(env::current_dir()?.as_os_str())),
+            (
+                "args",
+                Json::Array(args.iter().map(|arg| os_json(arg)).collect()),
+            ),
+        ]);
+        file.write_all(format!("{}\n", header).as_bytes())?;
+        lock.ok_or_else(|| io::Error::from(io::ErrorKind::WouldBlock))
+    }
+
+    /// Opens a journal to append to, creating it if needed.
+    pub fn open(path: &Path) -> io::Result<Journal> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Journal { file })
+    }
+
+    /// Records that the entry at `dest` is finished. Each line is written
+    /// with a single call, so an interrupted run leaves whole lines behind.
+    pub fn record(&mut self, dest: &Path) -> io::Result<()> {
+        let line = Json::object([("done", os_json(dest.as_os_str()))]);
+        self.file.write_all(format!("{}\n", line).as_bytes())
+    }
+}
+
+/// Finds the journal of the most recent interrupted run and claims it.
+///
+/// # Arguments
+///
+/// * `state` - The state directory
+///
+/// # Returns
+///
+/// * `io::Result<Option<(PathBuf, Lock)>>` - The newest journal no running
+///   run holds, with the claim on it, or `None` if there is none
+pub fn latest(state: &Path) -> io::Result<Option<(PathBuf, Lock)>> {
+    let entries = match fs::read_dir(state.join(DIR_NAME)) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let mut journals = entries
+        .map(|entry| entry.map(|entry| entry.path()))
+        .filter(|path| {
+            path.as_ref().map_or(true, |path| {
+                path.extension().is_some_and(|ext| ext == "ndjson")
+            })
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    journals.sort();
+    for path in journals.into_iter().rev() {
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            // A run that completed removed it.
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        if let Some(lock) = Lock::try_take(file)? {
+            return Ok(Some((path, lock)));
+        }
+    }
+    Ok(None)
+}
+
+/// Reads the journal of an interrupted run.
+///
+/// # Arguments
+///
+/// * `path` - The journal file
+///
+/// # Returns
+///
+/// * `io::Result<Interrupted>` - What the run was started with and what it
+///   finished, or an `InvalidData` error if the file is not a journal. A
+///   journal without a header, as written for a library caller, has no `args`
+pub fn read(path: &Path) -> io::Result<Interrupted> {
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} is not a flnk journal", path.display()),
+        )
+    };
+    let mut interrupted = Interrupted::default();
+    for line in fs::read_to_string(path)?.lines() {
+        // A line cut short by the interruption is ignored.
+        let Ok(entry) = Json::parse(line) else {
+            continue;
+        };
+        if let Some(done) = entry.get("done") {
+            interrupted
+                .done
+                .insert(json_os(done).ok_or_else(invalid)?.into());
+        } else if let Some(cwd) = entry.get("cwd") {
+            interrupted.cwd = json_os(cwd).ok_or_else(invalid)?.into();
+            interrupted.args = entry
+                .get("args")
+                .and_then(Json::as_array)
+                .ok_or_else(invalid)?
+                .iter()
+                .map(|arg| json_os(arg).ok_or_else(invalid))
+                .collect::<io::Result<_>>()?;
+        } else {
+            return Err(invalid());
+        }
+    }
+    Ok(interrupted)
+}
+
+/// Writes a path or argument as a string, or, if it is not UTF-8, as the
+/// array of its bytes, so it reads back as the same name.
+fn os_json(value: &OsStr) -> Json {
+    match value.to_str() {
+        Some(text) => Json::from(text),
+        #[cfg(unix)]
+        None => {
+            use std::os::unix::ffi::OsStrExt;
+            Json::Array(
+                value
+                    .as_bytes()
+                    .iter()
+                    .map(|&b| Json::from(b as u64))
+                    .collect(),
+            )
+        }
+        #[cfg(not(unix))]
+        None => Json::from(value.to_string_lossy().as_ref()),
+    }
+}
+
+/// Reads a path or argument written by `os_json`.
+fn json_os(value: &Json) -> Option<OsString> {
+    if let Some(text) = value.as_str() {
+        return Some(OsString::from(text));
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStringExt;
+        let bytes = value
+            .as_array()?
+            .iter()
+            .map(|b| u8::try_from(b.as_u64()?).ok());
+        bytes.collect::<Option<_>>().map(OsString::from_vec)
+    }
+    #[cfg(not(unix))]
+    None
+}
@@ -0,0 +1,180 @@
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+
+/// Which form of a source path symbolic link targets are made from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourcePaths {
+    /// The path as given, keeping any symlinks along it, like `ln -L`
+    Logical,
+    /// The fully resolved path, with every symlink along it followed
+    Physical,
+}
+
+impl SourcePaths {
+    /// The name of the resolution as given to `serve`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SourcePaths::Logical => "logical",
+            SourcePaths::Physical => "physical",
+        }
+    }
+}
+
+impl FromStr for SourcePaths {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "logical" => Ok(SourcePaths::Logical),
+            "physical" => Ok(SourcePaths::Physical),
+            _ => Err(format!(
+                "invalid source path resolution {} (expected logical or physical)",
+                s
+            )),
+        }
+    }
+}
+
+/// Resolves `.` and `..` components without touching the filesystem.
+///
+/// A `..` removes the component before it; at the root it is dropped, and
+/// at the start of a relative path it is kept. Symlinks are not followed,
+/// so `link/..` becomes `.` even if `link` points elsewhere.
+///
+/// # Arguments
+///
+/// * `path` - The path to normalize
+///
+/// # Returns
+///
+/// * `PathBuf` - The normalized path, or `.` if nothing remains
+pub fn normalize(path: &Path) -> PathBuf {
+    let out = resolve(path);
+    if out.as_os_str().is_empty() {
+        PathBuf::from(".")
+    } else {
+        out
+    }
+}
+
+/// Normalizes like `normalize`, leaving an empty path where nothing remains
+/// so prefix comparisons treat it as the starting directory.
+fn resolve(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    // The number of components of `out` a `..` may still remove.
+    let mut depth = 0;
+    for component in path.components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => out.push(component),
+            Component::CurDir => {}
+            Component::ParentDir if depth > 0 => {
+                out.pop();
+                depth -= 1;
+            }
+            Component::ParentDir if out.has_root() => {}
+            Component::ParentDir => out.push(".."),
+            Component::Normal(name) => {
+                out.push(name);
+                depth += 1;
+            }
+        }
+    }
+    out
+}
+
+/// Returns true if a path's `..` components climb back above the directory
+/// it starts in after naming something inside it, as `dest/../..` does.
+/// Leading `..`s, as in `../dest`, say where the path goes up front and do
+/// not count.
+///
+/// # Arguments
+///
+/// * `path` - The path to check
+///
+/// # Returns
+///
+/// * `bool` - True if the path lexically leaves its starting directory
+pub fn escapes(path: &Path) -> bool {
+    let mut named = false;
+    let mut depth = 0usize;
+    for component in path.components() {
+        match component {
+            Component::Normal(_) => {
+                named = true;
+                depth += 1;
+            }
+            Component::ParentDir if depth > 0 => depth -= 1,
+            Component::ParentDir if named => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Returns true if a relative path's `..` components lead above the
+/// directory it starts in, leading ones included, as `../a` and `a/../..` do.
+///
+/// # Arguments
+///
+/// * `path` - The path to check
+///
+/// # Returns
+///
+/// * `bool` - True if the path lexically leaves its starting directory
+pub fn climbs_out(path: &Path) -> bool {
+    let mut depth = 0usize;
+    for component in path.components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::ParentDir if depth == 0 => return true,
+            Component::ParentDir => depth -= 1,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Returns true if `path` is `root` or lies below it, comparing the
+/// normalized paths.
+///
+/// # Arguments
+///
+/// * `path` - The path to check
+/// * `root` - The directory it should stay within
+///
+/// # Returns
+///
+/// * `bool` - True if `path` stays inside `root`
+pub fn is_within(path: &Path, root: &Path) -> bool {
+    contains(&resolve(root), &resolve(path))
+}
+
+/// Joins an untrusted relative path onto `root`, refusing absolute paths and
+/// any whose `..` components would lead outside `root`.
+///
+/// # Arguments
+///
+/// * `root` - The directory the result must stay within
+/// * `path` - The relative path to join
+///
+/// # Returns
+///
+/// * `io::Result<PathBuf>` - The normalized joined path, or an error if it escapes `root`
+pub fn join_within(root: &Path, path: &Path) -> io::Result<PathBuf> {
+    let joined = resolve(&root.join(path));
+    if path.has_root() || !contains(&resolve(root), &joined) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} leads outside {}", path.display(), root.display()),
+        ));
+    }
+    Ok(normalize(&joined))
+}
+
+/// Returns true if the resolved `path` is the resolved `root` or below it. A
+/// relative root like `..` does not contain `../..`, which shares its prefix.
+fn contains(root: &Path, path: &Path) -> bool {
+    path.strip_prefix(root)
+        .is_ok_and(|rest| rest.components().next() != Some(Component::ParentDir))
+}
@@ -1,103 +1,306 @@
+use crate::clock::UtcTime;
+use crate::error::{FlnkError, Op};
+use crate::interrupt::{self, CancelToken};
+use crate::json::Json;
+use crate::link::archive;
+use crate::link::backups::{self, BackupControl, Generation};
+use crate::link::collision::{self, OnCollision};
+use crate::link::dry_run::Operation;
+use crate::link::failure::{ErrorPolicy, Failure};
+use crate::link::glob::{expand_braces, glob_match, has_glob};
+use crate::link::home;
+use crate::link::journal::{self, Journal};
+use crate::link::lexical::{self, SourcePaths};
 use crate::link::link_options::LinkOptions;
-use std::fs;
-use std::io;
+use crate::link::manifest::{self, Change, LinkType, Manifest, Reversal};
+use crate::link::mirror;
+use crate::link::netfs::{self, NetworkFsMode};
+use crate::link::overlay;
+use crate::link::owner;
+use crate::link::pipeline::{self, EntryKind, LeftOut, LinkJob};
+use crate::link::platform;
+use crate::link::probe::{self, FallbackPolicy, FsCapabilities};
+use crate::link::prompt::{self, Resolution};
+use crate::link::selinux::{self, ContextMode};
+use crate::link::skip::{SkipReason, Skipped};
+use crate::link::status::{State, Status, StatusEntry};
+use crate::link::warning::{Warning, WarningKind};
+use crate::link::xattr;
+use crate::sha256;
+use crate::term;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Write};
+use std::mem;
+use std::panic;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, Sender, channel, sync_channel};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
 use walkdir::WalkDir;
 
 /// Computes a relative path from the source to the target.
 ///
+/// A source on another Windows drive or UNC share cannot be reached by a
+/// relative path, so its absolute path is returned instead.
+///
 /// # Arguments
 ///
 /// * `source` - The source path to compute the relative path from
 /// * `target` - The target path to compute the relative path to
+/// * `base` - The directory to compute the path against instead of the
+///   target's parent; it need not exist and is taken as written
+/// * `as_written` - If true, both paths are taken as written instead of being
+///   resolved, so the source need not exist and symlinks along the way are kept
 ///
 /// # Returns
 ///
 /// * `io::Result<PathBuf>` - The relative path from source to target
-fn make_relative(source: &Path, target: &Path) -> io::Result<PathBuf> {
-    let source_abs = fs::canonicalize(source)?;
-    let target_abs = fs::canonicalize(target.parent().unwrap_or(target))?;
+fn make_relative(
+    source: &Path,
+    target: &Path,
+    base: Option<&Path>,
+    as_written: bool,
+) -> io::Result<PathBuf> {
+    let target_dir = match target.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        Some(_) => Path::new("."),
+        None => target,
+    };
+    let resolve = |path: &Path| -> io::Result<PathBuf> {
+        if as_written {
+            Ok(lexical::normalize(&std::path::absolute(path)?))
+        } else {
+            Ok(platform::strip_verbatim(&fs::canonicalize(path)?))
+        }
+    };
+    let source_abs = resolve(source)?;
+    let target_abs = match base {
+        Some(base) => lexical::normalize(&std::path::absolute(base)?),
+        None => resolve(target_dir)?,
+    };
 
+    if !platform::same_volume(&source_abs, &target_abs) {
+        return Ok(source_abs);
+    }
     pathdiff::diff_paths(&source_abs, &target_abs)
-        .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "Could not compute relative path"))
+        .ok_or_else(|| io::Error::other("Could not compute relative path"))
 }
 
-/// Creates a backup of a file by renaming it with a suffix.
+/// Creates a backup of a file by renaming it to its backup name.
+///
+/// A simple backup whose name is already taken is made as a numbered
+/// backup instead, so an earlier backup is never overwritten.
+///
+/// # Arguments
+///
+/// * `dest` - The path to the file to back up
+/// * `suffix` - The suffix to append to the backup file name
+/// * `control` - Whether the backup is simple, numbered, or numbered only
+///   if numbered backups exist
+///
+/// # Returns
 ///
-/// If a file with the backup name already exists, appends a counter
-/// to the backup name until a unique name is found.
+/// * `io::Result<(PathBuf, bool)>` - The backup path, and true if the plain
+///   backup name was taken and a numbered backup was made instead
+fn create_backup(dest: &Path, suffix: &str, control: BackupControl) -> io::Result<(PathBuf, bool)> {
+    let (backup_path, collision) = backup_path(dest, suffix, control);
+    fs::rename(dest, &backup_path)?;
+    Ok((backup_path, collision))
+}
+
+/// Returns true if a job's source was modified after its existing
+/// destination, which `update` requires before replacing it. Directories,
+/// and entries whose times cannot be read, always count as newer.
+fn source_is_newer(job: &LinkJob) -> bool {
+    if job.kind == EntryKind::Dir {
+        return true;
+    }
+    let modified = |path: &Path| fs::metadata(path).and_then(|meta| meta.modified());
+    match (modified(&job.source), modified(&job.dest)) {
+        (Ok(source), Ok(dest)) => source > dest,
+        _ => true,
+    }
+}
+
+/// Picks the name `create_backup` would move a file to.
 ///
 /// # Arguments
 ///
 /// * `dest` - The path to the file to back up
 /// * `suffix` - The suffix to append to the backup file name
+/// * `control` - Whether the backup is simple, numbered, or numbered only
+///   if numbered backups exist
 ///
 /// # Returns
 ///
-/// * `io::Result<()>` - Success if the backup was created
-fn create_backup(dest: &Path, suffix: &str) -> io::Result<()> {
+/// * `(PathBuf, bool)` - The backup path, and true if the plain backup name
+///   was taken so a numbered name was chosen
+fn backup_path(dest: &Path, suffix: &str, control: BackupControl) -> (PathBuf, bool) {
     let suffix = if suffix.is_empty() { "~" } else { suffix };
     let dest_str = dest.to_string_lossy();
-    let mut backup_path = PathBuf::from(format!("{}{}", dest_str, suffix));
+    let highest = backups::highest_numbered(dest);
+    let numbered = |highest: Option<u64>| {
+        PathBuf::from(format!("{}.~{}~", dest_str, highest.unwrap_or(0) + 1))
+    };
+    match control {
+        BackupControl::Numbered => return (numbered(highest), false),
+        BackupControl::Existing if highest.is_some() => return (numbered(highest), false),
+        _ => {}
+    }
 
-    if backup_path.exists() {
-        let mut counter = 1;
-        loop {
-            backup_path = PathBuf::from(format!("{}.~{}~", dest_str, counter));
-            if !backup_path.exists() {
-                break;
+    let simple = PathBuf::from(format!("{}{}", dest_str, suffix));
+    if fs::symlink_metadata(&simple).is_ok() {
+        (numbered(highest), true)
+    } else {
+        (simple, false)
+    }
+}
+
+/// Expands a source pattern's brace groups and globs into the paths it names.
+///
+/// A pattern naming an existing entry is taken literally. Release names such
+/// as `Show [1080p].mkv` or `Show {2019}.mkv` are common in media trees, and
+/// a pattern the user meant as a glob was already expanded by their shell,
+/// so an existing name is far more likely a file than a pattern. Names that
+/// are not valid UTF-8 are always literal.
+///
+/// # Arguments
+///
+/// * `pattern` - The source path or pattern
+///
+/// # Returns
+///
+/// * `io::Result<Vec<PathBuf>>` - The matching paths, which may be none, or
+///   an error if a glob's base directory cannot be walked
+pub(crate) fn expand_sources(pattern: impl AsRef<Path>) -> io::Result<Vec<PathBuf>> {
+    let pattern = pattern.as_ref();
+    // Only names that are valid UTF-8 can hold globs and brace groups;
+    // anything else is taken literally.
+    let Some(pattern) = pattern.to_str() else {
+        return Ok(vec![pattern.to_path_buf()]);
+    };
+    // Names such as `Show {2019}.mkv` are taken literally when they exist.
+    let alternatives = expand_braces(pattern);
+    if alternatives.len() == 1 || fs::symlink_metadata(pattern).is_ok() {
+        return expand_glob(pattern);
+    }
+
+    // Alternatives that match nothing are dropped, as a shell's failed globs would be.
+    let mut out = Vec::new();
+    for alternative in alternatives {
+        if !has_glob(&alternative) && fs::symlink_metadata(&alternative).is_err() {
+            continue;
+        }
+        let matched = match expand_glob(&alternative) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            matched => matched?,
+        };
+        for path in matched {
+            if !out.contains(&path) {
+                out.push(path);
             }
-            counter += 1;
         }
     }
-
-    fs::rename(dest, backup_path)
+    Ok(out)
 }
 
-fn has_glob(pattern: &str) -> bool {
-    pattern.chars().any(|c| matches!(c, '*' | '?' | '['))
+/// Returns the source tree a job's entry was found in: its source with the
+/// components of its relative path taken off.
+fn source_root(job: &LinkJob) -> PathBuf {
+    let mut root = job.source.clone();
+    for _ in job.rel_path.components() {
+        root.pop();
+    }
+    root
 }
 
-fn wildcard_match(pattern: &str, text: &str) -> bool {
-    if !pattern.contains('*') {
-        return pattern == text;
+fn expand_glob(pattern: &str) -> io::Result<Vec<PathBuf>> {
+    // Names such as `Show [1080p].mkv` are taken literally when they exist.
+    if !has_glob(pattern) || fs::symlink_metadata(pattern).is_ok() {
+        return Ok(vec![PathBuf::from(pattern)]);
     }
 
-    let mut parts = pattern.split('*');
-    let first = parts.next().unwrap();
-    if !text.starts_with(first) {
-        return false;
+    // Walk from the components before the first one holding a wildcard, and
+    // match what is below against the rest.
+    let parts: Vec<&str> = pattern.split('/').collect();
+    let first_glob = parts.iter().position(has_glob).unwrap();
+    let base = match parts[..first_glob].join("/") {
+        base if base.is_empty() && pattern.starts_with('/') => PathBuf::from("/"),
+        base => PathBuf::from(base),
+    };
+    let rest = parts[first_glob..].join("/");
+    let dirs_only = rest.ends_with('/');
+    let rest = rest.trim_end_matches('/');
+    let depth = rest.split('/').filter(|part| !part.is_empty()).count();
+    let recursive = rest.contains("**");
+
+    let root = if base.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        base.as_path()
+    };
+    if !root.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{}: No such directory", root.display()),
+        ));
+    }
+    let mut walk = WalkDir::new(root).min_depth(1);
+    if !recursive {
+        walk = walk.max_depth(depth);
     }
-    let mut remainder = &text[first.len()..];
-    for part in parts {
-        if part.is_empty() {
+    let mut out = Vec::new();
+    for entry in walk {
+        let entry = entry.map_err(io::Error::from)?;
+        if (!recursive && entry.depth() != depth) || (dirs_only && !entry.file_type().is_dir()) {
             continue;
         }
-        if let Some(pos) = remainder.find(part) {
-            remainder = &remainder[pos + part.len()..];
-        } else {
-            return false;
+        let rel = entry.path().strip_prefix(root).unwrap_or(entry.path());
+        if glob_match(rest, &rel.to_string_lossy()) {
+            out.push(base.join(rel));
         }
     }
-    pattern.ends_with('*') || remainder.is_empty()
+    // Sorted so which match wins a destination collision does not depend on
+    // directory order.
+    out.sort();
+    Ok(out)
 }
 
-fn expand_sources(pattern: &str) -> io::Result<Vec<PathBuf>> {
-    if !has_glob(pattern) {
-        return Ok(vec![PathBuf::from(pattern)]);
+/// Creates `dir` and any missing ancestors, marking each newly created
+/// directory and giving it the requested ownership and mode.
+///
+/// # Arguments
+///
+/// * `dir` - The directory to create
+/// * `opts` - The options naming the run label, ownership, and mode to apply, if any
+///
+/// # Returns
+///
+/// * `io::Result<Vec<PathBuf>>` - The directories that were created, outermost first
+fn create_dirs(dir: &Path, opts: &LinkOptions) -> io::Result<Vec<PathBuf>> {
+    let mut missing: Vec<PathBuf> = dir
+        .ancestors()
+        .take_while(|d| !d.as_os_str().is_empty() && !d.is_dir())
+        .map(Path::to_path_buf)
+        .collect();
+    if missing.is_empty() {
+        return Ok(missing);
     }
-    let path = Path::new(pattern);
-    let dir = path.parent().unwrap_or(Path::new("."));
-    let pat = path.file_name().unwrap_or_default().to_string_lossy();
-    let mut out = Vec::new();
-    for entry in fs::read_dir(dir)? {
-        let entry = entry?;
-        let name = entry.file_name();
-        if wildcard_match(&pat, &name.to_string_lossy()) {
-            out.push(entry.path());
+    fs::create_dir_all(dir)?;
+    missing.reverse();
+    for created in &missing {
+        if let Some(label) = &opts.mark {
+            xattr::mark_managed(created, label)?;
+        }
+        owner::apply(created, opts.owner, opts.group)?;
+        if let Some(spec) = &opts.chmod {
+            spec.apply(created)?;
         }
     }
-    Ok(out)
+    Ok(missing)
 }
 
 /// Creates either a hard link or symbolic link based on the provided options.
@@ -107,19 +310,24 @@ fn expand_sources(pattern: &str) -> io::Result<Vec<PathBuf>> {
 /// * `source_path` - The path to the source file to link from
 /// * `dest_path` - The path where the link should be created
 /// * `opts` - The options controlling the link behavior
+/// * `base` - The directory a relative symlink target is computed against, if
+///   not the directory holding the link
 ///
 /// # Returns
 ///
 /// * `io::Result<PathBuf>` - The path to the created link
-fn make_link(source_path: &Path, dest_path: &Path, opts: &LinkOptions) -> io::Result<PathBuf> {
+fn make_link(
+    source_path: &Path,
+    dest_path: &Path,
+    opts: &LinkOptions,
+    base: Option<&Path>,
+) -> io::Result<PathBuf> {
     if opts.symbolic {
-        let link_target = if opts.relative {
-            make_relative(source_path, dest_path)?
-        } else {
-            source_path.to_path_buf()
-        };
-
-        std::os::unix::fs::symlink(&link_target, dest_path)?;
+        let link_target = symlink_target(source_path, dest_path, opts, base)?;
+        platform::symlink(&link_target, dest_path)?;
+        Ok(dest_path.to_path_buf())
+    } else if opts.reflink {
+        clone_file(source_path, dest_path)?;
         Ok(dest_path.to_path_buf())
     } else {
         fs::hard_link(source_path, dest_path)?;
@@ -127,94 +335,2458 @@ fn make_link(source_path: &Path, dest_path: &Path, opts: &LinkOptions) -> io::Re
     }
 }
 
+/// Creates a copy-on-write clone of a file along with its extended attributes.
+///
+/// # Arguments
+///
+/// * `source_path` - The file to clone
+/// * `dest_path` - Where to create the clone
+///
+/// # Returns
+///
+/// * `io::Result<()>` - Success if the clone was created, or an error
+///   naming both files and why the filesystem would not clone them
+fn clone_file(source_path: &Path, dest_path: &Path) -> io::Result<()> {
+    platform::reflink(source_path, dest_path).map_err(FlnkError::wrap(
+        Op::Clone,
+        source_path,
+        dest_path,
+    ))?;
+    xattr::copy_all(source_path, dest_path)
+}
+
+/// Computes the target a symlink at `dest_path` to `source_path` is given.
+///
+/// # Arguments
+///
+/// * `source_path` - The path to the source file to link from
+/// * `dest_path` - The path where the link is created
+/// * `opts` - The options controlling the link behavior
+/// * `base` - The directory a relative target is computed against, if not
+///   the directory holding the link
+///
+/// # Returns
+///
+/// * `io::Result<PathBuf>` - The target to store in the link
+fn symlink_target(
+    source_path: &Path,
+    dest_path: &Path,
+    opts: &LinkOptions,
+    base: Option<&Path>,
+) -> io::Result<PathBuf> {
+    // A source that does not exist yet cannot be resolved, so it is taken as written.
+    let exists = fs::symlink_metadata(source_path).is_ok();
+    if opts.relative {
+        let as_written = opts.lexical || opts.source_paths == Some(SourcePaths::Logical) || !exists;
+        return make_relative(source_path, dest_path, base, as_written);
+    }
+    let source_path = match opts.source_paths {
+        Some(SourcePaths::Physical) if exists => {
+            platform::strip_verbatim(&fs::canonicalize(source_path)?)
+        }
+        _ => source_path.to_path_buf(),
+    };
+    match opts.home_style {
+        Some(style) => home::link_target(&source_path, style),
+        None => Ok(source_path),
+    }
+}
+
+/// Files at least this large are copied in chunks, reporting progress as they go.
+const PROGRESS_MIN_SIZE: u64 = 8 << 20;
+
+/// The size of each chunk of a copy that reports progress.
+const COPY_CHUNK: usize = 1 << 20;
+
+/// How often a copy in progress is reported.
+const COPY_REPORT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How far along the copy of a single file is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CopyProgress {
+    /// The bytes copied so far
+    pub copied: u64,
+    /// The size of the file being copied
+    pub total: u64,
+    /// The copy rate since the previous report, in bytes per second
+    pub bytes_per_sec: u64,
+}
+
+/// Copies a file for a destination that cannot hold links, along with its
+/// extended attributes.
+///
+/// Large files are copied in chunks so `on_progress` can report them while
+/// they are being copied; smaller ones are copied in one go.
+///
+/// # Arguments
+///
+/// * `source_path` - The file to copy
+/// * `dest_path` - Where to create the copy
+/// * `on_progress` - Called about every 100ms while a large file is copied, and once it is done
+///
+/// # Returns
+///
+/// * `io::Result<()>` - Success if the file and its attributes were copied
+pub(crate) fn copy_file(
+    source_path: &Path,
+    dest_path: &Path,
+    mut on_progress: impl FnMut(CopyProgress),
+) -> io::Result<()> {
+    let metadata = fs::metadata(source_path)?;
+    let total = metadata.len();
+    if total < PROGRESS_MIN_SIZE {
+        fs::copy(source_path, dest_path)?;
+        return xattr::copy_all(source_path, dest_path);
+    }
+
+    let mut reader = fs::File::open(source_path)?;
+    let mut writer = fs::File::create(dest_path)?;
+    let mut buf = vec![0; COPY_CHUNK];
+    let (mut copied, mut reported) = (0, 0);
+    let mut last = Instant::now();
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        writer.write_all(&buf[..n])?;
+        copied += n as u64;
+
+        let elapsed = last.elapsed();
+        if elapsed >= COPY_REPORT_INTERVAL || copied == total {
+            let rate = (copied - reported) as f64 / elapsed.as_secs_f64().max(1e-3);
+            on_progress(CopyProgress {
+                copied,
+                total,
+                bytes_per_sec: rate as u64,
+            });
+            reported = copied;
+            last = Instant::now();
+        }
+    }
+    fs::set_permissions(dest_path, metadata.permissions())?;
+    xattr::copy_all(source_path, dest_path)
+}
+
 /// Links files from a source directory to a destination directory.
 ///
 /// Can create either hard links or symbolic links based on the options provided.
-/// Handles existing files according to the backup and force options.
+/// Handles existing files according to the backup and force options. Unless
+/// disabled, the destination filesystem is probed first so that destinations
+/// which cannot hold links fail, or fall back to copying or skipping, before
+/// anything is created. The source
+/// tree is discovered on a separate thread so walking overlaps with linking.
 ///
 /// # Arguments
 ///
-/// * `source` - The source directory path as a string
-/// * `dest` - The destination directory path as a string
+/// * `source` - The source path or pattern
+/// * `dest` - The destination path
 /// * `opts` - Optional link options to control the behavior
 ///
 /// # Returns
 ///
-/// * `io::Result<Vec<PathBuf>>` - A list of relative paths that were linked
+/// * `io::Result<LinkReport>` - The links created, the entries skipped,
+///   the backups taken, the directories created, and the warnings of the run
 pub fn link_files(
+    source: impl AsRef<Path>,
+    dest: impl AsRef<Path>,
+    opts: Option<&LinkOptions>,
+) -> io::Result<LinkReport> {
+    link_files_with(source, dest, opts, |_: &LinkJob| {})
+}
+
+/// Links files like `link_files`, from paths given as strings.
+///
+/// # Arguments
+///
+/// * `source` - The source path or pattern as a string
+/// * `dest` - The destination path as a string
+/// * `opts` - Optional link options to control the behavior
+///
+/// # Returns
+///
+/// * `io::Result<LinkReport>` - The outcome of the run, as `link_files` returns it
+#[deprecated(note = "link_files takes any AsRef<Path>, including &str")]
+pub fn link_files_str(
     source: &str,
     dest: &str,
     opts: Option<&LinkOptions>,
-) -> io::Result<Vec<PathBuf>> {
-    let default_opts = LinkOptions::default();
-    let opts = opts.unwrap_or(&default_opts);
-    let dest_path = Path::new(dest);
-    let dest_is_dir = dest_path.is_dir();
-    let include_root = dest_path.is_relative();
-    let mut linked = Vec::new();
+) -> io::Result<LinkReport> {
+    link_files(source, dest, opts)
+}
 
-    let sources = expand_sources(source)?;
+/// Receives the events of a run as the link stage creates entries.
+///
+/// Any `FnMut(&LinkJob)` closure observes created links and ignores the rest.
+pub(crate) trait Observer {
+    /// Called with the job of every created link, once it exists.
+    fn linked(&mut self, job: &LinkJob);
 
-    for source_path in sources {
-        let base = if include_root && dest_is_dir {
-            source_path.parent().unwrap_or(Path::new(""))
-        } else {
-            source_path.as_path()
+    /// Called when the link stage moves on to the entries of a source root.
+    fn walk_started(&mut self, _root: &Path) {}
+
+    /// Called when an existing destination entry is moved aside to `backup`,
+    /// before the link replacing it is reported.
+    fn backed_up(&mut self, _dest: &Path, _backup: &Path) {}
+
+    /// Called with every source entry the link stage leaves out, and, with
+    /// `explain`, those discovery left out once their root is done.
+    fn skipped(&mut self, _skipped: &Skipped) {}
+
+    /// Called as a large file is being copied, before it is reported as linked.
+    fn copying(&mut self, _job: &LinkJob, _progress: &CopyProgress) {}
+
+    /// Called when an entry cannot be linked and the error policy may let
+    /// the run go on, before the user is asked.
+    fn failed(&mut self, _failure: &Failure) {}
+
+    /// Decides, with `interactive`, whether an existing destination entry is
+    /// replaced; by default the user is asked on the terminal.
+    fn conflict(&mut self, dest: &Path) -> io::Result<Resolution> {
+        prompt::ask_replace(dest)
+    }
+
+    /// Whether the run should stop before its next entry, as if interrupted.
+    fn stopped(&self) -> bool {
+        false
+    }
+
+    /// Whether the report lists every linked and skipped entry. An observer
+    /// that passes them on itself can leave them out, so a long run does
+    /// not hold them all.
+    fn keeps_entries(&self) -> bool {
+        true
+    }
+}
+
+impl<F: FnMut(&LinkJob)> Observer for F {
+    fn linked(&mut self, job: &LinkJob) {
+        self(job)
+    }
+}
+
+/// An event of a run, as reported by `link_many_progress`.
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+pub enum LinkEvent<'a> {
+    /// The run moved on to the entries of this source root
+    WalkStarted(&'a Path),
+    /// A link or copy was created
+    Linked {
+        /// The path of the link relative to its linked root
+        path: &'a Path,
+        /// The source it was made from
+        source: &'a Path,
+        /// The created link
+        dest: &'a Path,
+        /// Where the entry the link replaced was backed up, if it was
+        backup: Option<&'a Path>,
+    },
+    /// An existing destination entry was moved aside before being replaced
+    BackupCreated {
+        /// The destination entry
+        dest: &'a Path,
+        /// Where it was moved
+        backup: &'a Path,
+    },
+    /// A source entry was left out; discovery's reasons, such as filter
+    /// rules, are only reported with `explain`
+    Skipped(&'a Skipped),
+    /// The file at this relative path is being copied
+    Copying(&'a Path, CopyProgress),
+    /// An entry could not be linked; with `--on-error ask`, the user is
+    /// asked next whether to go on
+    Failed(&'a Failure),
+    /// This destination path already exists, and with `--interactive` the
+    /// user is asked next whether to replace it
+    Conflict(&'a Path),
+}
+
+/// Adapts a `LinkEvent` callback to the link stage, holding each backup
+/// until the link replacing it is reported.
+struct Events<F>(F, HashMap<PathBuf, PathBuf>);
+
+impl<F: FnMut(LinkEvent<'_>)> Observer for Events<F> {
+    fn linked(&mut self, job: &LinkJob) {
+        let backup = self.1.remove(&job.dest);
+        (self.0)(LinkEvent::Linked {
+            path: &job.rel_path,
+            source: &job.source,
+            dest: &job.dest,
+            backup: backup.as_deref(),
+        })
+    }
+
+    fn walk_started(&mut self, root: &Path) {
+        (self.0)(LinkEvent::WalkStarted(root))
+    }
+
+    fn backed_up(&mut self, dest: &Path, backup: &Path) {
+        (self.0)(LinkEvent::BackupCreated { dest, backup });
+        self.1.insert(dest.to_path_buf(), backup.to_path_buf());
+    }
+
+    fn skipped(&mut self, skipped: &Skipped) {
+        (self.0)(LinkEvent::Skipped(skipped))
+    }
+
+    fn copying(&mut self, job: &LinkJob, progress: &CopyProgress) {
+        (self.0)(LinkEvent::Copying(&job.rel_path, *progress))
+    }
+
+    fn failed(&mut self, failure: &Failure) {
+        (self.0)(LinkEvent::Failed(failure))
+    }
+
+    fn conflict(&mut self, dest: &Path) -> io::Result<Resolution> {
+        (self.0)(LinkEvent::Conflict(dest));
+        prompt::ask_replace(dest)
+    }
+}
+
+/// The outcome of a link operation.
+#[derive(Debug, Clone, Default)]
+pub struct LinkReport {
+    /// The relative paths that were linked or copied
+    pub linked: Vec<PathBuf>,
+    /// The non-fatal conditions met along the way
+    pub warnings: Vec<Warning>,
+    /// With `explain`, the source entries that were not linked and why
+    pub skipped: Vec<Skipped>,
+    /// With `dry_run`, the changes the run would have made, in order
+    pub planned: Vec<Operation>,
+    /// The existing destination entries moved aside, each with its backup path
+    pub backups: Vec<(PathBuf, PathBuf)>,
+    /// With `delete`, the destination entries removed for having no source
+    pub deleted: Vec<PathBuf>,
+    /// The destination directories the run created, outermost first
+    pub created_dirs: Vec<PathBuf>,
+    /// The total size in bytes of the files linked or copied; directories
+    /// linked whole are not counted
+    pub bytes: u64,
+    /// The part of `bytes` in files hard linked, whose contents are now
+    /// shared instead of stored twice
+    pub shared_bytes: u64,
+    /// The number of source entries left out by filter rules and include
+    /// globs; an excluded directory counts once
+    pub filtered: usize,
+    /// The number of destinations that already existed, whether they were
+    /// replaced, kept, or left the entry unlinked
+    pub conflicts: usize,
+    /// How long the run took
+    pub elapsed: Duration,
+    /// The entries that could not be linked, with `on_error` set to skip or ask
+    pub failures: Vec<Failure>,
+}
+
+impl LinkReport {
+    /// Renders the counts of the run as a JSON object: the entries `linked`,
+    /// `created_dirs`, `filtered`, `conflicts`, and `backups`, the `bytes`
+    /// and `shared_bytes` linked, and the `elapsed_ms`.
+    pub fn summary_json(&self) -> Json {
+        Json::object([
+            ("linked", Json::from(self.linked.len())),
+            ("created_dirs", Json::from(self.created_dirs.len())),
+            ("filtered", Json::from(self.filtered)),
+            ("conflicts", Json::from(self.conflicts)),
+            ("backups", Json::from(self.backups.len())),
+            ("bytes", Json::from(self.bytes)),
+            ("shared_bytes", Json::from(self.shared_bytes)),
+            ("elapsed_ms", Json::from(self.elapsed.as_millis() as u64)),
+        ])
+    }
+
+    /// Renders the report as a JSON object with `linked`, `backups`,
+    /// `deleted`, `created_dirs`, `warnings`, `skipped`, `planned`, and
+    /// `failures` arrays, the total `bytes`, and the `summary_json` counts
+    /// as `summary`.
+    pub fn to_json(&self) -> Json {
+        let paths = |paths: &[PathBuf]| {
+            Json::Array(paths.iter().map(|p| Json::from(p.as_path())).collect())
         };
+        let backups = self
+            .backups
+            .iter()
+            .map(|(dest, backup)| {
+                Json::object([
+                    ("dest", Json::from(dest.as_path())),
+                    ("backup", Json::from(backup.as_path())),
+                ])
+            })
+            .collect();
+        Json::object([
+            ("linked", paths(&self.linked)),
+            ("backups", Json::Array(backups)),
+            ("deleted", paths(&self.deleted)),
+            ("created_dirs", paths(&self.created_dirs)),
+            ("bytes", Json::from(self.bytes)),
+            (
+                "warnings",
+                Json::Array(self.warnings.iter().map(Warning::to_json).collect()),
+            ),
+            (
+                "skipped",
+                Json::Array(self.skipped.iter().map(Skipped::to_json).collect()),
+            ),
+            (
+                "planned",
+                Json::Array(self.planned.iter().map(Operation::to_json).collect()),
+            ),
+            (
+                "failures",
+                Json::Array(self.failures.iter().map(Failure::to_json).collect()),
+            ),
+            ("summary", self.summary_json()),
+        ])
+    }
+}
+
+/// Links files like `link_files`, calling `on_link` with the relative
+/// path of every entry as soon as it is linked, e.g. to drive a progress line.
+///
+/// # Arguments
+///
+/// * `source` - The source path or pattern
+/// * `dest` - The destination path
+/// * `opts` - Optional link options to control the behavior
+/// * `on_link` - Called with the relative path of every created link
+///
+/// # Returns
+///
+/// * `io::Result<LinkReport>` - The linked paths and the warnings of the run
+pub fn link_files_progress(
+    source: impl AsRef<Path>,
+    dest: impl AsRef<Path>,
+    opts: Option<&LinkOptions>,
+    mut on_link: impl FnMut(&Path),
+) -> io::Result<LinkReport> {
+    link_files_with(source, dest, opts, |job: &LinkJob| on_link(&job.rel_path))
+}
+
+/// Counts the existing destination entries a run would replace, by planning
+/// the run without creating anything. Directories that would be merged into
+/// an existing directory, and links already in place, are not counted.
+///
+/// # Arguments
+///
+/// * `source` - The source path or pattern
+/// * `dest` - The destination path
+/// * `opts` - Optional link options to control the behavior
+///
+/// # Returns
+///
+/// * `io::Result<usize>` - The number of destination entries that already exist
+pub fn count_existing(
+    source: impl AsRef<Path>,
+    dest: impl AsRef<Path>,
+    opts: Option<&LinkOptions>,
+) -> io::Result<usize> {
+    let update = opts.is_some_and(|opts| opts.update);
+    count_jobs(source.as_ref(), dest.as_ref(), opts, |job| {
+        fs::symlink_metadata(&job.dest)
+            .is_ok_and(|meta| !(job.kind == EntryKind::Dir && meta.is_dir()))
+            && (!update || source_is_newer(job))
+    })
+}
+
+/// Counts the links a run would create, by discovering its entries without
+/// creating anything, so progress can be shown against a total. Links
+/// already in place are not counted.
+///
+/// # Arguments
+///
+/// * `source` - The source path or pattern
+/// * `dest` - The destination path
+/// * `opts` - Optional link options to control the behavior
+///
+/// # Returns
+///
+/// * `io::Result<usize>` - The number of links the run would create
+pub fn count_links(
+    source: impl AsRef<Path>,
+    dest: impl AsRef<Path>,
+    opts: Option<&LinkOptions>,
+) -> io::Result<usize> {
+    count_jobs(source.as_ref(), dest.as_ref(), opts, |_| true)
+}
 
-        for (i, entry) in WalkDir::new(&source_path).into_iter().enumerate() {
-            let entry = entry?;
-            let path = entry.path();
-            let metadata = entry.metadata()?;
+/// Plans a run without creating anything and returns every entry that
+/// would fail: destinations that exist and would not be replaced, several
+/// sources mapping to one destination, directories that cannot be written
+/// to, and hard links that would cross filesystems. Unlike a dry run, which
+/// stops at the first conflict, all of them are found.
+///
+/// # Arguments
+///
+/// * `patterns` - The source paths or patterns of the run
+/// * `dest` - The destination path
+/// * `opts` - Optional link options to control the behavior
+///
+/// # Returns
+///
+/// * `io::Result<Vec<Failure>>` - The predicted failures, in discovery
+///   order; an unwritable directory is reported once, for the first entry
+///   below it
+pub fn check_conflicts(
+    patterns: &[impl AsRef<Path>],
+    dest: impl AsRef<Path>,
+    opts: Option<&LinkOptions>,
+) -> io::Result<Vec<Failure>> {
+    let default_opts = LinkOptions::default();
+    let opts = opts.unwrap_or(&default_opts);
+    let dest = dest.as_ref();
+    let mut sources = Vec::new();
+    for pattern in patterns.iter().map(AsRef::as_ref) {
+        // A missing literal source is linked dangling, not walked.
+        if opts.symbolic && !has_glob(pattern) && fs::symlink_metadata(pattern).is_err() {
+            continue;
+        }
+        sources.extend(expand_sources(pattern)?);
+    }
+    let jobs = pipeline::collect(&sources, dest, opts)?;
+    let creator = Creator {
+        opts,
+        dest_root: Some(dest),
+        copy: false,
+        retry_stale: false,
+        preserve_context: false,
+    };
 
-            if i == 0 && metadata.is_dir() {
+    let mut failures = Vec::new();
+    if !opts.force && !opts.backup && opts.on_collision == OnCollision::Error {
+        for collision in pipeline::find_collisions(&jobs, opts) {
+            let err = io::Error::from(FlnkError::Collision {
+                sources: collision.sources.clone(),
+                dest: collision.dest.clone(),
+                more: 0,
+            });
+            let source = collision.sources.last().unwrap();
+            failures.push(Failure::new(source, &collision.dest, &err));
+        }
+    }
+    let done = match &opts.journal {
+        Some(path) if opts.resume => journal::read(path)?.done,
+        _ => HashSet::new(),
+    };
+    let mut unwritable = HashSet::new();
+    let device = |path: &Path| fs::metadata(path).ok().and_then(|m| platform::device(&m));
+    for job in jobs.iter().filter(|job| job.kind.creates_link()) {
+        if creator.is_linked(job) || done.contains(&job.dest) {
+            continue;
+        }
+        let fail = |error: io::Error| Failure::new(&job.source, &job.dest, &error);
+        if let Ok(meta) = fs::symlink_metadata(&job.dest)
+            && !(job.kind == EntryKind::Dir && opts.archive && meta.is_dir())
+        {
+            if opts.skip_existing || opts.update && !source_is_newer(job) {
                 continue;
             }
-
-            if !metadata.is_file() && !opts.symbolic {
+            let replaces = opts.force || opts.backup || opts.interactive;
+            if !replaces || job.kind == EntryKind::Dir && opts.symbolic {
+                failures.push(fail(
+                    FlnkError::Exists {
+                        op: creator.op(job),
+                        source: job.source.clone(),
+                        dest: job.dest.clone(),
+                    }
+                    .into(),
+                ));
                 continue;
             }
+        }
 
-            if metadata.is_dir() && opts.symbolic && opts.symlink_files_only {
-                continue;
+        let parent = job.dest.parent().unwrap_or(Path::new(""));
+        let Some(dir) = probe::existing_ancestor(parent) else {
+            continue;
+        };
+        if !platform::is_writable(dir) {
+            if unwritable.insert(dir.to_path_buf()) {
+                let op = if dir == parent {
+                    creator.op(job)
+                } else {
+                    Op::CreateDir
+                };
+                let error = io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!("{} is not writable", dir.display()),
+                );
+                failures.push(fail(FlnkError::wrap(op, &job.source, &job.dest)(error)));
             }
+            continue;
+        }
+        if !opts.symbolic
+            && !opts.reflink
+            && opts.fallback == FallbackPolicy::Fail
+            && job.kind == EntryKind::File
+            && device(&job.source).is_some_and(|source| device(dir) != Some(source))
+        {
+            let error = io::Error::new(
+                io::ErrorKind::CrossesDevices,
+                "the source is on another filesystem than the destination",
+            );
+            failures.push(fail(FlnkError::wrap(Op::Link, &job.source, &job.dest)(
+                error,
+            )));
+        }
+    }
+    Ok(failures)
+}
 
-            let rel_path = path
-                .strip_prefix(base)
-                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+/// Compares a planned run with what is already at its destination, without
+/// changing anything: entries the run would create, entries that are not
+/// the link it would make, and entries its source no longer has.
+///
+/// # Arguments
+///
+/// * `patterns` - The source paths or patterns of the run
+/// * `dest` - The destination path
+/// * `opts` - Optional link options to control the behavior
+///
+/// # Returns
+///
+/// * `io::Result<Status>` - The number of entries in place and every entry
+///   that is not. Entries without a source counterpart are those `--delete`
+///   would remove, so with `--mark` only marked entries are listed
+pub fn status(
+    patterns: &[impl AsRef<Path>],
+    dest: impl AsRef<Path>,
+    opts: Option<&LinkOptions>,
+) -> io::Result<Status> {
+    let default_opts = LinkOptions::default();
+    let opts = opts.unwrap_or(&default_opts);
+    let dest = dest.as_ref();
+    let mut sources = Vec::new();
+    for pattern in patterns {
+        sources.extend(expand_sources(pattern.as_ref())?);
+    }
+    let jobs = pipeline::collect(&sources, dest, opts)?;
+    let creator = Creator {
+        opts,
+        dest_root: Some(dest),
+        copy: false,
+        retry_stale: false,
+        preserve_context: false,
+    };
+    let device = |path: &Path| fs::metadata(path).ok().and_then(|m| platform::device(&m));
 
-            let dest_file = if rel_path.as_os_str().is_empty() && dest_is_dir {
-                dest_path.join(path.file_name().unwrap())
-            } else {
-                dest_path.join(rel_path)
-            };
-            if let Some(parent) = dest_file.parent() {
-                fs::create_dir_all(parent)?;
+    let mut status = Status::default();
+    for job in jobs.iter().filter(|job| job.kind.creates_link()) {
+        let entry = |state, detail| StatusEntry {
+            state,
+            source: Some(job.source.clone()),
+            dest: job.dest.clone(),
+            detail,
+        };
+        let Ok(meta) = fs::symlink_metadata(&job.dest) else {
+            // The files of a directory that is made, not linked, are listed instead.
+            if job.kind != EntryKind::Dir || opts.symbolic {
+                status.entries.push(entry(State::Unlinked, None));
             }
-
-            if metadata.is_dir() && opts.symbolic {
-                make_link(path, &dest_file, opts)?;
-                linked.push(rel_path.to_path_buf());
-                continue;
+            continue;
+        };
+        let in_place = if job.kind == EntryKind::Dir && !opts.symbolic {
+            meta.is_dir()
+        } else if creator.op(job) == Op::Link && meta.is_file() && job.kind == EntryKind::File {
+            // A copy is what a reflink, or a hard link falling back across
+            // filesystems, leaves behind.
+            creator.is_linked(job)
+                || opts.reflink
+                || opts.fallback == FallbackPolicy::Copy
+                    && device(&job.source) != device(job.dest.parent().unwrap_or(Path::new("")))
+        } else {
+            creator.is_linked(job)
+        };
+        if in_place {
+            if job.kind != EntryKind::Dir || opts.symbolic {
+                status.linked += 1;
             }
+            continue;
+        }
+        let detail = format!(
+            "is {}, not {}",
+            describe(&job.dest, &meta),
+            creator.expected(job)
+        );
+        status.entries.push(entry(State::Differs, Some(detail)));
+    }
 
-            if dest_file.exists() {
-                if opts.backup {
-                    create_backup(&dest_file, &opts.backup_suffix)?;
-                } else if opts.force {
-                    fs::remove_file(&dest_file)?;
-                } else {
-                    return Err(io::Error::new(
-                        io::ErrorKind::AlreadyExists,
-                        "Destination file exists",
-                    ));
-                }
-            }
+    let keep = HashSet::new();
+    for (source, mirror) in pipeline::mirror_roots(&sources, Path::new(dest), opts) {
+        for path in mirror::extraneous(&source, &mirror, opts.mark.as_deref(), &keep)? {
+            status.entries.push(StatusEntry {
+                state: State::Extraneous,
+                source: None,
+                dest: path,
+                detail: None,
+            });
+        }
+    }
+    Ok(status)
+}
 
-            make_link(path, &dest_file, opts)?;
-            linked.push(rel_path.to_path_buf());
+/// Describes what an existing destination entry is, for `status`.
+fn describe(path: &Path, meta: &fs::Metadata) -> String {
+    if meta.file_type().is_symlink() {
+        match fs::read_link(path) {
+            Ok(target) => format!("a symlink to {}", target.display()),
+            Err(_) => String::from("a symlink"),
         }
+    } else if meta.is_dir() {
+        String::from("a directory")
+    } else if meta.is_file() {
+        String::from("a separate file")
+    } else {
+        String::from("a special file")
     }
+}
 
-    Ok(linked)
+/// Counts the jobs of a planned run that create a link not already in
+/// place and match `filter`.
+fn count_jobs(
+    source: &Path,
+    dest: &Path,
+    opts: Option<&LinkOptions>,
+    filter: impl Fn(&LinkJob) -> bool,
+) -> io::Result<usize> {
+    let default_opts = LinkOptions::default();
+    let opts = opts.unwrap_or(&default_opts);
+    let sources = expand_sources(source)?;
+    let jobs = pipeline::collect(&sources, dest, opts)?;
+    let creator = Creator {
+        opts,
+        dest_root: Some(dest),
+        copy: false,
+        retry_stale: false,
+        preserve_context: false,
+    };
+
+    Ok(jobs
+        .iter()
+        .filter(|job| job.kind.creates_link() && filter(job) && !creator.is_linked(job))
+        .count())
+}
+
+/// Links files like `link_files`, reporting each link as soon as it is created.
+///
+/// # Arguments
+///
+/// * `source` - The source path or pattern
+/// * `dest` - The destination path
+/// * `opts` - Optional link options to control the behavior
+/// * `on_link` - Called with the job of every created link
+///
+/// # Returns
+///
+/// * `io::Result<LinkReport>` - The linked paths and the warnings of the run
+pub(crate) fn link_files_with<F>(
+    source: impl AsRef<Path>,
+    dest: impl AsRef<Path>,
+    opts: Option<&LinkOptions>,
+    on_link: F,
+) -> io::Result<LinkReport>
+where
+    F: Observer,
+{
+    link_patterns(&[source.as_ref()], dest.as_ref(), opts, on_link)
+}
+
+/// Links several sources into one destination as a single run.
+///
+/// The destination is probed once, and destination paths that more than one
+/// source maps to are found while planning, before anything is created. Such
+/// a run fails unless `force` or `backup` is set, in which case the source
+/// listed last is linked and the earlier ones are skipped with a warning.
+///
+/// # Arguments
+///
+/// * `sources` - The source paths or patterns, in order of precedence
+/// * `dest` - The destination path
+/// * `opts` - Optional link options to control the behavior
+/// * `on_event` - Called with each `LinkEvent` of the run as it happens: roots
+///   walked, links created, backups made, entries skipped, failures, and the
+///   progress of large copies
+///
+/// # Returns
+///
+/// * `io::Result<LinkReport>` - The linked paths and the warnings of the run
+pub fn link_many_progress(
+    sources: &[impl AsRef<Path>],
+    dest: impl AsRef<Path>,
+    opts: Option<&LinkOptions>,
+    on_event: impl FnMut(LinkEvent<'_>),
+) -> io::Result<LinkReport> {
+    let sources: Vec<&Path> = sources.iter().map(AsRef::as_ref).collect();
+    let on_link = Events(on_event, HashMap::new());
+    link_patterns(&sources, dest.as_ref(), opts, on_link)
+}
+
+/// Probes the destination, then links every source matched by `patterns`.
+fn link_patterns<F>(
+    patterns: &[&Path],
+    dest: &Path,
+    opts: Option<&LinkOptions>,
+    on_link: F,
+) -> io::Result<LinkReport>
+where
+    F: Observer,
+{
+    let default_opts = LinkOptions::default();
+    let opts = opts.unwrap_or(&default_opts);
+
+    let caps = if opts.probe_filesystem {
+        probe::probe_destination(dest)
+    } else {
+        None
+    };
+    link_patterns_probed(patterns, dest, opts, caps.as_ref(), on_link)
+}
+
+/// Links files like `link_files_with`, using capabilities probed beforehand
+/// instead of probing the destination itself.
+///
+/// # Arguments
+///
+/// * `source` - The source path or pattern
+/// * `dest` - The destination path
+/// * `opts` - The options controlling the link behavior
+/// * `caps` - The capabilities of the destination filesystem, if known
+/// * `on_link` - Called with the job of every created link
+///
+/// # Returns
+///
+/// * `io::Result<LinkReport>` - The linked or copied paths and the warnings of the run
+pub(crate) fn link_files_probed<F>(
+    source: impl AsRef<Path>,
+    dest: impl AsRef<Path>,
+    opts: &LinkOptions,
+    caps: Option<&FsCapabilities>,
+    on_link: F,
+) -> io::Result<LinkReport>
+where
+    F: FnMut(&LinkJob),
+{
+    link_patterns_probed(&[source.as_ref()], dest.as_ref(), opts, caps, on_link)
+}
+
+/// Links every source matched by `patterns` as one run, using capabilities
+/// probed beforehand.
+fn link_patterns_probed<F>(
+    patterns: &[&Path],
+    dest: &Path,
+    opts: &LinkOptions,
+    caps: Option<&FsCapabilities>,
+    mut on_link: F,
+) -> io::Result<LinkReport>
+where
+    F: Observer,
+{
+    let started = Instant::now();
+    let dest_path = dest;
+    let mut sources = Vec::new();
+    let mut warnings = Vec::new();
+    if lexical::escapes(dest_path) {
+        warnings.push(Warning::new(
+            WarningKind::DestinationEscapes,
+            dest,
+            format!(
+                "{} climbs out of the directory it names and resolves to {}",
+                dest.display(),
+                lexical::normalize(dest_path).display()
+            ),
+        ));
+    }
+    // Symlinks may dangle, so a literal source that does not exist yet is
+    // linked as named rather than walked, as `ln -s` does.
+    let mut missing = Vec::new();
+    for &pattern in patterns {
+        if opts.symbolic && !has_glob(pattern) && fs::symlink_metadata(pattern).is_err() {
+            warnings.push(Warning::new(
+                WarningKind::DanglingLink,
+                pattern,
+                format!(
+                    "{} does not exist; its link dangles until it does",
+                    pattern.display()
+                ),
+            ));
+            missing.push(pattern.to_path_buf());
+            continue;
+        }
+        let matched = expand_sources(pattern)?;
+        if matched.is_empty() {
+            warnings.push(Warning::new(
+                WarningKind::NoMatch,
+                pattern,
+                format!("{} matched nothing", pattern.display()),
+            ));
+        }
+        sources.extend(matched);
+    }
+
+    let mut run_opts = opts.clone();
+    let mut copy = false;
+    let mut network = false;
+    if let Some(caps) = caps {
+        match probe::resolve(caps, opts)? {
+            Some(FallbackPolicy::Skip) => {
+                warnings.push(Warning::new(
+                    WarningKind::FallbackUsed,
+                    dest,
+                    format!(
+                        "destination filesystem ({}) at {} cannot hold links; skipped {}",
+                        caps.fs_type,
+                        caps.probed_dir.display(),
+                        dest.display()
+                    ),
+                ));
+                let skipped = if opts.explain {
+                    let skip = |source: &PathBuf| Skipped::new(source, SkipReason::Fallback);
+                    sources.iter().map(skip).collect()
+                } else {
+                    Vec::new()
+                };
+                return Ok(LinkReport {
+                    warnings,
+                    skipped,
+                    ..Default::default()
+                });
+            }
+            Some(FallbackPolicy::Copy) => {
+                warnings.push(Warning::new(
+                    WarningKind::FallbackUsed,
+                    dest,
+                    format!(
+                        "destination filesystem ({}) at {} cannot hold links; copied files into {} instead",
+                        caps.fs_type,
+                        caps.probed_dir.display(),
+                        dest.display()
+                    ),
+                ));
+                // Copies are made like hard links, descending into every directory.
+                run_opts.symbolic = false;
+                copy = true;
+            }
+            _ => {}
+        }
+        if caps.is_network() && opts.network_fs != NetworkFsMode::Off {
+            network = true;
+            if !run_opts.symbolic && !copy && opts.fallback == FallbackPolicy::Fail {
+                netfs::check_same_export(&sources, caps)?;
+            }
+            if run_opts.symbolic && opts.network_fs == NetworkFsMode::Strict {
+                run_opts.relative = true;
+            }
+        }
+    }
+    let opts = &run_opts;
+
+    // Runs over several roots, runs rewriting destination paths, and runs
+    // onto a filesystem with known length limits are planned first so
+    // conflicts surface before anything is created.
+    let limits = caps.filter(|caps| caps.name_max.is_some() || caps.path_max.is_some());
+    let rewrites = opts.flatten || opts.dest_template.is_some() || !opts.transform.is_empty();
+    let plan = if sources.len() > 1 || rewrites || limits.is_some() {
+        log::info!("planning the run before linking");
+        pipeline::collect(&sources, dest_path, opts)?
+    } else {
+        Vec::new()
+    };
+    if let Some(caps) = limits {
+        let dests = plan.iter().filter(|job| job.kind.creates_link());
+        probe::check_path_limits(caps, dests.map(|job| job.dest.as_path()))?;
+    }
+    let (winners, renames) = resolve_collisions(&plan, opts)?;
+    // Where each source directory lands is decided before the run creates it.
+    let mirrors = if opts.delete {
+        pipeline::mirror_roots(&sources, dest_path, opts)
+    } else {
+        Vec::new()
+    };
+
+    let mut linker = Linker::new(opts)?;
+    // The planning pass is part of the run.
+    linker.started = started;
+    linker.dest_root = Some(dest_path.to_path_buf());
+    linker.winners = winners;
+    linker.renames = renames;
+    linker.copy = copy;
+    linker.retry_stale = network;
+    linker.keep_entries = on_link.keeps_entries();
+    if opts.backup && opts.backup_generations.is_some() {
+        linker.generation = Some(Generation::new(dest_path));
+    } else if opts.backup
+        && let Some(dir) = &opts.backup_dir
+    {
+        linker.generation = Some(Generation::in_dir(dest_path, dir));
+    }
+    linker.warnings = warnings;
+    if let Some(path) = &opts.journal
+        && !opts.dry_run
+    {
+        if opts.resume {
+            linker.done = journal::read(path)?.done;
+        }
+        linker.journal = Some(Journal::open(path)?);
+    }
+    let run = linker
+        .link_all(&sources, dest_path, missing, &mut on_link)
+        .and_then(|()| linker.delete_extraneous(&mirrors));
+    if let Err(mut e) = run {
+        // Workers may have finished more links after the interruption.
+        if let Some(FlnkError::Interrupted { linked, .. }) =
+            e.get_mut().and_then(|inner| inner.downcast_mut())
+        {
+            *linked = linker.links;
+        }
+        if opts.atomic {
+            return Err(linker.roll_back(e));
+        }
+        // What was changed before the failure can still be undone.
+        let _ = linker.write_manifest();
+        return Err(e);
+    }
+    linker.finish()
+}
+
+/// The winning source for each contested destination path, and the
+/// destination path for each source moved aside.
+type Settled = (HashMap<PathBuf, PathBuf>, HashMap<PathBuf, PathBuf>);
+
+/// Finds the destination paths several jobs of a planned run map to and
+/// settles them by `on_collision`. With `error`, the run is refused unless
+/// `force` or `backup` allows replacing, in which case the job discovered
+/// last wins; with `overwrite`, it wins regardless; with `skip`, the job
+/// discovered first wins; with `number`, every job after the first is given
+/// the first numbered name no other job maps to.
+///
+/// # Arguments
+///
+/// * `plan` - The planned jobs of the run, in discovery order
+/// * `opts` - The options controlling the link behavior
+///
+/// # Returns
+///
+/// * `io::Result<Settled>` - The winning source for each contested
+///   destination path, and the numbered destination path for each renamed
+///   source
+fn resolve_collisions(plan: &[LinkJob], opts: &LinkOptions) -> io::Result<Settled> {
+    let collisions = pipeline::find_collisions(plan, opts);
+    match opts.on_collision {
+        OnCollision::Error | OnCollision::Overwrite => {}
+        OnCollision::Skip => {
+            let winners = collisions
+                .into_iter()
+                .map(|mut collision| (collision.dest, collision.sources.swap_remove(0)))
+                .collect();
+            return Ok((winners, HashMap::new()));
+        }
+        OnCollision::Number => {
+            let mut taken: HashSet<PathBuf> = plan.iter().map(|job| job.dest.clone()).collect();
+            let mut renames = HashMap::new();
+            for collision in collisions {
+                let mut n = 1;
+                for source in collision.sources.into_iter().skip(1) {
+                    while taken.contains(&collision::numbered(&collision.dest, n)) {
+                        n += 1;
+                    }
+                    let dest = collision::numbered(&collision.dest, n);
+                    taken.insert(dest.clone());
+                    renames.insert(source, dest);
+                }
+            }
+            return Ok((HashMap::new(), renames));
+        }
+    }
+    if let Some(first) = collisions.first()
+        && opts.on_collision == OnCollision::Error
+        && !opts.force
+        && !opts.backup
+    {
+        return Err(FlnkError::Collision {
+            sources: first.sources.clone(),
+            dest: first.dest.clone(),
+            more: collisions.len() - 1,
+        }
+        .into());
+    }
+
+    let winners = collisions
+        .into_iter()
+        .map(|mut collision| {
+            let winner = collision.sources.pop().unwrap();
+            (collision.dest, winner)
+        })
+        .collect();
+    Ok((winners, HashMap::new()))
+}
+
+/// Appends `sha256sum`-compatible lines to a checksum manifest, naming each
+/// file relative to the manifest's directory so `sha256sum -c` can be run
+/// from there.
+///
+/// # Arguments
+///
+/// * `manifest` - The manifest file, created if missing
+/// * `checksums` - The hex digest and destination path of every linked file
+///
+/// # Returns
+///
+/// * `io::Result<()>` - Success if every line was written
+fn write_checksums(manifest: &Path, checksums: &[(String, PathBuf)]) -> io::Result<()> {
+    let manifest = std::path::absolute(manifest)?;
+    let base = manifest.parent().unwrap_or(Path::new("/"));
+    let mut out = String::new();
+    for (hash, dest) in checksums {
+        let dest = std::path::absolute(dest)?;
+        let name = pathdiff::diff_paths(&dest, base).unwrap_or(dest);
+        let name = name.to_string_lossy();
+        // Like sha256sum, names with a backslash or newline are escaped and
+        // the line is flagged with a leading backslash.
+        if name.contains(['\\', '\n']) {
+            let escaped = name.replace('\\', "\\\\").replace('\n', "\\n");
+            out.push_str(&format!("\\{}  {}\n", hash, escaped));
+        } else {
+            out.push_str(&format!("{}  {}\n", hash, name));
+        }
+    }
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(manifest)?
+        .write_all(out.as_bytes())
+}
+
+/// The link stage: creates the entry for each job it is given, in order.
+pub(crate) struct Linker<'a> {
+    opts: &'a LinkOptions,
+    dest_root: Option<PathBuf>,
+    winners: HashMap<PathBuf, PathBuf>,
+    /// Numbered destination paths given to colliding sources, by source
+    renames: HashMap<PathBuf, PathBuf>,
+    copy: bool,
+    retry_stale: bool,
+    preserve_context: bool,
+    created_dirs: Vec<(PathBuf, PathBuf)>,
+    checksums: Vec<(String, PathBuf)>,
+    generation: Option<Generation>,
+    warnings: Vec<Warning>,
+    skipped: Vec<Skipped>,
+    planned: Vec<Operation>,
+    planned_dirs: HashSet<PathBuf>,
+    backups: Vec<(PathBuf, PathBuf)>,
+    replaced: Vec<PathBuf>,
+    journal: Option<Journal>,
+    done: HashSet<PathBuf>,
+    changes: Vec<Change>,
+    /// Whether linked and skipped entries are kept for the report
+    keep_entries: bool,
+    links: usize,
+    linked: Vec<PathBuf>,
+    deleted: Vec<PathBuf>,
+    made_dirs: Vec<PathBuf>,
+    bytes: u64,
+    shared_bytes: u64,
+    filtered: usize,
+    conflicts: usize,
+    crossed: usize,
+    failures: Vec<Failure>,
+    replace_all: bool,
+    started: Instant,
+    /// Entries skipped since the observer was last told
+    unreported: Vec<Skipped>,
+}
+
+impl<'a> Linker<'a> {
+    /// Prepares the calling thread for creating links with `opts`.
+    ///
+    /// # Arguments
+    ///
+    /// * `opts` - The options controlling the link behavior
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<Linker>` - The linker, or an error if the SELinux creation context cannot be reset
+    pub(crate) fn new(opts: &'a LinkOptions) -> io::Result<Self> {
+        let context = prepare_thread(opts)?;
+
+        Ok(Linker {
+            opts,
+            dest_root: None,
+            winners: HashMap::new(),
+            renames: HashMap::new(),
+            copy: false,
+            retry_stale: false,
+            preserve_context: context == ContextMode::Preserve,
+            created_dirs: Vec::new(),
+            checksums: Vec::new(),
+            generation: None,
+            warnings: Vec::new(),
+            skipped: Vec::new(),
+            planned: Vec::new(),
+            planned_dirs: HashSet::new(),
+            backups: Vec::new(),
+            replaced: Vec::new(),
+            journal: None,
+            done: HashSet::new(),
+            changes: Vec::new(),
+            keep_entries: true,
+            links: 0,
+            linked: Vec::new(),
+            deleted: Vec::new(),
+            made_dirs: Vec::new(),
+            bytes: 0,
+            shared_bytes: 0,
+            filtered: 0,
+            conflicts: 0,
+            crossed: 0,
+            failures: Vec::new(),
+            replace_all: false,
+            started: Instant::now(),
+            unreported: Vec::new(),
+        })
+    }
+
+    /// Creates the entry for a single job.
+    ///
+    /// # Arguments
+    ///
+    /// * `job` - The job to carry out
+    /// * `on_link` - Called with the job once its link exists
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<()>` - Success if the link was created, or if it failed
+    ///   and the error policy goes on without it
+    pub(crate) fn link<F>(&mut self, job: LinkJob, on_link: &mut F) -> io::Result<()>
+    where
+        F: Observer,
+    {
+        let (source, dest) = (job.source.clone(), job.dest.clone());
+        let job = match self.prepare(job, on_link) {
+            Ok(Some(job)) => job,
+            Ok(None) => return Ok(()),
+            Err(e) => return self.failed(source, dest, e, on_link),
+        };
+        match self
+            .creator()
+            .create(&job, |progress| on_link.copying(&job, &progress))
+        {
+            Ok(creation) => self.created(job, creation, on_link),
+            Err(e) => return self.failed(source, dest, e, on_link),
+        }
+        Ok(())
+    }
+
+    /// Handles an entry that could not be linked according to the run's
+    /// error policy: the run ends with the error, or the failure is recorded
+    /// and the run goes on.
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - The source entry of the failed job
+    /// * `dest` - The destination it was to be linked at
+    /// * `err` - The error met
+    /// * `on_link` - Told about the failure before the user is asked
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<()>` - Success if the run goes on, or the error otherwise
+    fn failed<F>(
+        &mut self,
+        source: PathBuf,
+        dest: PathBuf,
+        err: io::Error,
+        on_link: &mut F,
+    ) -> io::Result<()>
+    where
+        F: Observer,
+    {
+        let failure = Failure::new(source, dest, &err);
+        // Quitting at the replace prompt or with Ctrl-C ends the run whatever
+        // the policy.
+        let go_on = match self.opts.on_error {
+            _ if err.kind() == io::ErrorKind::Interrupted => false,
+            ErrorPolicy::Abort => false,
+            ErrorPolicy::Skip => {
+                on_link.failed(&failure);
+                true
+            }
+            ErrorPolicy::Ask => {
+                on_link.failed(&failure);
+                let question = format!("{}; skip it and continue?", failure);
+                term::confirm(&question).unwrap_or(false)
+            }
+        };
+        if !go_on {
+            return Err(err);
+        }
+        self.failures.push(failure);
+        Ok(())
+    }
+
+    /// Does everything for a job that depends on the jobs before it:
+    /// skips, overlay removals, parent directories, directory entries, and
+    /// replacing an existing destination.
+    ///
+    /// # Arguments
+    ///
+    /// * `job` - The job to carry out
+    /// * `on_link` - Called with the job if it is finished here
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<Option<LinkJob>>` - The job if its link or copy is still
+    ///   to be created, or `None` if nothing is left to do
+    fn prepare<F>(&mut self, job: LinkJob, on_link: &mut F) -> io::Result<Option<LinkJob>>
+    where
+        F: Observer,
+    {
+        let prepared = self.prepare_job(job, on_link);
+        self.report_skips(on_link);
+        prepared
+    }
+
+    /// Does the work of `prepare`, leaving its skips unreported.
+    fn prepare_job<F>(&mut self, mut job: LinkJob, on_link: &mut F) -> io::Result<Option<LinkJob>>
+    where
+        F: Observer,
+    {
+        let opts = self.opts;
+        if let Some(dest) = self.renames.get(&job.source) {
+            job.dest = dest.clone();
+        }
+
+        let cancelled = opts.cancel.as_ref().is_some_and(CancelToken::is_cancelled);
+        if interrupt::requested() || cancelled || on_link.stopped() {
+            return Err(FlnkError::Interrupted {
+                op: self.creator().op(&job),
+                source: job.source,
+                dest: job.dest,
+                linked: self.links,
+            }
+            .into());
+        }
+
+        if job.kind == EntryKind::Special {
+            self.warnings.push(Warning::new(
+                WarningKind::SpecialFileSkipped,
+                &job.source,
+                format!(
+                    "skipped {}: sockets, FIFOs, and device files cannot be linked",
+                    job.source.display()
+                ),
+            ));
+            self.skip(&job.source, SkipReason::SpecialFile);
+            return Ok(None);
+        }
+
+        if job.kind.creates_link()
+            && let Some(winner) = self.winners.get(&job.dest)
+            && *winner != job.source
+        {
+            self.warnings.push(Warning::new(
+                WarningKind::DestinationCollision,
+                &job.dest,
+                format!(
+                    "skipped {}: {} is linked from {}, which is listed {}",
+                    job.source.display(),
+                    job.dest.display(),
+                    winner.display(),
+                    if opts.on_collision == OnCollision::Skip {
+                        "earlier"
+                    } else {
+                        "later"
+                    }
+                ),
+            ));
+            self.skip(&job.source, SkipReason::Collision(winner.clone()));
+            return Ok(None);
+        }
+
+        // Links left in place by an earlier run make repeated runs no-ops.
+        if job.kind.creates_link() && self.creator().is_linked(&job) {
+            self.skip(&job.source, SkipReason::AlreadyLinked);
+            return Ok(None);
+        }
+        // What the interrupted run finished is left as it was made, even if
+        // it is a copy or was forced over something.
+        if job.kind.creates_link()
+            && self.done.contains(&job.dest)
+            && fs::symlink_metadata(&job.dest).is_ok()
+        {
+            self.skip(&job.source, SkipReason::Resumed);
+            return Ok(None);
+        }
+
+        if opts.dry_run {
+            self.plan(job)?;
+            return Ok(None);
+        }
+        match job.kind {
+            EntryKind::Whiteout => {
+                if overlay::apply_whiteout(&job.dest)? {
+                    self.record(Change::Removed(job.dest));
+                }
+                return Ok(None);
+            }
+            EntryKind::Opaque => {
+                for removed in overlay::clear_dir(&job.dest)? {
+                    self.record(Change::Removed(removed));
+                }
+                return Ok(None);
+            }
+            _ => {}
+        }
+
+        if let Some(parent) = job.dest.parent() {
+            let created = create_dirs(parent, opts).map_err(FlnkError::wrap(
+                Op::CreateDir,
+                &job.source,
+                &job.dest,
+            ))?;
+            for created in created {
+                self.created_dir(created);
+            }
+        }
+
+        if job.kind == EntryKind::Dir && opts.archive {
+            if !job.dest.is_dir() {
+                let created = create_dirs(&job.dest, opts).map_err(FlnkError::wrap(
+                    Op::CreateDir,
+                    &job.source,
+                    &job.dest,
+                ))?;
+                for created in created {
+                    self.created_dir(created);
+                }
+                if self.preserve_context {
+                    selinux::copy_context(&job.source, &job.dest)?;
+                }
+                self.created_dirs
+                    .push((job.source.clone(), job.dest.clone()));
+            }
+            let creation = Creation {
+                fallback: None,
+                copied: false,
+                checksum: None,
+            };
+            self.created(job, creation, on_link);
+            return Ok(None);
+        }
+
+        // A skipped entry must not replace what is at the destination.
+        if opts.fallback == FallbackPolicy::Skip && self.creator().crosses_devices(&job) {
+            self.fell_back(&job, FallbackPolicy::Skip);
+            return Ok(None);
+        }
+
+        if job.dest.exists() {
+            self.conflicts += 1;
+            if opts.skip_existing {
+                self.skip(&job.source, SkipReason::Existing);
+                return Ok(None);
+            }
+            if opts.update && !source_is_newer(&job) {
+                self.skip(&job.source, SkipReason::NotNewer);
+                return Ok(None);
+            }
+            if opts.interactive && !self.replace_all {
+                match on_link.conflict(&job.dest)? {
+                    Resolution::Replace => {}
+                    Resolution::ReplaceAll => self.replace_all = true,
+                    Resolution::Keep => {
+                        self.skip(&job.source, SkipReason::Declined);
+                        return Ok(None);
+                    }
+                    Resolution::Quit => {
+                        return Err(FlnkError::Quit {
+                            source: job.source,
+                            dest: job.dest,
+                        }
+                        .into());
+                    }
+                }
+            }
+            if opts.backup
+                && let Some(generation) = &mut self.generation
+            {
+                let backup = generation.stash(&job.dest).map_err(FlnkError::wrap(
+                    Op::Backup,
+                    &job.source,
+                    &job.dest,
+                ))?;
+                self.record(Change::BackedUp(job.dest.clone(), backup.clone()));
+                log::debug!("backed up {} to {}", job.dest.display(), backup.display());
+                on_link.backed_up(&job.dest, &backup);
+                self.backups.push((job.dest.clone(), backup));
+            } else if opts.backup {
+                let (backup, collision) =
+                    create_backup(&job.dest, &opts.backup_suffix, opts.backup_control)
+                        .map_err(FlnkError::wrap(Op::Backup, &job.source, &job.dest))?;
+                self.record(Change::BackedUp(job.dest.clone(), backup.clone()));
+                log::debug!("backed up {} to {}", job.dest.display(), backup.display());
+                on_link.backed_up(&job.dest, &backup);
+                self.backups.push((job.dest.clone(), backup));
+                if collision {
+                    self.warnings.push(Warning::new(
+                        WarningKind::BackupCollision,
+                        &job.dest,
+                        format!(
+                            "{}{} already exists; made a numbered backup of {} instead",
+                            job.dest.display(),
+                            opts.backup_suffix,
+                            job.dest.display()
+                        ),
+                    ));
+                }
+            } else if (opts.force || opts.interactive)
+                && opts.atomic
+                && !fs::symlink_metadata(&job.dest).is_ok_and(|m| m.is_dir())
+            {
+                // Kept aside until the run succeeds, so a rollback can put it
+                // back. Directories cannot be replaced either way.
+                let (aside, _) = backup_path(&job.dest, "~", BackupControl::Numbered);
+                fs::rename(&job.dest, &aside).map_err(FlnkError::wrap(
+                    Op::Replace,
+                    &job.source,
+                    &job.dest,
+                ))?;
+                self.record(Change::BackedUp(job.dest.clone(), aside.clone()));
+                self.replaced.push(aside);
+            } else if opts.force || opts.interactive {
+                platform::remove_link(&job.dest).map_err(FlnkError::wrap(
+                    Op::Replace,
+                    &job.source,
+                    &job.dest,
+                ))?;
+                self.record(Change::Removed(job.dest.clone()));
+            } else {
+                return Err(FlnkError::Exists {
+                    op: self.creator().op(&job),
+                    source: job.source,
+                    dest: job.dest,
+                }
+                .into());
+            }
+        }
+        Ok(Some(job))
+    }
+
+    /// Undoes every change of a run that failed with `err`, last first.
+    ///
+    /// # Arguments
+    ///
+    /// * `err` - The error that ended the run
+    ///
+    /// # Returns
+    ///
+    /// * `io::Error` - `err` if the destination is as it was, or an error
+    ///   naming what could not be put back
+    fn roll_back(&mut self, err: io::Error) -> io::Error {
+        let changes = mem::take(&mut self.changes);
+        let manifest = Manifest {
+            id: String::new(),
+            time: String::new(),
+            dest: self.dest_root.clone().unwrap_or_default(),
+            changes,
+        };
+        let left = match manifest.undo() {
+            Ok(done) => done
+                .into_iter()
+                .filter(|reversal| matches!(reversal, Reversal::Kept(..)))
+                .map(|kept| kept.to_string())
+                .collect::<Vec<_>>(),
+            Err(e) => vec![e.to_string()],
+        };
+        if left.is_empty() {
+            return err;
+        }
+        io::Error::new(
+            err.kind(),
+            format!("{}; rolling back was incomplete: {}", err, left.join("; ")),
+        )
+    }
+
+    /// Records a job whose entry now exists, unless the fallback policy skipped it.
+    fn created<F>(&mut self, job: LinkJob, creation: Creation, on_link: &mut F)
+    where
+        F: Observer,
+    {
+        if let Some(policy) = creation.fallback {
+            self.fell_back(&job, policy);
+            self.report_skips(on_link);
+            if policy == FallbackPolicy::Skip {
+                return;
+            }
+        }
+        if let Some(hash) = creation.checksum {
+            self.checksums.push((hash, job.dest.clone()));
+        }
+        // A recreated directory is recorded as created instead.
+        if !(job.kind == EntryKind::Dir && self.opts.archive) {
+            let link_type = if creation.copied {
+                LinkType::Copy
+            } else if self.opts.symbolic
+                || job.kind == EntryKind::Symlink
+                || creation.fallback.is_some()
+            {
+                LinkType::Symlink
+            } else {
+                LinkType::HardLink
+            };
+            self.record(Change::Linked(
+                link_type,
+                job.source.clone(),
+                job.dest.clone(),
+            ));
+        }
+        if job.kind == EntryKind::File {
+            let len = fs::metadata(&job.source).map_or(0, |m| m.len());
+            self.bytes += len;
+            if !creation.copied && !self.opts.symbolic && creation.fallback.is_none() {
+                self.shared_bytes += len;
+            }
+        }
+        on_link.linked(&job);
+        if let Some(journal) = &mut self.journal {
+            // A journal that cannot be written only costs a resume its shortcut.
+            let _ = journal.record(&job.dest);
+        }
+        self.links += 1;
+        if self.keep_entries {
+            self.linked.push(job.rel_path);
+        }
+    }
+
+    /// Records a destination directory the run created.
+    fn created_dir(&mut self, dir: PathBuf) {
+        self.made_dirs.push(dir.clone());
+        self.record(Change::CreatedDir(dir));
+    }
+
+    /// Returns the part of the linker that creates entries, which the worker
+    /// threads of a parallel run share.
+    fn creator(&self) -> Creator<'_> {
+        Creator {
+            opts: self.opts,
+            dest_root: self.dest_root.as_deref(),
+            copy: self.copy,
+            retry_stale: self.retry_stale,
+            preserve_context: self.preserve_context,
+        }
+    }
+
+    /// Creates the entries of a run with `workers` threads.
+    ///
+    /// Jobs are prepared on the calling thread in discovery order, so every
+    /// directory exists before the entries below it are handed to a worker;
+    /// only the links and copies themselves are created concurrently. Events
+    /// are passed to `on_link` on the calling thread as workers report them.
+    ///
+    /// # Arguments
+    ///
+    /// * `sources` - The expanded source paths to walk
+    /// * `dest_path` - The destination path the links are created under
+    /// * `workers` - The number of worker threads
+    /// * `on_link` - Called with the job of every created link
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<Vec<Skipped>>` - The entries discovery left out, if
+    ///   `explain` is set, or the first error of any stage
+    fn link_parallel<F>(
+        &mut self,
+        sources: &[PathBuf],
+        dest_path: &Path,
+        workers: usize,
+        on_link: &mut F,
+    ) -> io::Result<LeftOut>
+    where
+        F: Observer,
+    {
+        let opts = self.opts;
+        let dest_root = self.dest_root.clone();
+        let creator = Creator {
+            opts,
+            dest_root: dest_root.as_deref(),
+            copy: self.copy,
+            retry_stale: self.retry_stale,
+            preserve_context: self.preserve_context,
+        };
+        let (tx, rx) = sync_channel::<io::Result<LinkJob>>(pipeline::CHANNEL_CAPACITY);
+        let (work_tx, work_rx) = sync_channel::<LinkJob>(pipeline::CHANNEL_CAPACITY);
+        // Shared by the workers alone, so once they have all ended, however
+        // they ended, sending more work fails instead of blocking.
+        let work_rx = Arc::new(Mutex::new(work_rx));
+        let (done_tx, done_rx) = channel::<Outcome>();
+        let failed = AtomicBool::new(false);
+
+        thread::scope(|scope| {
+            let discovery = scope.spawn(move || pipeline::discover(sources, dest_path, opts, &tx));
+            let mut pool = Vec::new();
+            for _ in 0..workers {
+                let done_tx = done_tx.clone();
+                let (work_rx, failed) = (Arc::clone(&work_rx), &failed);
+                pool.push(scope.spawn(move || creator.work(&work_rx, &done_tx, failed)));
+            }
+            drop((done_tx, work_rx));
+
+            let mut result = Ok(());
+            for job in rx {
+                result = job.and_then(|job| {
+                    let (source, dest) = (job.source.clone(), job.dest.clone());
+                    match self.prepare(job, on_link) {
+                        // Workers only stop early if they could not be set up
+                        // or panicked, which is reported when they are joined.
+                        Ok(Some(job)) => {
+                            if work_tx.send(job).is_err() {
+                                failed.store(true, Ordering::Relaxed);
+                            }
+                            Ok(())
+                        }
+                        Ok(None) => Ok(()),
+                        Err(e) => self.failed(source, dest, e, on_link),
+                    }
+                });
+                // Finished work is passed on as it comes in to keep progress current.
+                for outcome in done_rx.try_iter() {
+                    result = self.settle(result, outcome, on_link);
+                }
+                if result.is_err() {
+                    failed.store(true, Ordering::Relaxed);
+                }
+                if failed.load(Ordering::Relaxed) {
+                    break;
+                }
+            }
+            drop(work_tx);
+            for outcome in done_rx {
+                result = self.settle(result, outcome, on_link);
+                if result.is_err() {
+                    failed.store(true, Ordering::Relaxed);
+                }
+            }
+            for worker in pool {
+                worker.join().unwrap_or_else(|e| panic::resume_unwind(e))?;
+            }
+            result?;
+            Ok(discovery.join().unwrap_or_else(|e| panic::resume_unwind(e)))
+        })
+    }
+
+    /// Passes an outcome reported by a worker to `on_link`.
+    fn deliver<F>(&mut self, outcome: Outcome, on_link: &mut F) -> io::Result<()>
+    where
+        F: Observer,
+    {
+        match outcome {
+            Outcome::Copying(job, progress) => on_link.copying(&job, &progress),
+            Outcome::Created(job, creation) => self.created(job, creation, on_link),
+            Outcome::Failed(job, e) => return self.failed(job.source, job.dest, e, on_link),
+        }
+        Ok(())
+    }
+
+    /// Passes an outcome on like `deliver` while the run goes on. Once it has
+    /// failed, only entries that were created are recorded, so they are
+    /// counted, journaled, and can be undone.
+    fn settle<F>(
+        &mut self,
+        result: io::Result<()>,
+        outcome: Outcome,
+        on_link: &mut F,
+    ) -> io::Result<()>
+    where
+        F: Observer,
+    {
+        match (result, outcome) {
+            (Ok(()), outcome) => self.deliver(outcome, on_link),
+            (Err(e), Outcome::Created(job, creation)) => {
+                self.created(job, creation, on_link);
+                Err(e)
+            }
+            (Err(e), _) => Err(e),
+        }
+    }
+
+    /// Links every entry of the run's sources, followed by the literal
+    /// sources that do not exist.
+    fn link_all<F>(
+        &mut self,
+        sources: &[PathBuf],
+        dest_path: &Path,
+        missing: Vec<PathBuf>,
+        on_link: &mut F,
+    ) -> io::Result<()>
+    where
+        F: Observer,
+    {
+        let opts = self.opts;
+        // Each root is walked on its own so the observer knows which one the
+        // entries that follow belong to.
+        for root in sources.chunks(1) {
+            on_link.walk_started(&root[0]);
+            let left_out = if opts.jobs > 1 && !opts.dry_run {
+                self.link_parallel(root, dest_path, opts.jobs, on_link)?
+            } else {
+                pipeline::run(root, dest_path, opts, |job| self.link(job, on_link))?
+            };
+            for skipped in &left_out.skipped {
+                on_link.skipped(skipped);
+            }
+            if self.keep_entries {
+                self.skipped.extend(left_out.skipped);
+            }
+            self.filtered += left_out.filtered;
+        }
+        self.skipped.sort_by(|a, b| a.path.cmp(&b.path));
+        for source in missing {
+            let (dest, rel_path) = match source.file_name() {
+                Some(name) if pipeline::links_into(dest_path, opts) => {
+                    (dest_path.join(name), PathBuf::from(name))
+                }
+                _ => (dest_path.to_path_buf(), PathBuf::new()),
+            };
+            let job = LinkJob {
+                source,
+                dest,
+                rel_path,
+                kind: EntryKind::File,
+            };
+            self.link(job, on_link)?;
+        }
+        Ok(())
+    }
+
+    /// Removes the entries of each mirrored directory that have no
+    /// counterpart in its source, or plans their removal in a dry run.
+    ///
+    /// # Arguments
+    ///
+    /// * `mirrors` - Each source directory with the destination directory it is linked into
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<()>` - Success if every extraneous entry was removed
+    fn delete_extraneous(&mut self, mirrors: &[(PathBuf, PathBuf)]) -> io::Result<()> {
+        let keep: HashSet<PathBuf> = self.backups.iter().map(|(_, b)| b.clone()).collect();
+        for (source, mirror) in mirrors {
+            for path in mirror::extraneous(source, mirror, self.opts.mark.as_deref(), &keep)? {
+                if self.opts.dry_run {
+                    self.planned.push(Operation::Remove(path));
+                    continue;
+                }
+                if fs::symlink_metadata(&path)?.is_dir() {
+                    fs::remove_dir(&path)?;
+                } else {
+                    platform::remove_link(&path)?;
+                }
+                self.record(Change::Removed(path.clone()));
+                self.deleted.push(path);
+            }
+        }
+        Ok(())
+    }
+
+    /// Records what `link` would do for a job, without changing anything.
+    ///
+    /// The same conflicts are reported: an existing destination is an error
+    /// unless `force` or `backup` would replace it. Nobody is asked with
+    /// `interactive`; such a destination is planned as overwritten.
+    fn plan(&mut self, job: LinkJob) -> io::Result<()> {
+        let opts = self.opts;
+        match job.kind {
+            EntryKind::Whiteout => {
+                if fs::symlink_metadata(&job.dest).is_ok() {
+                    self.planned.push(Operation::Remove(job.dest));
+                }
+                return Ok(());
+            }
+            EntryKind::Opaque => {
+                if job.dest.is_dir() {
+                    self.planned.push(Operation::Clear(job.dest));
+                }
+                return Ok(());
+            }
+            _ => {}
+        }
+
+        if let Some(parent) = job.dest.parent() {
+            self.plan_dirs(parent);
+        }
+        if job.kind == EntryKind::Dir && opts.archive {
+            self.plan_dirs(&job.dest);
+            return Ok(());
+        }
+
+        let crosses = self.creator().crosses_devices(&job);
+        if crosses {
+            self.fell_back(&job, opts.fallback);
+            if opts.fallback == FallbackPolicy::Skip {
+                return Ok(());
+            }
+        }
+
+        if job.dest.exists() {
+            if opts.skip_existing {
+                self.skip(&job.source, SkipReason::Existing);
+                return Ok(());
+            }
+            if opts.update && !source_is_newer(&job) {
+                self.skip(&job.source, SkipReason::NotNewer);
+                return Ok(());
+            }
+            if job.kind == EntryKind::Dir && opts.symbolic {
+                return Err(FlnkError::Exists {
+                    op: Op::Symlink,
+                    source: job.source,
+                    dest: job.dest,
+                }
+                .into());
+            }
+            if opts.backup
+                && let Some(generation) = &self.generation
+            {
+                let backup = generation.planned(&job.dest);
+                self.planned
+                    .push(Operation::Backup(job.dest.clone(), backup));
+            } else if opts.backup {
+                let (backup, _) = backup_path(&job.dest, &opts.backup_suffix, opts.backup_control);
+                self.planned
+                    .push(Operation::Backup(job.dest.clone(), backup));
+            } else if opts.force || opts.interactive {
+                self.planned.push(Operation::Overwrite(job.dest.clone()));
+            } else {
+                return Err(FlnkError::Exists {
+                    op: self.creator().op(&job),
+                    source: job.source,
+                    dest: job.dest,
+                }
+                .into());
+            }
+        }
+
+        if job.kind == EntryKind::Dir {
+            // A linked directory exists for the entries planned below it.
+            self.planned_dirs.insert(job.dest.clone());
+        }
+        let clones = if crosses {
+            opts.fallback == FallbackPolicy::Reflink
+        } else {
+            opts.reflink && !opts.symbolic && job.kind == EntryKind::File
+        };
+        self.planned.push(
+            if self.copy || crosses && opts.fallback == FallbackPolicy::Copy {
+                Operation::Copy(job.source, job.dest)
+            } else if clones {
+                Operation::Clone(job.source, job.dest)
+            } else {
+                Operation::Link(job.source, job.dest)
+            },
+        );
+        Ok(())
+    }
+
+    /// Plans the creation of `dir` and whichever of its ancestors are
+    /// missing and not already planned, outermost first.
+    fn plan_dirs(&mut self, dir: &Path) {
+        let mut missing: Vec<&Path> = dir
+            .ancestors()
+            .take_while(|d| !d.as_os_str().is_empty() && !d.is_dir())
+            .take_while(|d| !self.planned_dirs.contains(*d))
+            .collect();
+        missing.reverse();
+        for created in missing {
+            self.planned_dirs.insert(created.to_path_buf());
+            self.planned
+                .push(Operation::CreateDir(created.to_path_buf()));
+        }
+    }
+
+    /// Records a change made to the filesystem, if the run writes an undo
+    /// manifest or is rolled back on failure.
+    fn record(&mut self, change: Change) {
+        if self.opts.undo_manifest.is_some() || self.opts.atomic {
+            self.changes.push(change);
+        }
+    }
+
+    /// Writes the changes made so far to the run's undo manifest, if it has
+    /// one and anything was changed, then deletes the oldest manifests named
+    /// by a run ID beyond `manifest::KEEP_RUNS`.
+    fn write_manifest(&self) -> io::Result<()> {
+        let Some(path) = &self.opts.undo_manifest else {
+            return Ok(());
+        };
+        if self.changes.is_empty() {
+            return Ok(());
+        }
+        let manifest = Manifest {
+            id: path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            time: UtcTime::now().rfc3339(),
+            dest: self.dest_root.clone().unwrap_or_default(),
+            changes: self.changes.clone(),
+        };
+        manifest.write(path)?;
+        // The run itself succeeded, so a manifest that cannot be pruned is
+        // left for the next run.
+        if let Some(dir) = path.parent()
+            && let Err(e) = manifest::prune(dir, manifest::KEEP_RUNS)
+        {
+            log::warn!("cannot prune old manifests in {}: {}", dir.display(), e);
+        }
+        Ok(())
+    }
+
+    /// Records a file whose hard link would cross devices and was replaced,
+    /// or skipped, according to the fallback policy.
+    fn fell_back(&mut self, job: &LinkJob, policy: FallbackPolicy) {
+        self.crossed += 1;
+        if policy == FallbackPolicy::Skip {
+            self.skip(&job.source, SkipReason::CrossDevice);
+        }
+    }
+
+    /// Logs a source entry that is not linked and records it, if the run
+    /// explains its skips. The observer is told by `report_skips`.
+    fn skip(&mut self, source: &Path, reason: SkipReason) {
+        let skipped = Skipped::new(source, reason);
+        log::debug!("skipped {}", skipped);
+        // Kept destinations are part of the outcome, so they are always recorded.
+        if self.keep_entries && (self.opts.explain || skipped.reason == SkipReason::Existing) {
+            self.skipped.push(skipped.clone());
+        }
+        self.unreported.push(skipped);
+    }
+
+    /// Tells the observer about the entries skipped since it was last told.
+    fn report_skips<F>(&mut self, on_link: &mut F)
+    where
+        F: Observer,
+    {
+        for skipped in self.unreported.drain(..) {
+            on_link.skipped(&skipped);
+        }
+    }
+
+    /// Finishes the run, returning the relative paths of every created link
+    /// and the warnings met along the way.
+    pub(crate) fn finish(mut self) -> io::Result<LinkReport> {
+        if self.crossed > 0 {
+            let dest = self.dest_root.clone().unwrap_or_default();
+            let instead = match self.opts.fallback {
+                FallbackPolicy::Symlink => "symlinked",
+                FallbackPolicy::Copy => "copied",
+                FallbackPolicy::Reflink => "cloned",
+                FallbackPolicy::Fail | FallbackPolicy::Skip => "skipped",
+            };
+            self.warnings.push(Warning::new(
+                WarningKind::FallbackUsed,
+                &dest,
+                format!(
+                    "{} file{} cannot be hard linked across devices into {}; {} {} instead",
+                    self.crossed,
+                    if self.crossed == 1 { "" } else { "s" },
+                    dest.display(),
+                    if self.crossed == 1 {
+                        "it is"
+                    } else {
+                        "they are"
+                    },
+                    instead
+                ),
+            ));
+        }
+        if self.opts.dry_run {
+            return Ok(LinkReport {
+                warnings: self.warnings,
+                skipped: self.skipped,
+                planned: self.planned,
+                failures: self.failures,
+                filtered: self.filtered,
+                elapsed: self.started.elapsed(),
+                ..Default::default()
+            });
+        }
+        // Directory timestamps change as entries are linked into them, so they
+        // are restored last, deepest first.
+        for (source, dest) in self.created_dirs.iter().rev() {
+            archive::preserve_metadata(source, dest)?;
+            owner::apply(dest, self.opts.owner, self.opts.group)?;
+            if let Some(spec) = &self.opts.chmod {
+                spec.apply(dest)?;
+            }
+        }
+        if let Some(manifest) = &self.opts.checksum_manifest {
+            write_checksums(manifest, &self.checksums)?;
+        }
+        if let (Some(generation), Some(keep)) = (&self.generation, self.opts.backup_generations) {
+            backups::prune(generation.base(), keep)?;
+        }
+        // The run succeeded, so the entries it replaced are not coming back.
+        for aside in &self.replaced {
+            platform::remove_link(aside)?;
+        }
+        let replaced = mem::take(&mut self.replaced);
+        for change in &mut self.changes {
+            if let Change::BackedUp(dest, aside) = change
+                && replaced.contains(aside)
+            {
+                *change = Change::Removed(dest.clone());
+            }
+        }
+        self.write_manifest()?;
+        Ok(LinkReport {
+            linked: self.linked,
+            warnings: self.warnings,
+            skipped: self.skipped,
+            planned: Vec::new(),
+            backups: self.backups,
+            deleted: self.deleted,
+            created_dirs: self.made_dirs,
+            bytes: self.bytes,
+            shared_bytes: self.shared_bytes,
+            filtered: self.filtered,
+            conflicts: self.conflicts,
+            elapsed: self.started.elapsed(),
+            failures: self.failures,
+        })
+    }
+}
+
+/// What a worker thread of a parallel run reports back to the linker.
+enum Outcome {
+    /// A large file being copied made progress
+    Copying(LinkJob, CopyProgress),
+    /// The entry was created, or skipped by the fallback policy
+    Created(LinkJob, Creation),
+    /// The entry could not be created
+    Failed(LinkJob, io::Error),
+}
+
+/// What `Creator::create` did for a job.
+struct Creation {
+    /// The fallback policy applied because a hard link would have crossed devices
+    fallback: Option<FallbackPolicy>,
+    /// True if the entry is a copy or clone with its own inode instead of a link
+    copied: bool,
+    /// The hex digest of the linked file, if the run writes a checksum manifest
+    checksum: Option<String>,
+}
+
+/// Creates the link or copy for a prepared job, along with its context,
+/// ownership, mode, and mark.
+#[derive(Clone, Copy)]
+struct Creator<'a> {
+    opts: &'a LinkOptions,
+    dest_root: Option<&'a Path>,
+    copy: bool,
+    retry_stale: bool,
+    preserve_context: bool,
+}
+
+impl Creator<'_> {
+    /// Creates the entry for a job whose destination is free.
+    ///
+    /// A hard link that fails because it would cross devices is replaced
+    /// according to the fallback policy.
+    ///
+    /// # Arguments
+    ///
+    /// * `job` - The prepared job
+    /// * `on_progress` - Called as a large file is being copied
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<Creation>` - The fallback applied and the file's digest,
+    ///   or an error naming the job if the entry could not be created
+    fn create(&self, job: &LinkJob, on_progress: impl FnMut(CopyProgress)) -> io::Result<Creation> {
+        self.create_entry(job, on_progress).map_err(FlnkError::wrap(
+            self.op(job),
+            &job.source,
+            &job.dest,
+        ))
+    }
+
+    /// The operation that creates the entry for a job.
+    fn op(&self, job: &LinkJob) -> Op {
+        if self.copy {
+            Op::Copy
+        } else if self.opts.symbolic
+            || job.kind == EntryKind::Symlink && (self.opts.archive || self.opts.preserve_symlinks)
+        {
+            Op::Symlink
+        } else {
+            Op::Link
+        }
+    }
+
+    /// Describes the entry a job would create, for `status`.
+    fn expected(&self, job: &LinkJob) -> String {
+        let opts = self.opts;
+        if job.kind == EntryKind::Dir && !opts.symbolic {
+            return String::from("a directory");
+        }
+        if self.op(job) != Op::Symlink {
+            let what = if self.op(job) == Op::Link && !opts.reflink {
+                "a hard link to"
+            } else {
+                "a copy of"
+            };
+            return format!("{} {}", what, job.source.display());
+        }
+        let target = if job.kind == EntryKind::Symlink && opts.archive {
+            fs::read_link(&job.source)
+        } else if job.kind == EntryKind::Symlink && opts.preserve_symlinks {
+            archive::equivalent_target(&job.source, &job.dest, &source_root(job))
+        } else {
+            let base = self.relative_base(&job.dest);
+            symlink_target(&job.source, &job.dest, opts, base.as_deref())
+        };
+        match target {
+            Ok(target) => format!("a symlink to {}", target.display()),
+            Err(_) => String::from("a symlink"),
+        }
+    }
+
+    /// Does the work of `create`.
+    fn create_entry(
+        &self,
+        job: &LinkJob,
+        mut on_progress: impl FnMut(CopyProgress),
+    ) -> io::Result<Creation> {
+        let opts = self.opts;
+        let mut fallback = None;
+        if self.copy {
+            self.retry(|| copy_file(&job.source, &job.dest, &mut on_progress))?;
+        } else if job.kind == EntryKind::Symlink && opts.archive {
+            archive::copy_symlink(&job.source, &job.dest)?;
+            archive::preserve_metadata(&job.source, &job.dest)?;
+        } else if job.kind == EntryKind::Symlink && opts.preserve_symlinks {
+            let target = archive::equivalent_target(&job.source, &job.dest, &source_root(job))?;
+            self.retry(|| platform::symlink(&target, &job.dest))?;
+        } else {
+            let base = self.relative_base(&job.dest);
+            match self.retry(|| make_link(&job.source, &job.dest, opts, base.as_deref())) {
+                Err(e) if e.kind() == io::ErrorKind::CrossesDevices && self.falls_back(job) => {
+                    self.fall_back(job, base.as_deref(), &mut on_progress)?;
+                    fallback = Some(opts.fallback);
+                }
+                result => {
+                    result?;
+                }
+            }
+        }
+        if fallback == Some(FallbackPolicy::Skip) {
+            return Ok(Creation {
+                fallback,
+                copied: false,
+                checksum: None,
+            });
+        }
+
+        let copied = match fallback {
+            Some(policy) => matches!(policy, FallbackPolicy::Copy | FallbackPolicy::Reflink),
+            None => self.copy || opts.reflink && !opts.symbolic && job.kind == EntryKind::File,
+        };
+        // A hard link shares its inode, and so its context, ownership, and
+        // attributes, with the source, which must not be marked.
+        let own_inode =
+            copied || fallback.is_some() || opts.symbolic || job.kind == EntryKind::Symlink;
+        if self.preserve_context && own_inode {
+            selinux::copy_context(&job.source, &job.dest)?;
+        }
+        if own_inode {
+            owner::apply(&job.dest, opts.owner, opts.group)?;
+        }
+        if copied && let Some(spec) = &opts.chmod {
+            spec.apply(&job.dest)?;
+        }
+        if own_inode && let Some(label) = &opts.mark {
+            xattr::mark_managed(&job.dest, label)?;
+        }
+        let checksum =
+            if opts.checksum_manifest.is_some() && job.kind == EntryKind::File && job.dest.exists()
+            {
+                Some(sha256::hash_file(&job.dest)?)
+            } else {
+                None
+            };
+        Ok(Creation {
+            fallback,
+            copied,
+            checksum,
+        })
+    }
+
+    /// Returns true if a hard link for the job that cannot cross devices is
+    /// replaced according to the fallback policy instead of failing the run.
+    fn falls_back(&self, job: &LinkJob) -> bool {
+        let opts = self.opts;
+        opts.fallback != FallbackPolicy::Fail
+            && !opts.symbolic
+            && !self.copy
+            && job.kind == EntryKind::File
+    }
+
+    /// Predicts whether the fallback policy will replace the job's hard link
+    /// because the source is on another device than the destination.
+    fn crosses_devices(&self, job: &LinkJob) -> bool {
+        if !self.falls_back(job) {
+            return false;
+        }
+        let device = |path: &Path| fs::metadata(path).ok().and_then(|m| platform::device(&m));
+        let dir = probe::existing_ancestor(job.dest.parent().unwrap_or(Path::new("")));
+        match (device(&job.source), dir.and_then(device)) {
+            (Some(source), Some(dest)) => source != dest,
+            _ => false,
+        }
+    }
+
+    /// Creates what the fallback policy puts in place of a hard link that
+    /// cannot cross devices: a symlink, a copy, or a copy-on-write clone.
+    fn fall_back(
+        &self,
+        job: &LinkJob,
+        base: Option<&Path>,
+        on_progress: impl FnMut(CopyProgress),
+    ) -> io::Result<()> {
+        match self.opts.fallback {
+            FallbackPolicy::Symlink => {
+                let target = symlink_target(&job.source, &job.dest, self.opts, base)?;
+                platform::symlink(&target, &job.dest)
+            }
+            FallbackPolicy::Copy => copy_file(&job.source, &job.dest, on_progress),
+            FallbackPolicy::Reflink => clone_file(&job.source, &job.dest),
+            FallbackPolicy::Fail | FallbackPolicy::Skip => Ok(()),
+        }
+    }
+
+    /// Decides whether a job's destination already is the entry the job would
+    /// create: a hard link to the source's inode, or a symlink holding the
+    /// target the run would write. Copies are never taken as links.
+    ///
+    /// # Arguments
+    ///
+    /// * `job` - The job to check
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - True if there is nothing left to do for the job
+    fn is_linked(&self, job: &LinkJob) -> bool {
+        let opts = self.opts;
+        let Ok(dest) = fs::symlink_metadata(&job.dest) else {
+            return false;
+        };
+        if self.copy || opts.reflink && job.kind == EntryKind::File {
+            false
+        } else if job.kind == EntryKind::Symlink && opts.archive {
+            dest.file_type().is_symlink()
+                && fs::read_link(&job.dest).ok() == fs::read_link(&job.source).ok()
+        } else if job.kind == EntryKind::Symlink && opts.preserve_symlinks {
+            dest.file_type().is_symlink()
+                && fs::read_link(&job.dest).ok()
+                    == archive::equivalent_target(&job.source, &job.dest, &source_root(job)).ok()
+        } else if opts.symbolic {
+            let base = self.relative_base(&job.dest);
+            dest.file_type().is_symlink()
+                && match (
+                    fs::read_link(&job.dest),
+                    symlink_target(&job.source, &job.dest, opts, base.as_deref()),
+                ) {
+                    (Ok(target), Ok(expected)) => target == expected,
+                    _ => false,
+                }
+        } else if job.kind == EntryKind::Dir {
+            false
+        } else {
+            let source = fs::symlink_metadata(&job.source).ok();
+            let source = source.as_ref().and_then(platform::identity);
+            source.is_some() && source == platform::identity(&dest)
+        }
+    }
+
+    /// Runs a worker thread of a parallel run: creates the entry for each job
+    /// received until the linker hangs up, reporting every outcome.
+    ///
+    /// After any stage fails, the remaining jobs are drained without being
+    /// created; a failure of its own only stops the run if the error policy
+    /// is to abort.
+    fn work(
+        self,
+        jobs: &Mutex<Receiver<LinkJob>>,
+        done: &Sender<Outcome>,
+        failed: &AtomicBool,
+    ) -> io::Result<()> {
+        prepare_thread(self.opts)?;
+        loop {
+            let Ok(job) = jobs.lock().unwrap_or_else(PoisonError::into_inner).recv() else {
+                return Ok(());
+            };
+            if failed.load(Ordering::Relaxed) {
+                continue;
+            }
+            let outcome = match self.create(&job, |progress| {
+                let _ = done.send(Outcome::Copying(job.clone(), progress));
+            }) {
+                Ok(creation) => Outcome::Created(job, creation),
+                Err(e) => {
+                    if self.opts.on_error == ErrorPolicy::Abort {
+                        failed.store(true, Ordering::Relaxed);
+                    }
+                    Outcome::Failed(job, e)
+                }
+            };
+            let _ = done.send(outcome);
+        }
+    }
+
+    /// Returns the directory a relative symlink at `dest` is computed against
+    /// under `relative_to`: the link's directory, moved from below the run's
+    /// destination to below `relative_to`.
+    fn relative_base(&self, dest: &Path) -> Option<PathBuf> {
+        let base = self.opts.relative_to.as_ref()?;
+        let rel = dest
+            .parent()
+            .zip(self.dest_root)
+            .and_then(|(parent, root)| parent.strip_prefix(root).ok())
+            .unwrap_or(Path::new(""));
+        Some(base.join(rel))
+    }
+
+    /// Runs a filesystem operation, retrying stale file handles on network shares.
+    fn retry<T>(&self, mut op: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+        if self.retry_stale {
+            netfs::retry_stale(op)
+        } else {
+            op()
+        }
+    }
+}
+
+/// Prepares the calling thread for creating links with `opts`.
+///
+/// # Arguments
+///
+/// * `opts` - The options controlling the link behavior
+///
+/// # Returns
+///
+/// * `io::Result<ContextMode>` - How SELinux contexts are chosen on this
+///   system, or an error if the thread's creation context cannot be reset
+fn prepare_thread(opts: &LinkOptions) -> io::Result<ContextMode> {
+    let context = if selinux::is_enabled() {
+        opts.selinux
+    } else {
+        ContextMode::Inherit
+    };
+    if context == ContextMode::Default {
+        // The creation context is per thread, so every thread creating links clears its own.
+        selinux::reset_create_context()?;
+    }
+    Ok(context)
 }
@@ -1,18 +1,167 @@
+use crate::interrupt::CancelToken;
+use crate::json::Json;
+use crate::link::backups::BackupControl;
+use crate::link::chmod::ChmodSpec;
+use crate::link::collision::OnCollision;
+use crate::link::failure::ErrorPolicy;
+use crate::link::filter::{Filter, GITIGNORE_FILE, IGNORE_FILE};
+use crate::link::home::HomeStyle;
+use crate::link::lexical::SourcePaths;
+use crate::link::netfs::NetworkFsMode;
+use crate::link::overlay::OverlayMode;
+use crate::link::owner;
+use crate::link::probe::FallbackPolicy;
+use crate::link::selinux::ContextMode;
+use crate::link::template::Template;
+use crate::link::transform::Transform;
+use std::io;
+use std::path::PathBuf;
+
 /// A struct containing options for controlling the linking behavior.
+///
+/// New options are added over time, so outside this crate the struct is
+/// built with `LinkOptions::builder()` or changed field by field from
+/// `LinkOptions::default()`.
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub struct LinkOptions {
     /// If true, creates symbolic links instead of hard links
     pub symbolic: bool,
     /// If true and creating symbolic links, creates relative symbolic links
     pub relative: bool,
+    /// When set, relative symlink targets are computed as if the destination
+    /// were this directory, for trees that will be mounted or served elsewhere
+    pub relative_to: Option<PathBuf>,
+    /// If true, relative symlink targets are computed from the paths as given,
+    /// without resolving symlinks
+    pub lexical: bool,
+    /// When set, absolute symlink targets under the home directory are
+    /// written as `~/...` or `$HOME/...`
+    pub home_style: Option<HomeStyle>,
+    /// When set, symlink targets are made from the source path as given or
+    /// from its resolved path; by default relative targets are resolved and
+    /// absolute ones are taken as given
+    pub source_paths: Option<SourcePaths>,
+    /// If true, the destination is the link itself, like `ln -T`: a source
+    /// is never placed inside it under its own name, even if it is a directory
+    pub no_target_directory: bool,
+    /// If true, a destination that is a symlink is the link itself, like
+    /// `ln -n`, instead of being followed into the directory it points to;
+    /// with `symbolic`, a source directory then replaces it as a whole
+    pub no_dereference: bool,
     /// If true, removes existing destination files
     pub force: bool,
+    /// If true, an existing destination file is kept and its source skipped
+    pub skip_existing: bool,
+    /// If true, an existing destination file is only replaced when its
+    /// source was modified more recently; otherwise the source is skipped
+    pub update: bool,
+    /// If true, asks before replacing each existing destination file
+    pub interactive: bool,
     /// If true, creates backups of existing files
     pub backup: bool,
     /// The suffix to use for backup files
     pub backup_suffix: String,
+    /// Whether backups are named with the suffix or numbered
+    pub backup_control: BackupControl,
+    /// When set, backups are moved into a timestamped generation under
+    /// `.flnk-backups` in the destination, keeping only this many generations
+    pub backup_generations: Option<usize>,
+    /// When set, backups are moved into this directory at their path
+    /// relative to the destination instead of being renamed in place
+    pub backup_dir: Option<PathBuf>,
     /// When true and creating symbolic links, directories will not be symbolically linked
     pub symlink_files_only: bool,
+    /// If true, mimics `cp -al`: directories are recreated with their metadata and symlinks are reproduced as symlinks
+    pub archive: bool,
+    /// If true, symlinks in the source are recreated as symlinks to the same
+    /// place instead of being skipped or linked to
+    pub preserve_symlinks: bool,
+    /// If true, files are given copy-on-write clones instead of hard links,
+    /// failing on filesystems that cannot clone
+    pub reflink: bool,
+    /// Include/exclude rules deciding which source entries are linked
+    pub filter: Filter,
+    /// Rules rewriting the destination path of each entry, relative to the
+    /// destination directory, applied in order
+    pub transform: Vec<Transform>,
+    /// When set, each file is linked at the path this template builds below
+    /// the destination directory, before any `transform`, and directories
+    /// are walked but not linked themselves
+    pub dest_template: Option<Template>,
+    /// If true, each file is linked directly in the destination directory,
+    /// whatever its depth in the source, and directories are walked but not
+    /// linked themselves
+    pub flatten: bool,
+    /// What happens when several files of a run map to the same destination
+    /// path, through several sources, `flatten`, `dest_template`, or
+    /// `transform`
+    pub on_collision: OnCollision,
+    /// If false, entries below a source whose names start with `.` are not
+    /// linked, and hidden directories are not descended into
+    pub hidden: bool,
+    /// When set, entries more than this many levels below a source are not
+    /// walked; 1 links only a directory's immediate contents
+    pub max_depth: Option<usize>,
+    /// Entries fewer than this many levels below a source are not linked,
+    /// though directories are still walked through
+    pub min_depth: usize,
+    /// If true, symlinks met while walking a source are followed, and
+    /// what they point to is linked in their place
+    pub follow_links: bool,
+    /// If true, directories on a different filesystem than their source are
+    /// not descended into, like `find -xdev`
+    pub same_file_system: bool,
+    /// If true, probes the destination filesystem before linking and fails early if it cannot hold the links
+    pub probe_filesystem: bool,
+    /// What to do when the probe finds a destination filesystem that can hold no links
+    pub fallback: FallbackPolicy,
+    /// How destinations on NFS and SMB shares are handled
+    pub network_fs: NetworkFsMode,
+    /// How overlayfs whiteouts and opaque-directory markers in the source are handled
+    pub overlay: OverlayMode,
+    /// When set, created links and directories are tagged with a `user.flnk.managed` xattr holding this run label
+    pub mark: Option<String>,
+    /// How the SELinux security context of created entries is chosen
+    pub selinux: ContextMode,
+    /// When set, a `sha256sum`-style line for every linked file is appended to this file
+    pub checksum_manifest: Option<PathBuf>,
+    /// When set, created symlinks, directories, and copied files are given this user id
+    pub owner: Option<u32>,
+    /// When set, created symlinks, directories, and copied files are given this group id
+    pub group: Option<u32>,
+    /// When set, created directories and copied files get their mode from this spec
+    pub chmod: Option<ChmodSpec>,
+    /// If true, every source entry that is not linked is recorded with the reason
+    pub explain: bool,
+    /// If true, nothing is changed; the operations the run would make are
+    /// returned instead
+    pub dry_run: bool,
+    /// The number of threads creating links; directories are still created
+    /// before the entries inside them
+    pub jobs: usize,
+    /// When set, every change the run makes is recorded in this manifest so
+    /// it can be undone. If it is named by `manifest::new_id`, the oldest such
+    /// manifests next to it beyond `manifest::KEEP_RUNS` are deleted
+    pub undo_manifest: Option<PathBuf>,
+    /// If true, destination entries with no counterpart in the source are
+    /// removed after linking, making the destination a mirror
+    pub delete: bool,
+    /// What happens when an entry cannot be linked: end the run, or record
+    /// the failure and go on
+    pub on_error: ErrorPolicy,
+    /// If true, a run that fails partway removes the links and directories it
+    /// created and puts back the entries it replaced, leaving the destination
+    /// as it was
+    pub atomic: bool,
+    /// When set, every finished entry is appended to this journal, so an
+    /// interrupted run can be resumed
+    pub journal: Option<PathBuf>,
+    /// If true, the entries the journal lists as finished are skipped
+    pub resume: bool,
+    /// When set, the run stops before its next entry once the token is
+    /// cancelled, as if interrupted
+    pub cancel: Option<CancelToken>,
 }
 
 /// Default implementation for LinkOptions
@@ -21,10 +170,694 @@ impl Default for LinkOptions {
         Self {
             symbolic: false,
             relative: false,
+            relative_to: None,
+            lexical: false,
+            home_style: None,
+            source_paths: None,
+            no_target_directory: false,
+            no_dereference: false,
             force: false,
+            skip_existing: false,
+            update: false,
+            interactive: false,
             backup: false,
             backup_suffix: String::from("~"),
+            backup_control: BackupControl::Existing,
+            backup_generations: None,
+            backup_dir: None,
             symlink_files_only: false,
+            archive: false,
+            preserve_symlinks: false,
+            reflink: false,
+            filter: Filter::default(),
+            transform: Vec::new(),
+            dest_template: None,
+            flatten: false,
+            on_collision: OnCollision::Error,
+            hidden: true,
+            max_depth: None,
+            min_depth: 0,
+            follow_links: false,
+            same_file_system: false,
+            probe_filesystem: true,
+            fallback: FallbackPolicy::Fail,
+            network_fs: NetworkFsMode::Auto,
+            overlay: OverlayMode::Off,
+            mark: None,
+            selinux: ContextMode::Inherit,
+            checksum_manifest: None,
+            owner: None,
+            group: None,
+            chmod: None,
+            explain: false,
+            dry_run: false,
+            jobs: 1,
+            undo_manifest: None,
+            delete: false,
+            on_error: ErrorPolicy::Abort,
+            atomic: false,
+            journal: None,
+            resume: false,
+            cancel: None,
         }
     }
 }
+
+impl LinkOptions {
+    /// Starts building options from the defaults, for callers outside this
+    /// crate, which cannot construct `LinkOptions` directly.
+    pub fn builder() -> LinkOptionsBuilder {
+        LinkOptionsBuilder::default()
+    }
+}
+
+/// Builds `LinkOptions` one setting at a time, checking on `build` that the
+/// settings fit together.
+#[derive(Debug, Clone, Default)]
+pub struct LinkOptionsBuilder {
+    opts: LinkOptions,
+}
+
+/// Starts from existing options, to change a few of them.
+impl From<LinkOptions> for LinkOptionsBuilder {
+    fn from(opts: LinkOptions) -> Self {
+        LinkOptionsBuilder { opts }
+    }
+}
+
+impl LinkOptionsBuilder {
+    /// Creates symbolic links instead of hard links.
+    pub fn symbolic(mut self, symbolic: bool) -> Self {
+        self.opts.symbolic = symbolic;
+        self
+    }
+
+    /// Makes symbolic links relative to the link's location; needs `symbolic`.
+    pub fn relative(mut self, relative: bool) -> Self {
+        self.opts.relative = relative;
+        self
+    }
+
+    /// Computes relative targets as if the destination were this directory; needs `symbolic`.
+    pub fn relative_to(mut self, relative_to: Option<PathBuf>) -> Self {
+        self.opts.relative_to = relative_to;
+        self
+    }
+
+    /// Computes relative targets from the paths as given, without resolving symlinks; needs `symbolic`.
+    pub fn lexical(mut self, lexical: bool) -> Self {
+        self.opts.lexical = lexical;
+        self
+    }
+
+    /// Writes absolute targets under the home directory as `~/...` or `$HOME/...`; needs `symbolic`.
+    pub fn home_style(mut self, home_style: Option<HomeStyle>) -> Self {
+        self.opts.home_style = home_style;
+        self
+    }
+
+    /// Makes symlink targets from the source paths as given or as resolved; needs `symbolic`.
+    pub fn source_paths(mut self, source_paths: Option<SourcePaths>) -> Self {
+        self.opts.source_paths = source_paths;
+        self
+    }
+
+    /// Treats the destination as the link itself, never as a directory to link into.
+    pub fn no_target_directory(mut self, no_target_directory: bool) -> Self {
+        self.opts.no_target_directory = no_target_directory;
+        self
+    }
+
+    /// Treats a destination symlink as the link itself instead of following it.
+    pub fn no_dereference(mut self, no_dereference: bool) -> Self {
+        self.opts.no_dereference = no_dereference;
+        self
+    }
+
+    /// Removes existing destination files.
+    pub fn force(mut self, force: bool) -> Self {
+        self.opts.force = force;
+        self
+    }
+
+    /// Keeps existing destination files, skipping their sources.
+    pub fn skip_existing(mut self, skip_existing: bool) -> Self {
+        self.opts.skip_existing = skip_existing;
+        self
+    }
+
+    /// Only replaces existing destination files older than their sources.
+    pub fn update(mut self, update: bool) -> Self {
+        self.opts.update = update;
+        self
+    }
+
+    /// Asks before replacing each existing destination file.
+    pub fn interactive(mut self, interactive: bool) -> Self {
+        self.opts.interactive = interactive;
+        self
+    }
+
+    /// Backs up existing destination files.
+    pub fn backup(mut self, backup: bool) -> Self {
+        self.opts.backup = backup;
+        self
+    }
+
+    /// Sets the suffix of backup files.
+    pub fn backup_suffix(mut self, backup_suffix: String) -> Self {
+        self.opts.backup_suffix = backup_suffix;
+        self
+    }
+
+    /// Sets whether backups are named with the suffix or numbered.
+    pub fn backup_control(mut self, backup_control: BackupControl) -> Self {
+        self.opts.backup_control = backup_control;
+        self
+    }
+
+    /// Keeps backups in this many timestamped generations under `.flnk-backups`.
+    pub fn backup_generations(mut self, backup_generations: Option<usize>) -> Self {
+        self.opts.backup_generations = backup_generations;
+        self
+    }
+
+    /// Moves backups into this directory instead of renaming them in place.
+    pub fn backup_dir(mut self, backup_dir: Option<PathBuf>) -> Self {
+        self.opts.backup_dir = backup_dir;
+        self
+    }
+
+    /// Links the files inside directories instead of the directories themselves.
+    pub fn symlink_files_only(mut self, symlink_files_only: bool) -> Self {
+        self.opts.symlink_files_only = symlink_files_only;
+        self
+    }
+
+    /// Recreates source symlinks as equivalent symlinks; cannot be combined with `follow_links`.
+    pub fn preserve_symlinks(mut self, preserve_symlinks: bool) -> Self {
+        self.opts.preserve_symlinks = preserve_symlinks;
+        self
+    }
+
+    /// Mimics `cp -al`; cannot be combined with `symbolic`.
+    pub fn archive(mut self, archive: bool) -> Self {
+        self.opts.archive = archive;
+        self
+    }
+
+    /// Makes copy-on-write clones instead of hard links; cannot be combined with `symbolic`.
+    pub fn reflink(mut self, reflink: bool) -> Self {
+        self.opts.reflink = reflink;
+        self
+    }
+
+    /// Sets the include/exclude rules deciding which source entries are linked.
+    pub fn filter(mut self, filter: Filter) -> Self {
+        self.opts.filter = filter;
+        self
+    }
+
+    /// Sets the rules rewriting the destination path of each entry.
+    pub fn transform(mut self, transform: Vec<Transform>) -> Self {
+        self.opts.transform = transform;
+        self
+    }
+
+    /// Lays out each linked file below the destination with this template.
+    pub fn dest_template(mut self, template: Option<Template>) -> Self {
+        self.opts.dest_template = template;
+        self
+    }
+
+    /// Sets whether every file is linked directly in the destination directory.
+    pub fn flatten(mut self, flatten: bool) -> Self {
+        self.opts.flatten = flatten;
+        self
+    }
+
+    /// Sets what happens when several files map to the same destination path.
+    pub fn on_collision(mut self, on_collision: OnCollision) -> Self {
+        self.opts.on_collision = on_collision;
+        self
+    }
+
+    /// Sets whether dotfiles and dot-directories below a source are linked.
+    pub fn hidden(mut self, hidden: bool) -> Self {
+        self.opts.hidden = hidden;
+        self
+    }
+
+    /// Limits how many levels below each source are walked.
+    pub fn max_depth(mut self, max_depth: Option<usize>) -> Self {
+        self.opts.max_depth = max_depth;
+        self
+    }
+
+    /// Leaves out entries fewer than this many levels below each source.
+    pub fn min_depth(mut self, min_depth: usize) -> Self {
+        self.opts.min_depth = min_depth;
+        self
+    }
+
+    /// Follows symlinks met while walking the sources.
+    pub fn follow_links(mut self, follow_links: bool) -> Self {
+        self.opts.follow_links = follow_links;
+        self
+    }
+
+    /// Keeps the walk of each source on the source's filesystem.
+    pub fn same_file_system(mut self, same_file_system: bool) -> Self {
+        self.opts.same_file_system = same_file_system;
+        self
+    }
+
+    /// Probes the destination filesystem before linking.
+    pub fn probe_filesystem(mut self, probe_filesystem: bool) -> Self {
+        self.opts.probe_filesystem = probe_filesystem;
+        self
+    }
+
+    /// Sets what happens where links cannot be created.
+    pub fn fallback(mut self, fallback: FallbackPolicy) -> Self {
+        self.opts.fallback = fallback;
+        self
+    }
+
+    /// Sets how NFS and SMB destinations are handled.
+    pub fn network_fs(mut self, network_fs: NetworkFsMode) -> Self {
+        self.opts.network_fs = network_fs;
+        self
+    }
+
+    /// Sets how overlayfs whiteouts and opaque directories are handled.
+    pub fn overlay(mut self, overlay: OverlayMode) -> Self {
+        self.opts.overlay = overlay;
+        self
+    }
+
+    /// Tags created entries with this run label.
+    pub fn mark(mut self, mark: Option<String>) -> Self {
+        self.opts.mark = mark;
+        self
+    }
+
+    /// Sets how the SELinux context of created entries is chosen.
+    pub fn selinux(mut self, selinux: ContextMode) -> Self {
+        self.opts.selinux = selinux;
+        self
+    }
+
+    /// Appends a checksum line for every linked file to this file.
+    pub fn checksum_manifest(mut self, checksum_manifest: Option<PathBuf>) -> Self {
+        self.opts.checksum_manifest = checksum_manifest;
+        self
+    }
+
+    /// Gives created entries this user id.
+    pub fn owner(mut self, owner: Option<u32>) -> Self {
+        self.opts.owner = owner;
+        self
+    }
+
+    /// Gives created entries this group id.
+    pub fn group(mut self, group: Option<u32>) -> Self {
+        self.opts.group = group;
+        self
+    }
+
+    /// Sets the mode of created directories and copied files from this spec.
+    pub fn chmod(mut self, chmod: Option<ChmodSpec>) -> Self {
+        self.opts.chmod = chmod;
+        self
+    }
+
+    /// Records every source entry that is not linked, with the reason.
+    pub fn explain(mut self, explain: bool) -> Self {
+        self.opts.explain = explain;
+        self
+    }
+
+    /// Plans the run without changing anything.
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.opts.dry_run = dry_run;
+        self
+    }
+
+    /// Sets the number of threads creating links; must be at least 1.
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.opts.jobs = jobs;
+        self
+    }
+
+    /// Records every change in this manifest so the run can be undone.
+    pub fn undo_manifest(mut self, undo_manifest: Option<PathBuf>) -> Self {
+        self.opts.undo_manifest = undo_manifest;
+        self
+    }
+
+    /// Removes destination entries with no counterpart in the source.
+    pub fn delete(mut self, delete: bool) -> Self {
+        self.opts.delete = delete;
+        self
+    }
+
+    /// Sets what happens when an entry cannot be linked.
+    pub fn on_error(mut self, on_error: ErrorPolicy) -> Self {
+        self.opts.on_error = on_error;
+        self
+    }
+
+    /// Rolls back every change of a run that fails partway.
+    pub fn atomic(mut self, atomic: bool) -> Self {
+        self.opts.atomic = atomic;
+        self
+    }
+
+    /// Appends every finished entry to this journal.
+    pub fn journal(mut self, journal: Option<PathBuf>) -> Self {
+        self.opts.journal = journal;
+        self
+    }
+
+    /// Skips the entries the journal lists as finished; needs `journal`.
+    pub fn resume(mut self, resume: bool) -> Self {
+        self.opts.resume = resume;
+        self
+    }
+
+    /// Stops the run before its next entry once `cancel` is cancelled.
+    pub fn cancel(mut self, cancel: Option<CancelToken>) -> Self {
+        self.opts.cancel = cancel;
+        self
+    }
+
+    /// Checks that the settings fit together and returns the options.
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<LinkOptions>` - The options, or an `InvalidInput` error
+    ///   naming the settings that conflict
+    pub fn build(self) -> io::Result<LinkOptions> {
+        let opts = self.opts;
+        let problem = if !opts.symbolic && opts.relative {
+            Some("relative links need symbolic")
+        } else if !opts.symbolic && (opts.lexical || opts.relative_to.is_some()) {
+            Some("lexical and relative_to need symbolic")
+        } else if !opts.symbolic && opts.home_style.is_some() {
+            Some("home_style needs symbolic")
+        } else if !opts.symbolic && opts.source_paths.is_some() {
+            Some("source_paths needs symbolic")
+        } else if opts.lexical && opts.source_paths == Some(SourcePaths::Physical) {
+            Some("lexical cannot be combined with physical source_paths")
+        } else if opts.home_style.is_some()
+            && (opts.relative || opts.lexical || opts.relative_to.is_some())
+        {
+            Some("home_style cannot be combined with relative, lexical, or relative_to")
+        } else if opts.symbolic && opts.archive {
+            Some("archive cannot be combined with symbolic")
+        } else if opts.symbolic && opts.reflink {
+            Some("reflink cannot be combined with symbolic")
+        } else if opts.backup_dir.is_some() && opts.backup_generations.is_some() {
+            Some("backup_dir cannot be combined with backup_generations")
+        } else if opts.skip_existing && (opts.force || opts.backup || opts.interactive) {
+            Some("skip_existing cannot be combined with force, backup, or interactive")
+        } else if opts.skip_existing && opts.update {
+            Some("skip_existing cannot be combined with update")
+        } else if opts.preserve_symlinks && opts.follow_links {
+            Some("preserve_symlinks cannot be combined with follow_links")
+        } else if opts.max_depth.is_some_and(|max| opts.min_depth > max) {
+            Some("min_depth cannot be greater than max_depth")
+        } else if opts.atomic
+            && (opts.delete
+                || opts.on_error == ErrorPolicy::Skip
+                || opts.overlay == OverlayMode::Translate)
+        {
+            Some("atomic cannot be combined with delete, on_error skip, or overlay translate")
+        } else if !opts.transform.is_empty() && opts.delete {
+            Some("transform cannot be combined with delete")
+        } else if opts.dest_template.is_some() && (opts.delete || opts.archive) {
+            Some("dest_template cannot be combined with delete or archive")
+        } else if opts.flatten && (opts.dest_template.is_some() || opts.delete || opts.archive) {
+            Some("flatten cannot be combined with dest_template, delete, or archive")
+        } else if opts.resume && opts.journal.is_none() {
+            Some("resume needs journal")
+        } else if opts.jobs == 0 {
+            Some("jobs must be at least 1")
+        } else {
+            None
+        };
+        match problem {
+            Some(problem) => Err(io::Error::new(io::ErrorKind::InvalidInput, problem)),
+            None => Ok(opts),
+        }
+    }
+}
+
+/// Builds `LinkOptions` from an `options` object, as taken by `serve`
+/// requests, `batch` operations, and the config file.
+///
+/// # Arguments
+///
+/// * `value` - The options object, with the same names as the `LinkOptions` fields
+///
+/// # Returns
+///
+/// * `Result<LinkOptions, String>` - The options, or a description of the
+///   offending key or of the options that cannot be combined
+pub fn options_from_json(value: Option<&Json>) -> Result<LinkOptions, String> {
+    let mut opts = LinkOptions::default();
+    let mut respect_gitignore = false;
+    let pairs: &[(String, Json)] = match value {
+        None | Some(Json::Null) => &[],
+        Some(Json::Object(pairs)) => pairs,
+        Some(_) => return Err(String::from("options must be an object")),
+    };
+
+    for (key, value) in pairs {
+        let flag = || {
+            value
+                .as_bool()
+                .ok_or_else(|| format!("{} must be a boolean", key))
+        };
+        match key.as_str() {
+            "symbolic" => opts.symbolic = flag()?,
+            "relative" => opts.relative = flag()?,
+            "lexical" => opts.lexical = flag()?,
+            "force" => opts.force = flag()?,
+            "skip_existing" => opts.skip_existing = flag()?,
+            "update" => opts.update = flag()?,
+            "no_dereference" => opts.no_dereference = flag()?,
+            "backup" => opts.backup = flag()?,
+            "symlink_files_only" => opts.symlink_files_only = flag()?,
+            "archive" => opts.archive = flag()?,
+            "preserve_symlinks" => opts.preserve_symlinks = flag()?,
+            "reflink" => opts.reflink = flag()?,
+            "explain" => opts.explain = flag()?,
+            "dry_run" => opts.dry_run = flag()?,
+            "delete" => opts.delete = flag()?,
+            "flatten" => opts.flatten = flag()?,
+            "atomic" => opts.atomic = flag()?,
+            "hidden" => opts.hidden = flag()?,
+            "follow_links" => opts.follow_links = flag()?,
+            "same_file_system" => opts.same_file_system = flag()?,
+            "max_depth" | "min_depth" => {
+                let depth = value
+                    .as_u64()
+                    .ok_or_else(|| format!("{} must be a non-negative integer", key))?
+                    as usize;
+                if key == "max_depth" {
+                    opts.max_depth = Some(depth);
+                } else {
+                    opts.min_depth = depth;
+                }
+            }
+            "respect_gitignore" => respect_gitignore = flag()?,
+            "filter" => {
+                let rules = value
+                    .as_array()
+                    .ok_or_else(|| format!("{} must be an array of rules", key))?;
+                for rule in rules {
+                    let rule = rule
+                        .as_str()
+                        .ok_or_else(|| format!("{} must be an array of rules", key))?;
+                    opts.filter.add_rule(rule).map_err(|e| e.to_string())?;
+                }
+            }
+            "exclude" => {
+                let patterns = value
+                    .as_array()
+                    .ok_or_else(|| format!("{} must be an array of globs", key))?;
+                for pattern in patterns {
+                    let pattern = pattern
+                        .as_str()
+                        .ok_or_else(|| format!("{} must be an array of globs", key))?;
+                    opts.filter.add_exclude(pattern);
+                }
+            }
+            "include" => {
+                let patterns = value
+                    .as_array()
+                    .ok_or_else(|| format!("{} must be an array of globs", key))?;
+                for pattern in patterns {
+                    let pattern = pattern
+                        .as_str()
+                        .ok_or_else(|| format!("{} must be an array of globs", key))?;
+                    opts.filter.add_include(pattern);
+                }
+            }
+            "transform" => {
+                let rules = value
+                    .as_array()
+                    .ok_or_else(|| format!("{} must be an array of expressions", key))?;
+                for rule in rules {
+                    let rule = rule
+                        .as_str()
+                        .ok_or_else(|| format!("{} must be an array of expressions", key))?;
+                    opts.transform.push(rule.parse()?);
+                }
+            }
+            "overlay" => {
+                opts.overlay = value
+                    .as_str()
+                    .ok_or_else(|| format!("{} must be a string", key))?
+                    .parse()?
+            }
+            "fallback" => {
+                opts.fallback = value
+                    .as_str()
+                    .ok_or_else(|| format!("{} must be a string", key))?
+                    .parse()?
+            }
+            "on_error" => {
+                opts.on_error = value
+                    .as_str()
+                    .ok_or_else(|| format!("{} must be a string", key))?
+                    .parse()?;
+                if opts.on_error == ErrorPolicy::Ask {
+                    return Err(format!("{} cannot be ask: nobody is there to answer", key));
+                }
+            }
+            "network_fs" => {
+                opts.network_fs = value
+                    .as_str()
+                    .ok_or_else(|| format!("{} must be a string", key))?
+                    .parse()?
+            }
+            "selinux" => {
+                opts.selinux = value
+                    .as_str()
+                    .ok_or_else(|| format!("{} must be a string", key))?
+                    .parse()?
+            }
+            "mark" => {
+                opts.mark = Some(
+                    value
+                        .as_str()
+                        .ok_or_else(|| format!("{} must be a string", key))?
+                        .to_string(),
+                )
+            }
+            "on_collision" => {
+                opts.on_collision = value
+                    .as_str()
+                    .ok_or_else(|| format!("{} must be a string", key))?
+                    .parse()?
+            }
+            "dest_template" => {
+                opts.dest_template = Some(
+                    value
+                        .as_str()
+                        .ok_or_else(|| format!("{} must be a string", key))?
+                        .parse()?,
+                )
+            }
+            "owner" => {
+                let name = value
+                    .as_str()
+                    .ok_or_else(|| format!("{} must be a string", key))?;
+                opts.owner = Some(owner::lookup_user(name).map_err(|e| e.to_string())?);
+            }
+            "group" => {
+                let name = value
+                    .as_str()
+                    .ok_or_else(|| format!("{} must be a string", key))?;
+                opts.group = Some(owner::lookup_group(name).map_err(|e| e.to_string())?);
+            }
+            "relative_to" => {
+                let dir = value
+                    .as_str()
+                    .ok_or_else(|| format!("{} must be a string", key))?;
+                opts.relative = true;
+                opts.relative_to = Some(PathBuf::from(dir));
+            }
+            "source_paths" => {
+                opts.source_paths = Some(
+                    value
+                        .as_str()
+                        .ok_or_else(|| format!("{} must be a string", key))?
+                        .parse()?,
+                )
+            }
+            "home_style" => {
+                opts.home_style = Some(
+                    value
+                        .as_str()
+                        .ok_or_else(|| format!("{} must be a string", key))?
+                        .parse()?,
+                )
+            }
+            "chmod" => {
+                opts.chmod = Some(
+                    value
+                        .as_str()
+                        .ok_or_else(|| format!("{} must be a string", key))?
+                        .parse()?,
+                )
+            }
+            "backup_control" => {
+                opts.backup = true;
+                opts.backup_control = value
+                    .as_str()
+                    .ok_or_else(|| format!("{} must be a string", key))?
+                    .parse()?
+            }
+            "backup_dir" => {
+                let dir = value
+                    .as_str()
+                    .ok_or_else(|| format!("{} must be a string", key))?;
+                opts.backup = true;
+                opts.backup_dir = Some(PathBuf::from(dir));
+            }
+            "backup_suffix" => {
+                opts.backup_suffix = value
+                    .as_str()
+                    .ok_or_else(|| format!("{} must be a string", key))?
+                    .to_string()
+            }
+            "jobs" => {
+                let jobs = value
+                    .as_u64()
+                    .filter(|n| *n > 0)
+                    .ok_or_else(|| format!("{} must be a positive integer", key))?;
+                opts.jobs = jobs as usize;
+            }
+            "backup_generations" => {
+                let keep = value
+                    .as_u64()
+                    .filter(|n| *n > 0)
+                    .ok_or_else(|| format!("{} must be a positive integer", key))?;
+                opts.backup = true;
+                opts.backup_generations = Some(keep as usize);
+            }
+            _ => return Err(format!("unknown option {}", key)),
+        }
+    }
+    opts.filter.add_ignore_file(IGNORE_FILE);
+    if respect_gitignore {
+        opts.filter.add_ignore_file(GITIGNORE_FILE);
+    }
+    LinkOptionsBuilder::from(opts)
+        .build()
+        .map_err(|e| e.to_string())
+}
@@ -0,0 +1,465 @@
+use crate::clock::UtcTime;
+use crate::json::Json;
+use crate::link::platform;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// How a recorded link was made, which decides how `undo` checks it is
+/// still the entry the run created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkType {
+    /// A symbolic link, or a symlink copied as one
+    Symlink,
+    /// A hard link sharing the source's inode
+    HardLink,
+    /// A copy made on a destination that cannot hold links
+    Copy,
+}
+
+impl LinkType {
+    /// A short stable name for the manifest.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LinkType::Symlink => "symlink",
+            LinkType::HardLink => "hardlink",
+            LinkType::Copy => "copy",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "symlink" => Some(LinkType::Symlink),
+            "hardlink" => Some(LinkType::HardLink),
+            "copy" => Some(LinkType::Copy),
+            _ => None,
+        }
+    }
+}
+
+/// A change a run made to the filesystem, recorded so it can be undone.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    /// A directory the run created
+    CreatedDir(PathBuf),
+    /// An existing destination entry moved aside to the given backup path
+    BackedUp(PathBuf, PathBuf),
+    /// An existing entry deleted with `force` or by an overlay whiteout
+    Removed(PathBuf),
+    /// A link or copy created at the destination for the source
+    Linked(LinkType, PathBuf, PathBuf),
+}
+
+impl Change {
+    /// Renders the change as a JSON object tagged by `op`.
+    pub fn to_json(&self) -> Json {
+        match self {
+            Change::CreatedDir(path) => Json::object([
+                ("op", Json::from("create_dir")),
+                ("path", Json::from(path.as_path())),
+            ]),
+            Change::BackedUp(dest, backup) => Json::object([
+                ("op", Json::from("backup")),
+                ("dest", Json::from(dest.as_path())),
+                ("backup", Json::from(backup.as_path())),
+            ]),
+            Change::Removed(path) => Json::object([
+                ("op", Json::from("remove")),
+                ("path", Json::from(path.as_path())),
+            ]),
+            Change::Linked(link_type, source, dest) => Json::object([
+                ("op", Json::from("link")),
+                ("type", Json::from(link_type.as_str())),
+                ("source", Json::from(source.as_path())),
+                ("dest", Json::from(dest.as_path())),
+            ]),
+        }
+    }
+
+    /// Reads a change written by `to_json`.
+    fn from_json(value: &Json) -> Option<Self> {
+        let path = |key| value.get(key).and_then(Json::as_str).map(PathBuf::from);
+        match value.get("op").and_then(Json::as_str)? {
+            "create_dir" => Some(Change::CreatedDir(path("path")?)),
+            "backup" => Some(Change::BackedUp(path("dest")?, path("backup")?)),
+            "remove" => Some(Change::Removed(path("path")?)),
+            "link" => {
+                let link_type = LinkType::parse(value.get("type").and_then(Json::as_str)?)?;
+                Some(Change::Linked(link_type, path("source")?, path("dest")?))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// What undoing one recorded change did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reversal {
+    /// A created link or copy was deleted
+    Unlinked(PathBuf),
+    /// A backup was moved back to the destination path it was taken from
+    Restored(PathBuf, PathBuf),
+    /// A created directory was removed
+    RemovedDir(PathBuf),
+    /// The change was left alone, for the given reason
+    Kept(PathBuf, String),
+}
+
+impl Reversal {
+    /// Renders the reversal as a JSON object tagged by `action`.
+    pub fn to_json(&self) -> Json {
+        match self {
+            Reversal::Unlinked(path) => Json::object([
+                ("action", Json::from("unlinked")),
+                ("path", Json::from(path.as_path())),
+            ]),
+            Reversal::Restored(dest, backup) => Json::object([
+                ("action", Json::from("restored")),
+                ("path", Json::from(dest.as_path())),
+                ("backup", Json::from(backup.as_path())),
+            ]),
+            Reversal::RemovedDir(path) => Json::object([
+                ("action", Json::from("removed_dir")),
+                ("path", Json::from(path.as_path())),
+            ]),
+            Reversal::Kept(path, why) => Json::object([
+                ("action", Json::from("kept")),
+                ("path", Json::from(path.as_path())),
+                ("reason", Json::from(why.as_str())),
+            ]),
+        }
+    }
+}
+
+impl fmt::Display for Reversal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reversal::Unlinked(path) => write!(f, "Removed {}", path.display()),
+            Reversal::Restored(dest, backup) => {
+                write!(f, "Restored {} from {}", dest.display(), backup.display())
+            }
+            Reversal::RemovedDir(path) => write!(f, "Removed directory {}", path.display()),
+            Reversal::Kept(path, why) => write!(f, "Kept {}: {}", path.display(), why),
+        }
+    }
+}
+
+/// The record of one run: every change it made, in order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Manifest {
+    /// The run's ID, also the manifest's file name without `.json`
+    pub id: String,
+    /// When the run finished, as an RFC 3339 timestamp
+    pub time: String,
+    /// The destination the run linked into
+    pub dest: PathBuf,
+    /// The changes, in the order they were made
+    pub changes: Vec<Change>,
+}
+
+impl Manifest {
+    /// Renders the manifest as a JSON object.
+    pub fn to_json(&self) -> Json {
+        Json::object([
+            ("id", Json::from(self.id.as_str())),
+            ("time", Json::from(self.time.as_str())),
+            ("dest", Json::from(self.dest.as_path())),
+            (
+                "changes",
+                Json::Array(self.changes.iter().map(Change::to_json).collect()),
+            ),
+        ])
+    }
+
+    /// Writes the manifest to `path`, creating its directory if needed.
+    ///
+    /// Relative paths are written against the current directory, so the run
+    /// can be undone from anywhere.
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let cwd = env::current_dir()?;
+        let abs = |path: &PathBuf| cwd.join(path);
+        let manifest = Manifest {
+            dest: abs(&self.dest),
+            changes: self
+                .changes
+                .iter()
+                .map(|change| match change {
+                    Change::CreatedDir(path) => Change::CreatedDir(abs(path)),
+                    Change::BackedUp(dest, backup) => Change::BackedUp(abs(dest), abs(backup)),
+                    Change::Removed(path) => Change::Removed(abs(path)),
+                    Change::Linked(link_type, source, dest) => {
+                        Change::Linked(*link_type, abs(source), abs(dest))
+                    }
+                })
+                .collect(),
+            ..self.clone()
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, format!("{}\n", manifest.to_json()))
+    }
+
+    /// Reads a manifest written by `write`.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The manifest file
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<Manifest>` - The manifest, or an `InvalidData` error if
+    ///   the file is not one
+    pub fn read(path: &Path) -> io::Result<Manifest> {
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} is not a flnk manifest", path.display()),
+            )
+        };
+        let doc = Json::parse(&fs::read_to_string(path)?).map_err(|_| invalid())?;
+        let field = |key| doc.get(key).and_then(Json::as_str).ok_or_else(invalid);
+        let changes = doc
+            .get("changes")
+            .and_then(Json::as_array)
+            .ok_or_else(invalid)?
+            .iter()
+            .map(|change| Change::from_json(change).ok_or_else(invalid))
+            .collect::<io::Result<_>>()?;
+        Ok(Manifest {
+            id: field("id")?.to_string(),
+            time: field("time")?.to_string(),
+            dest: PathBuf::from(field("dest")?),
+            changes,
+        })
+    }
+
+    /// Reverses the recorded changes, last first: deletes the links and
+    /// copies the run created, moves its backups back, and removes the
+    /// directories it created once they are empty.
+    ///
+    /// An entry that changed since the run is kept, and so is a directory
+    /// that is not empty; entries deleted with `force` cannot be brought back.
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<Vec<Reversal>>` - What was done for each change, or the
+    ///   first error met while changing the filesystem
+    pub fn undo(&self) -> io::Result<Vec<Reversal>> {
+        let mut done = Vec::new();
+        for change in self.changes.iter().rev() {
+            if let Some(reversal) = reverse(change)? {
+                done.push(reversal);
+            }
+        }
+        Ok(done)
+    }
+}
+
+/// Reverses a single change, or returns `None` if there is nothing left to reverse.
+fn reverse(change: &Change) -> io::Result<Option<Reversal>> {
+    let kept = |path: &Path, why: String| Ok(Some(Reversal::Kept(path.to_path_buf(), why)));
+    match change {
+        Change::Linked(link_type, source, dest) => {
+            let Some(metadata) = existing(dest)? else {
+                return Ok(None);
+            };
+            let unchanged = match link_type {
+                LinkType::Symlink => metadata.file_type().is_symlink(),
+                LinkType::HardLink => match existing(source)? {
+                    Some(source) => same_inode(&source, &metadata),
+                    // Deleting the link would lose the last copy of the data.
+                    None => return kept(dest, format!("{} is gone", source.display())),
+                },
+                LinkType::Copy => {
+                    metadata.is_file()
+                        && existing(source)?.is_some_and(|s| s.len() == metadata.len())
+                }
+            };
+            if !unchanged {
+                return kept(dest, String::from("changed since the run"));
+            }
+            platform::remove_link(dest)?;
+            Ok(Some(Reversal::Unlinked(dest.clone())))
+        }
+        Change::BackedUp(dest, backup) => {
+            if existing(backup)?.is_none() {
+                return kept(dest, format!("backup {} is gone", backup.display()));
+            }
+            if existing(dest)?.is_some() {
+                return kept(backup, format!("{} is in the way", dest.display()));
+            }
+            fs::rename(backup, dest)?;
+            Ok(Some(Reversal::Restored(dest.clone(), backup.clone())))
+        }
+        Change::Removed(path) => kept(path, String::from("it was deleted and cannot be restored")),
+        Change::CreatedDir(path) => match fs::remove_dir(path) {
+            Ok(()) => Ok(Some(Reversal::RemovedDir(path.clone()))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) if e.kind() == io::ErrorKind::DirectoryNotEmpty => {
+                kept(path, String::from("not empty"))
+            }
+            Err(e) => Err(e),
+        },
+    }
+}
+
+/// Returns an entry's metadata without following a symlink, or `None` if it does not exist.
+fn existing(path: &Path) -> io::Result<Option<fs::Metadata>> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) => Ok(Some(metadata)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// True if two entries are the same inode, where the platform can tell.
+fn same_inode(a: &fs::Metadata, b: &fs::Metadata) -> bool {
+    match (platform::identity(a), platform::identity(b)) {
+        (Some(a), Some(b)) => a == b,
+        _ => a.len() == b.len(),
+    }
+}
+
+/// Returns the directory manifests are kept in: `$XDG_STATE_HOME/flnk`, or
+/// `~/.local/state/flnk` if that is not set.
+pub fn state_dir() -> Option<PathBuf> {
+    env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state")))
+        .map(|state| state.join("flnk"))
+}
+
+/// Generates the ID of a new run from the current time and process ID, so
+/// IDs sort by age. Later runs of the same process get a counter appended.
+pub fn new_id() -> String {
+    static RUNS: AtomicU64 = AtomicU64::new(0);
+    match RUNS.fetch_add(1, Ordering::Relaxed) {
+        0 => format!("{}-{}", UtcTime::now().compact(), process::id()),
+        n => format!("{}-{}-{}", UtcTime::now().compact(), process::id(), n),
+    }
+}
+
+/// Checks whether a name is a run ID as made by `new_id`, such as
+/// `20260101T120000Z-4242` or `20260101T120000Z-4242-1`.
+pub fn is_run_id(name: &str) -> bool {
+    let digits = |part: &str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit());
+    let Some((time, rest)) = name.split_once('-') else {
+        return false;
+    };
+    let time = time.as_bytes();
+    time.len() == 16
+        && time[8] == b'T'
+        && time[15] == b'Z'
+        && digits(&name[..8])
+        && digits(&name[9..15])
+        && match rest.split_once('-') {
+            Some((pid, run)) => digits(pid) && digits(run),
+            None => digits(rest),
+        }
+}
+
+/// Lists the manifests in a state directory, oldest first.
+///
+/// # Arguments
+///
+/// * `dir` - The directory holding the manifests
+///
+/// # Returns
+///
+/// * `io::Result<Vec<PathBuf>>` - The manifest files, or an empty list if there are none
+pub fn list(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut found = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "json") {
+            found.push(path);
+        }
+    }
+    found.sort();
+    Ok(found)
+}
+
+/// How many of the newest run manifests are kept; older ones are deleted
+/// whenever a run records a new one.
+pub const KEEP_RUNS: usize = 100;
+
+/// Deletes the manifests beyond the newest `keep`, so recording every run
+/// does not fill the state directory. Only manifests named by a run ID count,
+/// so other JSON files in the directory are never deleted.
+///
+/// # Arguments
+///
+/// * `dir` - The directory holding the manifests
+/// * `keep` - How many of the newest manifests to keep
+///
+/// # Returns
+///
+/// * `io::Result<Vec<PathBuf>>` - The manifests that were deleted, oldest first
+pub fn prune(dir: &Path, keep: usize) -> io::Result<Vec<PathBuf>> {
+    let mut expired = list(dir)?;
+    expired.retain(|path| {
+        path.file_stem()
+            .and_then(|stem| stem.to_str())
+            .is_some_and(is_run_id)
+    });
+    expired.truncate(expired.len().saturating_sub(keep));
+    for path in &expired {
+        fs::remove_file(path)?;
+    }
+    Ok(expired)
+}
+
+/// Finds the manifest of a run.
+///
+/// # Arguments
+///
+/// * `dir` - The directory holding the manifests
+/// * `id` - The run's ID, or `None` for the most recent run
+///
+/// # Returns
+///
+/// * `io::Result<PathBuf>` - The manifest file, or a `NotFound` error
+pub fn find(dir: &Path, id: Option<&str>) -> io::Result<PathBuf> {
+    let found = match id {
+        Some(id) => Some(dir.join(format!("{}.json", id))).filter(|path| path.is_file()),
+        None => list(dir)?.pop(),
+    };
+    found.ok_or_else(|| {
+        let what = match id {
+            Some(id) => format!("no run {} recorded in {}", id, dir.display()),
+            None => format!("no runs recorded in {}", dir.display()),
+        };
+        io::Error::new(io::ErrorKind::NotFound, what)
+    })
+}
+
+/// Undoes a recorded run and deletes its manifest, so the next undo without
+/// an ID reaches the run before it.
+///
+/// # Arguments
+///
+/// * `dir` - The directory holding the manifests
+/// * `id` - The run's ID, or `None` for the most recent run
+///
+/// # Returns
+///
+/// * `io::Result<(Manifest, Vec<Reversal>)>` - The undone run and what was
+///   done for each of its changes
+pub fn undo(dir: &Path, id: Option<&str>) -> io::Result<(Manifest, Vec<Reversal>)> {
+    let path = find(dir, id)?;
+    let manifest = Manifest::read(&path)?;
+    let reversals = manifest.undo()?;
+    fs::remove_file(&path)?;
+    Ok((manifest, reversals))
+}
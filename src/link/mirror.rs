@@ -0,0 +1,106 @@
+use crate::link::backups::BACKUP_DIR;
+use crate::link::home;
+use crate::link::lexical;
+use crate::link::xattr;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// Decides whether flnk made a destination entry with no source counterpart.
+///
+/// Marked entries must carry the run's label. Symlinks cannot be marked, so
+/// they count as flnk's when their target points into the source.
+fn is_managed(path: &Path, is_symlink: bool, source: &Path, label: &str) -> bool {
+    if !is_symlink {
+        return xattr::managed_label(path).as_deref() == Some(label);
+    }
+    let Ok(target) = fs::read_link(path) else {
+        return false;
+    };
+    let target = home::expand(&target)
+        .unwrap_or_else(|| path.parent().unwrap_or(Path::new("")).join(&target));
+    match (std::path::absolute(target), std::path::absolute(source)) {
+        (Ok(target), Ok(source)) => {
+            lexical::normalize(&target).starts_with(lexical::normalize(&source))
+        }
+        _ => false,
+    }
+}
+
+/// Checks whether a source entry exists, without following a symlink.
+///
+/// Only an entry known to be missing has no counterpart: one that cannot be
+/// checked, say for lack of permission, is an error rather than a reason to
+/// delete what mirrors it.
+fn has_counterpart(path: &Path) -> io::Result<bool> {
+    match fs::symlink_metadata(path) {
+        Ok(_) => Ok(true),
+        // A file where the source has a directory has nothing below it.
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::NotFound | io::ErrorKind::NotADirectory
+            ) =>
+        {
+            Ok(false)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Finds the entries of a destination directory that have no counterpart in
+/// the source directory it mirrors, for `--delete`.
+///
+/// `mirror/a/b` is the counterpart of `source/a/b`, so entries excluded from
+/// linking are kept as long as they exist in the source. Backup directories
+/// and the given backups are always kept, and a directory is only listed if
+/// everything below it is.
+///
+/// # Arguments
+///
+/// * `source` - The source directory
+/// * `mirror` - The destination directory its entries are linked into
+/// * `label` - With `--mark`, the run label an entry must carry to be listed,
+///   so files flnk did not create are left alone
+/// * `keep` - Entries never to list, such as the run's own backups
+///
+/// # Returns
+///
+/// * `io::Result<Vec<PathBuf>>` - The entries to remove, each listed after
+///   everything below it
+pub fn extraneous(
+    source: &Path,
+    mirror: &Path,
+    label: Option<&str>,
+    keep: &HashSet<PathBuf>,
+) -> io::Result<Vec<PathBuf>> {
+    // A directory linked whole is the source itself.
+    match fs::symlink_metadata(mirror) {
+        Ok(metadata) if metadata.is_dir() => {}
+        Ok(_) => return Ok(Vec::new()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    }
+
+    let mut extra = Vec::new();
+    let mut kept: HashSet<PathBuf> = HashSet::new();
+    for entry in WalkDir::new(mirror).min_depth(1).contents_first(true) {
+        let entry = entry?;
+        let path = entry.path();
+        let rel = path.strip_prefix(mirror).unwrap_or(path);
+        let is_symlink = entry.path_is_symlink();
+        let remove = !kept.contains(path)
+            && !keep.contains(path)
+            && !rel.components().any(|c| c.as_os_str() == BACKUP_DIR)
+            && !has_counterpart(&source.join(rel))?
+            && label.is_none_or(|label| is_managed(path, is_symlink, source, label));
+        if remove {
+            extra.push(path.to_path_buf());
+        } else if let Some(parent) = path.parent() {
+            kept.insert(parent.to_path_buf());
+        }
+    }
+    Ok(extra)
+}
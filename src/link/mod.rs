@@ -1,5 +1,34 @@
+pub mod archive;
+pub mod backups;
+pub mod chmod;
+pub mod collision;
+pub mod dry_run;
+pub mod failure;
+pub mod filter;
+pub mod glob;
+pub mod home;
+pub mod journal;
+pub mod lexical;
 pub mod link_files;
 pub mod link_options;
+pub mod manifest;
+pub mod mirror;
+pub mod netfs;
+pub mod overlay;
+pub mod owner;
+pub(crate) mod pipeline;
+pub mod platform;
+pub mod probe;
+pub mod prompt;
+pub mod regex;
+pub mod selinux;
+pub mod skip;
+pub mod status;
+pub mod stream;
+pub mod template;
+pub mod transform;
+pub mod warning;
+pub mod xattr;
 
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,108 @@
+use crate::link::platform;
+use crate::link::probe::FsCapabilities;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+
+/// How many times an operation failing with `ESTALE` is retried.
+const STALE_RETRIES: u32 = 3;
+
+/// How flnk adapts to destinations on NFS or SMB shares.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NetworkFsMode {
+    /// On network shares, hard links across exports are refused up front and
+    /// operations failing with a stale file handle are retried
+    #[default]
+    Auto,
+    /// Like `Auto`, and symbolic links are always made relative so they
+    /// resolve on clients mounting the share under a different prefix
+    Strict,
+    /// Network shares are treated like local filesystems
+    Off,
+}
+
+impl FromStr for NetworkFsMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(NetworkFsMode::Auto),
+            "strict" => Ok(NetworkFsMode::Strict),
+            "off" => Ok(NetworkFsMode::Off),
+            _ => Err(format!(
+                "invalid network filesystem mode {} (expected auto, strict, or off)",
+                s
+            )),
+        }
+    }
+}
+
+/// Checks that hard links from `sources` can be created on the network share
+/// described by `caps`, which requires every source to live on the same export.
+///
+/// # Arguments
+///
+/// * `sources` - The expanded source paths of the run
+/// * `caps` - The probed capabilities of the destination share
+///
+/// # Returns
+///
+/// * `io::Result<()>` - A `CrossesDevices` error naming the first source on another export
+pub fn check_same_export(sources: &[PathBuf], caps: &FsCapabilities) -> io::Result<()> {
+    for source in sources {
+        let Some(device) = fs::metadata(source).ok().and_then(|m| platform::device(&m)) else {
+            continue;
+        };
+        if device != caps.device {
+            return Err(io::Error::new(
+                io::ErrorKind::CrossesDevices,
+                format!(
+                    "{} is not on the {} export holding {}; hard links cannot cross exports, use -s instead",
+                    source.display(),
+                    caps.fs_type,
+                    caps.probed_dir.display()
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Returns true if an error is a stale NFS file handle.
+#[cfg(not(windows))]
+fn is_stale(err: &io::Error) -> bool {
+    err.raw_os_error() == Some(libc::ESTALE)
+}
+
+/// Windows has no `ESTALE`; its NFS client reports stale handles as ordinary
+/// I/O errors, so nothing is retried.
+#[cfg(windows)]
+fn is_stale(_err: &io::Error) -> bool {
+    false
+}
+
+/// Runs `op`, retrying with a short backoff while it fails with a stale file
+/// handle, which NFS reports after the server replaced a cached directory.
+///
+/// # Arguments
+///
+/// * `op` - The filesystem operation to run
+///
+/// # Returns
+///
+/// * `io::Result<T>` - The result of the first attempt that did not go stale, or the last error
+pub fn retry_stale<T>(mut op: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+    let mut attempt = 0;
+    loop {
+        match op() {
+            Err(e) if is_stale(&e) && attempt < STALE_RETRIES => {
+                attempt += 1;
+                thread::sleep(Duration::from_millis(50 * u64::from(attempt)));
+            }
+            result => return result,
+        }
+    }
+}
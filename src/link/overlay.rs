@@ -0,0 +1,165 @@
+use crate::link::xattr;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+
+/// The file name prefix marking a whiteout in container image layers.
+pub const WHITEOUT_PREFIX: &str = ".wh.";
+
+/// The file marking its directory as opaque in container image layers.
+pub const OPAQUE_MARKER: &str = ".wh..wh..opq";
+
+/// The extended attributes overlayfs uses to mark a directory as opaque.
+const OPAQUE_XATTRS: [&str; 2] = ["trusted.overlay.opaque", "user.overlay.opaque"];
+
+/// How overlayfs and container layer artifacts in the source are treated.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverlayMode {
+    /// Whiteouts and opaque markers are linked like any other entry
+    #[default]
+    Off,
+    /// Whiteouts and opaque markers are left out of the destination
+    Skip,
+    /// Whiteouts delete their entry from the destination and opaque
+    /// directories are emptied before the layer's contents are linked,
+    /// so linking layers in order flattens them
+    Translate,
+}
+
+impl FromStr for OverlayMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(OverlayMode::Off),
+            "skip" => Ok(OverlayMode::Skip),
+            "translate" => Ok(OverlayMode::Translate),
+            _ => Err(format!(
+                "invalid overlay mode {} (expected off, skip, or translate)",
+                s
+            )),
+        }
+    }
+}
+
+/// A recognized overlay artifact.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OverlayEntry {
+    /// Hides the named sibling entry of lower layers
+    Whiteout(OsString),
+    /// Hides everything lower layers have in the containing directory
+    OpaqueMarker,
+}
+
+/// Recognizes whiteouts in both the overlayfs format (a 0/0 character
+/// device) and the container layer format (a `.wh.` prefixed file).
+///
+/// # Arguments
+///
+/// * `name` - The file name of the entry
+/// * `metadata` - The entry's metadata, not following symlinks
+///
+/// # Returns
+///
+/// * `Option<OverlayEntry>` - What the entry means, or `None` for an ordinary entry
+pub fn classify(name: &OsStr, metadata: &fs::Metadata) -> Option<OverlayEntry> {
+    if name == OPAQUE_MARKER {
+        return Some(OverlayEntry::OpaqueMarker);
+    }
+    if let Some(target) = name
+        .as_encoded_bytes()
+        .strip_prefix(WHITEOUT_PREFIX.as_bytes())
+    {
+        // Splitting after an ASCII prefix leaves a valid encoding.
+        let target = unsafe { OsStr::from_encoded_bytes_unchecked(target) };
+        return hidden_sibling(target).map(|target| OverlayEntry::Whiteout(target.to_os_string()));
+    }
+    if is_whiteout_device(metadata) {
+        return Some(OverlayEntry::Whiteout(name.to_os_string()));
+    }
+    None
+}
+
+/// Returns the name a `.wh.` file hides, if it names a sibling entry.
+///
+/// `.wh..` and `.wh...` would hide the directory itself or its parent, and
+/// removing those would reach outside the destination entry the whiteout
+/// stands for; overlayfs never makes them, so they are ordinary files, as is
+/// a name that is empty or holds more than one component.
+fn hidden_sibling(target: &OsStr) -> Option<&OsStr> {
+    let mut components = Path::new(target).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(name)), None) if name == target => Some(name),
+        _ => None,
+    }
+}
+
+/// Returns true if an entry is an overlayfs whiteout, a 0/0 character device.
+#[cfg(unix)]
+fn is_whiteout_device(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+    metadata.file_type().is_char_device() && metadata.rdev() == 0
+}
+
+/// Returns true if an entry is an overlayfs whiteout; WASI cannot see device files.
+#[cfg(not(unix))]
+fn is_whiteout_device(_metadata: &fs::Metadata) -> bool {
+    false
+}
+
+/// Returns true if a source directory hides the contents of lower layers,
+/// either through an opaque marker file or the overlayfs opaque xattr.
+pub fn is_opaque_dir(dir: &Path) -> bool {
+    if fs::symlink_metadata(dir.join(OPAQUE_MARKER)).is_ok() {
+        return true;
+    }
+    OPAQUE_XATTRS
+        .iter()
+        .any(|attr| matches!(xattr::get(dir, attr), Ok(Some(value)) if value == b"y"))
+}
+
+/// Removes a destination entry hidden by a whiteout, if it exists.
+///
+/// # Arguments
+///
+/// * `dest` - The destination path the whiteout refers to
+///
+/// # Returns
+///
+/// * `io::Result<bool>` - Whether there was an entry to remove, once nothing is left at `dest`
+pub fn apply_whiteout(dest: &Path) -> io::Result<bool> {
+    match fs::symlink_metadata(dest) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(dest).map(|_| true),
+        Ok(_) => fs::remove_file(dest).map(|_| true),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Empties a destination directory whose source directory is opaque.
+///
+/// # Arguments
+///
+/// * `dest` - The destination directory
+///
+/// # Returns
+///
+/// * `io::Result<Vec<PathBuf>>` - The removed entries, once the directory is
+///   empty or if it does not exist
+pub fn clear_dir(dest: &Path) -> io::Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(dest) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut removed = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        apply_whiteout(&path)?;
+        removed.push(path);
+    }
+    Ok(removed)
+}
@@ -0,0 +1,122 @@
+use std::io;
+use std::path::Path;
+
+/// Resolves a user name, or a numeric id, to a user id.
+///
+/// # Arguments
+///
+/// * `name` - A user name such as `plex`, or a numeric id such as `1000`
+///
+/// # Returns
+///
+/// * `io::Result<u32>` - The user id, or a `NotFound` error for an unknown user
+#[cfg(unix)]
+pub fn lookup_user(name: &str) -> io::Result<u32> {
+    if let Ok(id) = name.parse() {
+        return Ok(id);
+    }
+    lookup(name, "user", |c_name, buf| {
+        let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut found = std::ptr::null_mut();
+        let rc = unsafe {
+            libc::getpwnam_r(c_name, &mut entry, buf.as_mut_ptr(), buf.len(), &mut found)
+        };
+        (rc, (!found.is_null()).then_some(entry.pw_uid))
+    })
+}
+
+/// Resolves a group name, or a numeric id, to a group id.
+///
+/// # Arguments
+///
+/// * `name` - A group name such as `media`, or a numeric id such as `1000`
+///
+/// # Returns
+///
+/// * `io::Result<u32>` - The group id, or a `NotFound` error for an unknown group
+#[cfg(unix)]
+pub fn lookup_group(name: &str) -> io::Result<u32> {
+    if let Ok(id) = name.parse() {
+        return Ok(id);
+    }
+    lookup(name, "group", |c_name, buf| {
+        let mut entry: libc::group = unsafe { std::mem::zeroed() };
+        let mut found = std::ptr::null_mut();
+        let rc = unsafe {
+            libc::getgrnam_r(c_name, &mut entry, buf.as_mut_ptr(), buf.len(), &mut found)
+        };
+        (rc, (!found.is_null()).then_some(entry.gr_gid))
+    })
+}
+
+/// Calls a reentrant `get*nam_r` lookup, growing its buffer until the entry fits.
+#[cfg(unix)]
+fn lookup(
+    name: &str,
+    what: &str,
+    mut query: impl FnMut(*const libc::c_char, &mut [libc::c_char]) -> (libc::c_int, Option<u32>),
+) -> io::Result<u32> {
+    let c_name = std::ffi::CString::new(name)?;
+    let mut buf = vec![0; 1024];
+    loop {
+        match query(c_name.as_ptr(), &mut buf) {
+            (libc::ERANGE, _) if buf.len() < 1 << 20 => buf.resize(buf.len() * 2, 0),
+            (0, Some(id)) => return Ok(id),
+            (0, None) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("unknown {} {}", what, name),
+                ));
+            }
+            (rc, _) => return Err(io::Error::from_raw_os_error(rc)),
+        }
+    }
+}
+
+/// Resolves a user name to a user id; WASI has no users.
+#[cfg(not(unix))]
+pub fn lookup_user(_name: &str) -> io::Result<u32> {
+    Err(unsupported())
+}
+
+/// Resolves a group name to a group id; WASI has no groups.
+#[cfg(not(unix))]
+pub fn lookup_group(_name: &str) -> io::Result<u32> {
+    Err(unsupported())
+}
+
+#[cfg(not(unix))]
+fn unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "ownership is not available in the WASI build",
+    )
+}
+
+/// Gives a created entry the requested owner and group, without following symlinks.
+///
+/// # Arguments
+///
+/// * `path` - The created entry
+/// * `owner` - The user id to set, or `None` to keep the current owner
+/// * `group` - The group id to set, or `None` to keep the current group
+///
+/// # Returns
+///
+/// * `io::Result<()>` - Success if nothing was requested or the ownership was changed
+#[cfg(unix)]
+pub fn apply(path: &Path, owner: Option<u32>, group: Option<u32>) -> io::Result<()> {
+    if owner.is_none() && group.is_none() {
+        return Ok(());
+    }
+    std::os::unix::fs::lchown(path, owner, group)
+}
+
+/// Gives a created entry the requested owner and group; WASI has no ownership.
+#[cfg(not(unix))]
+pub fn apply(_path: &Path, owner: Option<u32>, group: Option<u32>) -> io::Result<()> {
+    if owner.is_none() && group.is_none() {
+        return Ok(());
+    }
+    Err(unsupported())
+}
@@ -0,0 +1,532 @@
+use crate::link::filter::FilterWalk;
+use crate::link::link_options::LinkOptions;
+use crate::link::overlay::{self, OverlayEntry, OverlayMode};
+use crate::link::skip::{SkipReason, Skipped};
+use crate::link::transform;
+use std::collections::HashMap;
+use std::io;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{SyncSender, sync_channel};
+use std::thread;
+use walkdir::{DirEntry, WalkDir};
+
+/// The number of discovered entries that may be queued ahead of the link stage.
+///
+/// Keeps discovery from racing arbitrarily far ahead of linking (and holding
+/// every entry of a large tree in memory) while still letting the two stages
+/// overlap on high-latency filesystems.
+pub const CHANNEL_CAPACITY: usize = 1024;
+
+/// The type of a source entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Dir,
+    Symlink,
+    /// An overlay whiteout; the destination entry it hides is removed
+    Whiteout,
+    /// An opaque overlay directory; the destination directory is emptied first
+    Opaque,
+    /// A socket, FIFO, or device file that is left out with a warning
+    Special,
+}
+
+impl EntryKind {
+    /// Returns true if jobs of this kind create a link rather than remove entries.
+    pub fn creates_link(self) -> bool {
+        !matches!(
+            self,
+            EntryKind::Whiteout | EntryKind::Opaque | EntryKind::Special
+        )
+    }
+}
+
+/// A single entry found by the discovery stage, ready to be linked.
+#[derive(Debug, Clone)]
+pub struct LinkJob {
+    /// The path of the entry in the source tree
+    pub source: PathBuf,
+    /// The full path where the link should be created
+    pub dest: PathBuf,
+    /// The path of the entry relative to the linked root
+    pub rel_path: PathBuf,
+    /// The type of the source entry
+    pub kind: EntryKind,
+}
+
+/// Walks the given sources and sends a `LinkJob` for every entry that should be linked.
+///
+/// Stops early if the receiving side hangs up, which happens when the link
+/// stage aborts on an error.
+///
+/// # Arguments
+///
+/// * `sources` - The expanded source paths to walk
+/// * `dest_path` - The destination path the links are created under
+/// * `opts` - The options controlling which entries are linked
+/// * `tx` - The channel the discovered jobs are sent on
+///
+/// # Returns
+///
+/// * `LeftOut` - The entries left out, listed with `explain` and counted
+pub fn discover(
+    sources: &[PathBuf],
+    dest_path: &Path,
+    opts: &LinkOptions,
+    tx: &SyncSender<io::Result<LinkJob>>,
+) -> LeftOut {
+    let mut skipped = LeftOut::default();
+    let dest_is_dir = links_into(dest_path, opts);
+    let dest_is_link = opts.no_dereference && dest_path.is_symlink();
+    let include_root = dest_path.is_relative();
+
+    for source_path in sources {
+        let base = link_base(source_path, include_root && dest_is_dir);
+        log::info!(
+            "walking {} into {}",
+            source_path.display(),
+            dest_path.display()
+        );
+
+        let mut filter = opts.filter.walker();
+        let mut walker = WalkDir::new(source_path)
+            .max_depth(opts.max_depth.unwrap_or(usize::MAX))
+            .follow_links(opts.follow_links)
+            .same_file_system(opts.same_file_system)
+            .into_iter();
+        while let Some(entry) = walker.next() {
+            let is_root = matches!(&entry, Ok(e) if e.depth() == 0);
+            // Sources named explicitly are linked even if they are hidden.
+            if !opts.hidden
+                && let Ok(entry) = &entry
+                && entry.depth() > 0
+                && entry.file_name().as_encoded_bytes().starts_with(b".")
+            {
+                if entry.file_type().is_dir() {
+                    walker.skip_current_dir();
+                }
+                leave_out(&mut skipped, entry.path(), SkipReason::Hidden, opts);
+                continue;
+            }
+            if let Ok(entry) = &entry
+                && !opts.filter.is_empty()
+            {
+                match is_filtered(&mut filter, entry, source_path) {
+                    Ok(None) => {}
+                    Ok(Some(reason)) => {
+                        if entry.file_type().is_dir() {
+                            walker.skip_current_dir();
+                        }
+                        leave_out(&mut skipped, entry.path(), reason, opts);
+                        continue;
+                    }
+                    Err(e) => {
+                        let _ = tx.send(Err(e));
+                        return skipped;
+                    }
+                }
+            }
+
+            // Unlike WalkDir's own min_depth, shallower directories are still
+            // entered so their ignore files apply.
+            if matches!(&entry, Ok(e) if e.depth() < opts.min_depth) {
+                continue;
+            }
+
+            if opts.overlay == OverlayMode::Translate
+                && let Ok(entry) = &entry
+                && entry.file_type().is_dir()
+                && let Ok(rel_path) = entry.path().strip_prefix(base)
+                && !rel_path.as_os_str().is_empty()
+                && overlay::is_opaque_dir(entry.path())
+            {
+                let job = LinkJob {
+                    source: entry.path().to_path_buf(),
+                    dest: dest_path.join(rel_path),
+                    rel_path: rel_path.to_path_buf(),
+                    kind: EntryKind::Opaque,
+                };
+                if tx.send(Ok(job)).is_err() {
+                    return skipped;
+                }
+            }
+
+            let job = match make_job(
+                entry,
+                is_root,
+                base,
+                dest_path,
+                (dest_is_dir, dest_is_link),
+                opts,
+            ) {
+                Ok(Err(skip)) => {
+                    if let Some(skip) = skip {
+                        leave_out(&mut skipped, &skip.path, skip.reason, opts);
+                    }
+                    continue;
+                }
+                Ok(Ok(job)) => {
+                    log::trace!("found {} for {}", job.source.display(), job.dest.display());
+                    // A directory linked as a whole stands for everything below it.
+                    if job.kind == EntryKind::Dir && !opts.archive {
+                        walker.skip_current_dir();
+                    }
+                    Ok(job)
+                }
+                Err(e) => Err(e),
+            };
+
+            let failed = job.is_err();
+            if tx.send(job).is_err() || failed {
+                return skipped;
+            }
+        }
+    }
+    skipped
+}
+
+/// Logs an entry discovery leaves out and records it, if the run explains
+/// its skips.
+fn leave_out(left_out: &mut LeftOut, path: &Path, reason: SkipReason, opts: &LinkOptions) {
+    let skip = Skipped::new(path, reason);
+    log::debug!("skipped {}", skip);
+    if matches!(
+        skip.reason,
+        SkipReason::Excluded(_) | SkipReason::NotIncluded
+    ) {
+        left_out.filtered += 1;
+    }
+    if opts.explain {
+        left_out.skipped.push(skip);
+    }
+}
+
+/// The entries discovery left out of a run.
+#[derive(Debug, Default)]
+pub struct LeftOut {
+    /// With `explain`, the entries left out and why; otherwise empty
+    pub skipped: Vec<Skipped>,
+    /// The number of entries left out by filter rules and include globs; an
+    /// excluded directory counts once
+    pub filtered: usize,
+}
+
+/// Returns true if the sources are linked inside `dest_path` rather than at
+/// it: it is an existing directory, not named as the link itself with
+/// `no_target_directory`, and not a symlink kept as it is with `no_dereference`.
+///
+/// # Arguments
+///
+/// * `dest_path` - The destination path of the run
+/// * `opts` - The options the run is made with
+///
+/// # Returns
+///
+/// * `bool` - True if the destination is a directory to link into
+pub fn links_into(dest_path: &Path, opts: &LinkOptions) -> bool {
+    dest_path.is_dir()
+        && !opts.no_target_directory
+        && !(opts.no_dereference && dest_path.is_symlink())
+}
+
+/// Returns the path the entries of a source are placed relative to: its
+/// parent when it is linked into an existing directory under its own name,
+/// or the source itself when the destination stands for it.
+fn link_base(source_path: &Path, include_root: bool) -> &Path {
+    if include_root {
+        source_path.parent().unwrap_or(Path::new(""))
+    } else {
+        source_path
+    }
+}
+
+/// Pairs every source directory with the destination directory its entries
+/// are linked into, as `discover` will place them.
+///
+/// # Arguments
+///
+/// * `sources` - The expanded source paths of the run
+/// * `dest_path` - The destination path the links are created under
+/// * `opts` - The options the run is made with
+///
+/// # Returns
+///
+/// * `Vec<(PathBuf, PathBuf)>` - Each source directory with its destination
+pub fn mirror_roots(
+    sources: &[PathBuf],
+    dest_path: &Path,
+    opts: &LinkOptions,
+) -> Vec<(PathBuf, PathBuf)> {
+    let include_root = dest_path.is_relative() && links_into(dest_path, opts);
+    sources
+        .iter()
+        .filter(|source| source.is_dir())
+        .map(|source| {
+            let rel = source
+                .strip_prefix(link_base(source, include_root))
+                .unwrap_or(Path::new(""));
+            (source.clone(), dest_path.join(rel))
+        })
+        .collect()
+}
+
+/// Applies the filter rules and include globs to a walked entry, returning
+/// why it is left out if it is.
+///
+/// Directories that are kept have their per-directory merge files read so the
+/// rules apply to everything below them.
+fn is_filtered(
+    filter: &mut FilterWalk,
+    entry: &DirEntry,
+    root: &Path,
+) -> io::Result<Option<SkipReason>> {
+    let is_dir = entry.file_type().is_dir();
+    let rel = entry.path().strip_prefix(root).unwrap_or(entry.path());
+    let rel = if entry.depth() == 0 && !is_dir {
+        Path::new(entry.file_name())
+    } else {
+        rel
+    };
+
+    if (entry.depth() > 0 || !is_dir)
+        && let Some(rule) = filter.excluded_by(rel, entry.depth(), is_dir)
+    {
+        return Ok(Some(SkipReason::Excluded(rule)));
+    }
+    if !filter.is_included(rel, is_dir) {
+        return Ok(Some(SkipReason::NotIncluded));
+    }
+    if is_dir {
+        filter.enter_dir(entry.path(), rel, entry.depth())?;
+    }
+    Ok(None)
+}
+
+/// Turns a walked entry into a job, or into the reason it is left out if the
+/// options say to leave it alone. Directories that are descended into rather
+/// than linked, and overlay markers handled elsewhere, have no reason.
+fn make_job(
+    entry: walkdir::Result<DirEntry>,
+    is_root: bool,
+    base: &Path,
+    dest_path: &Path,
+    (dest_is_dir, dest_is_link): (bool, bool),
+    opts: &LinkOptions,
+) -> io::Result<Result<LinkJob, Option<Skipped>>> {
+    let entry = entry?;
+    let metadata = entry.metadata()?;
+    let path = entry.path();
+    let overlay_entry = match opts.overlay {
+        OverlayMode::Off => None,
+        _ => overlay::classify(entry.file_name(), &metadata),
+    };
+    let mut kind = if overlay_entry.is_some() {
+        EntryKind::Whiteout
+    } else if metadata.is_dir() {
+        EntryKind::Dir
+    } else if metadata.file_type().is_symlink() {
+        EntryKind::Symlink
+    } else {
+        EntryKind::File
+    };
+
+    let wanted = if kind == EntryKind::Whiteout {
+        opts.overlay == OverlayMode::Translate && !is_root
+    } else if (opts.dest_template.is_some() || opts.flatten) && kind == EntryKind::Dir {
+        // Templates and flattening lay out files alone; directories are
+        // walked through.
+        false
+    } else if opts.preserve_symlinks && kind == EntryKind::Symlink {
+        true
+    } else if opts.archive {
+        metadata.is_dir() || metadata.is_file() || kind == EntryKind::Symlink
+    } else if is_root && kind == EntryKind::Dir && dest_is_link {
+        // The destination symlink is replaced by a link to the whole directory.
+        if !opts.symbolic {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "cannot replace symlink {} with directory {}: hard link not allowed for directory",
+                    dest_path.display(),
+                    path.display()
+                ),
+            ));
+        }
+        true
+    } else if is_root && kind == EntryKind::Dir {
+        false
+    } else if !opts.symbolic {
+        metadata.is_file()
+    } else {
+        !(kind == EntryKind::Dir && opts.symlink_files_only)
+    };
+    if !wanted {
+        let special = kind == EntryKind::File && !metadata.is_file();
+        if !special {
+            let reason = match kind {
+                EntryKind::Whiteout if opts.overlay == OverlayMode::Skip => {
+                    Some(SkipReason::OverlayArtifact)
+                }
+                EntryKind::Symlink => Some(SkipReason::WrongType(
+                    "symlinks are only linked with -s or --archive-link",
+                )),
+                _ => None,
+            };
+            return Ok(Err(reason.map(|reason| Skipped::new(path, reason))));
+        }
+        kind = EntryKind::Special;
+    }
+
+    let rel_path = path.strip_prefix(base).map_err(io::Error::other)?;
+    if let Some(overlay_entry) = overlay_entry {
+        // Opaque markers are handled when their directory is entered.
+        let OverlayEntry::Whiteout(target) = overlay_entry else {
+            return Ok(Err(None));
+        };
+        let rel_path = rel_path.with_file_name(target);
+        return Ok(Ok(LinkJob {
+            source: path.to_path_buf(),
+            dest: dest_path.join(&rel_path),
+            rel_path,
+            kind,
+        }));
+    }
+
+    // Templates and transforms rewrite the path below the destination
+    // directory, which a destination named as the link itself has none of.
+    let place = |rel_path: &Path| -> io::Result<PathBuf> {
+        let rel_path = match &opts.dest_template {
+            Some(template) => template.render(rel_path, path, &metadata)?,
+            None if opts.flatten => PathBuf::from(path.file_name().unwrap()),
+            None => rel_path.to_path_buf(),
+        };
+        if opts.transform.is_empty() {
+            return Ok(dest_path.join(rel_path));
+        }
+        Ok(dest_path.join(transform::transform_path(&opts.transform, &rel_path)?))
+    };
+    let dest = if rel_path.as_os_str().is_empty() {
+        if dest_is_dir && kind != EntryKind::Dir {
+            place(Path::new(path.file_name().unwrap()))?
+        } else {
+            dest_path.to_path_buf()
+        }
+    } else {
+        place(rel_path)?
+    };
+
+    Ok(Ok(LinkJob {
+        source: path.to_path_buf(),
+        dest,
+        rel_path: rel_path.to_path_buf(),
+        kind,
+    }))
+}
+
+/// Runs discovery on a background thread and feeds its jobs to `link`.
+///
+/// The two stages are connected by a bounded channel so statting the source
+/// tree overlaps with conflict checking and link creation. The first error
+/// from either stage ends the run.
+///
+/// # Arguments
+///
+/// * `sources` - The expanded source paths to walk
+/// * `dest_path` - The destination path the links are created under
+/// * `opts` - The options controlling the link behavior
+/// * `link` - The link stage, called once per discovered job in discovery order
+///
+/// # Returns
+///
+/// * `io::Result<LeftOut>` - The entries discovery left out, or an error if
+///   a job could not be discovered or linked
+pub fn run<F>(
+    sources: &[PathBuf],
+    dest_path: &Path,
+    opts: &LinkOptions,
+    mut link: F,
+) -> io::Result<LeftOut>
+where
+    F: FnMut(LinkJob) -> io::Result<()>,
+{
+    let (tx, rx) = sync_channel::<io::Result<LinkJob>>(CHANNEL_CAPACITY);
+
+    thread::scope(|scope| {
+        let discovery = scope.spawn(move || discover(sources, dest_path, opts, &tx));
+
+        for job in rx {
+            link(job?)?;
+        }
+        // A discovery that panicked only closed the channel early; its tree
+        // was not all linked.
+        Ok(discovery.join().unwrap_or_else(|e| panic::resume_unwind(e)))
+    })
+}
+
+/// Runs only the discovery stage and collects every job it produces.
+///
+/// # Arguments
+///
+/// * `sources` - The expanded source paths to walk
+/// * `dest_path` - The destination path the links would be created under
+/// * `opts` - The options controlling which entries are linked
+///
+/// # Returns
+///
+/// * `io::Result<Vec<LinkJob>>` - The jobs in discovery order
+pub fn collect(
+    sources: &[PathBuf],
+    dest_path: &Path,
+    opts: &LinkOptions,
+) -> io::Result<Vec<LinkJob>> {
+    let mut jobs = Vec::new();
+    run(sources, dest_path, opts, |job| {
+        jobs.push(job);
+        Ok(())
+    })?;
+    Ok(jobs)
+}
+
+/// Source entries from different roots that map to the same destination path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Collision {
+    /// The destination path they share
+    pub dest: PathBuf,
+    /// The colliding source entries, in discovery order
+    pub sources: Vec<PathBuf>,
+}
+
+/// Returns every destination path more than one job of a planned run would
+/// be linked to. Directories recreated by `--archive-link` merge and do not
+/// collide.
+///
+/// # Arguments
+///
+/// * `jobs` - The planned jobs, in discovery order
+/// * `opts` - The options the run is made with
+///
+/// # Returns
+///
+/// * `Vec<Collision>` - The collisions, ordered by first discovery
+pub fn find_collisions(jobs: &[LinkJob], opts: &LinkOptions) -> Vec<Collision> {
+    let mut by_dest: HashMap<&Path, usize> = HashMap::new();
+    let mut claims: Vec<Collision> = Vec::new();
+    for job in jobs {
+        if !job.kind.creates_link() || (job.kind == EntryKind::Dir && opts.archive) {
+            continue;
+        }
+        match by_dest.get(job.dest.as_path()) {
+            Some(&i) => claims[i].sources.push(job.source.clone()),
+            None => {
+                by_dest.insert(&job.dest, claims.len());
+                claims.push(Collision {
+                    dest: job.dest.clone(),
+                    sources: vec![job.source.clone()],
+                });
+            }
+        }
+    }
+    claims.retain(|claim| claim.sources.len() > 1);
+    claims
+}
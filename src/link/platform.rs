@@ -0,0 +1,312 @@
+use std::borrow::Cow;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf, Prefix};
+
+/// Creates a symbolic link at `link` pointing at `target`.
+///
+/// # Arguments
+///
+/// * `target` - The path the link points at
+/// * `link` - The path where the link should be created
+///
+/// # Returns
+///
+/// * `io::Result<()>` - Success if the symlink was created
+#[cfg(unix)]
+pub fn symlink(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+/// Creates a symbolic link at `link` pointing at `target`.
+///
+/// Windows tells file and directory symlinks apart, so the kind follows what
+/// `target` names, resolved against the directory holding `link` when it is
+/// relative; a dangling target gets a file symlink. Creating symlinks needs
+/// Developer Mode or `SeCreateSymbolicLinkPrivilege`, which the destination
+/// probe checks before anything is linked.
+#[cfg(windows)]
+pub fn symlink(target: &Path, link: &Path) -> io::Result<()> {
+    use std::os::windows::fs::{symlink_dir, symlink_file};
+
+    if link.parent().unwrap_or(Path::new("")).join(target).is_dir() {
+        symlink_dir(target, link)
+    } else {
+        symlink_file(target, link)
+    }
+}
+
+/// Creates a symbolic link at `link` pointing at `target`.
+///
+/// WASI only grants symlink creation inside preopened directories and
+/// sandboxes commonly deny it, so the WASI build reports symlinks as
+/// unsupported. The destination probe then points users at hard links.
+#[cfg(all(not(unix), not(windows), feature = "wasi"))]
+pub fn symlink(_target: &Path, _link: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "symbolic links are not available in the WASI build",
+    ))
+}
+
+/// Removes a file or symbolic link.
+///
+/// Windows keeps directory symlinks as directories, which `remove_file`
+/// refuses, so they are removed with `remove_dir` without touching their target.
+///
+/// # Arguments
+///
+/// * `path` - The file or link to remove
+///
+/// # Returns
+///
+/// * `io::Result<()>` - Success if the entry was removed
+#[cfg(windows)]
+pub fn remove_link(path: &Path) -> io::Result<()> {
+    use std::os::windows::fs::FileTypeExt;
+
+    if fs::symlink_metadata(path)?.file_type().is_symlink_dir() {
+        fs::remove_dir(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+/// Removes a file or symbolic link.
+#[cfg(not(windows))]
+pub fn remove_link(path: &Path) -> io::Result<()> {
+    fs::remove_file(path)
+}
+
+/// Renders a relative path with `/` separators, the form filter rules and
+/// globs are written in.
+///
+/// # Arguments
+///
+/// * `path` - The path to render
+///
+/// # Returns
+///
+/// * `Cow<'_, str>` - The path, lossily converted, with `\` separators replaced on Windows
+pub fn slash_path(path: &Path) -> Cow<'_, str> {
+    let text = path.to_string_lossy();
+    if cfg!(windows) && text.contains('\\') {
+        Cow::Owned(text.replace('\\', "/"))
+    } else {
+        text
+    }
+}
+
+/// Returns the id of the device holding an entry.
+///
+/// # Arguments
+///
+/// * `metadata` - The entry's metadata
+///
+/// # Returns
+///
+/// * `Option<u64>` - The device id, or `None` where the platform does not expose it
+#[cfg(unix)]
+pub fn device(metadata: &fs::Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+
+    Some(metadata.dev())
+}
+
+/// Returns the id of the device holding an entry.
+///
+/// Device ids are not exposed by stable Rust on WASI.
+#[cfg(not(unix))]
+pub fn device(_metadata: &fs::Metadata) -> Option<u64> {
+    None
+}
+
+/// Returns the device and inode numbers identifying an entry's inode.
+///
+/// # Arguments
+///
+/// * `metadata` - The entry's metadata
+///
+/// # Returns
+///
+/// * `Option<(u64, u64)>` - The device and inode, or `None` where the platform does not expose them
+#[cfg(unix)]
+pub fn identity(metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+
+    Some((metadata.dev(), metadata.ino()))
+}
+
+/// Returns the device and inode numbers identifying an entry's inode.
+///
+/// Inode numbers are not exposed by stable Rust on WASI.
+#[cfg(not(unix))]
+pub fn identity(_metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// Returns true if the current user may create entries in `dir`.
+///
+/// # Arguments
+///
+/// * `dir` - The directory
+///
+/// # Returns
+///
+/// * `bool` - True if `access(2)` grants write permission
+#[cfg(unix)]
+pub fn is_writable(dir: &Path) -> bool {
+    use std::os::unix::ffi::OsStrExt;
+
+    std::ffi::CString::new(dir.as_os_str().as_bytes())
+        .map(|path| unsafe { libc::access(path.as_ptr(), libc::W_OK) } == 0)
+        .unwrap_or(false)
+}
+
+/// Returns true if the current user may create entries in `dir`.
+///
+/// Without `access(2)`, only the read-only attribute is checked.
+#[cfg(not(unix))]
+pub fn is_writable(dir: &Path) -> bool {
+    fs::metadata(dir).is_ok_and(|meta| !meta.permissions().readonly())
+}
+
+/// Creates a copy-on-write clone of `source` at `dest`, sharing its data
+/// blocks until either file is changed.
+///
+/// # Arguments
+///
+/// * `source` - The file to clone
+/// * `dest` - Where to create the clone; it must not exist
+///
+/// # Returns
+///
+/// * `io::Result<()>` - Success if the clone was created; on failure no file is left at `dest`
+#[cfg(target_os = "linux")]
+pub fn reflink(source: &Path, dest: &Path) -> io::Result<()> {
+    use std::fs::{File, OpenOptions};
+    use std::os::unix::io::AsRawFd;
+
+    let src = File::open(source)?;
+    let dst = OpenOptions::new().write(true).create_new(true).open(dest)?;
+    if unsafe { libc::ioctl(dst.as_raw_fd(), libc::FICLONE, src.as_raw_fd()) } != 0 {
+        let err = io::Error::last_os_error();
+        let _ = fs::remove_file(dest);
+        return Err(err);
+    }
+    fs::set_permissions(dest, src.metadata()?.permissions())
+}
+
+/// Creates a copy-on-write clone of `source` at `dest` with `clonefile`,
+/// which also copies the file's mode and extended attributes.
+#[cfg(target_os = "macos")]
+pub fn reflink(source: &Path, dest: &Path) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    /// `CLONE_NOFOLLOW` from `sys/clonefile.h`, which libc does not export.
+    const CLONE_NOFOLLOW: u32 = 0x0001;
+
+    let src = CString::new(source.as_os_str().as_bytes())?;
+    let dst = CString::new(dest.as_os_str().as_bytes())?;
+    if unsafe { libc::clonefile(src.as_ptr(), dst.as_ptr(), CLONE_NOFOLLOW) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Creates a copy-on-write clone of `source` at `dest`.
+///
+/// Only Linux's `FICLONE` and macOS's `clonefile` are supported.
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn reflink(_source: &Path, _dest: &Path) -> io::Result<()> {
+    Err(io::Error::from(io::ErrorKind::Unsupported))
+}
+
+/// The drive or share a Windows path is rooted in.
+#[derive(Debug, Clone, Copy)]
+enum Volume<'a> {
+    /// A drive letter, e.g. `C:`
+    Disk(u8),
+    /// A UNC share, e.g. `\\server\share`
+    Share(&'a OsStr, &'a OsStr),
+    /// A device namespace or other verbatim prefix
+    Other(&'a OsStr),
+}
+
+impl PartialEq for Volume<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Volume::Disk(a), Volume::Disk(b)) => a.eq_ignore_ascii_case(b),
+            (Volume::Share(a, x), Volume::Share(b, y)) => {
+                a.eq_ignore_ascii_case(b) && x.eq_ignore_ascii_case(y)
+            }
+            (Volume::Other(a), Volume::Other(b)) => a.eq_ignore_ascii_case(b),
+            _ => false,
+        }
+    }
+}
+
+/// Returns the volume a path's prefix names, treating the verbatim `\\?\`
+/// forms `canonicalize` returns on Windows like their plain equivalents.
+fn volume(path: &Path) -> Option<Volume<'_>> {
+    let Some(Component::Prefix(prefix)) = path.components().next() else {
+        return None;
+    };
+    Some(match prefix.kind() {
+        Prefix::Disk(d) | Prefix::VerbatimDisk(d) => Volume::Disk(d),
+        Prefix::UNC(server, share) | Prefix::VerbatimUNC(server, share) => {
+            Volume::Share(server, share)
+        }
+        Prefix::DeviceNS(name) | Prefix::Verbatim(name) => Volume::Other(name),
+    })
+}
+
+/// Returns true if a relative path can lead from one absolute path to the
+/// other, which on Windows requires both to be on the same drive or UNC share.
+/// On Unix every path shares the single root.
+///
+/// # Arguments
+///
+/// * `a` - An absolute path
+/// * `b` - Another absolute path
+///
+/// # Returns
+///
+/// * `bool` - True if both paths are on the same volume
+pub fn same_volume(a: &Path, b: &Path) -> bool {
+    volume(a) == volume(b)
+}
+
+/// Rewrites a verbatim `\\?\C:\...` or `\\?\UNC\server\share\...` path into
+/// its plain form, which other programs resolve when following a symlink.
+/// Paths without a verbatim drive or UNC prefix are returned unchanged.
+///
+/// # Arguments
+///
+/// * `path` - An absolute path, typically returned by `canonicalize`
+///
+/// # Returns
+///
+/// * `PathBuf` - The path without its verbatim prefix
+pub fn strip_verbatim(path: &Path) -> PathBuf {
+    let mut components = path.components();
+    let plain = match components.next() {
+        Some(Component::Prefix(prefix)) => match prefix.kind() {
+            Prefix::VerbatimDisk(d) => OsString::from(format!("{}:", d as char)),
+            Prefix::VerbatimUNC(server, share) => {
+                let mut plain = OsString::from(r"\\");
+                plain.push(server);
+                plain.push(r"\");
+                plain.push(share);
+                plain
+            }
+            _ => return path.to_path_buf(),
+        },
+        _ => return path.to_path_buf(),
+    };
+    let mut out = PathBuf::from(plain);
+    out.extend(components);
+    out
+}
@@ -0,0 +1,457 @@
+use crate::link::link_options::LinkOptions;
+use crate::link::platform;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// What the filesystem holding a destination is able to do.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FsCapabilities {
+    /// The directory the capabilities were probed in
+    pub probed_dir: PathBuf,
+    /// A short name for the filesystem type, e.g. "ext4" or "vfat"
+    pub fs_type: String,
+    /// The device id of the filesystem
+    pub device: u64,
+    /// True if hard links can be created
+    pub hard_links: bool,
+    /// True if symbolic links can be created
+    pub symlinks: bool,
+    /// True if copy-on-write clones (reflinks) can be created
+    pub reflinks: bool,
+    /// True if file names differing only in case refer to different files
+    pub case_sensitive: bool,
+    /// The longest file name the filesystem accepts, in bytes
+    pub name_max: Option<u64>,
+    /// The longest path the filesystem accepts, in bytes
+    pub path_max: Option<u64>,
+}
+
+impl FsCapabilities {
+    /// True if the filesystem is a FAT variant, which supports neither kind of link.
+    pub fn is_fat(&self) -> bool {
+        matches!(self.fs_type.as_str(), "vfat" | "exfat")
+    }
+
+    /// True if the filesystem is a network share.
+    pub fn is_network(&self) -> bool {
+        matches!(self.fs_type.as_str(), "nfs" | "smb" | "smb2" | "cifs")
+    }
+}
+
+/// What to do when files cannot be hard linked: because the destination
+/// filesystem can hold no links at all, as on FAT and exFAT volumes, or
+/// because a hard link would cross devices.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FallbackPolicy {
+    /// Stop before anything is created
+    #[default]
+    Fail,
+    /// Make a symbolic link instead, across devices only
+    Symlink,
+    /// Copy the files instead of linking them
+    Copy,
+    /// Clone the files copy-on-write instead, across devices only
+    Reflink,
+    /// Leave the destination alone, with a warning
+    Skip,
+}
+
+impl FallbackPolicy {
+    /// The name of the policy as given on the command line.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FallbackPolicy::Fail => "fail",
+            FallbackPolicy::Symlink => "symlink",
+            FallbackPolicy::Copy => "copy",
+            FallbackPolicy::Reflink => "reflink",
+            FallbackPolicy::Skip => "skip",
+        }
+    }
+}
+
+impl FromStr for FallbackPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fail" => Ok(FallbackPolicy::Fail),
+            "symlink" => Ok(FallbackPolicy::Symlink),
+            "copy" => Ok(FallbackPolicy::Copy),
+            "reflink" => Ok(FallbackPolicy::Reflink),
+            "skip" => Ok(FallbackPolicy::Skip),
+            _ => Err(format!(
+                "invalid fallback policy {} (expected fail, symlink, copy, reflink, or skip)",
+                s
+            )),
+        }
+    }
+}
+
+/// Returns a short name for the type of filesystem holding `path`.
+///
+/// # Arguments
+///
+/// * `path` - An existing path on the filesystem to identify
+///
+/// # Returns
+///
+/// * `io::Result<String>` - The filesystem name, or "unknown" if it is not recognized
+#[cfg(target_os = "linux")]
+pub fn filesystem_type(path: &Path) -> io::Result<String> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    let mut buf: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(c_path.as_ptr(), &mut buf) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let name = match (buf.f_type as u64) & 0xffff_ffff {
+        0xef53 => "ext4",
+        0x9123_683e => "btrfs",
+        0x5846_5342 => "xfs",
+        0x2fc1_2fc1 => "zfs",
+        0x0102_1994 => "tmpfs",
+        0x794c_7630 => "overlayfs",
+        0x4d44 => "vfat",
+        0x2011_bab0 => "exfat",
+        0x5346_544e => "ntfs",
+        0x6573_5546 => "fuse",
+        0x6969 => "nfs",
+        0x517b => "smb",
+        0xfe53_4d42 => "smb2",
+        0xff53_4d42 => "cifs",
+        _ => "unknown",
+    };
+    Ok(name.to_string())
+}
+
+/// Returns a short name for the type of filesystem holding `path`.
+///
+/// Filesystem identification is only implemented on Linux.
+#[cfg(not(target_os = "linux"))]
+pub fn filesystem_type(path: &Path) -> io::Result<String> {
+    fs::metadata(path)?;
+    Ok(String::from("unknown"))
+}
+
+/// Returns the space available to unprivileged users on the filesystem holding `path`.
+///
+/// # Arguments
+///
+/// * `path` - An existing path on the filesystem to query
+///
+/// # Returns
+///
+/// * `io::Result<u64>` - The available space in bytes
+#[cfg(unix)]
+pub fn free_space(path: &Path) -> io::Result<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    let mut buf: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut buf) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    #[allow(clippy::unnecessary_cast)]
+    Ok(buf.f_bavail as u64 * buf.f_frsize as u64)
+}
+
+/// Returns the space available to unprivileged users on the filesystem holding `path`.
+///
+/// WASI has no way to query free space.
+#[cfg(not(unix))]
+pub fn free_space(path: &Path) -> io::Result<u64> {
+    fs::metadata(path)?;
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "free space cannot be queried under WASI",
+    ))
+}
+
+/// Queries the longest file name and path `path`'s filesystem accepts,
+/// with `None` for a limit that is indeterminate.
+#[cfg(unix)]
+fn name_limits(path: &Path) -> (Option<u64>, Option<u64>) {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let Ok(c_path) = CString::new(path.as_os_str().as_bytes()) else {
+        return (None, None);
+    };
+    let limit = |name| {
+        let value = unsafe { libc::pathconf(c_path.as_ptr(), name) };
+        u64::try_from(value).ok().filter(|v| *v > 0)
+    };
+    (limit(libc::_PC_NAME_MAX), limit(libc::_PC_PATH_MAX))
+}
+
+/// Queries the longest file name and path `path`'s filesystem accepts; WASI
+/// has no `pathconf`, so both are unknown.
+#[cfg(not(unix))]
+fn name_limits(_path: &Path) -> (Option<u64>, Option<u64>) {
+    (None, None)
+}
+
+/// The name prefix of the temporary entries a probe creates, followed by the
+/// probing process id.
+pub const PROBE_PREFIX: &str = ".flnk-probe-";
+
+/// Probes a directory by performing test operations on temporary names inside it.
+///
+/// Every probe entry is removed again before returning, whether or not the
+/// operation it tested succeeded.
+///
+/// # Arguments
+///
+/// * `dir` - An existing, writable directory on the filesystem to probe
+///
+/// # Returns
+///
+/// * `io::Result<FsCapabilities>` - The capabilities of the filesystem holding `dir`
+pub fn probe(dir: &Path) -> io::Result<FsCapabilities> {
+    let nonce = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or_default();
+    let stem = format!("{}{}-{}", PROBE_PREFIX, process::id(), nonce);
+    let file = dir.join(format!("{}-a", stem));
+    let upper = dir.join(format!("{}-A", stem));
+    let hard = dir.join(format!("{}-hard", stem));
+    let sym = dir.join(format!("{}-sym", stem));
+    let clone = dir.join(format!("{}-clone", stem));
+
+    fs::write(&file, b"flnk")?;
+    let device = fs::metadata(&file)
+        .ok()
+        .and_then(|m| platform::device(&m))
+        .unwrap_or_default();
+
+    let hard_links = fs::hard_link(&file, &hard).is_ok();
+    let symlinks = platform::symlink(&file, &sym).is_ok();
+    let reflinks = platform::reflink(&file, &clone).is_ok();
+    let case_sensitive = fs::symlink_metadata(&upper).is_err();
+
+    for path in [&hard, &sym, &clone, &file] {
+        let _ = fs::remove_file(path);
+    }
+
+    let (name_max, path_max) = name_limits(dir);
+    Ok(FsCapabilities {
+        probed_dir: dir.to_path_buf(),
+        fs_type: filesystem_type(dir)?,
+        device,
+        hard_links,
+        symlinks,
+        reflinks,
+        case_sensitive,
+        name_max,
+        path_max,
+    })
+}
+
+/// Returns the nearest existing directory at or above a destination path,
+/// which is where the destination will be created.
+///
+/// # Arguments
+///
+/// * `dest` - The destination path of a link operation
+///
+/// # Returns
+///
+/// * `Option<&Path>` - The directory, or `None` if not even the current directory exists
+pub fn existing_ancestor(dest: &Path) -> Option<&Path> {
+    let mut dir = if dest.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dest
+    };
+    while !dir.is_dir() {
+        dir = match dir.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ if dir != Path::new(".") => Path::new("."),
+            _ => return None,
+        };
+    }
+    Some(dir)
+}
+
+/// Probes the filesystem a destination path will be created on.
+///
+/// The destination itself may not exist yet, so the nearest existing ancestor
+/// directory is probed instead. Probing is best effort: a read-only or
+/// otherwise unprobeable destination yields `None` and the run proceeds as usual.
+///
+/// # Arguments
+///
+/// * `dest` - The destination path of a link operation
+///
+/// # Returns
+///
+/// * `Option<FsCapabilities>` - The probed capabilities, if probing succeeded
+pub fn probe_destination(dest: &Path) -> Option<FsCapabilities> {
+    probe(existing_ancestor(dest)?).ok()
+}
+
+/// Checks up front that the probed filesystem can hold the links the options ask for.
+///
+/// # Arguments
+///
+/// * `caps` - The probed capabilities of the destination filesystem
+/// * `opts` - The options controlling the link behavior
+///
+/// # Returns
+///
+/// * `io::Result<()>` - An `Unsupported` error describing how to proceed if links cannot be created
+pub fn check_supported(caps: &FsCapabilities, opts: &LinkOptions) -> io::Result<()> {
+    let message = if opts.reflink && !caps.reflinks {
+        format!(
+            "destination filesystem ({}) at {} does not support copy-on-write clones; reflinks need a filesystem such as Btrfs, XFS, or APFS, so drop --reflink to create hard links instead",
+            caps.fs_type,
+            caps.probed_dir.display()
+        )
+    } else if opts.reflink {
+        return Ok(());
+    } else if opts.symbolic && !caps.symlinks {
+        if caps.hard_links {
+            format!(
+                "destination filesystem ({}) at {} does not support symbolic links; drop -s to create hard links instead",
+                caps.fs_type,
+                caps.probed_dir.display()
+            )
+        } else {
+            unsupported_message(caps)
+        }
+    } else if !opts.symbolic && !caps.hard_links {
+        if caps.symlinks {
+            format!(
+                "destination filesystem ({}) at {} does not support hard links; use -s to create symbolic links instead",
+                caps.fs_type,
+                caps.probed_dir.display()
+            )
+        } else {
+            unsupported_message(caps)
+        }
+    } else {
+        return Ok(());
+    };
+
+    Err(io::Error::new(io::ErrorKind::Unsupported, message))
+}
+
+/// The most offending paths `check_path_limits` lists in its error.
+const MAX_LISTED_PATHS: usize = 10;
+
+/// Checks every planned destination path against the probed filesystem's
+/// name and path length limits, so a run fails before anything is created
+/// instead of partway through.
+///
+/// # Arguments
+///
+/// * `caps` - The probed capabilities of the destination filesystem
+/// * `dests` - The destination paths the run would create
+///
+/// # Returns
+///
+/// * `io::Result<()>` - An `InvalidFilename` error listing the offending paths, if any
+pub fn check_path_limits<'a>(
+    caps: &FsCapabilities,
+    dests: impl IntoIterator<Item = &'a Path>,
+) -> io::Result<()> {
+    let mut offending = Vec::new();
+    let mut longest = 0;
+    for dest in dests {
+        let path_len = dest.as_os_str().len() as u64;
+        longest = longest.max(path_len);
+        let name_len = dest
+            .components()
+            .map(|c| c.as_os_str().len() as u64)
+            .max()
+            .unwrap_or(0);
+        if caps.name_max.is_some_and(|max| name_len > max) {
+            offending.push(format!("{} (a name is {} bytes)", dest.display(), name_len));
+        } else if caps.path_max.is_some_and(|max| path_len >= max) {
+            // PATH_MAX counts the terminating NUL.
+            offending.push(format!("{} (path is {} bytes)", dest.display(), path_len));
+        }
+    }
+    if offending.is_empty() {
+        return Ok(());
+    }
+
+    let limit = |max: Option<u64>| max.map_or(String::from("unknown"), |max| max.to_string());
+    let mut message = format!(
+        "{} destination path{} exceed{} the limits of the destination filesystem ({}) at {}: names up to {} bytes, paths up to {} bytes; the longest planned path is {} bytes",
+        offending.len(),
+        if offending.len() == 1 { "" } else { "s" },
+        if offending.len() == 1 { "s" } else { "" },
+        caps.fs_type,
+        caps.probed_dir.display(),
+        limit(caps.name_max),
+        limit(caps.path_max.map(|max| max - 1)),
+        longest
+    );
+    for path in offending.iter().take(MAX_LISTED_PATHS) {
+        message.push_str("\n  ");
+        message.push_str(path);
+    }
+    if offending.len() > MAX_LISTED_PATHS {
+        message.push_str(&format!(
+            "\n  ... and {} more",
+            offending.len() - MAX_LISTED_PATHS
+        ));
+    }
+    Err(io::Error::new(io::ErrorKind::InvalidFilename, message))
+}
+
+/// Decides once for the whole run how to proceed on the probed filesystem.
+///
+/// A filesystem holding neither kind of link gets the options' `copy` or
+/// `skip` fallback policy; such a filesystem cannot hold symlinks or clones
+/// either. A filesystem lacking only the requested kind is always an error,
+/// since switching link kinds is the better remedy.
+///
+/// # Arguments
+///
+/// * `caps` - The probed capabilities of the destination filesystem
+/// * `opts` - The options controlling the link behavior
+///
+/// # Returns
+///
+/// * `io::Result<Option<FallbackPolicy>>` - `None` to link as usual, the
+///   `Copy` or `Skip` policy to apply instead, or the error of `check_supported`
+pub fn resolve(caps: &FsCapabilities, opts: &LinkOptions) -> io::Result<Option<FallbackPolicy>> {
+    match check_supported(caps, opts) {
+        Ok(()) => Ok(None),
+        Err(_)
+            if !caps.hard_links
+                && !caps.symlinks
+                && matches!(opts.fallback, FallbackPolicy::Copy | FallbackPolicy::Skip) =>
+        {
+            Ok(Some(opts.fallback))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+fn unsupported_message(caps: &FsCapabilities) -> String {
+    let hint = if caps.is_fat() {
+        "FAT and exFAT volumes cannot hold links of any kind"
+    } else if caps.is_network() {
+        "the network share does not allow links; check the server's mount options"
+    } else {
+        "the filesystem does not allow links"
+    };
+    format!(
+        "destination filesystem ({}) at {} supports neither hard nor symbolic links: {}; use --fallback copy or --fallback skip to proceed",
+        caps.fs_type,
+        caps.probed_dir.display(),
+        hint
+    )
+}
@@ -0,0 +1,58 @@
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+/// What to do with a destination entry that already exists, as answered at
+/// the `--interactive` prompt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    /// Replace this entry
+    Replace,
+    /// Leave this entry alone and go on with the run
+    Keep,
+    /// Replace this entry and every later one without asking again
+    ReplaceAll,
+    /// Stop the run, leaving what was linked so far
+    Quit,
+}
+
+impl Resolution {
+    /// Reads an answer to the replace prompt: `y`, `n`, `a`, or `q`, in
+    /// either case. Like GNU ln, anything else keeps the entry.
+    ///
+    /// # Arguments
+    ///
+    /// * `answer` - The line typed at the prompt
+    ///
+    /// # Returns
+    ///
+    /// * `Resolution` - What the answer asks for
+    pub fn from_answer(answer: &str) -> Self {
+        match answer.trim_start().chars().next() {
+            Some('y' | 'Y') => Resolution::Replace,
+            Some('a' | 'A') => Resolution::ReplaceAll,
+            Some('q' | 'Q') => Resolution::Quit,
+            _ => Resolution::Keep,
+        }
+    }
+}
+
+/// Asks on the terminal whether to replace an existing destination entry,
+/// like `ln -i`: `replace 'foo'? [y/n/a/q] ` on stderr, answered on stdin.
+///
+/// # Arguments
+///
+/// * `dest` - The destination entry that already exists
+///
+/// # Returns
+///
+/// * `io::Result<Resolution>` - The answer; the entry is kept if stdin is at
+///   its end
+pub fn ask_replace(dest: &Path) -> io::Result<Resolution> {
+    let mut stderr = io::stderr().lock();
+    write!(stderr, "replace '{}'? [y/n/a/q] ", dest.display())?;
+    stderr.flush()?;
+
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(Resolution::from_answer(&answer))
+}
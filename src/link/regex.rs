@@ -0,0 +1,598 @@
+/// A compiled regular expression, for rewriting paths with `--transform`.
+///
+/// Supports the extended syntax of `sed -E`: literals and `.`, bracket
+/// expressions such as `[a-z]` or `[^[:digit:]]`, the anchors `^` and `$`,
+/// groups `( )` (and non-capturing `(?: )`), alternation `|`, and the
+/// quantifiers `*`, `+`, `?`, `{n}`, `{n,}`, and `{n,m}`, each of which can
+/// be made lazy with a trailing `?`. `\d`, `\w`, and `\s` and their negations
+/// stand for digits, word characters, and whitespace. Back-references are not
+/// supported.
+///
+/// Matching backtracks in the order of the alternatives, like Perl, but never
+/// visits the same position of the pattern and the text twice, so it takes
+/// time proportional to the size of both.
+#[derive(Debug, Clone)]
+pub struct Regex {
+    insts: Vec<Inst>,
+    groups: usize,
+    ignore_case: bool,
+}
+
+/// One step of a compiled pattern.
+#[derive(Debug, Clone)]
+enum Inst {
+    Char(char),
+    Any,
+    Class(Class),
+    /// Tries the first branch, then the second
+    Split(usize, usize),
+    Jmp(usize),
+    /// Records the current position in a capture slot
+    Save(usize),
+    Start,
+    End,
+    Match,
+}
+
+/// A bracket expression or class escape.
+#[derive(Debug, Clone, Default)]
+struct Class {
+    negated: bool,
+    items: Vec<ClassItem>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ClassItem {
+    Range(char, char),
+    Digit,
+    Word,
+    Space,
+    Alpha,
+    Alnum,
+    Upper,
+    Lower,
+    Punct,
+    XDigit,
+}
+
+impl ClassItem {
+    fn matches(self, c: char) -> bool {
+        match self {
+            ClassItem::Range(lo, hi) => lo <= c && c <= hi,
+            ClassItem::Digit => c.is_ascii_digit(),
+            ClassItem::Word => c.is_alphanumeric() || c == '_',
+            ClassItem::Space => c.is_whitespace(),
+            ClassItem::Alpha => c.is_alphabetic(),
+            ClassItem::Alnum => c.is_alphanumeric(),
+            ClassItem::Upper => c.is_uppercase(),
+            ClassItem::Lower => c.is_lowercase(),
+            ClassItem::Punct => c.is_ascii_punctuation(),
+            ClassItem::XDigit => c.is_ascii_hexdigit(),
+        }
+    }
+}
+
+impl Class {
+    fn of(item: ClassItem, negated: bool) -> Class {
+        Class {
+            negated,
+            items: vec![item],
+        }
+    }
+
+    fn matches(&self, c: char, ignore_case: bool) -> bool {
+        let hit = |c: char| self.items.iter().any(|item| item.matches(c));
+        let found = hit(c) || ignore_case && (hit(lower(c)) || hit(upper(c)));
+        found != self.negated
+    }
+}
+
+fn lower(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+fn upper(c: char) -> char {
+    c.to_uppercase().next().unwrap_or(c)
+}
+
+/// A parsed pattern, before it is compiled.
+#[derive(Debug)]
+enum Node {
+    Empty,
+    Char(char),
+    Any,
+    Class(Class),
+    Start,
+    End,
+    Group(Box<Node>, Option<usize>),
+    Concat(Vec<Node>),
+    Alt(Vec<Node>),
+    Repeat {
+        node: Box<Node>,
+        min: u32,
+        max: Option<u32>,
+        greedy: bool,
+    },
+}
+
+/// The largest count a `{n,m}` quantifier may give.
+const MAX_REPEAT: u32 = 1000;
+
+/// The most steps a compiled pattern may have, which nested counted
+/// repetitions could otherwise multiply without bound.
+const MAX_INSTS: usize = 100_000;
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    groups: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn alternation(&mut self) -> Result<Node, String> {
+        let mut branches = vec![self.sequence()?];
+        while self.eat('|') {
+            branches.push(self.sequence()?);
+        }
+        Ok(if branches.len() == 1 {
+            branches.pop().unwrap()
+        } else {
+            Node::Alt(branches)
+        })
+    }
+
+    fn sequence(&mut self) -> Result<Node, String> {
+        let mut nodes = Vec::new();
+        while let Some(c) = self.peek()
+            && c != '|'
+            && c != ')'
+        {
+            nodes.push(self.repeat()?);
+        }
+        Ok(match nodes.len() {
+            0 => Node::Empty,
+            1 => nodes.pop().unwrap(),
+            _ => Node::Concat(nodes),
+        })
+    }
+
+    fn repeat(&mut self) -> Result<Node, String> {
+        let mut node = self.atom()?;
+        loop {
+            let start = self.pos;
+            let (min, max) = match self.peek() {
+                Some('*') => (0, None),
+                Some('+') => (1, None),
+                Some('?') => (0, Some(1)),
+                Some('{') => match self.bounds()? {
+                    Some(bounds) => bounds,
+                    None => break,
+                },
+                _ => break,
+            };
+            if self.pos == start {
+                self.pos += 1;
+            }
+            let greedy = !self.eat('?');
+            node = Node::Repeat {
+                node: Box::new(node),
+                min,
+                max,
+                greedy,
+            };
+        }
+        Ok(node)
+    }
+
+    /// Parses `{n}`, `{n,}`, or `{n,m}`, or returns `None`, consuming
+    /// nothing, if the brace does not start one.
+    fn bounds(&mut self) -> Result<Option<(u32, Option<u32>)>, String> {
+        let rest: String = self.chars[self.pos + 1..].iter().collect();
+        let Some(close) = rest.find('}') else {
+            return Ok(None);
+        };
+        let body = &rest[..close];
+        let number = |text: &str| text.parse::<u32>().ok();
+        let (min, max) = match body.split_once(',') {
+            None => match number(body) {
+                Some(n) => (n, Some(n)),
+                None => return Ok(None),
+            },
+            Some((min, "")) => match number(min) {
+                Some(min) => (min, None),
+                None => return Ok(None),
+            },
+            Some((min, max)) => match (number(min), number(max)) {
+                (Some(min), Some(max)) => (min, Some(max)),
+                _ => return Ok(None),
+            },
+        };
+        if max.is_some_and(|max| max < min) || max.unwrap_or(min) > MAX_REPEAT {
+            return Err(format!("invalid repetition {{{}}}", body));
+        }
+        self.pos += body.chars().count() + 2;
+        Ok(Some((min, max)))
+    }
+
+    fn atom(&mut self) -> Result<Node, String> {
+        let c = self.peek().unwrap();
+        self.pos += 1;
+        Ok(match c {
+            '.' => Node::Any,
+            '^' => Node::Start,
+            '$' => Node::End,
+            '[' => Node::Class(self.bracket()?),
+            '(' => {
+                let index = if self.eat('?') {
+                    if !self.eat(':') {
+                        return Err(String::from("unsupported group syntax (?"));
+                    }
+                    None
+                } else {
+                    self.groups += 1;
+                    Some(self.groups)
+                };
+                let inner = self.alternation()?;
+                if !self.eat(')') {
+                    return Err(String::from("unmatched ("));
+                }
+                Node::Group(Box::new(inner), index)
+            }
+            ')' => return Err(String::from("unmatched )")),
+            '*' | '+' | '?' => return Err(format!("nothing to repeat before {}", c)),
+            '\\' => {
+                let Some(escaped) = self.peek() else {
+                    return Err(String::from("trailing backslash"));
+                };
+                self.pos += 1;
+                match escape(escaped) {
+                    Some(class) => Node::Class(class),
+                    None if escaped.is_ascii_digit() => {
+                        return Err(format!(
+                            "back-references such as \\{} are not supported",
+                            escaped
+                        ));
+                    }
+                    None => Node::Char(literal(escaped)),
+                }
+            }
+            c => Node::Char(c),
+        })
+    }
+
+    /// Parses a bracket expression after its `[`.
+    fn bracket(&mut self) -> Result<Class, String> {
+        let mut class = Class {
+            negated: self.eat('^'),
+            items: Vec::new(),
+        };
+        let mut first = true;
+        loop {
+            let Some(c) = self.peek() else {
+                return Err(String::from("unmatched ["));
+            };
+            self.pos += 1;
+            if c == ']' && !first {
+                return Ok(class);
+            }
+            first = false;
+            let lo = match c {
+                '[' if self.eat(':') => {
+                    let rest: String = self.chars[self.pos..].iter().collect();
+                    let Some(end) = rest.find(":]") else {
+                        return Err(String::from("unmatched [:"));
+                    };
+                    let name = &rest[..end];
+                    class.items.push(match name {
+                        "alpha" => ClassItem::Alpha,
+                        "digit" => ClassItem::Digit,
+                        "alnum" => ClassItem::Alnum,
+                        "upper" => ClassItem::Upper,
+                        "lower" => ClassItem::Lower,
+                        "space" => ClassItem::Space,
+                        "punct" => ClassItem::Punct,
+                        "xdigit" => ClassItem::XDigit,
+                        _ => return Err(format!("unknown character class [:{}:]", name)),
+                    });
+                    self.pos += name.chars().count() + 2;
+                    continue;
+                }
+                '\\' => {
+                    let Some(escaped) = self.peek() else {
+                        return Err(String::from("unmatched ["));
+                    };
+                    self.pos += 1;
+                    match escape(escaped) {
+                        Some(Class {
+                            negated: false,
+                            items,
+                        }) => {
+                            class.items.extend(items);
+                            continue;
+                        }
+                        Some(_) => {
+                            return Err(format!("\\{} cannot be used in brackets", escaped));
+                        }
+                        None => literal(escaped),
+                    }
+                }
+                c => c,
+            };
+            let hi = if self.peek() == Some('-')
+                && let Some(&hi) = self.chars.get(self.pos + 1)
+                && hi != ']'
+            {
+                self.pos += 2;
+                if hi < lo {
+                    return Err(format!("invalid range {}-{}", lo, hi));
+                }
+                hi
+            } else {
+                lo
+            };
+            class.items.push(ClassItem::Range(lo, hi));
+        }
+    }
+}
+
+/// Returns the class a `\` escape stands for, if it is one.
+fn escape(c: char) -> Option<Class> {
+    Some(match c {
+        'd' => Class::of(ClassItem::Digit, false),
+        'D' => Class::of(ClassItem::Digit, true),
+        'w' => Class::of(ClassItem::Word, false),
+        'W' => Class::of(ClassItem::Word, true),
+        's' => Class::of(ClassItem::Space, false),
+        'S' => Class::of(ClassItem::Space, true),
+        _ => return None,
+    })
+}
+
+/// Returns the character a `\` escape that is not a class stands for.
+fn literal(c: char) -> char {
+    match c {
+        't' => '\t',
+        'n' => '\n',
+        c => c,
+    }
+}
+
+fn compile(node: &Node, insts: &mut Vec<Inst>) {
+    match node {
+        Node::Empty => {}
+        Node::Char(c) => insts.push(Inst::Char(*c)),
+        Node::Any => insts.push(Inst::Any),
+        Node::Class(class) => insts.push(Inst::Class(class.clone())),
+        Node::Start => insts.push(Inst::Start),
+        Node::End => insts.push(Inst::End),
+        Node::Group(inner, index) => {
+            if let Some(index) = index {
+                insts.push(Inst::Save(index * 2));
+            }
+            compile(inner, insts);
+            if let Some(index) = index {
+                insts.push(Inst::Save(index * 2 + 1));
+            }
+        }
+        Node::Concat(nodes) => nodes.iter().for_each(|node| compile(node, insts)),
+        Node::Alt(branches) => {
+            let mut jumps = Vec::new();
+            for (i, branch) in branches.iter().enumerate() {
+                if i + 1 == branches.len() {
+                    compile(branch, insts);
+                    break;
+                }
+                let split = insts.len();
+                insts.push(Inst::Split(split + 1, 0));
+                compile(branch, insts);
+                jumps.push(insts.len());
+                insts.push(Inst::Jmp(0));
+                let next = insts.len();
+                insts[split] = Inst::Split(split + 1, next);
+            }
+            let end = insts.len();
+            for jump in jumps {
+                insts[jump] = Inst::Jmp(end);
+            }
+        }
+        Node::Repeat {
+            node,
+            min,
+            max,
+            greedy,
+        } => {
+            let split = |body: usize, skip: usize| {
+                if *greedy {
+                    Inst::Split(body, skip)
+                } else {
+                    Inst::Split(skip, body)
+                }
+            };
+            for _ in 0..*min {
+                compile(node, insts);
+            }
+            match max {
+                None => {
+                    let top = insts.len();
+                    insts.push(Inst::Jmp(0));
+                    compile(node, insts);
+                    insts.push(Inst::Jmp(top));
+                    let end = insts.len();
+                    insts[top] = split(top + 1, end);
+                }
+                Some(max) => {
+                    let mut splits = Vec::new();
+                    for _ in *min..*max {
+                        splits.push(insts.len());
+                        insts.push(Inst::Jmp(0));
+                        compile(node, insts);
+                    }
+                    let end = insts.len();
+                    for at in splits {
+                        insts[at] = split(at + 1, end);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A pending step of the backtracking search.
+enum Job {
+    Try(usize, usize),
+    Restore(usize, Option<usize>),
+}
+
+impl Regex {
+    /// Compiles a pattern.
+    ///
+    /// # Arguments
+    ///
+    /// * `pattern` - The pattern in the syntax described on `Regex`
+    /// * `ignore_case` - If true, letters match either case
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Regex, String>` - The compiled pattern, or what is wrong with it
+    pub fn new(pattern: &str, ignore_case: bool) -> Result<Regex, String> {
+        let mut parser = Parser {
+            chars: pattern.chars().collect(),
+            pos: 0,
+            groups: 0,
+        };
+        let node = parser.alternation()?;
+        if parser.pos < parser.chars.len() {
+            return Err(String::from("unmatched )"));
+        }
+        let mut insts = vec![Inst::Save(0)];
+        compile(&node, &mut insts);
+        if insts.len() > MAX_INSTS {
+            return Err(String::from("pattern is too large"));
+        }
+        insts.push(Inst::Save(1));
+        insts.push(Inst::Match);
+        if ignore_case {
+            for inst in &mut insts {
+                if let Inst::Char(c) = inst {
+                    *c = lower(*c);
+                }
+            }
+        }
+        Ok(Regex {
+            insts,
+            groups: parser.groups,
+            ignore_case,
+        })
+    }
+
+    /// Returns the number of capturing groups.
+    pub fn groups(&self) -> usize {
+        self.groups
+    }
+
+    /// Finds the first match starting at or after `from`.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The text to search, as characters
+    /// * `from` - The position to start searching at
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Vec<Option<usize>>>` - For a match, the start and end of the
+    ///   whole match followed by those of each group, `None` for a group
+    ///   that took no part in it
+    pub fn captures(&self, text: &[char], from: usize) -> Option<Vec<Option<usize>>> {
+        let width = text.len() + 1;
+        let mut visited = vec![false; self.insts.len() * width];
+        for start in from..=text.len() {
+            let mut slots = vec![None; (self.groups + 1) * 2];
+            if self.run(text, start, &mut visited, &mut slots) {
+                return Some(slots);
+            }
+        }
+        None
+    }
+
+    /// Runs the program from `start`. A failure from a position of the
+    /// program and the text fails again whatever led there, so `visited` is
+    /// kept across starts.
+    fn run(
+        &self,
+        text: &[char],
+        start: usize,
+        visited: &mut [bool],
+        slots: &mut [Option<usize>],
+    ) -> bool {
+        let width = text.len() + 1;
+        let mut jobs = vec![Job::Try(0, start)];
+        while let Some(job) = jobs.pop() {
+            let (mut pc, mut pos) = match job {
+                Job::Try(pc, pos) => (pc, pos),
+                Job::Restore(slot, old) => {
+                    slots[slot] = old;
+                    continue;
+                }
+            };
+            loop {
+                let seen = &mut visited[pc * width + pos];
+                if *seen {
+                    break;
+                }
+                *seen = true;
+                let next = text.get(pos).copied();
+                match &self.insts[pc] {
+                    Inst::Char(c) => match next {
+                        Some(n) if *c == n || self.ignore_case && *c == lower(n) => {
+                            pc += 1;
+                            pos += 1;
+                        }
+                        _ => break,
+                    },
+                    Inst::Any => match next {
+                        Some(_) => {
+                            pc += 1;
+                            pos += 1;
+                        }
+                        None => break,
+                    },
+                    Inst::Class(class) => match next {
+                        Some(n) if class.matches(n, self.ignore_case) => {
+                            pc += 1;
+                            pos += 1;
+                        }
+                        _ => break,
+                    },
+                    Inst::Split(first, second) => {
+                        jobs.push(Job::Try(*second, pos));
+                        pc = *first;
+                    }
+                    Inst::Jmp(to) => pc = *to,
+                    Inst::Save(slot) => {
+                        jobs.push(Job::Restore(*slot, slots[*slot]));
+                        slots[*slot] = Some(pos);
+                        pc += 1;
+                    }
+                    Inst::Start if pos == 0 => pc += 1,
+                    Inst::End if pos == text.len() => pc += 1,
+                    Inst::Start | Inst::End => break,
+                    Inst::Match => return true,
+                }
+            }
+        }
+        false
+    }
+}
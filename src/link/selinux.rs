@@ -0,0 +1,79 @@
+use crate::link::xattr;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+/// The extended attribute holding an entry's SELinux security context.
+pub const CONTEXT_XATTR: &str = "security.selinux";
+
+/// The per-thread file creation context, as set by `setfscreatecon`.
+const FSCREATE: &str = "/proc/thread-self/attr/fscreate";
+
+/// How the SELinux security context of created entries is chosen.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ContextMode {
+    /// Entries get whatever context the process would normally create them with
+    #[default]
+    Inherit,
+    /// Symlinks and directories flnk creates get the context of their source entry
+    Preserve,
+    /// Any inherited file creation context is cleared, so the policy's default
+    /// labeling for the destination directory applies
+    Default,
+}
+
+impl FromStr for ContextMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "inherit" => Ok(ContextMode::Inherit),
+            "preserve" => Ok(ContextMode::Preserve),
+            "default" => Ok(ContextMode::Default),
+            _ => Err(format!(
+                "invalid SELinux context mode {} (expected inherit, preserve, or default)",
+                s
+            )),
+        }
+    }
+}
+
+/// Returns true if SELinux is enabled on this system.
+pub fn is_enabled() -> bool {
+    Path::new("/sys/fs/selinux/enforce").exists()
+}
+
+/// Clears the calling thread's file creation context, like `setfscreatecon(NULL)`.
+///
+/// # Returns
+///
+/// * `io::Result<()>` - Success if the context was cleared
+pub fn reset_create_context() -> io::Result<()> {
+    let mut fscreate = OpenOptions::new().write(true).open(FSCREATE)?;
+    // An empty write clears the context; `write_all` would skip the call entirely.
+    let _cleared = fscreate.write(&[])?;
+    Ok(())
+}
+
+/// Gives `dest` the security context of `source`, without following symlinks.
+///
+/// # Arguments
+///
+/// * `source` - The entry whose context is copied
+/// * `dest` - The created entry to relabel
+///
+/// # Returns
+///
+/// * `io::Result<()>` - Success if the context was copied or `source` has none
+pub fn copy_context(source: &Path, dest: &Path) -> io::Result<()> {
+    match xattr::get(source, CONTEXT_XATTR)? {
+        Some(context) => xattr::set(dest, CONTEXT_XATTR, &context).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("cannot relabel {}: {}", dest.display(), e),
+            )
+        }),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,120 @@
+use crate::json::Json;
+use std::fmt;
+use std::path::PathBuf;
+
+/// Why a source entry was not linked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SkipReason {
+    /// A filter rule excluded the entry; holds the rule as written and the
+    /// merge file it came from
+    Excluded(String),
+    /// The entry is a file and matched none of the filter's include globs
+    NotIncluded,
+    /// The entry's name starts with `.` and hidden entries are left out
+    Hidden,
+    /// The entry's type is not linked with the run's options
+    WrongType(&'static str),
+    /// A socket, FIFO, or device file, which cannot be linked
+    SpecialFile,
+    /// An overlay whiteout or opaque marker left out by `--overlay skip`
+    OverlayArtifact,
+    /// Another source maps to the same destination and wins it; holds that source
+    Collision(PathBuf),
+    /// The destination could hold no links and the fallback policy skips the run
+    Fallback,
+    /// The destination already is the link the run would create
+    AlreadyLinked,
+    /// A hard link would cross devices and the fallback policy skips such files
+    CrossDevice,
+    /// The destination already exists and was kept at the `--interactive` prompt
+    Declined,
+    /// The destination already exists and was kept by `--skip-existing`
+    Existing,
+    /// The destination was modified no earlier than the source and was kept by `--update`
+    NotNewer,
+    /// The interrupted run being resumed finished the entry
+    Resumed,
+}
+
+impl SkipReason {
+    /// A short stable name for machine-readable output.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SkipReason::Excluded(_) => "excluded",
+            SkipReason::NotIncluded => "not_included",
+            SkipReason::Hidden => "hidden",
+            SkipReason::WrongType(_) => "wrong_type",
+            SkipReason::SpecialFile => "special_file",
+            SkipReason::OverlayArtifact => "overlay_artifact",
+            SkipReason::Collision(_) => "collision",
+            SkipReason::Fallback => "fallback",
+            SkipReason::AlreadyLinked => "already_linked",
+            SkipReason::CrossDevice => "cross_device",
+            SkipReason::Declined => "declined",
+            SkipReason::Existing => "existing",
+            SkipReason::NotNewer => "not_newer",
+            SkipReason::Resumed => "resumed",
+        }
+    }
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkipReason::Excluded(rule) => write!(f, "matched exclude rule {}", rule),
+            SkipReason::NotIncluded => write!(f, "matched none of the include patterns"),
+            SkipReason::Hidden => write!(f, "hidden entries are left out by --no-hidden"),
+            SkipReason::WrongType(why) => f.write_str(why),
+            SkipReason::SpecialFile => {
+                write!(f, "sockets, FIFOs, and device files cannot be linked")
+            }
+            SkipReason::OverlayArtifact => write!(f, "overlay artifact left out by --overlay skip"),
+            SkipReason::Collision(winner) => write!(
+                f,
+                "{} maps to the same destination and is linked there instead",
+                winner.display()
+            ),
+            SkipReason::Fallback => write!(f, "destination filesystem cannot hold links"),
+            SkipReason::AlreadyLinked => write!(f, "already linked"),
+            SkipReason::CrossDevice => write!(f, "hard link would cross devices"),
+            SkipReason::Declined => write!(f, "kept the existing destination when asked"),
+            SkipReason::Existing => write!(f, "destination already exists"),
+            SkipReason::NotNewer => write!(f, "destination is not older than the source"),
+            SkipReason::Resumed => write!(f, "linked by the interrupted run"),
+        }
+    }
+}
+
+/// A source entry a run did not link, recorded with `--explain`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Skipped {
+    /// The source entry; an excluded directory stands for everything below it
+    pub path: PathBuf,
+    /// Why it was not linked
+    pub reason: SkipReason,
+}
+
+impl Skipped {
+    /// Records that `path` was not linked.
+    pub fn new(path: impl Into<PathBuf>, reason: SkipReason) -> Self {
+        Skipped {
+            path: path.into(),
+            reason,
+        }
+    }
+
+    /// Renders the entry as a JSON object with `path`, `reason`, and `message`.
+    pub fn to_json(&self) -> Json {
+        Json::object([
+            ("path", Json::from(self.path.as_path())),
+            ("reason", Json::from(self.reason.as_str())),
+            ("message", Json::from(self.reason.to_string().as_str())),
+        ])
+    }
+}
+
+impl fmt::Display for Skipped {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.reason)
+    }
+}
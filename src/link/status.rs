@@ -0,0 +1,101 @@
+use crate::json::Json;
+use std::fmt;
+use std::path::PathBuf;
+
+/// How a destination entry compares with what a run would make of it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum State {
+    /// A source entry with nothing at its destination yet
+    Unlinked,
+    /// A destination entry with no source counterpart, which `--delete`
+    /// would remove
+    Extraneous,
+    /// A destination entry that is not the link the run would create
+    Differs,
+}
+
+impl State {
+    /// A short stable name for machine-readable output.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            State::Unlinked => "unlinked",
+            State::Extraneous => "extraneous",
+            State::Differs => "differs",
+        }
+    }
+}
+
+/// A destination entry that is out of step with its source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusEntry {
+    /// How the entry is out of step
+    pub state: State,
+    /// The source entry, which an extraneous entry has none of
+    pub source: Option<PathBuf>,
+    /// The destination entry
+    pub dest: PathBuf,
+    /// For an entry that differs, what it is and what the run would make it
+    pub detail: Option<String>,
+}
+
+impl StatusEntry {
+    /// Renders the entry as a JSON object with `state`, `dest`, and, where
+    /// the entry has them, `source` and `detail`.
+    pub fn to_json(&self) -> Json {
+        let mut pairs = vec![
+            ("state", Json::from(self.state.as_str())),
+            ("dest", Json::from(self.dest.as_path())),
+        ];
+        if let Some(source) = &self.source {
+            pairs.push(("source", Json::from(source.as_path())));
+        }
+        if let Some(detail) = &self.detail {
+            pairs.push(("detail", Json::from(detail.as_str())));
+        }
+        Json::object(pairs)
+    }
+}
+
+impl fmt::Display for StatusEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let dest = self.dest.display();
+        match (self.state, &self.source, &self.detail) {
+            (State::Unlinked, Some(source), _) => {
+                write!(f, "+ {} (not linked from {})", dest, source.display())
+            }
+            (State::Extraneous, ..) | (State::Unlinked, None, _) => {
+                write!(f, "- {} (no source counterpart)", dest)
+            }
+            (State::Differs, _, Some(detail)) => write!(f, "~ {}: {}", dest, detail),
+            (State::Differs, _, None) => write!(f, "~ {}", dest),
+        }
+    }
+}
+
+/// The outcome of comparing a source tree with its destination.
+#[derive(Debug, Clone, Default)]
+pub struct Status {
+    /// The number of entries already linked as the run would link them
+    pub linked: usize,
+    /// Every entry found out of step, unlinked entries and those that
+    /// differ in discovery order, followed by the extraneous ones
+    pub entries: Vec<StatusEntry>,
+}
+
+impl Status {
+    /// Returns the number of entries in the given state.
+    pub fn count(&self, state: State) -> usize {
+        self.entries.iter().filter(|e| e.state == state).count()
+    }
+
+    /// Renders the status as a JSON object with `linked` and `entries`.
+    pub fn to_json(&self) -> Json {
+        Json::object([
+            ("linked", Json::from(self.linked)),
+            (
+                "entries",
+                Json::Array(self.entries.iter().map(StatusEntry::to_json).collect()),
+            ),
+        ])
+    }
+}
@@ -0,0 +1,151 @@
+use crate::interrupt::CancelToken;
+use crate::link::failure::Failure;
+use crate::link::link_files::{LinkReport, Observer, link_files_with};
+use crate::link::link_options::LinkOptions;
+use crate::link::pipeline::{self, LinkJob};
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, SyncSender, sync_channel};
+use std::thread::{self, JoinHandle};
+
+/// An entry of a run streamed by `link_files_iter`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Entry {
+    /// A link or copy was created
+    Linked {
+        /// The path of the link relative to its linked root
+        path: PathBuf,
+        /// The source it was made from
+        source: PathBuf,
+        /// The created link
+        dest: PathBuf,
+        /// Where the entry the link replaced was backed up, if it was
+        backup: Option<PathBuf>,
+    },
+    /// An entry could not be linked, and `on_error` let the run go on
+    Failed(Failure),
+}
+
+/// The entries of a run as they are linked, from `link_files_iter`.
+///
+/// The run goes on in the background while the entries are read, held back
+/// by a bounded queue when they are read slowly. Dropping the iterator stops
+/// the run before its next entry and waits for it to end.
+pub struct LinkIter {
+    entries: Receiver<Entry>,
+    stop: CancelToken,
+    run: Option<JoinHandle<io::Result<LinkReport>>>,
+}
+
+impl Iterator for LinkIter {
+    type Item = io::Result<Entry>;
+
+    /// Returns the next entry, or the error that ended the run once every
+    /// entry before it was read.
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Ok(entry) = self.entries.recv() {
+            return Some(Ok(entry));
+        }
+        match self.run.take()?.join() {
+            Ok(Ok(_)) => None,
+            Ok(Err(err)) => Some(Err(err)),
+            Err(_) => Some(Err(io::Error::other("the link thread panicked"))),
+        }
+    }
+}
+
+impl Drop for LinkIter {
+    fn drop(&mut self) {
+        self.stop.cancel();
+        // The run may be waiting for room in the queue; it stops at its next
+        // entry once it has some.
+        while self.entries.recv().is_ok() {}
+        if let Some(run) = self.run.take() {
+            let _ = run.join();
+        }
+    }
+}
+
+/// Passes the events of a run to a `LinkIter`, holding each backup until
+/// the link replacing it is sent.
+struct Sink {
+    entries: SyncSender<Entry>,
+    stop: CancelToken,
+    backups: HashMap<PathBuf, PathBuf>,
+}
+
+impl Sink {
+    fn send(&mut self, entry: Entry) {
+        if self.entries.send(entry).is_err() {
+            self.stop.cancel();
+        }
+    }
+}
+
+impl Observer for Sink {
+    fn linked(&mut self, job: &LinkJob) {
+        let backup = self.backups.remove(&job.dest);
+        self.send(Entry::Linked {
+            path: job.rel_path.clone(),
+            source: job.source.clone(),
+            dest: job.dest.clone(),
+            backup,
+        });
+    }
+
+    fn backed_up(&mut self, dest: &Path, backup: &Path) {
+        self.backups
+            .insert(dest.to_path_buf(), backup.to_path_buf());
+    }
+
+    fn failed(&mut self, failure: &Failure) {
+        self.send(Entry::Failed(failure.clone()));
+    }
+
+    fn stopped(&self) -> bool {
+        self.stop.is_cancelled()
+    }
+
+    fn keeps_entries(&self) -> bool {
+        false
+    }
+}
+
+/// Links files like `link_files`, streaming each entry as it is linked
+/// instead of waiting for the report, so a caller can show progress or stop
+/// early. Linked and skipped entries are only passed on, not kept for a
+/// report, so memory stays bounded however large the tree; only the error
+/// the run ends with is passed on besides them.
+///
+/// # Arguments
+///
+/// * `source` - The source path or pattern
+/// * `dest` - The destination path
+/// * `opts` - Optional link options to control the behavior
+///
+/// # Returns
+///
+/// * `LinkIter` - The created links and skipped failures in the order they
+///   happen, then the error that ended the run, if any
+pub fn link_files_iter(
+    source: impl AsRef<Path>,
+    dest: impl AsRef<Path>,
+    opts: Option<&LinkOptions>,
+) -> LinkIter {
+    let (source, dest) = (source.as_ref().to_path_buf(), dest.as_ref().to_path_buf());
+    let opts = opts.cloned().unwrap_or_default();
+    let (tx, rx) = sync_channel(pipeline::CHANNEL_CAPACITY);
+    let stop = CancelToken::new();
+    let sink = Sink {
+        entries: tx,
+        stop: stop.clone(),
+        backups: HashMap::new(),
+    };
+    let run = thread::spawn(move || link_files_with(&source, &dest, Some(&opts), sink));
+    LinkIter {
+        entries: rx,
+        stop,
+        run: Some(run),
+    }
+}
@@ -0,0 +1,237 @@
+use crate::clock::UtcTime;
+use crate::link::lexical;
+use std::fmt;
+use std::fs::Metadata;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::UNIX_EPOCH;
+
+/// A `--dest-template` layout: the path of each linked file below the
+/// destination directory, built from variables in braces.
+///
+/// * `{name}` - The file name without its extension
+/// * `{ext}` - The extension without its dot, empty if there is none
+/// * `{filename}` - The whole file name
+/// * `{parent}` - The name of the directory holding the source file
+/// * `{dir}` - The directory of the file relative to the source, empty at
+///   its top
+/// * `{mtime:FORMAT}` - The modification time in UTC, formatted with `%Y`,
+///   `%y`, `%m`, `%d`, `%H`, `%M`, `%S`, and `%%`; `{mtime}` alone is
+///   `{mtime:%Y-%m-%d}`
+///
+/// `{{` and `}}` stand for literal braces. `{mtime:%Y}/{mtime:%m}/{filename}`
+/// sorts photos into year and month folders.
+#[derive(Debug, Clone)]
+pub struct Template {
+    text: String,
+    pieces: Vec<Piece>,
+}
+
+/// A part of a template.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Piece {
+    Literal(String),
+    Name,
+    Ext,
+    FileName,
+    Parent,
+    Dir,
+    Mtime(Vec<TimePiece>),
+}
+
+/// A part of an `{mtime:FORMAT}` format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TimePiece {
+    Literal(char),
+    Year,
+    ShortYear,
+    Month,
+    Day,
+    Hour,
+    Minute,
+    Second,
+}
+
+impl FromStr for Template {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let invalid = |why: &str| format!("invalid template {}: {}", text, why);
+        let mut pieces = Vec::new();
+        let mut literal = String::new();
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '}' => return Err(invalid("unmatched }")),
+                '{' => {
+                    let mut var = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => var.push(c),
+                            None => return Err(invalid("unmatched {")),
+                        }
+                    }
+                    if !literal.is_empty() {
+                        pieces.push(Piece::Literal(std::mem::take(&mut literal)));
+                    }
+                    pieces.push(parse_variable(&var).map_err(|e| invalid(&e))?);
+                }
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            pieces.push(Piece::Literal(literal));
+        }
+        if pieces.is_empty() {
+            return Err(invalid("the template is empty"));
+        }
+        Ok(Template {
+            text: text.to_string(),
+            pieces,
+        })
+    }
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+/// Parses the text between the braces of a template variable.
+fn parse_variable(var: &str) -> Result<Piece, String> {
+    let (name, format) = match var.split_once(':') {
+        Some((name, format)) => (name, Some(format)),
+        None => (var, None),
+    };
+    let piece = match name {
+        "name" => Piece::Name,
+        "ext" => Piece::Ext,
+        "filename" => Piece::FileName,
+        "parent" => Piece::Parent,
+        "dir" => Piece::Dir,
+        "mtime" => {
+            return Ok(Piece::Mtime(parse_time_format(
+                format.unwrap_or("%Y-%m-%d"),
+            )?));
+        }
+        _ => return Err(format!("unknown variable {{{}}}", name)),
+    };
+    match format {
+        Some(_) => Err(format!("{{{}}} takes no format", name)),
+        None => Ok(piece),
+    }
+}
+
+/// Parses the format of an `{mtime:FORMAT}` variable.
+fn parse_time_format(format: &str) -> Result<Vec<TimePiece>, String> {
+    let mut pieces = Vec::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            pieces.push(TimePiece::Literal(c));
+            continue;
+        }
+        pieces.push(match chars.next() {
+            Some('Y') => TimePiece::Year,
+            Some('y') => TimePiece::ShortYear,
+            Some('m') => TimePiece::Month,
+            Some('d') => TimePiece::Day,
+            Some('H') => TimePiece::Hour,
+            Some('M') => TimePiece::Minute,
+            Some('S') => TimePiece::Second,
+            Some('%') => TimePiece::Literal('%'),
+            Some(other) => return Err(format!("unknown time format %{}", other)),
+            None => return Err(String::from("time format ends with %")),
+        });
+    }
+    Ok(pieces)
+}
+
+impl Template {
+    /// Builds the destination path of a file, relative to the destination
+    /// directory.
+    ///
+    /// # Arguments
+    ///
+    /// * `rel_path` - The path of the file relative to its source directory
+    /// * `source` - The source file
+    /// * `metadata` - The metadata of the source file
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<PathBuf>` - The path, or an `InvalidInput` error if it
+    ///   is empty or would leave the destination directory
+    pub fn render(
+        &self,
+        rel_path: &Path,
+        source: &Path,
+        metadata: &Metadata,
+    ) -> io::Result<PathBuf> {
+        let lossy = |path: Option<&std::ffi::OsStr>| {
+            path.map(|p| p.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        };
+        let mut text = String::new();
+        for piece in &self.pieces {
+            match piece {
+                Piece::Literal(literal) => text.push_str(literal),
+                Piece::Name => text.push_str(&lossy(source.file_stem())),
+                Piece::Ext => text.push_str(&lossy(source.extension())),
+                Piece::FileName => text.push_str(&lossy(source.file_name())),
+                Piece::Parent => text.push_str(&lossy(source.parent().and_then(Path::file_name))),
+                Piece::Dir => {
+                    if let Some(dir) = rel_path.parent() {
+                        text.push_str(&dir.to_string_lossy());
+                    }
+                }
+                Piece::Mtime(format) => {
+                    let secs = metadata
+                        .modified()?
+                        .duration_since(UNIX_EPOCH)
+                        .map(|d| d.as_secs())
+                        .unwrap_or(0);
+                    format_time(&UtcTime::from_unix(secs), format, &mut text);
+                }
+            }
+        }
+        let path = PathBuf::from(&text);
+        if path.as_os_str().is_empty() || path.has_root() || lexical::climbs_out(&path) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "--dest-template turns {} into {:?}, which is not inside the destination",
+                    source.display(),
+                    text
+                ),
+            ));
+        }
+        Ok(path)
+    }
+}
+
+/// Appends a time formatted with the parts of an `{mtime:FORMAT}` format.
+fn format_time(time: &UtcTime, format: &[TimePiece], out: &mut String) {
+    for piece in format {
+        match piece {
+            TimePiece::Literal(c) => out.push(*c),
+            TimePiece::Year => out.push_str(&format!("{:04}", time.year)),
+            TimePiece::ShortYear => out.push_str(&format!("{:02}", time.year.rem_euclid(100))),
+            TimePiece::Month => out.push_str(&format!("{:02}", time.month)),
+            TimePiece::Day => out.push_str(&format!("{:02}", time.day)),
+            TimePiece::Hour => out.push_str(&format!("{:02}", time.hour)),
+            TimePiece::Minute => out.push_str(&format!("{:02}", time.minute)),
+            TimePiece::Second => out.push_str(&format!("{:02}", time.second)),
+        }
+    }
+}
@@ -1,11 +1,14 @@
+use crate::error::{FlnkError, Op};
 use crate::link::link_files::link_files;
 use crate::link::link_options::LinkOptions;
+use crate::link::probe;
+use crate::link::xattr;
 use std::{env, fs, io, path::Path, path::PathBuf};
 use tempfile::{TempDir, tempdir};
 
-/// ------------------------------------------------------------
-/// helpers
-/// ------------------------------------------------------------
+// ------------------------------------------------------------
+// helpers
+// ------------------------------------------------------------
 
 /// A tmp dir plus a `PathBuf` pointing to a child directory we can work in.
 fn create_temp_dir(name: &str) -> io::Result<(TempDir, PathBuf)> {
@@ -43,9 +46,9 @@ where
     Ok(())
 }
 
-/// ------------------------------------------------------------
-/// tests
-/// ------------------------------------------------------------
+// ------------------------------------------------------------
+// tests
+// ------------------------------------------------------------
 
 #[test]
 fn test_basic_hard_link() -> io::Result<()> {
@@ -57,7 +60,7 @@ fn test_basic_hard_link() -> io::Result<()> {
         src.to_str().unwrap(),
         dst.to_str().unwrap(),
         Some(&LinkOptions::default()),
-    )?;
+    )?.linked;
     assert_eq!(linked.len(), 1);
     assert!(dst.join("file1.txt").exists());
     Ok(())
@@ -76,7 +79,7 @@ fn test_relative_hard_link_with_spaces() -> io::Result<()> {
         &(src.to_str().unwrap().to_owned() + "/myDir/file 3 to link.txt"),
         ".",
         Some(&LinkOptions::default()),
-    )?;
+    )?.linked;
 
     assert_eq!(linked.len(), 1);
     assert!(dst.join("file 3 to link.txt").exists());
@@ -102,7 +105,7 @@ fn test_relative_hard_link_with_wildcard() -> io::Result<()> {
         &(src.to_str().unwrap().to_owned() + "/myDir/*"),
         ".",
         Some(&LinkOptions::default()),
-    )?;
+    )?.linked;
 
     assert_eq!(linked.len(), 3);
     assert!(dst.join("file 3 to link.txt").exists());
@@ -126,7 +129,7 @@ fn test_relative_hard_link_to_directory() -> io::Result<()> {
         &(src.to_str().unwrap().to_owned() + "/myDir"),
         ".",
         Some(&LinkOptions::default()),
-    )?;
+    )?.linked;
 
     assert_eq!(linked.len(), 1);
     assert!(dst.join("myDir/file 3 to link.txt").exists());
@@ -155,7 +158,7 @@ fn test_complex_hard_link() -> io::Result<()> {
         src.to_str().unwrap(),
         dst.to_str().unwrap(),
         Some(&LinkOptions::default()),
-    )?;
+    )?.linked;
     assert_eq!(linked.len(), 3);
     assert!(dst.join("file2.txt").exists());
     assert!(dst.join("filesToLink/file3.txt").exists());
@@ -187,6 +190,186 @@ fn test_backup_option() -> io::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_linked_events_carry_backups() -> io::Result<()> {
+    use crate::link::link_files::{LinkEvent, link_many_progress};
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_files([src.join("a.txt"), src.join("b.txt")], b"new content")?;
+    create_test_file(dst.join("a.txt"), b"existing content")?;
+
+    let opts = LinkOptions {
+        backup: true,
+        ..Default::default()
+    };
+    let mut events = Vec::new();
+    let source = src.to_str().unwrap();
+    link_many_progress(&[source], dst.to_str().unwrap(), Some(&opts), |event| {
+        if let LinkEvent::Linked { dest, backup, .. } = event {
+            events.push((dest.to_path_buf(), backup.map(Path::to_path_buf)));
+        }
+    })?;
+    events.sort();
+    assert_eq!(
+        events,
+        [
+            (dst.join("a.txt"), Some(dst.join("a.txt~"))),
+            (dst.join("b.txt"), None),
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_report_counts_summary() -> io::Result<()> {
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_files([src.join("a.txt"), src.join("sub/b.txt")], b"12345")?;
+    create_test_file(src.join("c.tmp"), b"skip me")?;
+    create_test_file(dst.join("a.txt"), b"existing content")?;
+
+    let mut opts = LinkOptions {
+        backup: true,
+        ..Default::default()
+    };
+    opts.filter.add_exclude("*.tmp");
+    let report = link_files(src.to_str().unwrap(), dst.to_str().unwrap(), Some(&opts))?;
+    assert_eq!(report.linked.len(), 2);
+    assert_eq!(report.created_dirs, [dst.join("sub")]);
+    assert_eq!((report.filtered, report.conflicts), (1, 1));
+    assert_eq!(report.backups.len(), 1);
+    assert_eq!(report.shared_bytes, 10);
+    Ok(())
+}
+
+#[test]
+fn test_events_report_walks_skips_and_backups() -> io::Result<()> {
+    use crate::link::link_files::{LinkEvent, link_many_progress};
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    let (first, second) = (src.join("first"), src.join("second"));
+    create_test_files([first.join("a.txt"), second.join("b.txt")], b"new content")?;
+    create_test_file(dst.join("a.txt"), b"existing content")?;
+    fs::hard_link(second.join("b.txt"), dst.join("b.txt"))?;
+
+    let opts = LinkOptions {
+        backup: true,
+        ..Default::default()
+    };
+    let mut events = Vec::new();
+    link_many_progress(&[&first, &second], &dst, Some(&opts), |event| {
+        events.push(match event {
+            LinkEvent::WalkStarted(root) => format!("walk {}", root.display()),
+            LinkEvent::BackupCreated { dest, .. } => format!("backup {}", dest.display()),
+            LinkEvent::Linked { dest, .. } => format!("link {}", dest.display()),
+            LinkEvent::Skipped(skipped) => format!("skip {}", skipped.path.display()),
+            _ => String::from("other"),
+        })
+    })?;
+    let expected = [
+        format!("walk {}", first.display()),
+        format!("backup {}", dst.join("a.txt").display()),
+        format!("link {}", dst.join("a.txt").display()),
+        format!("walk {}", second.display()),
+        format!("skip {}", second.join("b.txt").display()),
+    ];
+    assert_eq!(events, expected);
+    Ok(())
+}
+
+#[test]
+fn test_link_files_iter_streams_entries() -> io::Result<()> {
+    use crate::link::stream::{Entry, link_files_iter};
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_files(
+        [src.join("a.txt"), src.join("b.txt"), src.join("sub/c.txt")],
+        b"content",
+    )?;
+
+    let mut linked = Vec::new();
+    for entry in link_files_iter(&src, &dst, None) {
+        if let Entry::Linked { path, .. } = entry? {
+            linked.push(path);
+        }
+    }
+    linked.sort();
+    let expected = [
+        PathBuf::from("a.txt"),
+        PathBuf::from("b.txt"),
+        PathBuf::from("sub/c.txt"),
+    ];
+    assert_eq!(linked, expected);
+
+    // An existing entry ends the run with an error after what was linked.
+    create_test_file(src.join("d.txt"), b"content")?;
+    create_test_file(dst.join("d.txt"), b"other content")?;
+    let last = link_files_iter(&src, &dst, None).last();
+    assert!(matches!(last, Some(Err(_))));
+
+    // Stopping early must not wait for the rest of the run.
+    let (_more_tmp, more) = create_temp_dir("more")?;
+    assert_eq!(link_files_iter(&src, &more, None).take(1).count(), 1);
+    Ok(())
+}
+
+#[test]
+fn test_streamed_run_keeps_no_entries() -> io::Result<()> {
+    use crate::link::link_files::{Observer, link_files_with};
+    use crate::link::pipeline::LinkJob;
+
+    /// Counts created links without keeping them for the report.
+    struct Streamed<'a>(&'a mut usize);
+    impl Observer for Streamed<'_> {
+        fn linked(&mut self, _job: &LinkJob) {
+            *self.0 += 1;
+        }
+        fn keeps_entries(&self) -> bool {
+            false
+        }
+    }
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_files([src.join("a.txt"), src.join("sub/b.txt")], b"content")?;
+    // An entry linked before is skipped as existing.
+    fs::hard_link(src.join("a.txt"), dst.join("a.txt"))?;
+
+    let mut count = 0;
+    let streamed = Streamed(&mut count);
+    let report = link_files_with(&src, &dst, None, streamed)?;
+    assert_eq!(count, 1);
+    assert!(report.linked.is_empty());
+    assert!(report.skipped.is_empty());
+    assert!(dst.join("sub/b.txt").exists());
+    Ok(())
+}
+
+#[test]
+fn test_cancel_token_stops_between_entries() -> io::Result<()> {
+    use crate::interrupt::CancelToken;
+    use crate::link::link_files::link_files_with;
+    use crate::link::pipeline::LinkJob;
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_files([src.join("a.txt"), src.join("b.txt")], b"content")?;
+
+    let token = CancelToken::new();
+    let opts = LinkOptions {
+        cancel: Some(token.clone()),
+        ..Default::default()
+    };
+    let canceller = token.clone();
+    let cancel = move |_: &LinkJob| canceller.cancel();
+    let err = link_files_with(&src, &dst, Some(&opts), cancel).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+    assert_eq!(fs::read_dir(&dst)?.count(), 1);
+
+    // A cancelled token stops the run before its first entry.
+    let err = link_files(&src, &dst, Some(&opts)).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+    assert!(token.is_cancelled());
+    Ok(())
+}
+
 #[test]
 fn test_force_option() -> io::Result<()> {
     let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
@@ -220,7 +403,17 @@ fn test_existing_file_no_force() -> io::Result<()> {
         dst.to_str().unwrap(),
         Some(&LinkOptions::default()),
     );
-    assert!(res.is_err());
+    let err = res.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+    match FlnkError::find(&err) {
+        Some(FlnkError::Exists { op, source, dest }) => {
+            assert_eq!(*op, Op::Link);
+            assert_eq!(source, &src_file);
+            assert_eq!(dest, &dst_file);
+        }
+        other => panic!("expected an Exists error, got {:?}", other),
+    }
+    assert!(err.to_string().contains(&*dst_file.to_string_lossy()));
     Ok(())
 }
 
@@ -235,7 +428,7 @@ fn test_symbolic_link() -> io::Result<()> {
         ..Default::default()
     };
 
-    let linked = link_files(src.to_str().unwrap(), dst.to_str().unwrap(), Some(&opts))?;
+    let linked = link_files(src.to_str().unwrap(), dst.to_str().unwrap(), Some(&opts))?.linked;
     assert_eq!(linked.len(), 1);
     assert!(
         fs::symlink_metadata(dst.join("file1.txt"))?
@@ -257,7 +450,7 @@ fn test_relative_symbolic_link() -> io::Result<()> {
         ..Default::default()
     };
 
-    let linked = link_files(src.to_str().unwrap(), dst.to_str().unwrap(), Some(&opts))?;
+    let linked = link_files(src.to_str().unwrap(), dst.to_str().unwrap(), Some(&opts))?.linked;
     assert_eq!(linked.len(), 1);
     assert!(
         fs::symlink_metadata(dst.join("file1.txt"))?
@@ -266,3 +459,3082 @@ fn test_relative_symbolic_link() -> io::Result<()> {
     );
     Ok(())
 }
+
+#[test]
+fn test_relative_symbolic_link_named_in_current_directory() -> io::Result<()> {
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+
+    create_test_files([src.join("file1.txt")], b"test content")?;
+
+    let opts = LinkOptions {
+        symbolic: true,
+        relative: true,
+        ..Default::default()
+    };
+
+    // `flnk -sr FILE NAME`: the link is made relative to the current directory.
+    let prev = env::current_dir()?;
+    env::set_current_dir(&dst)?;
+    let result = link_files(src.join("file1.txt"), "name.txt", Some(&opts));
+    env::set_current_dir(prev)?;
+
+    assert_eq!(result?.linked.len(), 1);
+    let target = fs::read_link(dst.join("name.txt"))?;
+    assert!(target.is_relative());
+    assert_eq!(fs::read(dst.join("name.txt"))?, b"test content");
+    Ok(())
+}
+
+#[test]
+fn test_tree_larger_than_pipeline_capacity() -> io::Result<()> {
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+
+    let count = 2 * super::pipeline::CHANNEL_CAPACITY + 1;
+    create_test_files(
+        (0..count).map(|i| src.join(format!("dir{}/file{}.txt", i % 7, i))),
+        b"test content",
+    )?;
+
+    let linked = link_files(
+        src.to_str().unwrap(),
+        dst.to_str().unwrap(),
+        Some(&LinkOptions::default()),
+    )?.linked;
+    assert_eq!(linked.len(), count);
+    assert!(dst.join("dir0/file0.txt").exists());
+    Ok(())
+}
+
+#[test]
+fn test_probe_leaves_no_entries_behind() -> io::Result<()> {
+    let (_tmp, dir) = create_temp_dir("probe")?;
+
+    let caps = probe::probe(&dir)?;
+    assert!(caps.hard_links);
+    assert!(caps.symlinks);
+    assert_eq!(fs::read_dir(&dir)?.count(), 0);
+    Ok(())
+}
+
+#[test]
+fn test_probe_rejects_linkless_filesystem() -> io::Result<()> {
+    let (_tmp, dir) = create_temp_dir("probe")?;
+
+    let caps = probe::FsCapabilities {
+        fs_type: "vfat".into(),
+        hard_links: false,
+        symlinks: false,
+        ..probe::probe(&dir)?
+    };
+    let err = probe::check_supported(&caps, &LinkOptions::default()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+    assert!(err.to_string().contains("FAT"));
+    Ok(())
+}
+
+#[test]
+fn test_archive_link_recreates_tree() -> io::Result<()> {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+
+    create_test_files([src.join("sub/file1.txt")], b"test content")?;
+    std::os::unix::fs::symlink("file1.txt", src.join("sub/alias"))?;
+    fs::set_permissions(src.join("sub"), fs::Permissions::from_mode(0o750))?;
+
+    let out = dst.join("copy");
+    let opts = LinkOptions {
+        archive: true,
+        ..Default::default()
+    };
+    link_files(src.to_str().unwrap(), out.to_str().unwrap(), Some(&opts))?;
+
+    let src_meta = fs::metadata(src.join("sub"))?;
+    let dst_meta = fs::metadata(out.join("sub"))?;
+    assert_eq!(dst_meta.mode() & 0o777, 0o750);
+    assert_eq!(dst_meta.mtime(), src_meta.mtime());
+    assert_eq!(
+        fs::metadata(out.join("sub/file1.txt"))?.ino(),
+        fs::metadata(src.join("sub/file1.txt"))?.ino()
+    );
+    assert_eq!(
+        fs::read_link(out.join("sub/alias"))?,
+        PathBuf::from("file1.txt")
+    );
+    Ok(())
+}
+
+#[test]
+fn test_filter_rules() -> io::Result<()> {
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+
+    create_test_files(
+        [
+            src.join("movie.mkv"),
+            src.join("movie.mkv.part"),
+            src.join("keep.part"),
+            src.join("@eaDir/thumb.jpg"),
+            src.join("sub/build/out.o"),
+        ],
+        b"test content",
+    )?;
+
+    let mut opts = LinkOptions::default();
+    for rule in ["+ keep.part", "- *.part", "- @eaDir/", "exclude build/"] {
+        opts.filter.add_rule(rule)?;
+    }
+
+    let linked = link_files(src.to_str().unwrap(), dst.to_str().unwrap(), Some(&opts))?.linked;
+    assert_eq!(linked.len(), 2);
+    assert!(dst.join("movie.mkv").exists());
+    assert!(dst.join("keep.part").exists());
+    assert!(!dst.join("@eaDir").exists());
+    Ok(())
+}
+
+#[test]
+fn test_exclude_globs() -> io::Result<()> {
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+
+    create_test_files(
+        [
+            src.join("show/ep1.mkv"),
+            src.join("show/ep2.mkv.part"),
+            src.join("show/.DS_Store"),
+            src.join("show/@eaDir/ep1.mkv/SYNOPHOTO_THUMB.jpg"),
+        ],
+        b"test content",
+    )?;
+
+    let mut opts = LinkOptions::default();
+    for pattern in ["*.part", ".DS_Store", "@eaDir"] {
+        opts.filter.add_exclude(pattern);
+    }
+
+    let linked = link_files(src.to_str().unwrap(), dst.to_str().unwrap(), Some(&opts))?.linked;
+    assert_eq!(linked, vec![PathBuf::from("show/ep1.mkv")]);
+    assert!(!dst.join("show/@eaDir").exists());
+    Ok(())
+}
+
+#[test]
+fn test_include_globs() -> io::Result<()> {
+    use crate::link::link_files::link_files;
+    use crate::link::skip::{SkipReason, Skipped};
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+
+    create_test_files(
+        [
+            src.join("movie/movie.mkv"),
+            src.join("movie/movie.srt"),
+            src.join("movie/movie.nfo"),
+            src.join("movie/sample/sample.mkv"),
+        ],
+        b"test content",
+    )?;
+
+    let mut opts = LinkOptions {
+        explain: true,
+        ..Default::default()
+    };
+    opts.filter.add_include("*.mkv");
+    opts.filter.add_include("*.srt");
+    // Excludes win over includes.
+    opts.filter.add_exclude("sample");
+
+    let report = link_files(src.to_str().unwrap(), dst.to_str().unwrap(), Some(&opts))?;
+    let mut linked = report.linked;
+    linked.sort();
+    assert_eq!(
+        linked,
+        vec![PathBuf::from("movie/movie.mkv"), PathBuf::from("movie/movie.srt")]
+    );
+    assert!(
+        report
+            .skipped
+            .contains(&Skipped::new(src.join("movie/movie.nfo"), SkipReason::NotIncluded))
+    );
+    Ok(())
+}
+
+#[test]
+fn test_transform_rewrites_dest_paths() -> io::Result<()> {
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+
+    create_test_files(
+        [
+            src.join("[Group] Show/[Group] Show s01e01.mkv"),
+            src.join("[Group] Show/notes.txt"),
+        ],
+        b"test content",
+    )?;
+
+    let opts = LinkOptions {
+        transform: vec![
+            r"s/\[[^]]*\] //g".parse().unwrap(),
+            r"s/s([0-9]+)e([0-9]+)/\U&/".parse().unwrap(),
+        ],
+        ..Default::default()
+    };
+    link_files(src.to_str().unwrap(), dst.to_str().unwrap(), Some(&opts))?;
+    assert!(dst.join("Show/Show S01E01.mkv").exists());
+    assert!(dst.join("Show/notes.txt").exists());
+
+    let escape = LinkOptions {
+        transform: vec!["s|^|../|".parse().unwrap()],
+        ..Default::default()
+    };
+    let err = link_files(src.to_str().unwrap(), dst.to_str().unwrap(), Some(&escape)).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    Ok(())
+}
+
+#[test]
+fn test_dest_template_lays_out_files() -> io::Result<()> {
+    use crate::link::dry_run::Operation;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    let photos = ["IMG_1.JPG", "IMG_2.JPG"];
+    create_test_files(photos.map(|n| src.join("trip").join(n)), b"photo")?;
+    // 2024-03-15T12:00:00Z
+    let taken = UNIX_EPOCH + Duration::from_secs(1_710_504_000);
+    for name in photos {
+        fs::File::options()
+            .write(true)
+            .open(src.join("trip").join(name))?
+            .set_modified(taken)?;
+    }
+    let (source, dest) = (src.to_str().unwrap(), dst.to_str().unwrap());
+
+    let template = "{mtime:%Y}/{mtime:%m}/{parent}-{name}.{ext}";
+    let opts = LinkOptions {
+        dest_template: Some(template.parse().unwrap()),
+        dry_run: true,
+        ..Default::default()
+    };
+    let report = link_files(source, dest, Some(&opts))?;
+    assert!(report.planned.contains(&Operation::Link(
+        src.join("trip/IMG_1.JPG"),
+        dst.join("2024/03/trip-IMG_1.JPG")
+    )));
+    assert!(!dst.join("2024").exists());
+
+    let opts = LinkOptions {
+        dry_run: false,
+        ..opts
+    };
+    link_files(source, dest, Some(&opts))?;
+    assert!(dst.join("2024/03/trip-IMG_1.JPG").is_file());
+    assert!(dst.join("2024/03/trip-IMG_2.JPG").is_file());
+    assert!(!dst.join("trip").exists());
+
+    assert!("{size}".parse::<crate::link::template::Template>().is_err());
+    Ok(())
+}
+
+#[test]
+fn test_flatten_collisions() -> io::Result<()> {
+    use crate::link::collision::OnCollision;
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_files(
+        [
+            src.join("a/ep.mkv"),
+            src.join("b/c/ep.mkv"),
+            src.join("b/ep (1).mkv"),
+        ],
+        b"test content",
+    )?;
+    let (source, dest) = (src.to_str().unwrap(), dst.to_str().unwrap());
+
+    let opts = LinkOptions {
+        flatten: true,
+        ..Default::default()
+    };
+    let err = link_files(source, dest, Some(&opts)).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+    assert_eq!(fs::read_dir(&dst)?.count(), 0);
+
+    let opts = LinkOptions {
+        on_collision: OnCollision::Skip,
+        ..opts
+    };
+    let report = link_files(source, dest, Some(&opts))?;
+    assert_eq!(report.linked.len(), 2);
+    assert_eq!(report.warnings.len(), 1);
+    fs::remove_dir_all(&dst)?;
+
+    // Numbered names skip over ones another file already maps to.
+    let opts = LinkOptions {
+        on_collision: OnCollision::Number,
+        ..opts
+    };
+    link_files(source, dest, Some(&opts))?;
+    let mut names: Vec<_> = fs::read_dir(&dst)?
+        .map(|entry| entry.map(|e| e.file_name()))
+        .collect::<io::Result<_>>()?;
+    names.sort();
+    assert_eq!(names, ["ep (1).mkv", "ep (2).mkv", "ep.mkv"]);
+    Ok(())
+}
+
+#[test]
+fn test_on_collision_within_one_source() -> io::Result<()> {
+    use crate::link::collision::OnCollision;
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_files(["Notes.txt", "NOTES.txt"].map(|n| src.join(n)), b"notes")?;
+    let (source, dest) = (src.to_str().unwrap(), dst.to_str().unwrap());
+
+    // Lowercasing maps both files to one name, which the plan catches
+    // before anything is linked.
+    let opts = LinkOptions {
+        transform: vec![r"s/.*/\L&/".parse().unwrap()],
+        ..Default::default()
+    };
+    let err = link_files(source, dest, Some(&opts)).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+    assert!(!dst.join("notes.txt").exists());
+
+    let opts = LinkOptions {
+        on_collision: OnCollision::Overwrite,
+        ..opts
+    };
+    let report = link_files(source, dest, Some(&opts))?;
+    assert_eq!(report.linked.len(), 1);
+    assert_eq!(report.warnings.len(), 1);
+    assert!(dst.join("notes.txt").is_file());
+    Ok(())
+}
+
+#[test]
+fn test_filter_dir_merge() -> io::Result<()> {
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+
+    create_test_files(
+        [
+            src.join("a/skip.txt"),
+            src.join("a/keep.txt"),
+            src.join("a/nested/skip.txt"),
+            src.join("b/skip.txt"),
+        ],
+        b"test content",
+    )?;
+    fs::write(src.join("a/.rules"), "- skip.txt\n- /.rules\n")?;
+
+    let mut opts = LinkOptions::default();
+    opts.filter.add_rule("dir-merge .rules")?;
+
+    let mut linked = link_files(src.to_str().unwrap(), dst.to_str().unwrap(), Some(&opts))?.linked;
+    linked.sort();
+    assert_eq!(
+        linked,
+        vec![PathBuf::from("a/keep.txt"), PathBuf::from("b/skip.txt")]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_rsync_filter_keeps_its_place_among_filters() -> io::Result<()> {
+    use crate::link::filter::{RSYNC_FILTER_RULES, command_line_rules};
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_files([src.join("keep.txt"), src.join("b.txt")], b"test")?;
+    fs::write(src.join(".rsync-filter"), "+ keep.txt\n")?;
+    let filters = [(2, "- *.txt"), (5, "- *.log")];
+    let link = |rsync_filters: &[usize], dst: &Path| -> io::Result<Vec<PathBuf>> {
+        let rules = command_line_rules(filters, rsync_filters.iter().copied());
+        let mut opts = LinkOptions::default();
+        for rule in rules {
+            opts.filter.add_rule(rule)?;
+        }
+        let mut linked = link_files(&src, dst, Some(&opts))?.linked;
+        linked.sort();
+        Ok(linked)
+    };
+
+    // `--filter '- *.txt' -F`: the exclude comes first and wins.
+    let linked = link(&[3], &dst.join("after"))?;
+    assert_eq!(linked, [Path::new(".rsync-filter")]);
+    // `-F --filter '- *.txt' --filter '- *.log' -F`: the merged file comes
+    // first, and the second -F leaves out the file itself.
+    assert_eq!(link(&[1, 6], &dst.join("before"))?, [Path::new("keep.txt")]);
+    let [merge, skip] = RSYNC_FILTER_RULES;
+    let rules = command_line_rules(filters, [1, 3]);
+    assert_eq!(rules, [merge, "- *.txt", skip, "- *.log"]);
+    Ok(())
+}
+
+#[test]
+fn test_overlay_layers() -> io::Result<()> {
+    use crate::link::overlay::OverlayMode;
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+
+    let (lower, upper) = (src.join("lower"), src.join("upper"));
+    create_test_files(
+        [
+            lower.join("etc/gone.conf"),
+            lower.join("etc/kept.conf"),
+            lower.join("opt/old.bin"),
+            upper.join("etc/.wh.gone.conf"),
+            upper.join("opt/.wh..wh..opq"),
+            upper.join("opt/new.bin"),
+        ],
+        b"test content",
+    )?;
+
+    let skipped = dst.join("skipped");
+    let opts = LinkOptions {
+        overlay: OverlayMode::Skip,
+        ..Default::default()
+    };
+    let linked = link_files(upper.to_str().unwrap(), skipped.to_str().unwrap(), Some(&opts))?.linked;
+    assert_eq!(linked, vec![PathBuf::from("opt/new.bin")]);
+
+    let merged = dst.join("merged");
+    let opts = LinkOptions {
+        overlay: OverlayMode::Translate,
+        ..Default::default()
+    };
+    link_files(lower.to_str().unwrap(), merged.to_str().unwrap(), Some(&opts))?;
+    link_files(upper.to_str().unwrap(), merged.to_str().unwrap(), Some(&opts))?;
+    assert!(!merged.join("etc/gone.conf").exists());
+    assert!(merged.join("etc/kept.conf").exists());
+    assert!(!merged.join("opt/old.bin").exists());
+    assert!(merged.join("opt/new.bin").exists());
+    assert!(!merged.join("etc/.wh.gone.conf").exists());
+    Ok(())
+}
+
+#[test]
+fn test_overlay_ignores_whiteouts_of_parents() -> io::Result<()> {
+    use crate::link::overlay::{self, OverlayMode};
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    let (lower, upper) = (src.join("lower"), src.join("upper"));
+    create_test_files([lower.join("etc/kept.conf"), lower.join("top.conf")], b"a")?;
+    create_test_files([upper.join("etc/.wh.."), upper.join("etc/.wh...")], b"b")?;
+
+    let merged = dst.join("merged");
+    let opts = LinkOptions {
+        overlay: OverlayMode::Translate,
+        ..Default::default()
+    };
+    link_files(&lower, &merged, Some(&opts))?;
+    link_files(&upper, &merged, Some(&opts))?;
+    assert!(merged.join("etc/kept.conf").exists());
+    assert!(merged.join("top.conf").exists());
+    assert!(merged.join("etc/.wh..").exists());
+
+    let metadata = fs::metadata(lower.join("top.conf"))?;
+    for name in [".wh..", ".wh...", ".wh.a/b", ".wh."] {
+        let entry = overlay::classify(name.as_ref(), &metadata);
+        assert!(entry.is_none(), "{}", name);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_mark_managed_entries() -> io::Result<()> {
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+
+    create_test_files([src.join("sub/file1.txt")], b"test content")?;
+    fs::write(dst.join("mine.txt"), b"user file")?;
+
+    let out = dst.join("out");
+    let opts = LinkOptions {
+        mark: Some(String::from("nightly")),
+        ..Default::default()
+    };
+    link_files(src.to_str().unwrap(), out.to_str().unwrap(), Some(&opts))?;
+
+    let label = Some(String::from("nightly"));
+    assert_eq!(xattr::managed_label(&out), label);
+    assert_eq!(xattr::managed_label(&out.join("sub")), label);
+    assert_eq!(xattr::managed_label(&dst), None);
+    assert_eq!(xattr::managed_label(&dst.join("mine.txt")), None);
+
+    // A hard link shares the source's inode, so marking it would mark the source.
+    assert_eq!(xattr::managed_label(&out.join("sub/file1.txt")), None);
+    assert_eq!(xattr::managed_label(&src.join("sub/file1.txt")), None);
+    Ok(())
+}
+
+#[test]
+fn test_copy_xattrs() -> io::Result<()> {
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+
+    create_test_files([src.join("file1.txt"), dst.join("file1.txt")], b"test content")?;
+    xattr::set(&src.join("file1.txt"), "user.origin", b"camera")?;
+    xattr::mark_managed(&src.join("file1.txt"), "nightly")?;
+
+    xattr::copy_all(&src.join("file1.txt"), &dst.join("file1.txt"))?;
+    assert_eq!(
+        xattr::get(&dst.join("file1.txt"), "user.origin")?,
+        Some(b"camera".to_vec())
+    );
+    assert_eq!(xattr::managed_label(&dst.join("file1.txt")), None);
+    Ok(())
+}
+
+#[test]
+fn test_selinux_modes() -> io::Result<()> {
+    use crate::link::selinux::{self, CONTEXT_XATTR, ContextMode};
+
+    assert_eq!("inherit".parse(), Ok(ContextMode::Inherit));
+    assert_eq!("preserve".parse(), Ok(ContextMode::Preserve));
+    assert_eq!("default".parse(), Ok(ContextMode::Default));
+    assert!("enforcing".parse::<ContextMode>().is_err());
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_files([src.join("file1.txt"), dst.join("copy.txt")], b"content")?;
+
+    // A copy is labeled by the mode, not by the source's context.
+    let label = b"system_u:object_r:flnk_test_t:s0";
+    if xattr::set(&src.join("file1.txt"), CONTEXT_XATTR, label).is_ok() {
+        xattr::copy_all(&src.join("file1.txt"), &dst.join("copy.txt"))?;
+        let copied = xattr::get(&dst.join("copy.txt"), CONTEXT_XATTR)?;
+        assert_ne!(copied.as_deref(), Some(&label[..]));
+    }
+
+    // Without SELinux every mode leaves contexts alone instead of failing.
+    if !selinux::is_enabled() {
+        for mode in [ContextMode::Preserve, ContextMode::Default] {
+            let opts = LinkOptions {
+                symbolic: true,
+                selinux: mode,
+                ..Default::default()
+            };
+            let out = dst.join(format!("{:?}", mode));
+            link_files(&src, &out, Some(&opts))?;
+            assert!(fs::symlink_metadata(out.join("file1.txt"))?.is_symlink());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_sentinel_reports_deleted_source() -> io::Result<()> {
+    use crate::sentinel::{SourceEventKind, SourceSentinel};
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+
+    create_test_files([src.join("file1.txt"), src.join("file2.txt")], b"test content")?;
+    let sentinel = SourceSentinel::new()?;
+    sentinel.track(&src.join("file1.txt"), &dst.join("file1.txt"))?;
+
+    fs::remove_file(src.join("file2.txt"))?;
+    fs::remove_file(src.join("file1.txt"))?;
+    let events = sentinel.next_events()?;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].kind, SourceEventKind::Deleted);
+    assert_eq!(events[0].source, src.join("file1.txt"));
+    assert_eq!(events[0].links, vec![dst.join("file1.txt")]);
+    Ok(())
+}
+
+#[test]
+fn test_import_hook_links_download() -> io::Result<()> {
+    use crate::import_hook::{self, ImportEvent};
+    use std::collections::HashMap;
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+
+    let download = src.join("Show.S01E01.[1080p].mkv");
+    create_test_files([download.clone()], b"test content")?;
+    let vars = HashMap::from([
+        ("sonarr_eventtype", String::from("Download")),
+        ("sonarr_episodefile_sourcepath", download.to_string_lossy().into_owned()),
+        ("sonarr_series_path", String::from("/tv/Show")),
+        ("sonarr_episodefile_relativepath", String::from("Season 01/Show - S01E01.mkv")),
+    ]);
+    let event = ImportEvent::from_env(|name| vars.get(name).cloned())?;
+
+    let log = dst.join("hook.log");
+    import_hook::run(&event, Some(&dst), &LinkOptions::default(), Some(&log))?;
+
+    let linked = dst.join("Show/Season 01/Show - S01E01.mkv");
+    assert_eq!(fs::read(&linked)?, b"test content");
+    assert!(fs::read_to_string(&log)?.contains("Sonarr Download: linked"));
+
+    // Without a library, the only destination is the file Sonarr just imported.
+    let err = import_hook::run(&event, None, &LinkOptions::default(), Some(&log)).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    assert!(fs::read_to_string(&log)?.contains("--library is required"));
+    Ok(())
+}
+
+#[test]
+fn test_existing_sources_are_not_globbed() -> io::Result<()> {
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    let (tagged, plain) = (src.join("Show [1080p].mkv"), src.join("Show 1.mkv"));
+    create_test_files([&tagged, &plain], b"show")?;
+
+    // An existing name is a file, even if it reads as a glob.
+    link_files(&tagged, &dst, None)?;
+    assert!(dst.join("Show [1080p].mkv").exists());
+    assert!(!dst.join("Show 1.mkv").exists());
+
+    // A pattern naming nothing still globs.
+    fs::remove_file(dst.join("Show [1080p].mkv"))?;
+    link_files(src.join("Show [12].mkv"), &dst, None)?;
+    assert!(dst.join("Show 1.mkv").exists());
+    assert!(!dst.join("Show [1080p].mkv").exists());
+    Ok(())
+}
+
+#[test]
+fn test_farm_priorities() -> io::Result<()> {
+    use crate::farm::{self, Package};
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+
+    for name in ["low", "high"] {
+        create_test_files(
+            [src.join(name).join("bin/tool"), src.join(name).join(format!("bin/{}-only", name))],
+            name.as_bytes(),
+        )?;
+    }
+    let farm_dir = dst.join("farm");
+    let packages = [
+        Package::parse(src.join("low").to_str().unwrap()),
+        Package::parse(&format!("{}=10", src.join("high").display())),
+    ];
+
+    let report = farm::apply(&farm_dir, &packages, &LinkOptions::default())?;
+    assert_eq!(fs::read(farm_dir.join("bin/tool"))?, b"high");
+    assert_eq!(fs::read(farm_dir.join("bin/low-only"))?, b"low");
+    assert_eq!(report.conflicts.len(), 1);
+    assert_eq!(report.conflicts[0].winner.priority, 10);
+
+    // A later run with a lower priority leaves the winner in place.
+    let report = farm::apply(&farm_dir, &packages[..1], &LinkOptions::default())?;
+    assert_eq!(fs::read(farm_dir.join("bin/tool"))?, b"high");
+    assert!(report.linked.is_empty());
+
+    // The lowest priority there is sorts last instead of overflowing.
+    let lowest = Package::parse(&format!("{}={}", src.join("low").display(), i64::MIN));
+    let (_other_tmp, other) = create_temp_dir("other")?;
+    let packages = [lowest, packages[1].clone()];
+    farm::apply(&other, &packages, &LinkOptions::default())?;
+    assert_eq!(fs::read(other.join("bin/tool"))?, b"high");
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn test_links_non_utf8_paths() -> io::Result<()> {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    // Not UTF-8, so its `*` is taken literally rather than as a glob.
+    let name = OsStr::from_bytes(b"caf\xe9*");
+    create_test_file(src.join(name).join("menu.txt"), b"menu")?;
+    create_test_file(src.join("cafe").join("other.txt"), b"other")?;
+
+    let report = link_files(src.join(name), &dst, None)?;
+    assert_eq!(report.linked, [Path::new("menu.txt")]);
+    assert_eq!(fs::read(dst.join("menu.txt"))?, b"menu");
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn test_stow_folds_and_splits_trees() -> io::Result<()> {
+    use crate::stow::{self, StowOptions};
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_files(
+        [src.join("vim/.vim/colors/dark"), src.join("vim/.vimrc")],
+        b"vim",
+    )?;
+    create_test_files([src.join("nvim/.vim/plugin/lsp")], b"nvim")?;
+    let (vim, nvim) = (src.join("vim"), src.join("nvim"));
+
+    // A directory only one package has is linked whole.
+    let report = stow::stow(&dst, std::slice::from_ref(&vim), StowOptions::default())?;
+    assert!(report.conflicts.is_empty());
+    assert!(fs::symlink_metadata(dst.join(".vim"))?.is_symlink());
+
+    // A second package splits it into a directory of links.
+    stow::stow(&dst, std::slice::from_ref(&nvim), StowOptions::default())?;
+    assert!(fs::symlink_metadata(dst.join(".vim"))?.is_dir());
+    assert_eq!(fs::read(dst.join(".vim/colors/dark"))?, b"vim");
+    assert_eq!(fs::read(dst.join(".vim/plugin/lsp"))?, b"nvim");
+
+    // Unstowing it folds the directory back into one link.
+    stow::unstow(&dst, std::slice::from_ref(&nvim), StowOptions::default())?;
+    assert!(fs::symlink_metadata(dst.join(".vim"))?.is_symlink());
+    assert_eq!(
+        fs::canonicalize(dst.join(".vim"))?,
+        fs::canonicalize(vim.join(".vim"))?
+    );
+
+    // A file in the way stops the whole run.
+    create_test_file(src.join("git/.gitconfig"), b"git")?;
+    create_test_file(src.join("git/.vimrc"), b"git")?;
+    create_test_file(dst.join(".gitconfig"), b"mine")?;
+    let report = stow::stow(&dst, &[src.join("git")], StowOptions::default())?;
+    assert_eq!(report.conflicts.len(), 2);
+    assert_eq!(fs::read(dst.join(".gitconfig"))?, b"mine");
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn test_stow_adopts_existing_files() -> io::Result<()> {
+    use crate::stow::{self, StowOptions};
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_file(src.join("git/.gitconfig"), b"package")?;
+    create_test_file(dst.join(".gitconfig"), b"machine")?;
+    let opts = StowOptions {
+        adopt: true,
+        ..Default::default()
+    };
+
+    // The machine's file replaces the package's and is linked back.
+    let report = stow::stow(&dst, &[src.join("git")], opts)?;
+    assert!(report.conflicts.is_empty());
+    assert!(fs::symlink_metadata(dst.join(".gitconfig"))?.is_symlink());
+    assert_eq!(fs::read(src.join("git/.gitconfig"))?, b"machine");
+    assert_eq!(fs::read(dst.join(".gitconfig"))?, b"machine");
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_serve_keeps_a_file_at_the_socket_path() -> io::Result<()> {
+    use crate::serve::{ServeOptions, serve};
+
+    let (_tmp, dir) = create_temp_dir("serve")?;
+    let notes = dir.join("notes.txt");
+    create_test_file(&notes, b"keep me")?;
+
+    let err = serve(&notes, &ServeOptions::default()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+    assert_eq!(fs::read(&notes)?, b"keep me");
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_systemd_units() -> io::Result<()> {
+    use crate::systemd;
+
+    let socket = Path::new("/run/flnk/serve.sock");
+    let args = [
+        "serve",
+        "--on-source-gone",
+        r#"notify-send "gone" $FLNK_SOURCE 100%"#,
+    ]
+    .map(String::from);
+    let units = systemd::units("flnk-serve", &args, Some(socket), true)?;
+    assert_eq!(units.service_name, "flnk-serve.service");
+    assert!(units.service.contains("Type=notify\n"));
+    assert!(units.service.contains("Requires=flnk-serve.socket\n"));
+    assert!(units.service.contains("WantedBy=default.target\n"));
+
+    // Arguments with spaces or quotes are quoted, and nothing is expanded.
+    let mut lines = units.service.lines();
+    let exec = lines.find(|line| line.starts_with("ExecStart=")).unwrap();
+    let hook = r#""notify-send \"gone\" $$FLNK_SOURCE 100%%""#;
+    assert!(exec.ends_with(&format!(" serve --on-source-gone {}", hook)));
+
+    let (name, contents) = units.socket.unwrap();
+    assert_eq!(name, "flnk-serve.socket");
+    assert!(contents.contains("ListenStream=/run/flnk/serve.sock\n"));
+
+    // A system unit without a socket is started with the system.
+    let units = systemd::units("flnk-watch", &args[..1], None, false)?;
+    assert!(units.service.contains("WantedBy=multi-user.target\n"));
+    assert!(!units.service.contains("Requires="));
+    assert!(units.socket.is_none());
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_systemd_watchdog_and_activation() {
+    use crate::systemd::{is_activated, parse_watchdog};
+    use std::time::Duration;
+
+    let own = std::process::id();
+    let pid = own.to_string();
+    let other = (own + 1).to_string();
+
+    assert_eq!(parse_watchdog(None, None, own), None);
+    let interval = Some(Duration::from_secs(30));
+    assert_eq!(parse_watchdog(None, Some("30000000"), own), interval);
+    assert_eq!(parse_watchdog(Some(&pid), Some("30000000"), own), interval);
+    assert_eq!(parse_watchdog(Some(&other), Some("30000000"), own), None);
+    assert_eq!(parse_watchdog(Some(&pid), Some("0"), own), None);
+    assert_eq!(parse_watchdog(Some(&pid), Some("soon"), own), None);
+
+    assert!(is_activated(&pid, "1", own));
+    assert!(!is_activated(&other, "1", own));
+    assert!(!is_activated(&pid, "0", own));
+    assert!(!is_activated("", "1", own));
+}
+
+#[test]
+fn test_batch_continues_past_failures() -> io::Result<()> {
+    use crate::batch;
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_files([src.join("a/one.txt"), src.join("b/two.txt")], b"batch")?;
+
+    let op = |source: &Path, dest: &Path| {
+        format!(
+            r#"{{"source": "{}", "dest": "{}", "options": {{"symbolic": true}}}}"#,
+            source.display(),
+            dest.display()
+        )
+    };
+    // A symlink to a missing source would dangle; a hard link cannot be made.
+    let missing = op(&src.join("missing"), &dst.join("missing")).replace("true", "false");
+    let ndjson = [
+        op(&src.join("a"), &dst.join("a")),
+        missing,
+        op(&src.join("b"), &dst.join("b")),
+    ]
+    .join("\n");
+
+    let ops = batch::parse(&ndjson)?;
+    assert_eq!(ops.len(), 3);
+    assert!(ops[0].opts.symbolic);
+
+    let report = batch::run(&ops);
+    assert_eq!(report.linked(), 2);
+    assert_eq!(report.failed(), 1);
+    assert!(dst.join("a/one.txt").is_symlink());
+    assert!(dst.join("b/two.txt").is_symlink());
+
+    // The same operations as a JSON array parse identically.
+    let array = batch::parse(&format!("[{}]", ndjson.replace('\n', ",")))?;
+    assert_eq!(array.len(), 3);
+
+    // Options that cannot be combined are refused as on the command line.
+    let relative = op(&src.join("a"), &dst.join("a")).replace("symbolic", "relative");
+    assert!(batch::parse(&relative).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_fallback_on_linkless_filesystem() -> io::Result<()> {
+    use crate::link::link_files::link_files_probed;
+    use crate::link::probe::FallbackPolicy;
+    use std::os::unix::fs::MetadataExt;
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_files([src.join("a.txt"), src.join("sub/b.txt")], b"fat")?;
+
+    let caps = probe::FsCapabilities {
+        fs_type: "vfat".into(),
+        hard_links: false,
+        symlinks: false,
+        ..probe::probe(&dst)?
+    };
+    let (source, dest) = (src.to_str().unwrap(), dst.join("out"));
+    let dest = dest.to_str().unwrap();
+
+    let opts = LinkOptions {
+        symbolic: true,
+        fallback: FallbackPolicy::Skip,
+        ..Default::default()
+    };
+    assert!(link_files_probed(source, dest, &opts, Some(&caps), |_| {})?.linked.is_empty());
+    assert!(!Path::new(dest).exists());
+
+    let opts = LinkOptions {
+        fallback: FallbackPolicy::Copy,
+        ..opts
+    };
+    link_files_probed(source, dest, &opts, Some(&caps), |_| {})?;
+    let copied = Path::new(dest).join("sub/b.txt");
+    assert!(!copied.is_symlink());
+    assert_eq!(fs::metadata(&copied)?.nlink(), 1);
+    assert_eq!(fs::read(&copied)?, b"fat");
+    Ok(())
+}
+
+#[test]
+fn test_fallback_across_devices() -> io::Result<()> {
+    use crate::link::link_files::link_files_probed;
+    use crate::link::probe::FallbackPolicy;
+    use crate::link::warning::WarningKind;
+    use std::os::unix::fs::MetadataExt;
+
+    // Needs a source on another device than the temporary directory.
+    let (_dst_tmp, dst) = create_temp_dir("dest")?;
+    let Ok(src_tmp) = tempfile::tempdir_in("/dev/shm") else {
+        return Ok(());
+    };
+    if fs::metadata(src_tmp.path())?.dev() == fs::metadata(&dst)?.dev() {
+        return Ok(());
+    }
+    let src = src_tmp.path().join("src");
+    create_test_files([src.join("a.txt"), src.join("sub/b.txt")], b"shm")?;
+    let source = src.to_str().unwrap();
+
+    let opts = LinkOptions::default();
+    let err = link_files_probed(source, dst.to_str().unwrap(), &opts, None, |_| {}).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::CrossesDevices);
+
+    let skipped = dst.join("skipped");
+    let opts = LinkOptions {
+        fallback: FallbackPolicy::Skip,
+        ..Default::default()
+    };
+    let report = link_files_probed(source, skipped.to_str().unwrap(), &opts, None, |_| {})?;
+    assert!(report.linked.is_empty());
+    assert!(!skipped.join("a.txt").exists());
+    assert_eq!(report.warnings[0].kind, WarningKind::FallbackUsed);
+
+    let symlinked = dst.join("symlinked");
+    let opts = LinkOptions {
+        fallback: FallbackPolicy::Symlink,
+        ..Default::default()
+    };
+    let report = link_files_probed(source, symlinked.to_str().unwrap(), &opts, None, |_| {})?;
+    assert_eq!(report.linked.len(), 2);
+    assert_eq!(fs::read_link(symlinked.join("sub/b.txt"))?, src.join("sub/b.txt"));
+    Ok(())
+}
+
+#[test]
+fn test_reflink_mode() -> io::Result<()> {
+    use crate::link::link_files::link_files_probed;
+    use std::os::unix::fs::MetadataExt;
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_file(src.join("a.txt"), b"cow")?;
+    let caps = probe::probe(&dst)?;
+    let opts = LinkOptions {
+        reflink: true,
+        ..Default::default()
+    };
+    let (source, dest) = (src.to_str().unwrap(), dst.join("out"));
+    let result = link_files_probed(source, dest.to_str().unwrap(), &opts, Some(&caps), |_| {});
+
+    // The clone is only made where the temporary directory's filesystem can.
+    if caps.reflinks {
+        let clone = dest.join("a.txt");
+        result?;
+        assert_eq!(fs::metadata(&clone)?.nlink(), 1);
+        assert_eq!(fs::read(&clone)?, b"cow");
+    } else {
+        let err = result.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        assert!(err.to_string().contains("copy-on-write"));
+        assert!(!dest.exists());
+    }
+    Ok(())
+}
+
+#[test]
+fn test_platform_link_helpers() -> io::Result<()> {
+    use crate::link::platform;
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_file(src.join("sub/a.txt"), b"kept")?;
+
+    // A directory symlink is removed without touching its target.
+    let link = dst.join("sub");
+    platform::symlink(&src.join("sub"), &link)?;
+    assert!(link.is_dir());
+    platform::remove_link(&link)?;
+    assert!(fs::symlink_metadata(&link).is_err());
+    assert!(src.join("sub/a.txt").exists());
+
+    assert_eq!(platform::slash_path(&Path::new("sub").join("a.txt")), "sub/a.txt");
+    Ok(())
+}
+
+#[test]
+fn test_network_fs_handling() -> io::Result<()> {
+    use crate::link::link_files::link_files_probed;
+    use crate::link::netfs::{self, NetworkFsMode};
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_files([src.join("a.txt")], b"nfs")?;
+
+    let caps = probe::FsCapabilities {
+        fs_type: "nfs".into(),
+        device: u64::MAX,
+        ..probe::probe(&dst)?
+    };
+    let source = src.join("a.txt");
+    let (source, dest) = (source.to_str().unwrap(), dst.join("a.txt"));
+    let dest = dest.to_str().unwrap();
+
+    let err = link_files_probed(source, dest, &LinkOptions::default(), Some(&caps), |_| {})
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::CrossesDevices);
+
+    let opts = LinkOptions {
+        symbolic: true,
+        network_fs: NetworkFsMode::Strict,
+        ..Default::default()
+    };
+    link_files_probed(source, dest, &opts, Some(&caps), |_| {})?;
+    assert!(fs::read_link(dest)?.is_relative());
+
+    let mut attempts = 0;
+    let result = netfs::retry_stale(|| {
+        attempts += 1;
+        if attempts < 3 {
+            Err(io::Error::from_raw_os_error(libc::ESTALE))
+        } else {
+            Ok(attempts)
+        }
+    });
+    assert_eq!(result?, 3);
+    Ok(())
+}
+
+#[test]
+fn test_checksum_manifest() -> io::Result<()> {
+    use crate::sha256::{self, Sha256};
+
+    let mut hasher = Sha256::new();
+    hasher.update(b"abc");
+    assert_eq!(
+        sha256::to_hex(&hasher.finish()),
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_files([src.join("a.txt"), src.join("sub/b.txt")], b"abc")?;
+    let manifest = dst.join("SHA256SUMS");
+    let opts = LinkOptions {
+        checksum_manifest: Some(manifest.clone()),
+        ..Default::default()
+    };
+    link_files(
+        src.to_str().unwrap(),
+        dst.join("out").to_str().unwrap(),
+        Some(&opts),
+    )?;
+
+    let mut lines: Vec<String> = fs::read_to_string(&manifest)?
+        .lines()
+        .map(str::to_string)
+        .collect();
+    lines.sort();
+    let hash = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+    assert_eq!(
+        lines,
+        [
+            format!("{}  out/a.txt", hash),
+            format!("{}  out/sub/b.txt", hash)
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_response_file_expansion() -> io::Result<()> {
+    use crate::response_file;
+    use std::ffi::OsString;
+
+    let (_tmp, dir) = create_temp_dir("args")?;
+    let inner = dir.join("inner.txt");
+    let outer = dir.join("outer.txt");
+    fs::write(&inner, "dest dir\n")?;
+    fs::write(
+        &outer,
+        format!("# sources\r\n-s\r\n\r\nsrc one\r\n@{}\r\n", inner.display()),
+    )?;
+
+    let args = response_file::expand([
+        OsString::from("-v"),
+        OsString::from(format!("@{}", outer.display())),
+        OsString::from("@missing"),
+    ])?;
+    assert_eq!(args, ["-v", "-s", "src one", "dest dir", "@missing"]);
+    Ok(())
+}
+
+#[test]
+fn test_file_list_entries() -> io::Result<()> {
+    use crate::file_list;
+
+    // Newlines separate paths unless NULs do; empty entries are left out.
+    let text = b"a b.txt\n\nsub/c.txt\n";
+    let paths = file_list::split(text, false, "list")?;
+    assert_eq!(paths, ["a b.txt", "sub/c.txt"]);
+    let nul = b"line\nbreak\0\0d.txt\0";
+    let paths = file_list::split(nul, true, "list")?;
+    assert_eq!(paths, ["line\nbreak", "d.txt"]);
+    let err = file_list::split(b"ok\ncaf\xe9\n", false, "list").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(err.to_string().starts_with("list: "));
+
+    // `-` reads standard input; anything else names a file.
+    let stdin = &b"x.txt\ny.txt"[..];
+    assert_eq!(file_list::read("-", false, stdin)?, ["x.txt", "y.txt"]);
+    let (_tmp, dir) = create_temp_dir("list")?;
+    fs::write(dir.join("files.txt"), "z.txt\n")?;
+    let list = dir.join("files.txt").to_string_lossy().into_owned();
+    assert_eq!(file_list::read(&list, false, stdin)?, ["z.txt"]);
+    let missing = list.replace("files", "missing");
+    assert!(file_list::read(&missing, false, stdin).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_warnings_reported() -> io::Result<()> {
+    use crate::link::link_files::link_files;
+    use crate::link::warning::WarningKind;
+    use std::os::unix::net::UnixListener;
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_files([src.join("a.txt")], b"new")?;
+    let _socket = UnixListener::bind(src.join("app.sock"))?;
+    create_test_files([dst.join("a.txt"), dst.join("a.txt~")], b"old")?;
+
+    let opts = LinkOptions {
+        backup: true,
+        ..Default::default()
+    };
+    let report = link_files(src.to_str().unwrap(), dst.to_str().unwrap(), Some(&opts))?;
+    let mut kinds: Vec<WarningKind> = report.warnings.iter().map(|w| w.kind).collect();
+    kinds.sort_by_key(|kind| kind.as_str());
+    assert_eq!(
+        kinds,
+        [WarningKind::BackupCollision, WarningKind::SpecialFileSkipped]
+    );
+    assert_eq!(report.linked, [PathBuf::from("a.txt")]);
+
+    let pattern = src.join("*.none");
+    let report = link_files(pattern.to_str().unwrap(), dst.to_str().unwrap(), None)?;
+    assert_eq!(report.warnings[0].kind, WarningKind::NoMatch);
+    Ok(())
+}
+
+#[test]
+fn test_progress_callback_and_when() -> io::Result<()> {
+    use crate::link::link_files::link_files_progress;
+    use crate::term::When;
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_files([src.join("a.txt"), src.join("sub/b.txt")], b"x")?;
+
+    let mut seen = Vec::new();
+    let report = link_files_progress(
+        src.to_str().unwrap(),
+        dst.to_str().unwrap(),
+        None,
+        |file| seen.push(file.to_path_buf()),
+    )?;
+    seen.sort();
+    let mut linked = report.linked;
+    linked.sort();
+    assert_eq!(seen, linked);
+    assert_eq!(seen.len(), 2);
+
+    assert_eq!("never".parse::<When>(), Ok(When::Never));
+    assert!("sometimes".parse::<When>().is_err());
+    Ok(())
+}
+
+#[test]
+fn test_count_existing_destinations() -> io::Result<()> {
+    use crate::link::link_files::count_existing;
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_files(
+        [src.join("a.txt"), src.join("sub/b.txt"), src.join("sub/c.txt")],
+        b"new",
+    )?;
+    create_test_files([dst.join("a.txt"), dst.join("sub/c.txt")], b"old")?;
+
+    let count = count_existing(src.to_str().unwrap(), dst.to_str().unwrap(), None)?;
+    assert_eq!(count, 2, "the merged sub directory is not counted");
+    assert_eq!(fs::read(dst.join("a.txt"))?, b"old", "planning changes nothing");
+    Ok(())
+}
+
+#[test]
+fn test_intra_run_destination_collision() -> io::Result<()> {
+    use crate::link::link_files::link_files;
+    use crate::link::warning::WarningKind;
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_files([src.join("r1/x.txt")], b"from r1")?;
+    create_test_files([src.join("r2/x.txt")], b"from r2")?;
+    let pattern = src.join("r*");
+    let pattern = pattern.to_str().unwrap();
+    let dest = dst.to_str().unwrap();
+
+    let err = link_files(pattern, dest, None).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+    assert!(!dst.join("x.txt").exists(), "nothing is created");
+
+    let opts = LinkOptions {
+        force: true,
+        ..Default::default()
+    };
+    let report = link_files(pattern, dest, Some(&opts))?;
+    assert_eq!(fs::read(dst.join("x.txt"))?, b"from r2");
+    assert_eq!(report.linked.len(), 1);
+    assert_eq!(report.warnings[0].kind, WarningKind::DestinationCollision);
+    Ok(())
+}
+
+#[test]
+fn test_lexical_normalization() {
+    use crate::link::lexical::{escapes, is_within, join_within, normalize};
+
+    assert_eq!(normalize(Path::new("a/./b/../c")), Path::new("a/c"));
+    assert_eq!(normalize(Path::new("dest/../..")), Path::new(".."));
+    assert_eq!(normalize(Path::new("/../a/..")), Path::new("/"));
+    assert_eq!(normalize(Path::new("a/..")), Path::new("."));
+
+    assert!(escapes(Path::new("dest/../..")));
+    assert!(!escapes(Path::new("../dest")));
+    assert!(!escapes(Path::new("dest/../other")));
+
+    assert!(is_within(Path::new("jail/a/../b"), Path::new("jail")));
+    assert!(!is_within(Path::new("../../x"), Path::new("..")));
+    assert_eq!(
+        join_within(Path::new("/jail"), Path::new("a/../b")).unwrap(),
+        Path::new("/jail/b")
+    );
+    assert!(join_within(Path::new("/jail"), Path::new("a/../../etc")).is_err());
+    assert!(join_within(Path::new("/jail"), Path::new("/etc")).is_err());
+}
+
+#[test]
+fn test_path_limits_checked_up_front() {
+    let caps = probe::FsCapabilities {
+        probed_dir: PathBuf::from("/media/usb"),
+        fs_type: String::from("vfat"),
+        device: 0,
+        hard_links: false,
+        symlinks: false,
+        reflinks: false,
+        case_sensitive: false,
+        name_max: Some(255),
+        path_max: Some(64),
+    };
+    let long_name = PathBuf::from("/media/usb").join("n".repeat(300));
+    let deep = PathBuf::from("/media/usb").join("d/".repeat(40));
+    let short = PathBuf::from("/media/usb/ok.mkv");
+
+    assert!(probe::check_path_limits(&caps, [short.as_path()]).is_ok());
+    let err = probe::check_path_limits(&caps, [&short, &long_name, &deep].map(PathBuf::as_path))
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidFilename);
+    let message = err.to_string();
+    assert!(message.starts_with("2 destination paths exceed"));
+    assert!(message.contains("a name is 300 bytes"));
+    assert!(!message.contains("ok.mkv"));
+}
+
+#[test]
+fn test_negated_patterns_and_ignore_file() -> io::Result<()> {
+    use crate::link::filter::IGNORE_FILE;
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_files(
+        [
+            src.join("Season 01/e01.mkv"),
+            src.join("Season 01/e01-sample.mkv"),
+            src.join("Season 02/e01.mkv"),
+            src.join("extras/a.nfo"),
+            src.join("extras/b.nfo"),
+        ],
+        b"x",
+    )?;
+    fs::write(src.join(IGNORE_FILE), "# keep only b.nfo\n*.nfo\n!b.nfo\n")?;
+
+    let mut opts = LinkOptions::default();
+    opts.filter.add_rule("- *sample*")?;
+    opts.filter.add_rule("- !Season 01/***")?;
+    let mut linked = link_files(src.to_str().unwrap(), dst.to_str().unwrap(), Some(&opts))?.linked;
+    linked.sort();
+    assert_eq!(linked, vec![PathBuf::from("Season 01/e01.mkv")]);
+
+    let mut opts = LinkOptions::default();
+    opts.filter.add_ignore_file(IGNORE_FILE);
+    opts.filter.add_rule(&format!("- /{}", IGNORE_FILE))?;
+    let dst = dst.join("second");
+    let mut linked = link_files(src.to_str().unwrap(), dst.to_str().unwrap(), Some(&opts))?.linked;
+    linked.sort();
+    assert_eq!(
+        linked,
+        vec![
+            PathBuf::from("Season 01/e01-sample.mkv"),
+            PathBuf::from("Season 01/e01.mkv"),
+            PathBuf::from("Season 02/e01.mkv"),
+            PathBuf::from("extras/b.nfo"),
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_brace_expansion_in_sources() -> io::Result<()> {
+    use crate::link::glob::expand_braces;
+
+    assert_eq!(
+        expand_braces("*.{mkv,mp4,srt}"),
+        ["*.mkv", "*.mp4", "*.srt"]
+    );
+    assert_eq!(expand_braces("a{b,c{d,e}}f"), ["abf", "acdf", "acef"]);
+    assert_eq!(expand_braces("Show {2019}.mkv"), ["Show {2019}.mkv"]);
+    assert_eq!(expand_braces("x\\{a,b}"), ["x\\{a,b}"]);
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_files(
+        [
+            src.join("ep.mkv"),
+            src.join("ep.srt"),
+            src.join("ep.nfo"),
+            src.join("Show {2019}.mkv"),
+        ],
+        b"x",
+    )?;
+
+    let pattern = src.join("*.{mkv,srt,mp4}");
+    let linked = link_files(pattern.to_str().unwrap(), dst.to_str().unwrap(), None)?.linked;
+    assert_eq!(linked.len(), 3);
+    assert!(dst.join("ep.mkv").exists());
+    assert!(dst.join("ep.srt").exists());
+    assert!(dst.join("Show {2019}.mkv").exists());
+    assert!(!dst.join("ep.nfo").exists());
+    Ok(())
+}
+
+#[test]
+fn test_recursive_glob_sources() -> io::Result<()> {
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_files(
+        [
+            src.join("shows/A/S01/e1.mkv"),
+            src.join("shows/A/S01/e1.nfo"),
+            src.join("shows/B/extra/S01b/e2.mkv"),
+            src.join("shows/B/S02/e3.mkv"),
+        ],
+        b"x",
+    )?;
+
+    let pattern = src.join("shows/**/S01*/*.mkv");
+    let linked = link_files(pattern.to_str().unwrap(), dst.to_str().unwrap(), None)?.linked;
+    assert_eq!(linked.len(), 2);
+    assert!(dst.join("e1.mkv").exists());
+    assert!(dst.join("e2.mkv").exists());
+    assert!(!dst.join("e3.mkv").exists());
+
+    let pattern = src.join("shows/?/S0[2-9]/*");
+    let linked = link_files(pattern.to_str().unwrap(), dst.to_str().unwrap(), None)?.linked;
+    assert_eq!(linked.len(), 1);
+    assert!(dst.join("e3.mkv").exists());
+    Ok(())
+}
+
+#[test]
+fn test_owner_and_group_overrides() -> io::Result<()> {
+    use crate::link::archive::is_root;
+    use crate::link::owner::{lookup_group, lookup_user};
+    use std::os::unix::fs::MetadataExt;
+
+    assert_eq!(lookup_user("root")?, 0);
+    assert_eq!(lookup_group("1234")?, 1234);
+    assert_eq!(
+        lookup_user("no-such-flnk-user").unwrap_err().kind(),
+        io::ErrorKind::NotFound
+    );
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_files([src.join("show/ep.mkv")], b"x")?;
+    let before = fs::metadata(src.join("show/ep.mkv"))?;
+    // Only root may give entries away; others can still set their own ids.
+    let (uid, gid) = if is_root() {
+        (65534, 65534)
+    } else {
+        (before.uid(), before.gid())
+    };
+
+    let opts = LinkOptions {
+        symbolic: true,
+        symlink_files_only: true,
+        owner: Some(uid),
+        group: Some(gid),
+        ..Default::default()
+    };
+    link_files(src.to_str().unwrap(), dst.to_str().unwrap(), Some(&opts))?;
+    for path in [dst.join("show"), dst.join("show/ep.mkv")] {
+        let meta = fs::symlink_metadata(&path)?;
+        assert_eq!((meta.uid(), meta.gid()), (uid, gid), "{}", path.display());
+    }
+    let source = fs::metadata(src.join("show/ep.mkv"))?;
+    assert_eq!((source.uid(), source.gid()), (before.uid(), before.gid()));
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn test_chmod_spec_for_created_dirs() -> io::Result<()> {
+    use crate::link::chmod::ChmodSpec;
+    use std::os::unix::fs::PermissionsExt;
+
+    let spec: ChmodSpec = "D2775,F664".parse().unwrap();
+    assert_eq!(spec.apply_to(0o755, true), 0o2775);
+    assert_eq!(spec.apply_to(0o600, false), 0o664);
+    let spec: ChmodSpec = "Dg+s,ug+w,Fo-w,a+X".parse().unwrap();
+    assert_eq!(spec.apply_to(0o755, true), 0o2775);
+    assert_eq!(spec.apply_to(0o646, false), 0o664);
+    assert_eq!(spec.apply_to(0o744, false), 0o775);
+    assert!("D2775,Fq+w".parse::<ChmodSpec>().is_err());
+    assert!("D17777".parse::<ChmodSpec>().is_err());
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_files([src.join("show/season/ep.mkv")], b"x")?;
+    fs::set_permissions(src.join("show/season/ep.mkv"), fs::Permissions::from_mode(0o600))?;
+
+    let opts = LinkOptions {
+        chmod: Some("D2770,F664".parse().unwrap()),
+        ..Default::default()
+    };
+    link_files(src.to_str().unwrap(), dst.to_str().unwrap(), Some(&opts))?;
+    for dir in [dst.join("show"), dst.join("show/season")] {
+        let mode = fs::metadata(&dir)?.permissions().mode() & 0o7777;
+        assert_eq!(mode, 0o2770, "{}", dir.display());
+    }
+    // Hard links share the source inode, whose mode is left alone.
+    let mode = fs::metadata(dst.join("show/season/ep.mkv"))?.permissions().mode();
+    assert_eq!(mode & 0o7777, 0o600);
+    Ok(())
+}
+
+#[test]
+fn test_backup_generations() -> io::Result<()> {
+    use crate::link::backups::{self, BACKUP_DIR};
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_files([src.join("show/ep.mkv")], b"new")?;
+    let opts = LinkOptions {
+        backup: true,
+        backup_generations: Some(2),
+        ..Default::default()
+    };
+
+    // Fake two older generations so the run's own generation prunes the oldest.
+    let root = dst.join(BACKUP_DIR);
+    fs::create_dir_all(root.join("20000101T000000Z"))?;
+    fs::create_dir_all(root.join("20000101T000000Z-1"))?;
+    create_test_files([dst.join("show/ep.mkv")], b"old")?;
+
+    link_files(src.to_str().unwrap(), dst.to_str().unwrap(), Some(&opts))?;
+    assert_eq!(fs::read(dst.join("show/ep.mkv"))?, b"new");
+    assert!(!dst.join("show/ep.mkv~").exists());
+
+    let kept = backups::generations(&dst)?;
+    assert_eq!(kept.len(), 2);
+    assert!(kept[0].ends_with("20000101T000000Z-1"));
+    assert_eq!(fs::read(kept[1].join("show/ep.mkv"))?, b"old");
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn test_gc_expired_generations_and_stale_probes() -> io::Result<()> {
+    use crate::gc::{self, Leftover};
+    use crate::link::backups::BACKUP_DIR;
+
+    let (_tmp, dst) = create_temp_dir("dst")?;
+    let root = dst.join(BACKUP_DIR);
+    for name in ["20000101T000000Z", "20000102T000000Z", "20000103T000000Z"] {
+        create_test_files([root.join(name).join("show/ep.mkv")], b"old")?;
+    }
+    // No process has id i32::MAX, while this one is alive.
+    let stale = dst.join(format!("{}{}-1-a", probe::PROBE_PREFIX, i32::MAX));
+    let live = dst.join(format!("{}{}-1-a", probe::PROBE_PREFIX, std::process::id()));
+    create_test_files([stale.clone(), live.clone()], b"flnk")?;
+
+    let items = gc::scan(&dst, 2)?;
+    let found: Vec<_> = items.iter().map(|i| (i.path.clone(), i.kind)).collect();
+    assert_eq!(
+        found,
+        [
+            (root.join("20000101T000000Z"), Leftover::BackupGeneration),
+            (stale.clone(), Leftover::StaleProbe),
+        ]
+    );
+    assert_eq!(gc::reclaim(&items)?, 7);
+    assert!(!root.join("20000101T000000Z").exists() && !stale.exists());
+    assert!(root.join("20000102T000000Z").exists() && live.exists());
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn test_clean_broken_symlinks() -> io::Result<()> {
+    use crate::clean;
+    use crate::link::backups::BACKUP_DIR;
+    use std::os::unix::fs::symlink;
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    let src = fs::canonicalize(src)?;
+    create_test_files(["a.txt", "b.txt"].map(|n| src.join(n)), b"x")?;
+    fs::create_dir_all(dst.join("sub"))?;
+    fs::create_dir_all(dst.join(BACKUP_DIR))?;
+    symlink(src.join("a.txt"), dst.join("sub/a.txt"))?;
+    symlink(src.join("b.txt"), dst.join("b.txt"))?;
+    symlink("elsewhere", dst.join("other"))?;
+    symlink(src.join("a.txt"), dst.join(BACKUP_DIR).join("a.txt"))?;
+    fs::remove_file(src.join("a.txt"))?;
+
+    let broken = clean::scan(&dst, None)?;
+    let paths: Vec<_> = broken.iter().map(|link| link.path.clone()).collect();
+    assert_eq!(paths, [dst.join("other"), dst.join("sub/a.txt")]);
+    assert_eq!(broken[1].target, src.join("a.txt"));
+
+    // Limited to the source tree, the link to `elsewhere` stays.
+    let broken = clean::scan(&dst, Some(&src))?;
+    assert_eq!(broken.len(), 1);
+    clean::remove(&broken)?;
+    assert!(fs::symlink_metadata(dst.join("sub/a.txt")).is_err());
+    assert!(fs::symlink_metadata(dst.join("other")).is_ok());
+    assert!(dst.join("b.txt").exists());
+    assert!(fs::symlink_metadata(dst.join(BACKUP_DIR).join("a.txt")).is_ok());
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn test_dedup_links_identical_files() -> io::Result<()> {
+    use crate::dedupe;
+    use std::os::unix::fs::MetadataExt;
+
+    let (_tmp, dir) = create_temp_dir("dedup")?;
+    create_test_files(
+        [dir.join("a.txt"), dir.join("b.txt"), dir.join("sub/c.txt")],
+        b"same",
+    )?;
+    create_test_file(dir.join("other.txt"), b"diff")?;
+    fs::hard_link(dir.join("b.txt"), dir.join("b2.txt"))?;
+
+    // The file with two names is kept, and each name counts once.
+    let duplicates = dedupe::scan(&dir)?;
+    assert_eq!(duplicates.len(), 1);
+    assert_eq!(duplicates[0].keep, dir.join("b.txt"));
+    assert_eq!(
+        duplicates[0].copies,
+        [dir.join("a.txt"), dir.join("sub/c.txt")]
+    );
+    assert_eq!(dedupe::link_copies(&duplicates)?, 8);
+
+    let inode = |name: &str| fs::metadata(dir.join(name)).map(|m| m.ino());
+    assert_eq!(inode("a.txt")?, inode("b.txt")?);
+    assert_eq!(inode("sub/c.txt")?, inode("b.txt")?);
+    assert_ne!(inode("other.txt")?, inode("b.txt")?);
+    assert!(dedupe::scan(&dir)?.is_empty());
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn test_share_extents() -> io::Result<()> {
+    use crate::dedupe::share_extents;
+    use std::os::unix::fs::MetadataExt;
+
+    let (_tmp, dir) = create_temp_dir("share")?;
+    let (keep, copy) = (dir.join("keep"), dir.join("copy"));
+    create_test_files([&keep, &copy], vec![7; 64 * 1024])?;
+    create_test_file(dir.join("short"), b"short")?;
+    create_test_file(dir.join("differs"), vec![8; 64 * 1024])?;
+
+    // Files of different sizes are refused before the filesystem is asked.
+    let err = share_extents(&keep, &dir.join("short")).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    let differs = share_extents(&keep, &dir.join("differs"));
+    match share_extents(&keep, &copy) {
+        // tmpfs and ext4, where tests usually run, cannot share extents.
+        Err(err) if err.kind() == io::ErrorKind::Unsupported => {
+            assert_eq!(differs.unwrap_err().kind(), io::ErrorKind::Unsupported);
+        }
+        shared => {
+            assert_eq!(shared?, 64 * 1024);
+            assert_eq!(differs.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        }
+    }
+    // Either way, both stay separate files with their own contents.
+    let ino = |path: &Path| fs::metadata(path).map(|m| m.ino());
+    assert_ne!(ino(&keep)?, ino(&copy)?);
+    assert_eq!(fs::read(dir.join("differs"))?, vec![8; 64 * 1024]);
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn test_doctor_diagnose() -> io::Result<()> {
+    use crate::doctor::diagnose;
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_files([src.join("a.txt"), src.join("sub/b.txt")], b"data")?;
+    let opts = LinkOptions::default();
+
+    let report = diagnose(&src, &dst, &opts)?;
+    let caps = report.dest_caps.as_ref().expect("a temp dir can be probed");
+    assert!(caps.hard_links && caps.symlinks);
+    assert_eq!(report.same_device, Some(true));
+    assert!(report.free_bytes.is_some());
+    assert_eq!((report.entries, report.conflicts), (2, 0));
+    assert_eq!(report.source_bytes, 8);
+    assert!(report.is_healthy(), "{}", report);
+
+    // An existing destination file is a conflict without -f.
+    create_test_file(dst.join("a.txt"), b"old")?;
+    let report = diagnose(&src, &dst, &opts)?;
+    assert_eq!(report.conflicts, 1);
+    assert!(!report.is_healthy());
+
+    // A missing source is a problem, which `flnk doctor` exits 1 on.
+    let report = diagnose(src.join("missing"), &dst, &opts)?;
+    assert_eq!((report.entries, report.same_device), (0, None));
+    assert_eq!(report.source_fs, "unknown");
+    assert!(!report.is_healthy());
+    assert!(report.problems[0].contains("No such file"), "{}", report);
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn test_inspect_symlink_chains() -> io::Result<()> {
+    use crate::inspect::{Resolution, inspect};
+    use std::os::unix::fs::symlink;
+
+    let (_tmp, dir) = create_temp_dir("inspect")?;
+    create_test_files([dir.join("file")], b"data")?;
+    fs::hard_link(dir.join("file"), dir.join("twin"))?;
+    symlink("file", dir.join("one"))?;
+    symlink(dir.join("one"), dir.join("two"))?;
+    symlink("missing", dir.join("dangling"))?;
+    symlink("self", dir.join("self"))?;
+
+    let found = inspect(&dir.join("file"))?;
+    assert_eq!((found.identity.kind, found.identity.links), ("file", 2));
+    assert!(found.chain.is_empty() && found.resolution.is_none());
+
+    let found = inspect(&dir.join("two"))?;
+    assert_eq!(found.chain.len(), 2);
+    assert_eq!(found.chain[1].1, PathBuf::from("file"));
+    let real = fs::canonicalize(dir.join("file"))?;
+    assert!(matches!(
+        found.resolution,
+        Some(Resolution::Resolved(path, identity)) if path == real && identity.size == 4
+    ));
+
+    let found = inspect(&dir.join("dangling"))?;
+    assert!(matches!(found.resolution, Some(Resolution::Dangling(path)) if path.ends_with("missing")));
+    assert_eq!(inspect(&dir.join("self"))?.resolution, Some(Resolution::Loop));
+    assert!(inspect(&dir.join("absent")).is_err());
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn test_relative_to_base() -> io::Result<()> {
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_files([src.join("show/ep.mkv")], b"x")?;
+    let src = fs::canonicalize(&src)?;
+
+    // The destination will be served from /srv/site, which need not exist here.
+    let opts = LinkOptions {
+        symbolic: true,
+        symlink_files_only: true,
+        relative: true,
+        relative_to: Some(PathBuf::from("/srv/site")),
+        ..Default::default()
+    };
+    link_files(src.to_str().unwrap(), dst.to_str().unwrap(), Some(&opts))?;
+    let target = fs::read_link(dst.join("show/ep.mkv"))?;
+    let expected = pathdiff::diff_paths(src.join("show/ep.mkv"), "/srv/site/show").unwrap();
+    assert_eq!(target, expected);
+    assert!(target.starts_with("../.."));
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn test_lexical_relative_links() -> io::Result<()> {
+    use std::os::unix::fs::symlink;
+
+    let (_tmp, dir) = create_temp_dir("lexical")?;
+    create_test_files([dir.join("real/file")], b"x")?;
+    fs::create_dir_all(dir.join("via"))?;
+    fs::create_dir_all(dir.join("out"))?;
+    symlink("../real", dir.join("via/up"))?;
+
+    let opts = LinkOptions {
+        symbolic: true,
+        relative: true,
+        lexical: true,
+        ..Default::default()
+    };
+    // Symlinks along the source path are kept rather than resolved.
+    let source = dir.join("via/up/file");
+    link_files(source.to_str().unwrap(), dir.join("out/kept").to_str().unwrap(), Some(&opts))?;
+    assert_eq!(fs::read_link(dir.join("out/kept"))?, PathBuf::from("../via/up/file"));
+
+    // A source that does not exist yet is linked as named.
+    let future = dir.join("future/file");
+    let out = dir.join("out");
+    link_files(future.to_str().unwrap(), out.to_str().unwrap(), Some(&opts))?;
+    assert_eq!(fs::read_link(out.join("file"))?, PathBuf::from("../future/file"));
+    let named = out.join("named");
+    link_files(future.to_str().unwrap(), named.to_str().unwrap(), Some(&opts))?;
+    assert_eq!(fs::read_link(named)?, PathBuf::from("../future/file"));
+    Ok(())
+}
+
+#[test]
+fn test_copy_progress_for_large_files() -> io::Result<()> {
+    use crate::link::link_files::copy_file;
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    let content: Vec<u8> = (0..(9u32 << 20)).map(|i| (i % 251) as u8).collect();
+    create_test_file(src.join("big.img"), &content)?;
+    create_test_file(src.join("small.txt"), b"small")?;
+
+    let mut seen = Vec::new();
+    copy_file(&src.join("big.img"), &dst.join("big.img"), |p| seen.push(p))?;
+    assert!(!seen.is_empty());
+    assert!(seen.windows(2).all(|w| w[0].copied < w[1].copied));
+    let last = seen.last().unwrap();
+    assert_eq!((last.copied, last.total), (content.len() as u64, content.len() as u64));
+    assert_eq!(fs::read(dst.join("big.img"))?, content);
+
+    // Small files are copied in one go without progress.
+    copy_file(&src.join("small.txt"), &dst.join("small.txt"), |_| panic!("progress"))?;
+    assert_eq!(fs::read(dst.join("small.txt"))?, b"small");
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn test_explain_skipped_entries() -> io::Result<()> {
+    use crate::link::filter::{Filter, IGNORE_FILE};
+    use crate::link::link_files::link_files;
+    use crate::link::skip::SkipReason;
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_files([src.join("a.txt"), src.join("b.tmp"), src.join("sub/c.log")], b"x")?;
+    create_test_file(src.join("sub").join(IGNORE_FILE), "*.log\n")?;
+    std::os::unix::fs::symlink("a.txt", src.join("alias"))?;
+
+    let mut filter = Filter::default();
+    filter.add_rule("- *.tmp")?;
+    filter.add_ignore_file(IGNORE_FILE);
+    let opts = LinkOptions {
+        filter,
+        explain: true,
+        ..Default::default()
+    };
+    let report = link_files(src.to_str().unwrap(), dst.to_str().unwrap(), Some(&opts))?;
+    let reasons: Vec<_> = report
+        .skipped
+        .iter()
+        .map(|skip| (skip.path.strip_prefix(&src).unwrap().to_path_buf(), skip.reason.clone()))
+        .collect();
+    assert_eq!(
+        reasons,
+        [
+            (
+                PathBuf::from("alias"),
+                SkipReason::WrongType("symlinks are only linked with -s or --archive-link")
+            ),
+            (PathBuf::from("b.tmp"), SkipReason::Excluded("- *.tmp".into())),
+            (
+                PathBuf::from("sub/c.log"),
+                SkipReason::Excluded(format!("*.log in sub/{}", IGNORE_FILE))
+            ),
+        ]
+    );
+
+    // Without --explain nothing is recorded.
+    let opts = LinkOptions {
+        explain: false,
+        force: true,
+        ..opts
+    };
+    let report = link_files(src.to_str().unwrap(), dst.to_str().unwrap(), Some(&opts))?;
+    assert!(report.skipped.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_home_relative_targets() {
+    use crate::link::home::{HomeStyle, expand_in};
+
+    let home = Path::new("/home/alice");
+    let vimrc = Path::new("/home/alice/dotfiles/vimrc");
+    assert_eq!(
+        HomeStyle::Tilde.abbreviate(vimrc, home),
+        Some(PathBuf::from("~/dotfiles/vimrc"))
+    );
+    assert_eq!(
+        HomeStyle::Env.abbreviate(vimrc, home),
+        Some(PathBuf::from("$HOME/dotfiles/vimrc"))
+    );
+    assert_eq!(HomeStyle::Tilde.abbreviate(Path::new("/home/alicia/x"), home), None);
+
+    // Synced to a machine whose home is elsewhere, both forms expand there.
+    let other = Path::new("/Users/alice");
+    for target in ["~/dotfiles/vimrc", "$HOME/dotfiles/vimrc", "${HOME}/dotfiles/vimrc"] {
+        assert_eq!(
+            expand_in(Path::new(target), other),
+            Some(other.join("dotfiles/vimrc"))
+        );
+    }
+    assert_eq!(expand_in(Path::new("~alice/x"), other), None);
+    assert_eq!(expand_in(Path::new("dotfiles/vimrc"), other), None);
+    assert!("fish".parse::<HomeStyle>().is_err());
+}
+
+#[test]
+fn test_dry_run_plans_without_changes() -> io::Result<()> {
+    use crate::link::dry_run::Operation;
+    use crate::link::link_files::link_files;
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_files([src.join("a.txt"), src.join("sub/b.txt")], b"new")?;
+    create_test_file(dst.join("a.txt"), b"old")?;
+    let (source, dest) = (src.to_str().unwrap(), dst.to_str().unwrap());
+
+    let opts = LinkOptions {
+        dry_run: true,
+        ..Default::default()
+    };
+    // The same conflicts are reported as for a real run.
+    let err = link_files(source, dest, Some(&opts)).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+
+    let opts = LinkOptions {
+        backup: true,
+        ..opts
+    };
+    let report = link_files(source, dest, Some(&opts))?;
+    assert!(report.linked.is_empty());
+    let mut planned = report.planned;
+    planned.sort_by_key(|op| op.to_string());
+    assert_eq!(
+        planned,
+        [
+            Operation::Backup(dst.join("a.txt"), dst.join("a.txt~")),
+            Operation::CreateDir(dst.join("sub")),
+            Operation::Link(src.join("a.txt"), dst.join("a.txt")),
+            Operation::Link(src.join("sub/b.txt"), dst.join("sub/b.txt")),
+        ]
+    );
+
+    let opts = LinkOptions {
+        backup: false,
+        force: true,
+        ..opts
+    };
+    let report = link_files(source, dest, Some(&opts))?;
+    assert!(report.planned.contains(&Operation::Overwrite(dst.join("a.txt"))));
+
+    // Nothing was touched.
+    assert_eq!(fs::read(dst.join("a.txt"))?, b"old");
+    assert!(!dst.join("a.txt~").exists());
+    assert!(!dst.join("sub").exists());
+    Ok(())
+}
+
+#[test]
+fn test_report_json_lists_backups() -> io::Result<()> {
+    use crate::json::Json;
+    use crate::link::link_files::link_files;
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_files([src.join("a.txt")], b"new")?;
+    create_test_file(dst.join("a.txt"), b"old")?;
+
+    let opts = LinkOptions {
+        backup: true,
+        ..Default::default()
+    };
+    let report = link_files(src.to_str().unwrap(), dst.to_str().unwrap(), Some(&opts))?;
+    assert_eq!(report.backups, [(dst.join("a.txt"), dst.join("a.txt~"))]);
+
+    let json = Json::parse(&report.to_json().to_string()).unwrap();
+    assert_eq!(json.get("linked").and_then(Json::as_array).map(<[_]>::len), Some(1));
+    let backup = &json.get("backups").and_then(Json::as_array).unwrap()[0];
+    assert_eq!(
+        backup.get("backup").and_then(Json::as_str),
+        dst.join("a.txt~").to_str()
+    );
+    assert_eq!(fs::read(dst.join("a.txt~"))?, b"old");
+    Ok(())
+}
+
+#[test]
+fn test_parallel_link_matches_sequential() -> io::Result<()> {
+    use crate::link::link_files::link_files;
+    use std::os::unix::fs::MetadataExt;
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    // More entries than the channels between the stages hold.
+    let files: Vec<PathBuf> = (0..40)
+        .flat_map(|d| (0..40).map(move |f| PathBuf::from(format!("d{d}/e{}/f{f}.txt", d % 3))))
+        .collect();
+    create_test_files(files.iter().map(|f| src.join(f)), b"data")?;
+
+    let opts = LinkOptions {
+        jobs: 4,
+        ..Default::default()
+    };
+    let report = link_files(src.to_str().unwrap(), dst.to_str().unwrap(), Some(&opts))?;
+    for file in &files {
+        assert_eq!(fs::metadata(dst.join(file))?.ino(), fs::metadata(src.join(file))?.ino());
+    }
+    // The same entries are reported as by a sequential run.
+    let (_seq_tmp, seq_dst) = create_temp_dir("sequential")?;
+    let sequential = link_files(src.to_str().unwrap(), seq_dst.to_str().unwrap(), None)?;
+    let mut linked = report.linked;
+    linked.sort();
+    assert_eq!(linked.len(), files.len());
+    assert_eq!(linked, {
+        let mut linked = sequential.linked;
+        linked.sort();
+        linked
+    });
+
+    // Conflicts still end the run.
+    fs::remove_file(dst.join(&files[0]))?;
+    fs::write(dst.join(&files[0]), b"other")?;
+    let err = link_files(src.to_str().unwrap(), dst.to_str().unwrap(), Some(&opts))
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+    Ok(())
+}
+
+#[test]
+fn test_undo_reverses_a_run() -> io::Result<()> {
+    use crate::link::manifest::{self, Change, Manifest, Reversal};
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    let (_state_tmp, state) = create_temp_dir("state")?;
+    create_test_files([src.join("a.txt"), src.join("sub/b.txt")], b"new")?;
+    create_test_file(dst.join("a.txt"), b"old")?;
+
+    let manifest_path = state.join("run.json");
+    let opts = LinkOptions {
+        backup: true,
+        undo_manifest: Some(manifest_path.clone()),
+        ..Default::default()
+    };
+    link_files(src.to_str().unwrap(), dst.to_str().unwrap(), Some(&opts))?;
+
+    let run = Manifest::read(&manifest_path)?;
+    assert_eq!(run.id, "run");
+    assert!(run.changes.contains(&Change::CreatedDir(dst.join("sub"))));
+    assert!(
+        run.changes
+            .contains(&Change::BackedUp(dst.join("a.txt"), dst.join("a.txt~")))
+    );
+
+    let (_, reversals) = manifest::undo(&state, None)?;
+    assert!(reversals.contains(&Reversal::Restored(dst.join("a.txt"), dst.join("a.txt~"))));
+    assert_eq!(fs::read(dst.join("a.txt"))?, b"old");
+    assert!(!dst.join("a.txt~").exists());
+    assert!(!dst.join("sub").exists());
+    // The manifest is used up.
+    assert!(manifest::undo(&state, None).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_old_manifests_are_pruned() -> io::Result<()> {
+    use crate::link::manifest::{self, KEEP_RUNS};
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    let (_state_tmp, state) = create_temp_dir("state")?;
+    create_test_files([src.join("a.txt")], b"new")?;
+    let old: Vec<_> = (0..KEEP_RUNS)
+        .map(|n| state.join(format!("20260101T000000Z-{:03}.json", n)))
+        .collect();
+    create_test_files(&old, b"{}")?;
+    // Files not named by a run ID are never pruned.
+    create_test_files([state.join("0-notes.json")], b"{}")?;
+
+    let run = state.join(format!("{}.json", manifest::new_id()));
+    let opts = LinkOptions {
+        undo_manifest: Some(run.clone()),
+        ..Default::default()
+    };
+    link_files(&src, &dst, Some(&opts))?;
+    assert_eq!(manifest::list(&state)?.len(), KEEP_RUNS + 1);
+    assert!(!old[0].exists());
+    assert!(old[1].exists());
+    assert!(run.exists());
+
+    assert_eq!(manifest::prune(&state, 1)?.len(), KEEP_RUNS - 1);
+    assert_eq!(manifest::list(&state)?, [state.join("0-notes.json"), run]);
+    assert!(manifest::is_run_id("20260101T120000Z-4242-1"));
+    assert!(!manifest::is_run_id("2026-01-01"));
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_verify_reports_mismatched_links() -> io::Result<()> {
+    use crate::verify::{Problem, verify};
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    let (_other_tmp, other) = create_temp_dir("other")?;
+    create_test_files(
+        [src.join("a.txt"), src.join("b.txt"), src.join("sub/c.txt")],
+        b"data",
+    )?;
+    create_test_file(other.join("x.txt"), b"data")?;
+    link_files(src.to_str().unwrap(), dst.to_str().unwrap(), None)?;
+    assert!(verify(&src, &dst)?.mismatches.is_empty());
+
+    // A replaced file, a symlink elsewhere, a dangling symlink, and a lost link.
+    fs::remove_file(dst.join("a.txt"))?;
+    fs::write(dst.join("a.txt"), b"data")?;
+    fs::remove_file(dst.join("b.txt"))?;
+    std::os::unix::fs::symlink(other.join("x.txt"), dst.join("b.txt"))?;
+    std::os::unix::fs::symlink(other.join("gone.txt"), dst.join("d.txt"))?;
+    fs::remove_file(dst.join("sub/c.txt"))?;
+
+    let mut mismatches = verify(&src, &dst)?.mismatches;
+    mismatches.sort_by(|a, b| a.dest.cmp(&b.dest));
+    let problems: Vec<_> = mismatches
+        .iter()
+        .map(|m| (m.dest.strip_prefix(&dst).unwrap(), &m.problem))
+        .collect();
+    assert_eq!(problems.len(), 4);
+    assert_eq!(problems[0].0, Path::new("a.txt"));
+    assert!(matches!(problems[0].1, Problem::InodeMismatch(..)));
+    assert_eq!(
+        problems[1],
+        (Path::new("b.txt"), &Problem::WrongTarget(other.join("x.txt")))
+    );
+    assert_eq!(
+        problems[2],
+        (Path::new("d.txt"), &Problem::Broken(other.join("gone.txt")))
+    );
+    assert_eq!(problems[3], (Path::new("sub/c.txt"), &Problem::Missing));
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn test_status_compares_trees() -> io::Result<()> {
+    use crate::link::link_files::status;
+    use crate::link::status::State;
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_files([src.join("a.txt"), src.join("b.txt")], b"data")?;
+    let (src_str, dst_str) = (src.to_str().unwrap(), dst.to_str().unwrap());
+    link_files(src_str, dst_str, None)?;
+    let current = status(&[src_str], dst_str, None)?;
+    assert_eq!((current.linked, current.entries.len()), (2, 0));
+
+    // A new source file, a replaced link, and a file the source lacks.
+    create_test_file(src.join("c.txt"), b"data")?;
+    fs::remove_file(dst.join("b.txt"))?;
+    fs::write(dst.join("b.txt"), b"data")?;
+    create_test_file(dst.join("stale.txt"), b"data")?;
+
+    let current = status(&[src_str], dst_str, None)?;
+    let mut entries: Vec<_> = current
+        .entries
+        .iter()
+        .map(|e| (e.state, e.dest.strip_prefix(&dst).unwrap()))
+        .collect();
+    entries.sort();
+    assert_eq!(current.linked, 1);
+    assert_eq!(
+        entries,
+        [
+            (State::Unlinked, Path::new("c.txt")),
+            (State::Extraneous, Path::new("stale.txt")),
+            (State::Differs, Path::new("b.txt")),
+        ]
+    );
+    let differs = current.entries.iter().find(|e| e.state == State::Differs);
+    let expected = format!("a hard link to {}", src.join("b.txt").display());
+    assert_eq!(
+        differs.unwrap().detail,
+        Some(format!("is a separate file, not {}", expected))
+    );
+    Ok(())
+}
+
+#[test]
+fn test_delete_prunes_entries_without_source() -> io::Result<()> {
+    use crate::link::backups::BACKUP_DIR;
+    use crate::link::link_files::link_files;
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_files([src.join("a.txt"), src.join("sub/b.txt")], b"data")?;
+    create_test_files(
+        [
+            dst.join("a.txt"),
+            dst.join("stale.txt"),
+            dst.join("gone/deep/c.txt"),
+            dst.join(BACKUP_DIR).join("old/a.txt"),
+        ],
+        b"old",
+    )?;
+
+    let opts = LinkOptions {
+        backup: true,
+        delete: true,
+        ..Default::default()
+    };
+    let report = link_files(src.to_str().unwrap(), dst.to_str().unwrap(), Some(&opts))?;
+
+    let mut deleted = report.deleted;
+    deleted.sort();
+    assert_eq!(
+        deleted,
+        [
+            dst.join("gone"),
+            dst.join("gone/deep"),
+            dst.join("gone/deep/c.txt"),
+            dst.join("stale.txt")
+        ]
+    );
+    assert!(dst.join("sub/b.txt").exists());
+    // Backups, whether made by this run or kept in generations, stay.
+    assert_eq!(fs::read(dst.join("a.txt~"))?, b"old");
+    assert!(dst.join(BACKUP_DIR).join("old/a.txt").exists());
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_delete_keeps_entries_whose_source_cannot_be_checked() -> io::Result<()> {
+    use crate::link::mirror::extraneous;
+    use std::collections::HashSet;
+    use std::os::unix::fs::symlink;
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_file(src.join("file"), b"data")?;
+    create_test_files([dst.join("file/below"), dst.join("loop/below")], b"old")?;
+    let keep = HashSet::new();
+
+    // Below a source file, there is nothing to keep.
+    let extra = extraneous(&src, &dst, None, &keep)?;
+    assert!(extra.contains(&dst.join("file/below")));
+
+    // A source path that cannot be resolved is an error, not a deletion.
+    symlink("loop", src.join("loop"))?;
+    let err = extraneous(&src, &dst, None, &keep).unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::ELOOP));
+    Ok(())
+}
+
+#[test]
+fn test_rerun_skips_existing_links() -> io::Result<()> {
+    use crate::link::link_files::link_files;
+    use crate::link::skip::{SkipReason, Skipped};
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_files([src.join("a.txt"), src.join("sub/b.txt")], b"data")?;
+    let (src_str, dst_str) = (src.to_str().unwrap(), dst.to_str().unwrap());
+
+    for symbolic in [false, true] {
+        let dst = dst.join(if symbolic { "sym" } else { "hard" });
+        let dst_str = dst.to_str().unwrap();
+        let opts = LinkOptions {
+            symbolic,
+            relative: symbolic,
+            symlink_files_only: true,
+            explain: true,
+            ..Default::default()
+        };
+        link_files(src_str, dst_str, Some(&opts))?;
+
+        // Without force, a second run neither fails nor relinks anything.
+        let report = link_files(src_str, dst_str, Some(&opts))?;
+        assert!(report.linked.is_empty());
+        assert!(
+            report
+                .skipped
+                .contains(&Skipped::new(src.join("sub/b.txt"), SkipReason::AlreadyLinked))
+        );
+    }
+
+    // A different file at the destination is still a conflict.
+    fs::remove_file(dst.join("hard/a.txt"))?;
+    fs::write(dst.join("hard/a.txt"), b"data")?;
+    let err = link_files(src_str, format!("{}/hard", dst_str), None).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+    Ok(())
+}
+
+#[test]
+fn test_count_links_for_progress() -> io::Result<()> {
+    use crate::link::link_files::count_links;
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_files(["a.txt", "b.txt", "sub/c.txt"].map(|f| src.join(f)), b"data")?;
+    let (src_str, dst_str) = (src.to_str().unwrap(), dst.to_str().unwrap());
+
+    assert_eq!(count_links(src_str, dst_str, None)?, 3);
+    let linked = link_files(src_str, dst_str, None)?.linked;
+    assert_eq!(linked.len(), 3);
+
+    // Links already in place are not counted again.
+    assert_eq!(count_links(src_str, dst_str, None)?, 0);
+    Ok(())
+}
+
+#[test]
+fn test_link_options_builder() -> io::Result<()> {
+    let opts = LinkOptions::builder()
+        .symbolic(true)
+        .relative(true)
+        .backup_suffix(String::from(".orig"))
+        .jobs(4)
+        .build()?;
+    assert!(opts.symbolic && opts.relative);
+    assert_eq!(opts.backup_suffix, ".orig");
+    assert_eq!(opts.jobs, 4);
+    assert!(opts.probe_filesystem);
+
+    // Settings that do not fit together are refused.
+    let err = LinkOptions::builder().relative(true).build().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    assert!(LinkOptions::builder().symbolic(true).archive(true).build().is_err());
+    assert!(LinkOptions::builder().jobs(0).build().is_err());
+
+    // Existing options can be changed through the builder.
+    let changed = crate::link::link_options::LinkOptionsBuilder::from(opts)
+        .dry_run(true)
+        .build()?;
+    assert!(changed.dry_run && changed.relative);
+    Ok(())
+}
+
+#[test]
+fn test_link_report_details() -> io::Result<()> {
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_file(src.join("a.txt"), b"12345")?;
+    create_test_file(src.join("sub/deep/b.txt"), b"123")?;
+    create_test_file(dst.join("a.txt"), b"old")?;
+    let opts = LinkOptions {
+        backup: true,
+        ..Default::default()
+    };
+
+    let report = link_files(src.to_str().unwrap(), dst.to_str().unwrap(), Some(&opts))?;
+    assert_eq!(report.linked.len(), 2);
+    assert_eq!(report.bytes, 8);
+    assert_eq!(report.created_dirs, [dst.join("sub"), dst.join("sub/deep")]);
+    assert_eq!(report.backups, [(dst.join("a.txt"), dst.join("a.txt~"))]);
+    Ok(())
+}
+
+#[test]
+fn test_skip_existing() -> io::Result<()> {
+    use crate::link::skip::{SkipReason, Skipped};
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_files(["a.txt", "b.txt"].map(|n| src.join(n)), b"new")?;
+    create_test_file(dst.join("b.txt"), b"in the way")?;
+    let (source, dest) = (src.to_str().unwrap(), dst.to_str().unwrap());
+
+    let opts = LinkOptions::builder()
+        .skip_existing(true)
+        .no_target_directory(true)
+        .build()?;
+    for dry_run in [true, false] {
+        let opts = LinkOptions {
+            dry_run,
+            ..opts.clone()
+        };
+        let report = link_files(source, dest, Some(&opts))?;
+        // Kept destinations are reported even without explain.
+        assert_eq!(
+            report.skipped,
+            vec![Skipped::new(src.join("b.txt"), SkipReason::Existing)]
+        );
+    }
+    assert!(dst.join("a.txt").exists());
+    assert_eq!(fs::read(dst.join("b.txt"))?, b"in the way");
+
+    assert!(
+        LinkOptions::builder()
+            .skip_existing(true)
+            .force(true)
+            .build()
+            .is_err()
+    );
+    Ok(())
+}
+
+#[test]
+fn test_update_replaces_only_older() -> io::Result<()> {
+    use crate::link::skip::{SkipReason, Skipped};
+    use std::time::{Duration, SystemTime};
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_files(["old.txt", "new.txt"].map(|n| src.join(n)), b"source")?;
+    create_test_files(["old.txt", "new.txt"].map(|n| dst.join(n)), b"dest")?;
+    let hour_ago = SystemTime::now() - Duration::from_secs(3600);
+    fs::File::options()
+        .write(true)
+        .open(dst.join("old.txt"))?
+        .set_modified(hour_ago)?;
+    fs::File::options()
+        .write(true)
+        .open(src.join("new.txt"))?
+        .set_modified(hour_ago)?;
+
+    let opts = LinkOptions::builder()
+        .update(true)
+        .force(true)
+        .explain(true)
+        .no_target_directory(true)
+        .build()?;
+    let report = link_files(src.to_str().unwrap(), dst.to_str().unwrap(), Some(&opts))?;
+    assert_eq!(report.linked, vec![PathBuf::from("old.txt")]);
+    assert_eq!(fs::read(dst.join("old.txt"))?, b"source");
+    assert_eq!(fs::read(dst.join("new.txt"))?, b"dest");
+    assert_eq!(
+        report.skipped,
+        vec![Skipped::new(src.join("new.txt"), SkipReason::NotNewer)]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_check_conflicts_finds_all() -> io::Result<()> {
+    use crate::link::link_files::check_conflicts;
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_files(["a.txt", "b.txt", "c.txt"].map(|n| src.join(n)), b"x")?;
+    create_test_files(["a.txt", "c.txt"].map(|n| dst.join(n)), b"in the way")?;
+    let source = src.to_str().unwrap();
+    let dest = dst.to_str().unwrap();
+
+    let opts = LinkOptions {
+        no_target_directory: true,
+        ..Default::default()
+    };
+    let mut conflicts = check_conflicts(&[source], dest, Some(&opts))?;
+    conflicts.sort_by(|a, b| a.dest.cmp(&b.dest));
+    let dests: Vec<_> = conflicts.iter().map(|c| c.dest.clone()).collect();
+    assert_eq!(dests, vec![dst.join("a.txt"), dst.join("c.txt")]);
+    assert!(
+        conflicts
+            .iter()
+            .all(|c| c.kind == io::ErrorKind::AlreadyExists)
+    );
+    assert!(!dst.join("b.txt").exists());
+
+    let opts = LinkOptions {
+        force: true,
+        ..opts
+    };
+    assert!(check_conflicts(&[source], dest, Some(&opts))?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_on_error_skip() -> io::Result<()> {
+    use crate::link::failure::ErrorPolicy;
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_files(["a.txt", "b.txt", "c.txt"].map(|n| src.join(n)), b"x")?;
+    create_test_file(dst.join("b.txt"), b"in the way")?;
+    let (src, dest) = (src.to_str().unwrap(), dst.to_str().unwrap());
+
+    // By default the existing file ends the run.
+    assert!(link_files(src, dest, None).is_err());
+
+    for jobs in [1, 4] {
+        fs::remove_file(dst.join("a.txt")).ok();
+        fs::remove_file(dst.join("c.txt")).ok();
+        let opts = LinkOptions {
+            on_error: ErrorPolicy::Skip,
+            jobs,
+            ..Default::default()
+        };
+        let report = link_files(src, dest, Some(&opts))?;
+        assert_eq!(report.linked.len(), 2);
+        assert!(dst.join("a.txt").exists() && dst.join("c.txt").exists());
+        assert_eq!(report.failures.len(), 1);
+        assert_eq!(report.failures[0].dest, dst.join("b.txt"));
+        assert_eq!(report.failures[0].kind, io::ErrorKind::AlreadyExists);
+        assert_eq!(report.failures[0].op, Some(Op::Link));
+        assert_eq!(report.failures[0].message, "the destination exists");
+        assert_eq!(fs::read(dst.join("b.txt"))?, b"in the way");
+    }
+    Ok(())
+}
+
+#[test]
+fn test_atomic_rolls_back() -> io::Result<()> {
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_files(
+        ["a.txt", "m/n.txt", "z.txt"].map(|n| src.join(n)),
+        b"source",
+    )?;
+    create_test_files([dst.join("a.txt"), dst.join("z.txt/inner")], b"old")?;
+    let (src, dest) = (src.to_str().unwrap(), dst.to_str().unwrap());
+    let names = || -> io::Result<Vec<_>> {
+        let mut names = fs::read_dir(&dst)?
+            .map(|entry| entry.map(|e| e.file_name()))
+            .collect::<io::Result<Vec<_>>>()?;
+        names.sort();
+        Ok(names)
+    };
+
+    for jobs in [1, 4] {
+        let opts = LinkOptions {
+            force: true,
+            atomic: true,
+            jobs,
+            ..Default::default()
+        };
+        // A directory cannot be replaced by a file, which ends the run.
+        assert!(link_files(src, dest, Some(&opts)).is_err());
+        assert_eq!(names()?, ["a.txt", "z.txt"]);
+        assert_eq!(fs::read(dst.join("a.txt"))?, b"old");
+    }
+
+    // A run that succeeds leaves nothing of the replaced entries behind.
+    fs::remove_dir_all(dst.join("z.txt"))?;
+    let opts = LinkOptions {
+        force: true,
+        atomic: true,
+        ..Default::default()
+    };
+    link_files(src, dest, Some(&opts))?;
+    assert_eq!(names()?, ["a.txt", "m", "z.txt"]);
+    assert_eq!(fs::read(dst.join("a.txt"))?, b"source");
+    Ok(())
+}
+
+#[test]
+fn test_resume_skips_finished_entries() -> io::Result<()> {
+    use crate::link::journal::{self, Journal};
+    use std::ffi::OsString;
+    use std::os::unix::fs::MetadataExt;
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_files(["a.txt", "b.txt"].map(|n| src.join(n)), b"source")?;
+    // The interrupted run copied a.txt and was stopped before b.txt.
+    create_test_file(dst.join("a.txt"), b"source")?;
+    create_test_file(dst.join("b.txt"), b"old")?;
+    let path = dst.join("journal.ndjson");
+    Journal::start(&path, &[OsString::from("-f")])?;
+    Journal::open(&path)?.record(&dst.join("a.txt"))?;
+
+    let opts = LinkOptions {
+        force: true,
+        journal: Some(path.clone()),
+        resume: true,
+        ..Default::default()
+    };
+    let report = link_files(src.to_str().unwrap(), dst.to_str().unwrap(), Some(&opts))?;
+    assert_eq!(report.linked, [PathBuf::from("b.txt")]);
+    let ino = |path: PathBuf| fs::metadata(path).map(|m| m.ino());
+    assert_ne!(ino(dst.join("a.txt"))?, ino(src.join("a.txt"))?);
+    assert_eq!(ino(dst.join("b.txt"))?, ino(src.join("b.txt"))?);
+    let interrupted = journal::read(&path)?;
+    assert_eq!(interrupted.args, ["-f"]);
+    assert!(interrupted.done.contains(&dst.join("b.txt")));
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_journals_are_kept_per_run() -> io::Result<()> {
+    use crate::link::journal::{self, Journal};
+    use std::ffi::{OsStr, OsString};
+    use std::os::unix::ffi::OsStrExt;
+
+    let (_state_tmp, state) = create_temp_dir("state")?;
+    assert!(journal::latest(&state)?.is_none());
+    let (old, new) = (journal::path(&state, "1"), journal::path(&state, "2"));
+    let name = OsStr::from_bytes(b"caf\xe9.txt");
+    drop(Journal::start(&old, &[name.to_os_string()])?);
+    Journal::open(&old)?.record(Path::new(name))?;
+    let running = Journal::start(&new, &[OsString::from("-f")])?;
+
+    // The newest journal is held by a run still going, so the one before
+    // it is resumed, and it cannot be claimed twice.
+    let (path, claim) = journal::latest(&state)?.unwrap();
+    assert_eq!(path, old);
+    assert!(journal::latest(&state)?.is_none());
+    let interrupted = journal::read(&path)?;
+    assert_eq!(interrupted.args, [name]);
+    assert!(interrupted.done.contains(Path::new(name)));
+    drop((running, claim));
+    assert_eq!(journal::latest(&state)?.unwrap().0, new);
+    Ok(())
+}
+
+#[test]
+fn test_interactive_replace() -> io::Result<()> {
+    use crate::link::link_files::{Observer, link_files_with};
+    use crate::link::pipeline::LinkJob;
+    use crate::link::prompt::Resolution;
+
+    /// Answers the replace prompt from a script, remembering what was asked.
+    struct Answers<'a>(Vec<Resolution>, &'a mut Vec<PathBuf>);
+    impl Observer for Answers<'_> {
+        fn linked(&mut self, _job: &LinkJob) {}
+        fn conflict(&mut self, dest: &Path) -> io::Result<Resolution> {
+            self.1.push(dest.to_path_buf());
+            Ok(self.0.remove(0))
+        }
+    }
+
+    assert_eq!(Resolution::from_answer("Y\n"), Resolution::Replace);
+    assert_eq!(Resolution::from_answer("a"), Resolution::ReplaceAll);
+    assert_eq!(Resolution::from_answer("q"), Resolution::Quit);
+    assert_eq!(Resolution::from_answer(""), Resolution::Keep);
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    let names = ["a.txt", "b.txt", "c.txt", "d.txt"];
+    create_test_files(names.map(|n| src.join(n)), b"new")?;
+    create_test_files(names.map(|n| dst.join(n)), b"old")?;
+    let opts = LinkOptions {
+        interactive: true,
+        ..Default::default()
+    };
+    let (source, dest) = (src.to_str().unwrap(), dst.to_str().unwrap());
+
+    // "n" keeps the first file seen; "a" replaces it and all the rest.
+    let mut asked = Vec::new();
+    let answers = Answers(vec![Resolution::Keep, Resolution::ReplaceAll], &mut asked);
+    let report = link_files_with(source, dest, Some(&opts), answers)?;
+    assert_eq!(asked.len(), 2);
+    assert_eq!(report.linked.len(), 3);
+    let kept: Vec<_> = names
+        .iter()
+        .filter(|n| fs::read(dst.join(n)).unwrap() == b"old")
+        .collect();
+    assert_eq!(kept.len(), 1);
+    assert_eq!(dst.join(kept[0]), asked[0]);
+
+    // "q" stops the run, even when failures would otherwise be skipped.
+    let opts = LinkOptions {
+        on_error: crate::link::failure::ErrorPolicy::Skip,
+        ..opts
+    };
+    let answers = Answers(vec![Resolution::Quit], &mut asked);
+    let err = link_files_with(source, dest, Some(&opts), answers).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+    Ok(())
+}
+
+#[test]
+fn test_backup_control() -> io::Result<()> {
+    use crate::link::backups::BackupControl;
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_file(src.join("a.txt"), b"new")?;
+    let (source, dest) = (src.to_str().unwrap(), dst.to_str().unwrap());
+    let run = |control: BackupControl| -> io::Result<PathBuf> {
+        create_test_file(dst.join("a.txt"), b"old")?;
+        let opts = LinkOptions {
+            backup: true,
+            backup_control: control,
+            ..Default::default()
+        };
+        let report = link_files(source, dest, Some(&opts))?;
+        Ok(report.backups[0].1.clone())
+    };
+
+    // With no numbered backups yet, existing makes a simple one.
+    assert_eq!(run(BackupControl::Existing)?, dst.join("a.txt~"));
+    fs::remove_file(dst.join("a.txt"))?;
+    assert_eq!(run(BackupControl::Numbered)?, dst.join("a.txt.~1~"));
+    fs::remove_file(dst.join("a.txt"))?;
+    assert_eq!(run(BackupControl::Numbered)?, dst.join("a.txt.~2~"));
+    // Once there are numbered backups, existing continues them.
+    fs::remove_file(dst.join("a.txt"))?;
+    assert_eq!(run(BackupControl::Existing)?, dst.join("a.txt.~3~"));
+    assert_eq!(fs::read(dst.join("a.txt~"))?, b"old");
+
+    assert_eq!("t".parse(), Ok(BackupControl::Numbered));
+    assert_eq!("never".parse(), Ok(BackupControl::Simple));
+    assert!("none".parse::<BackupControl>().is_err());
+    Ok(())
+}
+
+#[test]
+fn test_backup_dir() -> io::Result<()> {
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    let (_keep_tmp, keep) = create_temp_dir("backups")?;
+    create_test_files([src.join("a.txt"), src.join("sub/b.txt")], b"new")?;
+    create_test_files([dst.join("a.txt"), dst.join("sub/b.txt")], b"old")?;
+    let opts = LinkOptions {
+        backup: true,
+        backup_dir: Some(keep.clone()),
+        ..Default::default()
+    };
+
+    let report = link_files(src.to_str().unwrap(), dst.to_str().unwrap(), Some(&opts))?;
+    assert_eq!(report.linked.len(), 2);
+    assert_eq!(fs::read(keep.join("a.txt"))?, b"old");
+    assert_eq!(fs::read(keep.join("sub/b.txt"))?, b"old");
+    assert!(!dst.join("a.txt~").exists());
+
+    // A later backup of the same file does not overwrite the first.
+    fs::remove_file(dst.join("a.txt"))?;
+    create_test_file(dst.join("a.txt"), b"older")?;
+    link_files(src.to_str().unwrap(), dst.to_str().unwrap(), Some(&opts))?;
+    assert_eq!(fs::read(keep.join("a.txt"))?, b"old");
+    assert_eq!(fs::read(keep.join("a.txt.~1~"))?, b"older");
+    Ok(())
+}
+
+#[test]
+fn test_no_target_directory() -> io::Result<()> {
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_files([src.join("a.txt"), src.join("sub/b.txt")], b"data")?;
+    let opts = LinkOptions {
+        no_target_directory: true,
+        ..Default::default()
+    };
+
+    // The existing destination directory receives the source's contents,
+    // not a directory named after the source.
+    link_files(src.to_str().unwrap(), dst.to_str().unwrap(), Some(&opts))?;
+    assert!(dst.join("a.txt").exists());
+    assert!(dst.join("sub/b.txt").exists());
+    assert!(!dst.join(src.file_name().unwrap()).exists());
+
+    // A file linked onto an existing directory is not placed inside it.
+    fs::create_dir(dst.join("c.txt"))?;
+    let result = link_files(
+        src.join("a.txt").to_str().unwrap(),
+        dst.join("c.txt").to_str().unwrap(),
+        Some(&opts),
+    );
+    assert!(result.is_err());
+    assert!(!dst.join("c.txt/a.txt").exists());
+    Ok(())
+}
+
+#[test]
+fn test_respect_gitignore() -> io::Result<()> {
+    use crate::link::filter::{GITIGNORE_FILE, IGNORE_FILE};
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_files(
+        [
+            src.join("src/main.rs"),
+            src.join("target/debug/app"),
+            src.join("web/node_modules/x.js"),
+            src.join("web/build.log"),
+        ],
+        b"x",
+    )?;
+    fs::write(src.join(GITIGNORE_FILE), "target/\n*.log\n")?;
+    fs::write(src.join("web").join(GITIGNORE_FILE), "node_modules/\n")?;
+    fs::write(src.join(IGNORE_FILE), "!build.log\n")?;
+
+    let mut opts = LinkOptions::default();
+    opts.filter.add_ignore_file(IGNORE_FILE);
+    opts.filter.add_ignore_file(GITIGNORE_FILE);
+    link_files(src.to_str().unwrap(), dst.to_str().unwrap(), Some(&opts))?;
+
+    assert!(dst.join("src/main.rs").exists());
+    assert!(!dst.join("target").exists());
+    assert!(!dst.join("web/node_modules").exists());
+    // The .flnkignore exception wins over the .gitignore rule.
+    assert!(dst.join("web/build.log").exists());
+    Ok(())
+}
+
+#[test]
+fn test_no_hidden() -> io::Result<()> {
+    use crate::link::skip::SkipReason;
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_files(
+        [
+            src.join("a.txt"),
+            src.join(".env"),
+            src.join(".git/config"),
+            src.join("sub/.cache/x"),
+            src.join("sub/b.txt"),
+        ],
+        b"x",
+    )?;
+    let opts = LinkOptions {
+        hidden: false,
+        explain: true,
+        ..Default::default()
+    };
+
+    let report = link_files(src.to_str().unwrap(), dst.to_str().unwrap(), Some(&opts))?;
+    assert!(dst.join("a.txt").exists());
+    assert!(dst.join("sub/b.txt").exists());
+    assert!(!dst.join(".env").exists());
+    assert!(!dst.join(".git").exists());
+    assert!(!dst.join("sub/.cache").exists());
+    assert_eq!(
+        report
+            .skipped
+            .iter()
+            .filter(|s| s.reason == SkipReason::Hidden)
+            .count(),
+        3
+    );
+
+    // A hidden source named explicitly is still linked.
+    let (_other_tmp, other) = create_temp_dir("other")?;
+    link_files(
+        src.join(".env").to_str().unwrap(),
+        other.join(".env").to_str().unwrap(),
+        Some(&opts),
+    )?;
+    assert!(other.join(".env").exists());
+    Ok(())
+}
+
+#[test]
+fn test_depth_limits() -> io::Result<()> {
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_files(
+        [
+            src.join("a.txt"),
+            src.join("sub/b.txt"),
+            src.join("sub/deep/c.txt"),
+        ],
+        b"x",
+    )?;
+
+    let opts = LinkOptions {
+        max_depth: Some(1),
+        ..Default::default()
+    };
+    let report = link_files(src.to_str().unwrap(), dst.to_str().unwrap(), Some(&opts))?;
+    assert_eq!(report.linked, [PathBuf::from("a.txt")]);
+    assert!(!dst.join("sub").exists());
+
+    let opts = LinkOptions {
+        min_depth: 2,
+        max_depth: Some(2),
+        ..Default::default()
+    };
+    let report = link_files(src.to_str().unwrap(), dst.to_str().unwrap(), Some(&opts))?;
+    assert_eq!(report.linked, [PathBuf::from("sub/b.txt")]);
+    assert!(!dst.join("sub/deep").exists());
+    Ok(())
+}
+
+#[test]
+fn test_follow_links() -> io::Result<()> {
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    let (_other_tmp, other) = create_temp_dir("other")?;
+    create_test_file(other.join("c.txt"), b"x")?;
+    std::os::unix::fs::symlink(&other, src.join("linked"))?;
+
+    let opts = LinkOptions {
+        follow_links: true,
+        ..Default::default()
+    };
+    link_files(src.to_str().unwrap(), dst.to_str().unwrap(), Some(&opts))?;
+    assert_eq!(fs::read(dst.join("linked/c.txt"))?, b"x");
+    assert!(!dst.join("linked").is_symlink());
+    Ok(())
+}
+
+#[test]
+fn test_preserve_symlinks() -> io::Result<()> {
+    use std::os::unix::fs::symlink;
+
+    let (_root_tmp, root) = create_temp_dir("root")?;
+    let (src, dst) = (root.join("src"), root.join("out/dst"));
+    create_test_files([src.join("a.txt"), root.join("shared.txt")], b"x")?;
+    fs::create_dir_all(src.join("sub"))?;
+    fs::create_dir_all(&dst)?;
+    symlink("../a.txt", src.join("sub/inside"))?;
+    symlink("../shared.txt", src.join("outside"))?;
+    symlink("/etc/hostname", src.join("absolute"))?;
+
+    let opts = LinkOptions {
+        preserve_symlinks: true,
+        ..Default::default()
+    };
+    link_files(src.to_str().unwrap(), dst.to_str().unwrap(), Some(&opts))?;
+    assert_eq!(fs::read_link(dst.join("sub/inside"))?, Path::new("../a.txt"));
+    assert_eq!(fs::read_link(dst.join("absolute"))?, Path::new("/etc/hostname"));
+    assert_eq!(
+        fs::read_link(dst.join("outside"))?,
+        Path::new("../../shared.txt")
+    );
+    assert_eq!(fs::read(dst.join("outside"))?, b"x");
+
+    // A second run finds the symlinks already in place.
+    let report = link_files(src.to_str().unwrap(), dst.to_str().unwrap(), Some(&opts))?;
+    assert!(report.linked.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_symlinked_directory_not_descended() -> io::Result<()> {
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_files([src.join("sub/x.txt"), src.join("y.txt")], b"x")?;
+    let opts = LinkOptions {
+        symbolic: true,
+        ..Default::default()
+    };
+
+    let report = link_files(src.to_str().unwrap(), dst.to_str().unwrap(), Some(&opts))?;
+    assert_eq!(report.linked.len(), 2);
+    assert!(dst.join("sub").is_symlink());
+    Ok(())
+}
+
+#[test]
+fn test_no_dereference() -> io::Result<()> {
+    use std::os::unix::fs::symlink;
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_files([src.join("v1/a.txt"), src.join("v2/b.txt")], b"x")?;
+    let current = dst.join("current");
+    symlink(src.join("v1"), &current)?;
+
+    // The symlink is replaced instead of being followed into v1.
+    let opts = LinkOptions {
+        symbolic: true,
+        force: true,
+        no_dereference: true,
+        ..Default::default()
+    };
+    link_files(
+        src.join("v2").to_str().unwrap(),
+        current.to_str().unwrap(),
+        Some(&opts),
+    )?;
+    assert_eq!(fs::read_link(&current)?, src.join("v2"));
+    assert!(!src.join("v1/v2").exists());
+    assert!(!src.join("v1/b.txt").exists());
+
+    let opts = LinkOptions {
+        force: true,
+        no_dereference: true,
+        ..Default::default()
+    };
+    let result = link_files(
+        src.join("v1").to_str().unwrap(),
+        current.to_str().unwrap(),
+        Some(&opts),
+    );
+    assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    Ok(())
+}
+
+#[test]
+fn test_logical_and_physical_source_paths() -> io::Result<()> {
+    use crate::link::lexical::SourcePaths;
+    use std::os::unix::fs::symlink;
+
+    let (_root_tmp, root) = create_temp_dir("root")?;
+    let root = fs::canonicalize(root)?;
+    create_test_file(root.join("store/v1/a.txt"), b"x")?;
+    symlink("store/v1", root.join("current"))?;
+    fs::create_dir_all(root.join("dst"))?;
+    let source = root.join("current/a.txt");
+
+    let logical = LinkOptions {
+        symbolic: true,
+        relative: true,
+        source_paths: Some(SourcePaths::Logical),
+        ..Default::default()
+    };
+    let dest = root.join("dst/logical.txt");
+    link_files(source.to_str().unwrap(), dest.to_str().unwrap(), Some(&logical))?;
+    assert_eq!(fs::read_link(&dest)?, Path::new("../current/a.txt"));
+
+    let physical = LinkOptions {
+        symbolic: true,
+        source_paths: Some(SourcePaths::Physical),
+        ..Default::default()
+    };
+    let dest = root.join("dst/physical.txt");
+    link_files(source.to_str().unwrap(), dest.to_str().unwrap(), Some(&physical))?;
+    assert_eq!(fs::read_link(&dest)?, root.join("store/v1/a.txt"));
+    Ok(())
+}
+
+#[test]
+fn test_dangling_symlinks() -> io::Result<()> {
+    use crate::link::warning::WarningKind;
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    let missing = src.join("not-yet.txt");
+    let opts = LinkOptions {
+        symbolic: true,
+        ..Default::default()
+    };
+
+    let report = link_files(missing.to_str().unwrap(), dst.to_str().unwrap(), Some(&opts))?;
+    assert_eq!(fs::read_link(dst.join("not-yet.txt"))?, missing);
+    assert_eq!(report.warnings[0].kind, WarningKind::DanglingLink);
+
+    let relative = LinkOptions {
+        relative: true,
+        ..opts.clone()
+    };
+    let dest = dst.join("sub/rel.txt");
+    fs::create_dir_all(dst.join("sub"))?;
+    link_files(missing.to_str().unwrap(), dest.to_str().unwrap(), Some(&relative))?;
+    assert!(fs::read_link(&dest)?.is_relative());
+
+    // Hard links still need the source to exist.
+    let result = link_files(missing.to_str().unwrap(), dst.to_str().unwrap(), None);
+    assert!(result.is_err());
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_config_file() -> io::Result<()> {
+    use crate::config::{self, Config};
+    use crate::json::Json;
+
+    let text = r#"
+# defaults for every run
+symbolic = true
+backup_control = "numbered"
+backup_suffix = '.orig'
+max_depth = 1_0
+exclude = [
+    "*.part", # partial downloads
+    "cache/",
+]
+verbose = true
+
+[tools."vim"]
+keys = { dotted.name = "a\tb" }
+"#;
+    let config = Config::parse(text).map_err(io::Error::other)?;
+    assert_eq!(config.get("verbose"), Some(&Json::Bool(true)));
+    assert_eq!(
+        config.get("tools"),
+        Some(&Json::object([(
+            "vim",
+            Json::object([(
+                "keys",
+                Json::object([("dotted", Json::object([("name", Json::from("a\tb"))]))])
+            )])
+        )]))
+    );
+
+    let opts = config.link_options().map_err(io::Error::other)?;
+    assert!(opts.symbolic && opts.backup);
+    assert_eq!(opts.backup_suffix, ".orig");
+    assert_eq!(opts.max_depth, Some(10));
+    assert!(!opts.filter.is_empty());
+
+    let err = Config::parse("symbolic = true\nforce = yes\n").unwrap_err();
+    assert!(err.starts_with("line 2:"), "{}", err);
+    assert!(Config::parse("a = 1\na = 2\n").is_err());
+    assert!(
+        Config::parse("bogus = 1\n")
+            .unwrap()
+            .link_options()
+            .is_err()
+    );
+
+    let (_tmp, dir) = create_temp_dir("config")?;
+    let path = dir.join("config.toml");
+    create_test_file(&path, text)?;
+    assert_eq!(config::load(Some(&path))?.path, Some(path));
+    assert!(config::load(Some(&dir.join("missing.toml"))).is_err());
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_config_profiles() -> io::Result<()> {
+    use crate::config::{Config, expand_dest};
+    use crate::json::Json;
+
+    let config = Config::parse(
+        r#"
+symbolic = true
+exclude = ["*.tmp"]
+
+[profile.media]
+exclude = ["*.part"]
+dest = "/srv/media/{parent}/{stem}.{ext}"
+
+[profile.dotfiles]
+relative = true
+"#,
+    )
+    .map_err(io::Error::other)?;
+
+    let media = config.profile("media").map_err(io::Error::other)?;
+    assert_eq!(media.get("symbolic"), Some(&Json::Bool(true)));
+    assert_eq!(
+        media.get("exclude"),
+        Some(&Json::Array(vec![Json::from("*.part")]))
+    );
+    assert!(media.link_options().map_err(io::Error::other)?.symbolic);
+    let dotfiles = config.profile("dotfiles").map_err(io::Error::other)?;
+    assert!(dotfiles.link_options().map_err(io::Error::other)?.relative);
+
+    let err = config.profile("work").unwrap_err();
+    assert!(err.contains("media, dotfiles"), "{}", err);
+
+    let template = media.get("dest").and_then(Json::as_str).unwrap();
+    assert_eq!(
+        expand_dest(template, Path::new("dl/show/ep1.mkv")).map_err(io::Error::other)?,
+        PathBuf::from("/srv/media/show/ep1.mkv")
+    );
+    assert!(expand_dest("/srv/{title}", Path::new("a")).is_err());
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_watch_links_new_files() -> io::Result<()> {
+    use crate::watch::{self, WatchOptions};
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    let ((_src_tmp, src), (_dst_tmp, dst)) = setup_test_env()?;
+    create_test_file(src.join("old.mkv"), b"old")?;
+
+    let (tx, rx) = mpsc::channel();
+    let (source, dest) = (src.clone(), dst.clone());
+    thread::spawn(move || {
+        let opts = LinkOptions::default();
+        watch::watch(&source, &dest, &opts, &WatchOptions::default(), |result| {
+            let _ = tx.send(result.map(|report| report.linked));
+        })
+    });
+    let next = || rx.recv_timeout(Duration::from_secs(5)).unwrap();
+
+    assert_eq!(next()?, vec![PathBuf::from("old.mkv")]);
+    assert!(dst.join("old.mkv").exists());
+
+    // A file in a new directory is linked once it is written.
+    create_test_file(src.join("show/new.mkv"), b"new")?;
+    let mut linked = next()?;
+    while !linked.contains(&PathBuf::from("show/new.mkv")) {
+        linked = next()?;
+    }
+    assert_eq!(fs::read(dst.join("show/new.mkv"))?, b"new");
+    Ok(())
+}
@@ -0,0 +1,240 @@
+use crate::link::lexical;
+use crate::link::platform;
+use crate::link::regex::Regex;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// A `--transform` rule: a sed-style `s/regex/replacement/flags` command
+/// applied to the destination path of every entry, relative to the
+/// destination directory and with `/` separators.
+///
+/// Any character can stand in for `/` as the delimiter, and is escaped with
+/// `\` where it is meant literally. The regex takes the syntax described on
+/// `Regex`. In the replacement, `&` stands for the whole match, `\1` to `\9`
+/// for the groups, `\U` and `\L` turn what follows to upper or lower case
+/// until `\E`, and `\u` and `\l` do so for the next character only. The
+/// flags are `g`, to replace every match rather than the first, and `i`, to
+/// ignore case.
+#[derive(Debug, Clone)]
+pub struct Transform {
+    text: String,
+    regex: Regex,
+    replacement: Vec<Piece>,
+    global: bool,
+}
+
+/// A part of a replacement.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Piece {
+    Literal(char),
+    /// The text of a group, 0 for the whole match
+    Group(usize),
+    Case(Case),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Case {
+    Upper,
+    Lower,
+    End,
+    UpperNext,
+    LowerNext,
+}
+
+impl FromStr for Transform {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let invalid = |why: &str| format!("invalid transform {}: {}", text, why);
+        let mut chars = text.chars();
+        if chars.next() != Some('s') {
+            return Err(invalid("expected s/regex/replacement/"));
+        }
+        let delimiter = chars
+            .next()
+            .filter(|c| *c != '\\' && *c != '\n')
+            .ok_or_else(|| invalid("expected s/regex/replacement/"))?;
+
+        // Splits at unescaped delimiters, unescaping the delimiter itself.
+        let mut parts = vec![String::new()];
+        while let Some(c) = chars.next() {
+            if c == delimiter {
+                parts.push(String::new());
+                continue;
+            }
+            let part = parts.last_mut().unwrap();
+            if c == '\\' {
+                match chars.next() {
+                    Some(next) if next == delimiter => part.push(next),
+                    Some(next) => {
+                        part.push('\\');
+                        part.push(next);
+                    }
+                    None => return Err(invalid("trailing backslash")),
+                }
+            } else {
+                part.push(c);
+            }
+        }
+        let [pattern, replacement, flags] = &parts[..] else {
+            return Err(invalid("expected s/regex/replacement/"));
+        };
+
+        let mut global = false;
+        let mut ignore_case = false;
+        for flag in flags.chars() {
+            match flag {
+                'g' => global = true,
+                'i' | 'I' => ignore_case = true,
+                _ => return Err(invalid(&format!("unknown flag {}", flag))),
+            }
+        }
+        let regex = Regex::new(pattern, ignore_case).map_err(|e| invalid(&e))?;
+        let replacement =
+            parse_replacement(replacement, regex.groups()).map_err(|e| invalid(&e))?;
+        Ok(Transform {
+            text: text.to_string(),
+            regex,
+            replacement,
+            global,
+        })
+    }
+}
+
+impl fmt::Display for Transform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+/// Parses the replacement of a transform whose regex has `groups` groups.
+fn parse_replacement(text: &str, groups: usize) -> Result<Vec<Piece>, String> {
+    let mut pieces = Vec::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        pieces.push(match c {
+            '&' => Piece::Group(0),
+            '\\' => match chars.next() {
+                Some(digit @ '0'..='9') => {
+                    let group = digit.to_digit(10).unwrap() as usize;
+                    if group > groups {
+                        return Err(format!("the regex has no group {}", group));
+                    }
+                    Piece::Group(group)
+                }
+                Some('U') => Piece::Case(Case::Upper),
+                Some('L') => Piece::Case(Case::Lower),
+                Some('E') => Piece::Case(Case::End),
+                Some('u') => Piece::Case(Case::UpperNext),
+                Some('l') => Piece::Case(Case::LowerNext),
+                Some('n') => Piece::Literal('\n'),
+                Some('t') => Piece::Literal('\t'),
+                Some(other) => Piece::Literal(other),
+                None => return Err(String::from("trailing backslash")),
+            },
+            c => Piece::Literal(c),
+        });
+    }
+    Ok(pieces)
+}
+
+impl Transform {
+    /// Applies the rule to a path given as text.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The text to rewrite
+    ///
+    /// # Returns
+    ///
+    /// * `String` - The text with the first match, or every match with the
+    ///   `g` flag, replaced; the text as it is if nothing matches
+    pub fn apply(&self, text: &str) -> String {
+        let chars: Vec<char> = text.chars().collect();
+        let mut out = String::new();
+        let mut copied = 0;
+        let mut from = 0;
+        let mut last_end = None;
+        while from <= chars.len() {
+            let Some(slots) = self.regex.captures(&chars, from) else {
+                break;
+            };
+            let (start, end) = (slots[0].unwrap(), slots[1].unwrap());
+            // An empty match right after the previous one is not a new match.
+            if start == end && last_end == Some(start) {
+                from = start + 1;
+                continue;
+            }
+            out.extend(&chars[copied..start]);
+            self.replace(&chars, &slots, &mut out);
+            copied = end;
+            last_end = Some(end);
+            if !self.global {
+                break;
+            }
+            from = if start == end { end + 1 } else { end };
+        }
+        out.extend(&chars[copied.min(chars.len())..]);
+        out
+    }
+
+    /// Appends the replacement for one match to `out`.
+    fn replace(&self, chars: &[char], slots: &[Option<usize>], out: &mut String) {
+        let mut case = None;
+        let mut next = None;
+        let mut push =
+            |c: char, case: Option<Case>, next: &mut Option<Case>| match next.take().or(case) {
+                Some(Case::Upper | Case::UpperNext) => out.extend(c.to_uppercase()),
+                Some(Case::Lower | Case::LowerNext) => out.extend(c.to_lowercase()),
+                _ => out.push(c),
+            };
+        for piece in &self.replacement {
+            match *piece {
+                Piece::Literal(c) => push(c, case, &mut next),
+                Piece::Group(group) => {
+                    if let (Some(start), Some(end)) = (slots[group * 2], slots[group * 2 + 1]) {
+                        for &c in &chars[start..end] {
+                            push(c, case, &mut next);
+                        }
+                    }
+                }
+                Piece::Case(Case::End) => case = None,
+                Piece::Case(one @ (Case::UpperNext | Case::LowerNext)) => next = Some(one),
+                Piece::Case(all) => case = Some(all),
+            }
+        }
+    }
+}
+
+/// Rewrites the destination path of an entry, relative to the destination
+/// directory, with every rule in turn.
+///
+/// # Arguments
+///
+/// * `rules` - The rules to apply, in order
+/// * `rel_path` - The path to rewrite
+///
+/// # Returns
+///
+/// * `io::Result<PathBuf>` - The rewritten path, or an `InvalidInput` error
+///   if it is empty or would leave the destination directory
+pub fn transform_path(rules: &[Transform], rel_path: &Path) -> io::Result<PathBuf> {
+    let mut text = platform::slash_path(rel_path).into_owned();
+    for rule in rules {
+        text = rule.apply(&text);
+    }
+    let path = PathBuf::from(&text);
+    if text.is_empty() || path.has_root() || lexical::climbs_out(&path) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "--transform turns {} into {:?}, which is not inside the destination",
+                rel_path.display(),
+                text
+            ),
+        ));
+    }
+    Ok(path)
+}
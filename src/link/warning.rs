@@ -0,0 +1,75 @@
+use crate::json::Json;
+use std::fmt;
+use std::path::PathBuf;
+
+/// The kind of non-fatal condition a run ran into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WarningKind {
+    /// A socket, FIFO, or device file was left out because it cannot be linked
+    SpecialFileSkipped,
+    /// A source pattern matched no entries
+    NoMatch,
+    /// The backup name was taken, so a numbered backup was made instead
+    BackupCollision,
+    /// The destination could hold no links, or hard links would cross
+    /// devices, and the fallback policy was applied
+    FallbackUsed,
+    /// Several sources mapped to one destination and a later one was linked instead
+    DestinationCollision,
+    /// The destination's `..` components climb out of the directory it names
+    DestinationEscapes,
+    /// A symlink was made to a source that does not exist yet
+    DanglingLink,
+}
+
+impl WarningKind {
+    /// A short stable name for machine-readable output.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            WarningKind::SpecialFileSkipped => "special_file_skipped",
+            WarningKind::NoMatch => "no_match",
+            WarningKind::BackupCollision => "backup_collision",
+            WarningKind::FallbackUsed => "fallback_used",
+            WarningKind::DestinationCollision => "destination_collision",
+            WarningKind::DestinationEscapes => "destination_escapes",
+            WarningKind::DanglingLink => "dangling_link",
+        }
+    }
+}
+
+/// A non-fatal condition met during a run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    /// What kind of condition this is
+    pub kind: WarningKind,
+    /// The path the warning is about
+    pub path: PathBuf,
+    /// A human-readable description
+    pub message: String,
+}
+
+impl Warning {
+    /// Creates a warning about `path`.
+    pub fn new(kind: WarningKind, path: impl Into<PathBuf>, message: impl Into<String>) -> Self {
+        Warning {
+            kind,
+            path: path.into(),
+            message: message.into(),
+        }
+    }
+
+    /// Renders the warning as a JSON object with `kind`, `path`, and `message`.
+    pub fn to_json(&self) -> Json {
+        Json::object([
+            ("kind", Json::from(self.kind.as_str())),
+            ("path", Json::from(self.path.as_path())),
+            ("message", Json::from(self.message.as_str())),
+        ])
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
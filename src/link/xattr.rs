@@ -0,0 +1,257 @@
+use crate::link::selinux;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use std::ffi::CString;
+use std::fs;
+use std::io;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+/// The extended attribute marking entries created by flnk; its value is the run label.
+pub const MANAGED_XATTR: &str = "user.flnk.managed";
+
+/// The error an entry without the attribute asked for reads with.
+#[cfg(target_os = "linux")]
+const NO_ATTR: i32 = libc::ENODATA;
+#[cfg(target_os = "macos")]
+const NO_ATTR: i32 = libc::ENOATTR;
+
+/// macOS spells the calls that do not follow symlinks as an option.
+#[cfg(target_os = "macos")]
+unsafe fn lgetxattr(
+    path: *const libc::c_char,
+    name: *const libc::c_char,
+    value: *mut libc::c_void,
+    size: usize,
+) -> isize {
+    unsafe { libc::getxattr(path, name, value, size, 0, libc::XATTR_NOFOLLOW) }
+}
+
+#[cfg(target_os = "macos")]
+unsafe fn llistxattr(path: *const libc::c_char, names: *mut libc::c_char, size: usize) -> isize {
+    unsafe { libc::listxattr(path, names, size, libc::XATTR_NOFOLLOW) }
+}
+
+#[cfg(target_os = "macos")]
+unsafe fn lsetxattr(
+    path: *const libc::c_char,
+    name: *const libc::c_char,
+    value: *const libc::c_void,
+    size: usize,
+    flags: libc::c_int,
+) -> libc::c_int {
+    unsafe { libc::setxattr(path, name, value, size, 0, flags | libc::XATTR_NOFOLLOW) }
+}
+
+#[cfg(target_os = "linux")]
+use libc::{lgetxattr, llistxattr, lsetxattr};
+
+/// Reads an extended attribute without following symlinks.
+///
+/// # Arguments
+///
+/// * `path` - The entry to read from
+/// * `name` - The attribute name, e.g. `user.flnk.managed`
+///
+/// # Returns
+///
+/// * `io::Result<Option<Vec<u8>>>` - The value, or `None` if the attribute is not set
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub fn get(path: &Path, name: &str) -> io::Result<Option<Vec<u8>>> {
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    let c_name = CString::new(name)?;
+    let mut value = vec![0u8; 256];
+    loop {
+        let len = unsafe {
+            lgetxattr(
+                c_path.as_ptr(),
+                c_name.as_ptr(),
+                value.as_mut_ptr().cast(),
+                value.len(),
+            )
+        };
+        if len >= 0 {
+            value.truncate(len as usize);
+            return Ok(Some(value));
+        }
+        let err = io::Error::last_os_error();
+        match err.raw_os_error() {
+            Some(NO_ATTR) => return Ok(None),
+            Some(libc::ERANGE) => value.resize(value.len() * 4, 0),
+            _ => return Err(err),
+        }
+    }
+}
+
+/// Lists the names of the extended attributes of an entry without following symlinks.
+///
+/// # Arguments
+///
+/// * `path` - The entry to list
+///
+/// # Returns
+///
+/// * `io::Result<Vec<String>>` - The attribute names, empty on filesystems without xattrs
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub fn list(path: &Path) -> io::Result<Vec<String>> {
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    let mut names = vec![0u8; 1024];
+    loop {
+        let len = unsafe { llistxattr(c_path.as_ptr(), names.as_mut_ptr().cast(), names.len()) };
+        if len >= 0 {
+            names.truncate(len as usize);
+            break;
+        }
+        let err = io::Error::last_os_error();
+        match err.raw_os_error() {
+            Some(libc::EOPNOTSUPP) => return Ok(Vec::new()),
+            Some(libc::ERANGE) => names.resize(names.len() * 4, 0),
+            _ => return Err(err),
+        }
+    }
+    Ok(names
+        .split(|b| *b == 0)
+        .filter(|name| !name.is_empty())
+        .map(|name| String::from_utf8_lossy(name).into_owned())
+        .collect())
+}
+
+/// Sets an extended attribute without following symlinks.
+///
+/// # Arguments
+///
+/// * `path` - The entry to update
+/// * `name` - The attribute name
+/// * `value` - The attribute value
+///
+/// # Returns
+///
+/// * `io::Result<()>` - Success if the attribute was set
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub fn set(path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    let c_name = CString::new(name)?;
+    let rc = unsafe {
+        lsetxattr(
+            c_path.as_ptr(),
+            c_name.as_ptr(),
+            value.as_ptr().cast(),
+            value.len(),
+            0,
+        )
+    };
+    if rc != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Extended attributes are only reachable on Linux and macOS, so none are
+/// ever set elsewhere.
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn get(_path: &Path, _name: &str) -> io::Result<Option<Vec<u8>>> {
+    Ok(None)
+}
+
+/// Extended attributes are only reachable on Linux and macOS, so none are
+/// ever listed elsewhere.
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn list(_path: &Path) -> io::Result<Vec<String>> {
+    Ok(Vec::new())
+}
+
+/// Extended attributes are only reachable on Linux and macOS.
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn set(path: &Path, _name: &str, _value: &[u8]) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!(
+            "cannot set extended attributes on {} on this platform",
+            path.display()
+        ),
+    ))
+}
+
+/// Copies the extended attributes of `source`, including its POSIX ACLs
+/// (stored as `system.posix_acl_access` and `system.posix_acl_default`), onto `dest`.
+///
+/// Used when a file has to be copied instead of linked, so the copy carries
+/// the same attributes a link would have shared. The flnk mark is not copied,
+/// nor is the SELinux context, which `--selinux` chooses: with `preserve`
+/// the copy is relabeled after it is made. `trusted.` and `security.`
+/// attributes that need privileges the process lacks are skipped, and a
+/// destination filesystem without extended attributes gets none.
+///
+/// # Arguments
+///
+/// * `source` - The entry whose attributes are copied
+/// * `dest` - The copy to update
+///
+/// # Returns
+///
+/// * `io::Result<()>` - Success if every copyable attribute was set
+pub fn copy_all(source: &Path, dest: &Path) -> io::Result<()> {
+    for name in list(source)? {
+        if name == MANAGED_XATTR || name == selinux::CONTEXT_XATTR {
+            continue;
+        }
+        let Some(value) = get(source, &name)? else {
+            continue;
+        };
+        match set(dest, &name, &value) {
+            Ok(()) => {}
+            Err(e) if e.raw_os_error() == Some(libc::EOPNOTSUPP) => return Ok(()),
+            Err(e)
+                if e.raw_os_error() == Some(libc::EPERM)
+                    && (name.starts_with("trusted.") || name.starts_with("security.")) => {}
+            Err(e) => {
+                return Err(io::Error::new(
+                    e.kind(),
+                    format!("cannot copy {} to {}: {}", name, dest.display(), e),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Marks an entry as managed by the flnk run with the given label.
+///
+/// Linux does not allow `user.` attributes on symlinks, so symlinks are left
+/// unmarked. Callers must not mark hard links: they share their attributes
+/// with the source file, which would be marked along with them.
+///
+/// # Arguments
+///
+/// * `path` - The created link or directory
+/// * `label` - The label or ID of the run
+///
+/// # Returns
+///
+/// * `io::Result<()>` - Success if the entry was marked or is a symlink
+pub fn mark_managed(path: &Path, label: &str) -> io::Result<()> {
+    if fs::symlink_metadata(path)?.file_type().is_symlink() {
+        return Ok(());
+    }
+    set(path, MANAGED_XATTR, label.as_bytes()).map_err(|e| {
+        if e.raw_os_error() == Some(libc::EOPNOTSUPP) {
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "cannot mark {}: the filesystem does not support extended attributes",
+                    path.display()
+                ),
+            )
+        } else {
+            e
+        }
+    })
+}
+
+/// Returns the label of the run that created an entry, if it carries a flnk mark.
+pub fn managed_label(path: &Path) -> Option<String> {
+    get(path, MANAGED_XATTR)
+        .ok()
+        .flatten()
+        .map(|value| String::from_utf8_lossy(&value).into_owned())
+}
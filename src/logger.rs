@@ -0,0 +1,75 @@
+use flnk::clock::UtcTime;
+use log::{LevelFilter, Log, Metadata, Record};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Writes the diagnostics of a run, one line per event, to stderr or a file.
+struct Logger {
+    level: LevelFilter,
+    file: Option<Mutex<File>>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = format!(
+            "{} {:<5} {}: {}\n",
+            UtcTime::now().rfc3339(),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+        // A diagnostic that cannot be written must not stop the run.
+        match &self.file {
+            Some(file) => {
+                let _ = file.lock().unwrap().write_all(line.as_bytes());
+            }
+            None => {
+                let _ = io::stderr().write_all(line.as_bytes());
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(file) = &self.file {
+            let _ = file.lock().unwrap().flush();
+        }
+    }
+}
+
+/// Sends the diagnostics of the library at `level` and above to `file`,
+/// appending to it, or to stderr. Nothing is installed for `Off`.
+///
+/// # Arguments
+///
+/// * `level` - The most detailed level to write
+/// * `file` - The file to append to, or `None` for stderr
+///
+/// # Returns
+///
+/// * `io::Result<()>` - An error if the file cannot be opened
+pub fn init(level: LevelFilter, file: Option<&Path>) -> io::Result<()> {
+    if level == LevelFilter::Off {
+        return Ok(());
+    }
+    let file = match file {
+        Some(path) => Some(Mutex::new(
+            OpenOptions::new().create(true).append(true).open(path)?,
+        )),
+        None => None,
+    };
+    let logger = Box::leak(Box::new(Logger { level, file }));
+    // Only the first logger is kept, and main installs one.
+    if log::set_logger(logger).is_ok() {
+        log::set_max_level(level);
+    }
+    Ok(())
+}
@@ -1,137 +1,2391 @@
-use clap::{Arg, ArgAction, Command};
-use flnk::link::link_files::link_files;
-use flnk::link::link_options::LinkOptions;
-use flnk::ui;
-use std::path::PathBuf;
+mod logger;
+mod render;
+
+use clap::parser::ValueSource;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use flnk::error::FlnkError;
+use flnk::json::Json;
+use flnk::link::backups::BackupControl;
+use flnk::link::chmod::ChmodSpec;
+use flnk::link::collision::OnCollision;
+use flnk::link::dry_run::Operation;
+use flnk::link::failure::ErrorPolicy;
+use flnk::link::filter::{self, Filter, GITIGNORE_FILE, IGNORE_FILE};
+use flnk::link::glob::has_glob;
+use flnk::link::home::HomeStyle;
+use flnk::link::journal::{self, Journal};
+use flnk::link::lexical::SourcePaths;
+use flnk::link::link_files::{
+    self, LinkEvent, LinkReport, check_conflicts, count_existing, count_links, link_many_progress,
+};
+use flnk::link::link_options::{LinkOptions, LinkOptionsBuilder};
+use flnk::link::manifest::{self, Reversal};
+use flnk::link::netfs::NetworkFsMode;
+use flnk::link::overlay::OverlayMode;
+use flnk::link::owner;
+use flnk::link::probe::FallbackPolicy;
+use flnk::link::selinux::ContextMode;
+use flnk::link::status::State;
+use flnk::link::template::Template;
+use flnk::link::transform::Transform;
+use flnk::term::{self, OutputFormat, PrintPaths, Progress, Terminal};
+#[cfg(target_os = "linux")]
+use flnk::watch;
+use flnk::{batch, farm, file_list, import_hook, interrupt, response_file, stow};
+#[cfg(unix)]
+use flnk::{clean, config, dedupe, doctor, gc, inspect, serve, systemd, verify};
+use std::env;
+use std::ffi::OsString;
+use std::io;
+use std::path::{Path, PathBuf};
 use std::process;
+use std::thread;
 
 fn main() {
-    let matches = Command::new("flnk")
+    let mut args = std::env::args_os();
+    let program = args.next();
+    let args = match response_file::expand(args) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            process::exit(1);
+        }
+    };
+
+    let command = Command::new("flnk")
+        .args_conflicts_with_subcommands(true)
+        .subcommand_negates_reqs(true)
+        .subcommand(
+            Command::new("batch")
+                .about("run the link operations in a JSON array or NDJSON stream and print a combined report")
+                .arg(
+                    Arg::new("file")
+                        .required(true)
+                        .value_name("FILE")
+                        .help("the operations to run, or - for standard input"),
+                ),
+        )
+        .subcommand(
+            Command::new("doctor")
+                .about("report everything relevant before linking SOURCE into DEST")
+                .arg(
+                    Arg::new("symbolic")
+                        .short('s')
+                        .long("symbolic")
+                        .help("check for symbolic links instead of hard links")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(Arg::new("source").required(true).value_name("SOURCE"))
+                .arg(Arg::new("dest").required(true).value_name("DEST")),
+        )
+        .subcommand(
+            Command::new("gc")
+                .about("remove expired backup generations and entries left behind by interrupted runs in DEST")
+                .arg(
+                    Arg::new("keep")
+                        .long("keep")
+                        .help("the number of newest backup generations to keep")
+                        .value_name("N")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("5"),
+                )
+                .arg(
+                    Arg::new("dry-run")
+                        .short('n')
+                        .long("dry-run")
+                        .help("report what would be removed without removing it")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(Arg::new("dest").required(true).value_name("DEST")),
+        )
+        .subcommand(
+            Command::new("clean")
+                .about("remove the symlinks under DIR whose targets no longer exist")
+                .arg(
+                    Arg::new("source")
+                        .long("source")
+                        .help("only remove symlinks that point into the tree at SOURCE")
+                        .value_name("SOURCE"),
+                )
+                .arg(
+                    Arg::new("dry-run")
+                        .short('n')
+                        .long("dry-run")
+                        .help("list the broken symlinks without removing them")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(Arg::new("dir").required(true).value_name("DIR")),
+        )
+        .subcommand(
+            Command::new("dedup")
+                .about("replace files under DIR with identical contents by hard links to one copy")
+                .arg(
+                    Arg::new("block-dedupe")
+                        .long("block-dedupe")
+                        .help("share the copies' blocks instead, keeping them separate files (btrfs, XFS)")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("dry-run")
+                        .short('n')
+                        .long("dry-run")
+                        .help("list the duplicates and the space linking them would save without changing anything")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(Arg::new("dir").required(true).value_name("DIR")),
+        )
+        .subcommand(
+            Command::new("inspect")
+                .about("print the device, inode, link count, symlink chain, filesystem, and flnk ownership of PATH")
+                .arg(Arg::new("path").required(true).value_name("PATH")),
+        )
+        .subcommand(
+            Command::new("serve")
+                .about("serve link requests as JSON-RPC over a Unix socket")
+                .arg(
+                    Arg::new("socket")
+                        .long("socket")
+                        .required(true)
+                        .value_name("PATH")
+                        .help("the socket to listen on"),
+                )
+                .arg(
+                    Arg::new("on-source-gone")
+                        .long("on-source-gone")
+                        .value_name("COMMAND")
+                        .help("run COMMAND when the source of a link created by the server is deleted or moved"),
+                )
+                .arg(
+                    Arg::new("install-systemd")
+                        .long("install-systemd")
+                        .help("write socket-activated systemd units for this service and exit")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("farm")
+                .about("symlink several package trees into one TARGET, resolving conflicts by priority")
+                .arg(
+                    Arg::new("relative")
+                        .short('r')
+                        .long("relative")
+                        .help("create links relative to link location")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("force")
+                        .short('f')
+                        .long("force")
+                        .help("replace existing files that belong to no package")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("backup")
+                        .short('b')
+                        .help("make a backup of existing files that belong to no package")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(Arg::new("target").required(true).value_name("TARGET"))
+                .arg(
+                    Arg::new("packages")
+                        .required(true)
+                        .num_args(1..)
+                        .value_name("PACKAGE[=PRIORITY]"),
+                ),
+        )
+        .subcommand(
+            Command::new("import-hook")
+                .about("link the file a Sonarr or Radarr import just delivered; run as a custom-script connection")
+                .arg(
+                    Arg::new("symbolic")
+                        .short('s')
+                        .long("symbolic")
+                        .help("make symbolic links instead of hard links")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("relative")
+                        .short('r')
+                        .long("relative")
+                        .help("with -s, create links relative to link location")
+                        .action(ArgAction::SetTrue)
+                        .requires("symbolic"),
+                )
+                .arg(
+                    Arg::new("force")
+                        .short('f')
+                        .long("force")
+                        .help("remove an existing destination file")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("backup")
+                        .short('b')
+                        .help("make a backup of an existing destination file")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("library")
+                        .long("library")
+                        .value_name("DIR")
+                        .help("link into DIR/<series or movie folder>; required, as the import itself is already in the *arr's folder"),
+                )
+                .arg(
+                    Arg::new("log")
+                        .long("log")
+                        .value_name("FILE")
+                        .help("append a line about every event to FILE"),
+                )
+                .arg(
+                    Arg::new("profile")
+                        .long("profile")
+                        .value_name("NAME")
+                        .help("start from the options of the [profile.NAME] table of the config file"),
+                ),
+        )
+        .subcommand(
+            Command::new("stow")
+                .about("symlink the contents of each PACKAGE directory into TARGET, folding directories no other package shares")
+                .arg(
+                    Arg::new("target")
+                        .short('t')
+                        .long("target")
+                        .required(true)
+                        .value_name("TARGET")
+                        .help("the directory to link the packages into"),
+                )
+                .arg(
+                    Arg::new("adopt")
+                        .long("adopt")
+                        .help("move files in the way into the package, replacing its copies, and link them back")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("unstow")
+                        .short('D')
+                        .long("unstow")
+                        .help("remove the links of the packages instead")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("dry-run")
+                        .short('n')
+                        .long("dry-run")
+                        .help("print the changes and conflicts without changing anything")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("packages")
+                        .required(true)
+                        .num_args(1..)
+                        .value_name("PACKAGE"),
+                ),
+        )
+        .subcommand(
+            Command::new("undo")
+                .about("reverse a recorded run: delete the links it created, restore its backups, and remove the directories it created")
+                .arg(
+                    Arg::new("id")
+                        .value_name("ID")
+                        .help("the run to undo; defaults to the most recent one"),
+                ),
+        )
+        .subcommand(
+            Command::new("status")
+                .about("compare SOURCE with DEST: report entries not linked yet, entries with no source counterpart, and links other than the options given would create")
+                .args(
+                    link_args()
+                        .into_iter()
+                        .filter(|arg| !RUN_ONLY_ARGS.contains(&arg.get_id().as_str())),
+                )
+                .arg(Arg::new("source").required(true).value_name("SOURCE"))
+                .arg(Arg::new("dest").required(true).value_name("DEST")),
+        )
+        .subcommand(
+            Command::new("verify")
+                .about("report links in DEST that are broken, point somewhere other than SOURCE, or no longer share its inode")
+                .arg(Arg::new("source").required(true).value_name("SOURCE"))
+                .arg(Arg::new("dest").required(true).value_name("DEST")),
+        )
+        .subcommand(
+            Command::new("watch")
+                .about("link SOURCE into DEST, then keep linking the files that appear in SOURCE")
+                .args(
+                    link_args()
+                        .into_iter()
+                        .filter(|arg| !RUN_ONLY_ARGS.contains(&arg.get_id().as_str())),
+                )
+                .arg(
+                    Arg::new("on-source-gone")
+                        .long("on-source-gone")
+                        .value_name("COMMAND")
+                        .help("run COMMAND when the source of a link created by the watch is deleted or moved"),
+                )
+                .arg(
+                    Arg::new("install-systemd")
+                        .long("install-systemd")
+                        .help("write a systemd unit running this watch and exit")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(Arg::new("source").required(true).value_name("SOURCE"))
+                .arg(Arg::new("dest").required(true).value_name("DEST")),
+        )
+        .args(link_args())
         .arg(
-            Arg::new("symbolic")
-                .short('s')
-                .long("symbolic")
-                .help("make symbolic links instead of hard links")
+            Arg::new("ui-mode")
+                .short('u')
+                .help("run in ui mode")
                 .action(ArgAction::SetTrue),
         )
         .arg(
-            Arg::new("force")
-                .short('f')
-                .long("force")
-                .help("remove existing destination files")
-                .action(ArgAction::SetTrue),
+            Arg::new("pick-dir")
+                .long("pick-dir")
+                .help("with -u, browse for a directory and write the picked path to stdout or FILE")
+                .value_name("FILE")
+                .num_args(0..=1)
+                .require_equals(true)
+                .default_missing_value("")
+                .requires("ui-mode"),
         )
         .arg(
-            Arg::new("backup")
-                .short('b')
-                .help("make a backup of each existing destination file")
+            Arg::new("resume")
+                .long("resume")
+                .help("finish the previous run, which was interrupted, from its journal: run it again in the same directory, leaving out the entries it finished")
                 .action(ArgAction::SetTrue),
         )
         .arg(
-            Arg::new("relative")
-                .short('r')
-                .long("relative")
-                .help("with -s, create links relative to link location")
-                .action(ArgAction::SetTrue),
+            Arg::new("files-from")
+                .long("files-from")
+                .help("also link every path listed in FILE, one per line, or - for standard input, into the target directory: -t DIRECTORY or the only TARGET")
+                .value_name("FILE"),
         )
         .arg(
-            Arg::new("verbose")
-                .short('v')
-                .long("verbose")
-                .help("print name of each linked file")
-                .action(ArgAction::SetTrue),
+            Arg::new("null")
+                .short('0')
+                .long("null")
+                .help("with --files-from, paths are separated by NUL characters, as find -print0 writes them")
+                .action(ArgAction::SetTrue)
+                .requires("files-from"),
         )
         .arg(
-            Arg::new("target-directory")
-                .short('t')
-                .help("specify the DIRECTORY in which to create the links")
-                .value_name("DIRECTORY"),
+            Arg::new("print")
+                .long("print")
+                .help("write only the paths of created links on stdout, one per line: the link (dest, default), its source (src), or the source then the link (both)")
+                .value_name("WHAT")
+                .value_parser(["dest", "src", "both"])
+                .conflicts_with("output"),
         )
         .arg(
-            Arg::new("suffix")
-                .short('S')
-                .help("override the usual backup suffix")
-                .default_value("~"),
+            Arg::new("print0")
+                .long("print0")
+                .help("like --print, but end each path with a NUL character instead of a newline, for xargs -0")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("output"),
         )
         .arg(
-            Arg::new("ui-mode")
-                .short('u')
-                .help("run in ui mode")
-                .action(ArgAction::SetTrue),
+            Arg::new("summary-only")
+                .long("summary-only")
+                .help("print only the one-line recap of the run on stdout, for cron jobs; warnings and errors still go to stderr")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["output", "print", "print0"]),
+        )
+        .arg(
+            Arg::new("log-level")
+                .long("log-level")
+                .help("write diagnostics down to LEVEL: each source root walked at info, each skipped, filtered, or backed-up entry at debug, each entry found at trace; off unless --log-file is given, then debug, or for serve and watch, warn")
+                .value_name("LEVEL")
+                .value_parser(["off", "error", "warn", "info", "debug", "trace"])
+                .global(true),
+        )
+        .arg(
+            Arg::new("log-file")
+                .long("log-file")
+                .help("append diagnostics to FILE instead of writing them on stderr")
+                .value_name("FILE")
+                .global(true),
         )
         .arg(
             Arg::new("targets")
-                .required_unless_present("ui-mode")
+                .required_unless_present_any(["ui-mode", "files-from"])
                 .num_args(1..)
                 .value_name("TARGET"),
-        )
-        .get_matches();
+        );
+    let mut args: Vec<OsString> = program.into_iter().chain(args).collect();
+    let resumed = resume_args(&mut args);
+    let run_args: Vec<OsString> = args.iter().skip(1).cloned().collect();
 
-    let opts = LinkOptions {
-        symbolic: matches.get_flag("symbolic"),
-        force: matches.get_flag("force"),
-        backup: matches.get_flag("backup"),
-        relative: matches.get_flag("relative"),
-        backup_suffix: matches.get_one::<String>("suffix").unwrap().clone(),
-        symlink_files_only: false,
-    };
+    // Taken before the config is applied, which spells out what it adds.
+    let short_dry_run = short_flag_given(&command, &run_args, 'n');
 
-    let targets: Vec<&String> = matches
+    #[cfg(unix)]
+    apply_config(&command, &mut args);
+    let matches = command.get_matches_from(&args);
+    start_logging(&matches);
+
+    match matches.subcommand() {
+        Some(("batch", sub)) => return run_batch(sub),
+        #[cfg(unix)]
+        Some(("clean", sub)) => return run_clean(sub),
+        #[cfg(unix)]
+        Some(("dedup", sub)) => return run_dedup(sub),
+        #[cfg(unix)]
+        Some(("doctor", sub)) => return run_doctor(sub),
+        Some(("farm", sub)) => return run_farm(sub),
+        #[cfg(unix)]
+        Some(("gc", sub)) => return run_gc(sub),
+        Some(("import-hook", sub)) => return run_import_hook(sub),
+        #[cfg(unix)]
+        Some(("inspect", sub)) => return run_inspect(sub),
+        #[cfg(unix)]
+        Some(("serve", sub)) => return run_serve(sub),
+        Some(("status", sub)) => return run_status(sub),
+        Some(("stow", sub)) => return run_stow(sub),
+        Some(("undo", sub)) => return run_undo(sub),
+        #[cfg(unix)]
+        Some(("verify", sub)) => return run_verify(sub),
+        #[cfg(target_os = "linux")]
+        Some(("watch", sub)) => return run_watch(sub),
+        #[cfg(not(target_os = "linux"))]
+        Some((name, _)) => {
+            eprintln!("Error: {} is not available on this platform", name);
+            process::exit(1);
+        }
+        _ => {}
+    }
+
+    let term = Terminal::detect(
+        matches.get_one::<String>("color").unwrap().parse().unwrap(),
+        matches
+            .get_one::<String>("progress")
+            .unwrap()
+            .parse()
+            .unwrap(),
+    );
+
+    let format: OutputFormat = matches
+        .get_one::<String>("output")
+        .unwrap()
+        .parse()
+        .unwrap();
+
+    // `ln -sfn` is --no-dereference, so a script carried over from ln must
+    // not silently become a dry run.
+    if short_dry_run && (matches.get_flag("symbolic") || matches.get_flag("force")) {
+        eprintln!(
+            "{} -n is --dry-run in flnk, and is refused with -s or -f",
+            term.error()
+        );
+        eprintln!("  hint: for ln's -n, use --no-dereference; to preview, spell out --dry-run");
+        process::exit(1);
+    }
+
+    let mut opts = link_options(&matches, &term, format);
+
+    // Every link operation appends to the manifest, so start it empty.
+    if let Some(manifest) = &opts.checksum_manifest
+        && !opts.dry_run
+        && let Err(err) = std::fs::File::create(manifest)
+    {
+        eprintln!("{} {}: {}", term.error(), manifest.display(), err);
+        process::exit(1);
+    }
+
+    let mut targets: Vec<&String> = matches
         .get_many::<String>("targets")
         .map(|v| v.collect())
         .unwrap_or_default();
+    let mut target_dir = matches.get_one::<String>("target-directory");
+    let files_from = matches.get_one::<String>("files-from");
+    let listed = match files_from {
+        Some(file) => {
+            let null = matches.get_flag("null");
+            file_list::read(file, null, io::stdin()).unwrap_or_else(|err| {
+                eprintln!("{} {}", term.error(), err);
+                process::exit(1);
+            })
+        }
+        None => Vec::new(),
+    };
+    if files_from.is_some() {
+        // Listed paths are always linked into a directory, named by -t or
+        // as the only operand.
+        target_dir = match (target_dir, &targets[..]) {
+            (Some(dir), _) => Some(dir),
+            (None, [dir]) => Some(*dir),
+            (None, _) => {
+                eprintln!(
+                    "{} --files-from takes the target directory as -t DIRECTORY or as the only TARGET",
+                    term.error()
+                );
+                process::exit(1);
+            }
+        };
+        if matches.get_one::<String>("target-directory").is_none() {
+            targets.clear();
+        }
+        targets.extend(&listed);
+    }
 
     if matches.get_flag("ui-mode") {
-        if let Err(err) = ui::run_ui(&Vec::new()) {
-            eprintln!("Error in UI mode: {}", err);
+        if !term.prompts {
+            eprintln!("{} -u needs a terminal on stdin and stderr", term.error());
+            process::exit(1);
+        }
+        if let Some(output) = matches.get_one::<String>("pick-dir") {
+            return run_pick_dir(output);
+        }
+        return run_ui(&opts);
+    }
+
+    let print = match matches.get_one::<String>("print") {
+        Some(which) => Some(which.parse().unwrap()),
+        None if matches.get_flag("print0") => Some(PrintPaths::Dest),
+        None => None,
+    };
+    let mut out = RunOutput {
+        verbose: matches.get_flag("verbose")
+            && format == OutputFormat::Text
+            && print.is_none()
+            && !matches.get_flag("summary-only"),
+        quiet: matches.get_flag("quiet"),
+        summary_only: matches.get_flag("summary-only"),
+        format,
+        term,
+        progress: Progress::new(term.progress),
+        print,
+        terminator: if matches.get_flag("print0") {
+            b'\0'
+        } else {
+            b'\n'
+        },
+    };
+    let (sources, dest, literal) = operands(
+        &targets,
+        target_dir,
+        matches.get_flag("no-target-directory"),
+        matches.get_flag("no-dereference"),
+    )
+    .unwrap_or_else(|err| {
+        eprintln!("{} {}", term.error(), err);
+        process::exit(1);
+    });
+    opts.no_target_directory = literal;
+
+    if opts.interactive && !opts.dry_run && !term.prompts {
+        eprintln!("{} -i needs a terminal on stdin and stderr", term.error());
+        process::exit(1);
+    }
+
+    if opts.on_error == ErrorPolicy::Ask && !term.prompts {
+        eprintln!(
+            "{} --on-error ask needs a terminal on stdin and stderr",
+            term.error()
+        );
+        process::exit(1);
+    }
+
+    if matches.get_flag("check-first") && !opts.dry_run {
+        let patterns: Vec<&str> = sources.iter().map(|s| s.as_str()).collect();
+        let conflicts = check_conflicts(&patterns, &dest, Some(&opts)).unwrap_or_else(|err| {
+            render::error(&term, &err);
+            process::exit(1);
+        });
+        if !conflicts.is_empty() {
+            if format != OutputFormat::Text {
+                let report = LinkReport {
+                    failures: conflicts,
+                    ..Default::default()
+                };
+                return print_json(Ok(report), format, matches.get_flag("strict"));
+            }
+            for conflict in &conflicts {
+                render::failure(&term, conflict);
+            }
+            eprintln!(
+                "{} {} entr{} would fail; nothing was linked",
+                term.error(),
+                conflicts.len(),
+                if conflicts.len() == 1 { "y" } else { "ies" }
+            );
             process::exit(1);
         }
-        return;
     }
 
-    let result = if let Some(target_dir) = matches.get_one::<String>("target-directory") {
-        link_multiple_to_directory(&targets, target_dir, &opts)
-    } else if targets.len() == 1 {
-        handle_link_files(targets[0], ".", &opts)
-    } else if targets.len() == 2 {
-        let (target, link_name) = (targets[0], targets[1]);
-        if PathBuf::from(link_name).is_dir() {
-            let new_link =
-                PathBuf::from(link_name).join(PathBuf::from(target).file_name().unwrap());
-            handle_link_files(target, new_link.to_str().unwrap(), &opts)
+    if matches.get_flag("interactive-once") && opts.force && !opts.dry_run {
+        let threshold = *matches.get_one::<usize>("confirm-threshold").unwrap();
+        if let Err(err) = confirm_bulk(sources, &dest, &opts, threshold, &term) {
+            eprintln!("{} {}", term.error(), err);
+            process::exit(1);
+        }
+    }
+
+    // A pre-count pass gives the progress bar its total.
+    if out.progress.is_enabled() && format == OutputFormat::Text && !opts.dry_run {
+        let total = sources
+            .iter()
+            .map(|source| count_links(source, &dest, Some(&opts)).unwrap_or(0))
+            .sum();
+        out.progress.set_total(total);
+    }
+
+    // Ctrl-C stops the run between entries, so the journal and the undo
+    // manifest cover everything it did.
+    interrupt::install();
+
+    // The journal lets an interrupted run be finished with --resume, which
+    // cannot read standard input again. Each run has its own, held locked
+    // until it ends, so runs side by side neither clobber nor resume it.
+    let mut _journal_lock = None;
+    if let Some((path, lock)) = resumed {
+        opts.resume = true;
+        opts.journal = Some(path);
+        _journal_lock = Some(lock);
+    } else if !opts.dry_run
+        && files_from.is_none_or(|file| file != "-")
+        && let Some(dir) = manifest::state_dir()
+    {
+        // The journal is named like the run's undo manifest.
+        let id = match opts.undo_manifest.as_deref().and_then(Path::file_stem) {
+            Some(id) => id.to_string_lossy().into_owned(),
+            None => manifest::new_id(),
+        };
+        let path = journal::path(&dir, &id);
+        let mut run_args = run_args;
+        // LINK_NAME is a directory once the run has created it, so a resumed
+        // run must still treat it as the link itself.
+        if targets.len() == 2 && !literal && target_dir.is_none() && !Path::new(&dest).is_dir() {
+            run_args.insert(0, OsString::from("-T"));
+        }
+        if let Ok(lock) = Journal::start(&path, &run_args) {
+            opts.journal = Some(path);
+            _journal_lock = Some(lock);
         } else {
-            handle_link_files(target, link_name, &opts)
+            eprintln!(
+                "{} cannot write {}; an interrupted run cannot be resumed",
+                term.warning(),
+                path.display()
+            );
+        }
+    }
+
+    let result = link_targets(sources, &dest, &opts, &mut out);
+    out.progress.finish();
+    if result.is_ok()
+        && let Some(path) = &opts.journal
+    {
+        let _ = std::fs::remove_file(path);
+    }
+    if out.format != OutputFormat::Text {
+        let result = result.map_err(|e| e.to_string());
+        return print_json(result, out.format, matches.get_flag("strict"));
+    }
+
+    let report = match result {
+        Ok(report) => report,
+        Err(err) => {
+            render::error(&term, &err);
+            // Like a shell, exit with 128 + SIGINT when stopped by Ctrl-C.
+            if let Some(FlnkError::Interrupted { .. }) = FlnkError::find(&err) {
+                process::exit(130);
+            }
+            process::exit(1);
+        }
+    };
+    render::problems(&term, &report);
+    if let Some(which) = out.print {
+        // Only paths go to stdout, for scripts to read.
+        for operation in &report.planned {
+            if let Operation::Link(source, dest)
+            | Operation::Copy(source, dest)
+            | Operation::Clone(source, dest) = operation
+            {
+                term::print_paths(which, source, dest, out.terminator);
+            }
         }
     } else {
-        let dir = targets.last().unwrap();
-        link_multiple_to_directory(&targets[..targets.len() - 1], dir, &opts)
+        render::report(
+            &term,
+            &report,
+            &opts,
+            out.verbose,
+            out.quiet,
+            out.summary_only,
+        );
+    }
+    if !report.failures.is_empty() {
+        let failed = report.failures.len();
+        eprintln!(
+            "{} {} entr{} could not be linked",
+            term.error(),
+            failed,
+            if failed == 1 { "y" } else { "ies" }
+        );
+        process::exit(1);
+    }
+    if !report.warnings.is_empty() && matches.get_flag("strict") {
+        eprintln!(
+            "{} {} warning{} with --strict",
+            term.error(),
+            report.warnings.len(),
+            if report.warnings.len() == 1 { "" } else { "s" }
+        );
+        process::exit(1);
+    }
+}
+
+/// The options of a link run that `watch` leaves out: the operands it does
+/// not have, and the prompts and reports of a run that ends.
+const RUN_ONLY_ARGS: [&str; 7] = [
+    "interactive-once",
+    "confirm-threshold",
+    "progress",
+    "strict",
+    "check-first",
+    "target-directory",
+    "no-target-directory",
+];
+
+/// The options of a link run, shared by the main command and `watch`.
+fn link_args() -> Vec<Arg> {
+    vec![
+        Arg::new("symbolic")
+            .short('s')
+            .long("symbolic")
+            .help("make symbolic links instead of hard links")
+            .action(ArgAction::SetTrue),
+        Arg::new("force")
+            .short('f')
+            .long("force")
+            .help("remove existing destination files")
+            .action(ArgAction::SetTrue),
+        Arg::new("skip-existing")
+            .long("skip-existing")
+            .help("keep existing destination files and skip their sources, listing them in the report")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all([
+                "force",
+                "backup",
+                "interactive",
+                "suffix",
+                "backup-generations",
+                "backup-dir",
+            ]),
+        Arg::new("update")
+            .long("update")
+            .help("replace an existing destination file (with -f or -b) only if its source was modified more recently")
+            .action(ArgAction::SetTrue)
+            .conflicts_with("skip-existing"),
+        Arg::new("backup")
+            .short('b')
+            .long("backup")
+            .help("make a backup of each existing destination file, named per CONTROL: numbered, existing (numbered if numbered backups exist; the default unless $VERSION_CONTROL says otherwise), simple, or none")
+            .value_name("CONTROL")
+            .num_args(0..=1)
+            .require_equals(true)
+            .default_missing_value(""),
+        Arg::new("relative")
+            .short('r')
+            .long("relative")
+            .help("with -s, create links relative to link location")
+            .action(ArgAction::SetTrue)
+            .requires("symbolic"),
+        Arg::new("lexical")
+            .long("lexical")
+            .help("with -s, compute relative targets from the paths as given, without resolving symlinks or requiring the source to exist (implies -r)")
+            .action(ArgAction::SetTrue)
+            .requires("symbolic"),
+        Arg::new("logical")
+            .short('L')
+            .long("logical")
+            .help("with -s, make link targets from the TARGET paths as given, keeping symlinks along them")
+            .action(ArgAction::SetTrue)
+            .requires("symbolic")
+            .overrides_with("physical"),
+        Arg::new("physical")
+            .short('P')
+            .long("physical")
+            .help("with -s, make link targets from the fully resolved TARGET paths")
+            .action(ArgAction::SetTrue)
+            .requires("symbolic")
+            .conflicts_with("lexical")
+            .overrides_with("logical"),
+        Arg::new("relative-to")
+            .long("relative-to")
+            .help("with -s, compute relative targets as if DEST were DIR, for trees mounted or served from another prefix (implies -r)")
+            .value_name("DIR")
+            .requires("symbolic"),
+        Arg::new("home-style")
+            .long("home-style")
+            .help("with -s, write targets under $HOME as ~/... (tilde) or $HOME/... (env), for dotfiles synced between machines with different home directories")
+            .value_name("STYLE")
+            .value_parser(|s: &str| s.parse::<HomeStyle>())
+            .requires("symbolic")
+            .conflicts_with_all(["relative", "lexical", "relative-to"]),
+        Arg::new("interactive")
+            .short('i')
+            .long("interactive")
+            .help("prompt whether to replace each existing destination file: y(es), n(o), a(ll remaining), or q(uit)")
+            .action(ArgAction::SetTrue),
+        Arg::new("interactive-once")
+            .short('I')
+            .long("interactive-once")
+            .help("with -f, prompt once before replacing more than --confirm-threshold existing files")
+            .action(ArgAction::SetTrue),
+        Arg::new("confirm-threshold")
+            .long("confirm-threshold")
+            .help("the number of existing files -I may replace without asking")
+            .value_name("N")
+            .value_parser(clap::value_parser!(usize))
+            .default_value("3"),
+        Arg::new("verbose")
+            .short('v')
+            .long("verbose")
+            .help("print each created link as ln does: 'LINK' -> 'TARGET' for symlinks, 'LINK' => 'SOURCE' for hard links, with any backup")
+            .action(ArgAction::SetTrue),
+        Arg::new("quiet")
+            .short('q')
+            .long("quiet")
+            .help("print nothing on stdout but what -n, --explain, or --print asks for; warnings and errors still go to stderr")
+            .action(ArgAction::SetTrue)
+            .overrides_with("verbose"),
+        Arg::new("color")
+            .long("color")
+            .help("color labels, created links, skips, and the summary; auto colors each stream that is a terminal unless NO_COLOR is set")
+            .value_name("WHEN")
+            .value_parser(["auto", "always", "never"])
+            .default_value("auto"),
+        Arg::new("progress")
+            .long("progress")
+            .help("draw a progress bar with the rate and ETA on stderr; auto draws it when stdout and stderr are terminals, --progress alone always does")
+            .value_name("WHEN")
+            .value_parser(["auto", "always", "never"])
+            .num_args(0..=1)
+            .require_equals(true)
+            .default_missing_value("always")
+            .default_value("auto"),
+        Arg::new("output")
+            .long("output")
+            .help("report created links, backups, skipped entries, warnings, and errors as text, one JSON document (json), or JSON Lines records (jsonl) on stdout")
+            .value_name("FORMAT")
+            .value_parser(["text", "json", "jsonl"])
+            .default_value("text"),
+        Arg::new("preserve-symlinks")
+            .long("preserve-symlinks")
+            .help("recreate symlinks found in the source as symlinks to the same place, instead of skipping or linking to them")
+            .action(ArgAction::SetTrue)
+            .conflicts_with("follow-links"),
+        Arg::new("archive-link")
+            .long("archive-link")
+            .help("like cp -al: hard link files, recreate directories with their metadata, and copy symlinks")
+            .conflicts_with("symbolic")
+            .action(ArgAction::SetTrue),
+        Arg::new("reflink")
+            .long("reflink")
+            .help("make copy-on-write clones of files (Btrfs, XFS, APFS) instead of hard links; fails where the filesystem cannot clone")
+            .conflicts_with("symbolic")
+            .action(ArgAction::SetTrue),
+        Arg::new("exclude")
+            .long("exclude")
+            .help("skip files and directories matching GLOB, e.g. '*.part' or '@eaDir'; repeatable")
+            .value_name("GLOB")
+            .action(ArgAction::Append),
+        Arg::new("max-depth")
+            .long("max-depth")
+            .help("walk at most N levels below each TARGET; 1 links only a directory's immediate contents")
+            .value_name("N")
+            .value_parser(clap::value_parser!(usize)),
+        Arg::new("min-depth")
+            .long("min-depth")
+            .help("leave out entries fewer than N levels below each TARGET")
+            .value_name("N")
+            .value_parser(clap::value_parser!(usize))
+            .default_value("0"),
+        Arg::new("follow-links")
+            .long("follow-links")
+            .help("follow symlinks inside TARGET directories and link what they point to")
+            .action(ArgAction::SetTrue),
+        Arg::new("one-file-system")
+            .short('x')
+            .long("one-file-system")
+            .help("do not descend into directories on other filesystems than their TARGET")
+            .action(ArgAction::SetTrue),
+        Arg::new("hidden")
+            .long("hidden")
+            .help("link dotfiles and descend into dot-directories (default)")
+            .action(ArgAction::SetTrue)
+            .overrides_with("no-hidden"),
+        Arg::new("no-hidden")
+            .long("no-hidden")
+            .help("leave out entries below a TARGET whose names start with '.', such as .git or .cache")
+            .action(ArgAction::SetTrue)
+            .overrides_with("hidden"),
+        Arg::new("respect-gitignore")
+            .long("respect-gitignore")
+            .help("skip entries the source tree's .gitignore files ignore, such as target/ or node_modules/")
+            .action(ArgAction::SetTrue),
+        Arg::new("include")
+            .long("include")
+            .help("link only files matching GLOB, e.g. '*.mkv'; repeatable, and --exclude wins over it")
+            .value_name("GLOB")
+            .action(ArgAction::Append),
+        Arg::new("filter")
+            .long("filter")
+            .help("add an rsync-style filter RULE, e.g. '- *.part' or 'merge FILE'")
+            .value_name("RULE")
+            .action(ArgAction::Append),
+        Arg::new("transform")
+            .long("transform")
+            .help("rewrite the destination path of each entry below DEST with a sed-style 's/REGEX/REPLACEMENT/FLAGS'; repeatable, applied in order")
+            .value_name("EXPR")
+            .value_parser(|expr: &str| expr.parse::<Transform>())
+            .action(ArgAction::Append),
+        Arg::new("dest-template")
+            .long("dest-template")
+            .help("link each file at the path TEMPLATE builds below DEST from {name}, {ext}, {filename}, {parent}, {dir}, and {mtime:FORMAT}, e.g. '{mtime:%Y}/{mtime:%m}/{filename}'")
+            .value_name("TEMPLATE")
+            .value_parser(|text: &str| text.parse::<Template>()),
+        Arg::new("flatten")
+            .long("flatten")
+            .help("link every file directly into DEST, whatever its depth in the source")
+            .action(ArgAction::SetTrue)
+            .conflicts_with("dest-template"),
+        Arg::new("on-collision")
+            .long("on-collision")
+            .help("when files of the run map to the same destination, refuse the run (error, default), keep the first (skip), link later ones as 'name (1).ext' (number), or keep the last (overwrite)")
+            .value_name("STRATEGY")
+            .value_parser(["error", "skip", "number", "overwrite"]),
+        Arg::new("checksum-manifest")
+            .long("checksum-manifest")
+            .help("write a sha256sum-compatible line for every linked file to FILE")
+            .value_name("FILE"),
+        Arg::new("owner")
+            .long("owner")
+            .help("give created symlinks, directories, and copied files to USER (name or id)")
+            .value_name("USER"),
+        Arg::new("group")
+            .long("group")
+            .help("give created symlinks, directories, and copied files to GROUP (name or id)")
+            .value_name("GROUP"),
+        Arg::new("chmod")
+            .long("chmod")
+            .help("set the mode of created directories and copied files from an rsync-style SPEC such as D2775,F664")
+            .value_name("SPEC")
+            .value_parser(|spec: &str| spec.parse::<ChmodSpec>()),
+        Arg::new("dry-run")
+            .short('n')
+            .long("dry-run")
+            .help("print the links, backups, and overwrites the run would make without changing anything; with -s or -f, spell it out, as ln's -n is --no-dereference")
+            .action(ArgAction::SetTrue),
+        Arg::new("jobs")
+            .short('j')
+            .long("jobs")
+            .help("create links on N threads, or one per CPU with 0; directories are still created before the entries inside them")
+            .value_name("N")
+            .value_parser(clap::value_parser!(usize))
+            .default_value("1"),
+        Arg::new("delete")
+            .long("delete")
+            .help("after linking, remove entries in the destination that have no counterpart in the source")
+            .action(ArgAction::SetTrue),
+        Arg::new("explain")
+            .long("explain")
+            .help("print every source entry that was not linked and why")
+            .action(ArgAction::SetTrue),
+        Arg::new("strict")
+            .long("strict")
+            .help("exit with an error if any warning was reported")
+            .action(ArgAction::SetTrue),
+        Arg::new("check-first")
+            .long("check-first")
+            .help("before linking anything, report every entry that would fail (existing files, unwritable directories, hard links across filesystems) and stop if there are any")
+            .action(ArgAction::SetTrue),
+        Arg::new("fallback")
+            .long("fallback")
+            .help("when DEST's filesystem holds no links (FAT, exFAT), or a hard link would cross devices, symlink, copy, or clone the files instead, skip them with a warning, or fail (default)")
+            .value_name("POLICY")
+            .value_parser(["fail", "symlink", "copy", "reflink", "skip"]),
+        Arg::new("on-error")
+            .long("on-error")
+            .help("when an entry cannot be linked, stop the run (abort, default), go on and report every failure at the end (skip), or ask whether to go on (ask)")
+            .value_name("POLICY")
+            .value_parser(["abort", "skip", "ask"]),
+        Arg::new("atomic")
+            .long("atomic")
+            .help("if the run fails partway, remove the links and directories it created and put back the entries it replaced")
+            .action(ArgAction::SetTrue)
+            .conflicts_with("delete"),
+        Arg::new("network-fs")
+            .long("network-fs")
+            .help("on NFS/SMB destinations, refuse cross-export hard links and retry stale handles (auto), also make symlinks relative (strict), or do neither (off)")
+            .value_name("MODE")
+            .value_parser(["auto", "strict", "off"]),
+        Arg::new("overlay")
+            .long("overlay")
+            .help("skip overlayfs whiteouts and opaque markers, or translate them into removals in DEST")
+            .value_name("MODE")
+            .value_parser(["skip", "translate"]),
+        Arg::new("mark")
+            .long("mark")
+            .help("tag created links and directories with a user.flnk.managed xattr holding LABEL (default: a generated run ID)")
+            .value_name("LABEL")
+            .num_args(0..=1)
+            .require_equals(true)
+            .default_missing_value(""),
+        Arg::new("selinux")
+            .long("selinux")
+            .help("give created symlinks and directories the SELinux context of their source, or clear any inherited creation context so DEST's default labeling applies")
+            .value_name("MODE")
+            .value_parser(["preserve", "default"]),
+        Arg::new("rsync-filter")
+            .short('F')
+            .help("read .rsync-filter files in the source tree; repeat to also skip the files themselves")
+            // Appended rather than counted, so each -F keeps its place among
+            // the --filter rules.
+            .action(ArgAction::Append)
+            .num_args(0)
+            .default_missing_value("true")
+            .value_parser(clap::value_parser!(bool)),
+        Arg::new("target-directory")
+            .short('t')
+            .help("specify the DIRECTORY in which to create the links")
+            .value_name("DIRECTORY"),
+        Arg::new("no-dereference")
+            .long("no-dereference")
+            .help("treat a LINK_NAME that is a symlink to a directory as a file, replacing it with -f")
+            .action(ArgAction::SetTrue),
+        Arg::new("no-target-directory")
+            .short('T')
+            .long("no-target-directory")
+            .help("treat LINK_NAME as the link itself, never as a directory to link into")
+            .action(ArgAction::SetTrue)
+            .conflicts_with("target-directory"),
+        Arg::new("suffix")
+            .short('S')
+            .long("suffix")
+            .help("override the usual backup suffix, ~ or $SIMPLE_BACKUP_SUFFIX; implies -b")
+            .value_name("SUFFIX"),
+        Arg::new("backup-generations")
+            .long("backup-generations")
+            .help("move replaced files into DEST/.flnk-backups/<timestamp>/ instead of suffixed backups, keeping the newest N generations")
+            .value_name("N")
+            .value_parser(clap::value_parser!(u64).range(1..)),
+        Arg::new("backup-dir")
+            .long("backup-dir")
+            .help("move replaced files into DIR at their path relative to DEST instead of renaming them in place (implies -b)")
+            .value_name("DIR")
+            .conflicts_with("backup-generations"),
+        Arg::new("config")
+            .long("config")
+            .help("read default options from PATH instead of ~/.config/flnk/config.toml")
+            .value_name("PATH"),
+        Arg::new("profile")
+            .long("profile")
+            .help("use the options of the [profile.NAME] table of the config file")
+            .value_name("NAME"),
+    ]
+}
+
+/// Builds the options of a link run from its command line, exiting with an
+/// error if they do not fit together.
+fn link_options(matches: &ArgMatches, term: &Terminal, format: OutputFormat) -> LinkOptions {
+    let filters = matches
+        .indices_of("filter")
+        .into_iter()
+        .flatten()
+        .zip(matches.get_many::<String>("filter").unwrap_or_default().map(String::as_str));
+    let rsync_filters = matches.indices_of("rsync-filter").into_iter().flatten();
+    let mut filter = Filter::default();
+    for rule in filter::command_line_rules(filters, rsync_filters) {
+        if let Err(err) = filter.add_rule(rule) {
+            eprintln!("{} {}", term.error(), err);
+            process::exit(1);
+        }
+    }
+    for pattern in matches.get_many::<String>("exclude").unwrap_or_default() {
+        filter.add_exclude(pattern);
+    }
+    for pattern in matches.get_many::<String>("include").unwrap_or_default() {
+        filter.add_include(pattern);
+    }
+    filter.add_ignore_file(IGNORE_FILE);
+    if matches.get_flag("respect-gitignore") {
+        filter.add_ignore_file(GITIGNORE_FILE);
+    }
+
+    let backup_control = backup_control(matches).unwrap_or_else(|err| {
+        eprintln!("{} {}", term.error(), err);
+        process::exit(1);
+    });
+    // The default --mark label names the run's undo manifest.
+    let run_id = manifest::new_id();
+    LinkOptions::builder()
+        .symbolic(matches.get_flag("symbolic"))
+        .force(matches.get_flag("force"))
+        .skip_existing(matches.get_flag("skip-existing"))
+        .update(matches.get_flag("update"))
+        .interactive(matches.get_flag("interactive"))
+        .no_dereference(matches.get_flag("no-dereference"))
+        .hidden(!matches.get_flag("no-hidden"))
+        .max_depth(matches.get_one::<usize>("max-depth").copied())
+        .min_depth(*matches.get_one::<usize>("min-depth").unwrap())
+        .follow_links(matches.get_flag("follow-links"))
+        .same_file_system(matches.get_flag("one-file-system"))
+        .backup(
+            backup_control.is_some()
+                || matches.contains_id("backup-generations")
+                || matches.contains_id("backup-dir"),
+        )
+        .backup_control(backup_control.unwrap_or_default())
+        .relative(
+            matches.get_flag("relative")
+                || matches.get_flag("lexical")
+                || matches.contains_id("relative-to"),
+        )
+        .relative_to(matches.get_one::<String>("relative-to").map(PathBuf::from))
+        .lexical(matches.get_flag("lexical"))
+        .source_paths(if matches.get_flag("logical") {
+            Some(SourcePaths::Logical)
+        } else if matches.get_flag("physical") {
+            Some(SourcePaths::Physical)
+        } else {
+            None
+        })
+        .home_style(matches.get_one::<HomeStyle>("home-style").copied())
+        .backup_suffix(
+            matches
+                .get_one::<String>("suffix")
+                .cloned()
+                .or_else(|| env::var("SIMPLE_BACKUP_SUFFIX").ok())
+                .unwrap_or_else(|| String::from("~")),
+        )
+        .backup_generations(
+            matches
+                .get_one::<u64>("backup-generations")
+                .map(|&n| n as usize),
+        )
+        .backup_dir(matches.get_one::<String>("backup-dir").map(PathBuf::from))
+        .archive(matches.get_flag("archive-link"))
+        .preserve_symlinks(matches.get_flag("preserve-symlinks"))
+        .reflink(matches.get_flag("reflink"))
+        .filter(filter)
+        .fallback(
+            matches
+                .get_one::<String>("fallback")
+                .map_or(FallbackPolicy::Fail, |policy| policy.parse().unwrap()),
+        )
+        .network_fs(
+            matches
+                .get_one::<String>("network-fs")
+                .map_or(NetworkFsMode::Auto, |mode| mode.parse().unwrap()),
+        )
+        .overlay(
+            matches
+                .get_one::<String>("overlay")
+                .map_or(OverlayMode::Off, |mode| mode.parse().unwrap()),
+        )
+        .mark(matches.get_one::<String>("mark").map(|label| {
+            if label.is_empty() {
+                run_id.clone()
+            } else {
+                label.clone()
+            }
+        }))
+        .selinux(
+            matches
+                .get_one::<String>("selinux")
+                .map_or(ContextMode::Inherit, |mode| mode.parse().unwrap()),
+        )
+        .checksum_manifest(
+            matches
+                .get_one::<String>("checksum-manifest")
+                .map(PathBuf::from),
+        )
+        .owner(lookup_or_exit(
+            matches.get_one::<String>("owner"),
+            owner::lookup_user,
+            term,
+        ))
+        .group(lookup_or_exit(
+            matches.get_one::<String>("group"),
+            owner::lookup_group,
+            term,
+        ))
+        .chmod(matches.get_one::<ChmodSpec>("chmod").cloned())
+        .transform(
+            matches
+                .get_many::<Transform>("transform")
+                .unwrap_or_default()
+                .cloned()
+                .collect(),
+        )
+        .dest_template(matches.get_one::<Template>("dest-template").cloned())
+        .flatten(matches.get_flag("flatten"))
+        .on_collision(
+            matches
+                .get_one::<String>("on-collision")
+                .map_or(OnCollision::Error, |strategy| strategy.parse().unwrap()),
+        )
+        .explain(matches.get_flag("explain") || format != OutputFormat::Text)
+        .dry_run(matches.get_flag("dry-run"))
+        .jobs(match *matches.get_one::<usize>("jobs").unwrap() {
+            0 => thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n,
+        })
+        .undo_manifest(
+            manifest::state_dir()
+                .filter(|_| !matches.get_flag("dry-run"))
+                .map(|dir| dir.join(format!("{}.json", run_id))),
+        )
+        .delete(matches.get_flag("delete"))
+        .on_error(
+            matches
+                .get_one::<String>("on-error")
+                .map_or(ErrorPolicy::Abort, |policy| policy.parse().unwrap()),
+        )
+        .atomic(matches.get_flag("atomic"))
+        .build()
+        .unwrap_or_else(|err| {
+            eprintln!("{} {}", term.error(), err);
+            process::exit(1);
+        })
+}
+
+#[cfg(feature = "tui")]
+fn run_ui(opts: &LinkOptions) {
+    if let Err(err) = flnk::ui::run_ui(&Vec::new(), opts) {
+        eprintln!("Error in UI mode: {}", err);
+        process::exit(1);
+    }
+}
+
+#[cfg(not(feature = "tui"))]
+fn run_ui(_opts: &LinkOptions) {
+    eprintln!("Error: this build of flnk has no interactive mode");
+    process::exit(1);
+}
+
+/// Lets the user pick a directory and writes its path, followed by a newline,
+/// to `output`, or to stdout when `output` is empty. Exits non-zero if the
+/// user quits without picking.
+#[cfg(feature = "tui")]
+fn run_pick_dir(output: &str) {
+    let picked = match flnk::ui::pick_dir() {
+        Ok(Some(picked)) => picked,
+        Ok(None) => process::exit(1),
+        Err(err) => {
+            eprintln!("Error in UI mode: {}", err);
+            process::exit(1);
+        }
+    };
+    let line = format!("{}\n", picked.display());
+    let written = if output.is_empty() {
+        io::Write::write_all(&mut io::stdout(), line.as_bytes())
+    } else {
+        std::fs::write(output, line)
+    };
+    if let Err(err) = written {
+        eprintln!("Error: {}", err);
+        process::exit(1);
+    }
+}
+
+#[cfg(not(feature = "tui"))]
+fn run_pick_dir(_output: &str) {
+    run_ui(&LinkOptions::default());
+}
+
+#[cfg(unix)]
+fn run_doctor(matches: &ArgMatches) {
+    let mut opts = LinkOptions::default();
+    opts.symbolic = matches.get_flag("symbolic");
+    let source = matches.get_one::<String>("source").unwrap();
+    let dest = matches.get_one::<String>("dest").unwrap();
+
+    match doctor::diagnose(source, dest, &opts) {
+        Ok(report) => {
+            println!("{}", report);
+            if !report.is_healthy() {
+                process::exit(1);
+            }
+        }
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            process::exit(1);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn run_gc(matches: &ArgMatches) {
+    let dest = Path::new(matches.get_one::<String>("dest").unwrap());
+    let keep = *matches.get_one::<usize>("keep").unwrap();
+    let dry_run = matches.get_flag("dry-run");
+
+    let items = match gc::scan(dest, keep) {
+        Ok(items) => items,
+        Err(err) => {
+            eprintln!("Error: {}: {}", dest.display(), err);
+            process::exit(1);
+        }
+    };
+    let verb = if dry_run { "Would remove" } else { "Removed" };
+    let total: u64 = items.iter().map(|item| item.bytes).sum();
+    if !dry_run && let Err(err) = gc::reclaim(&items) {
+        eprintln!("Error: {}", err);
+        process::exit(1);
+    }
+    for item in &items {
+        println!(
+            "{} {}: {} ({} bytes)",
+            verb,
+            item.kind,
+            item.path.display(),
+            item.bytes
+        );
+    }
+    println!(
+        "{} {} entr{}, {} bytes",
+        verb,
+        items.len(),
+        if items.len() == 1 { "y" } else { "ies" },
+        total
+    );
+}
+
+#[cfg(unix)]
+fn run_clean(matches: &ArgMatches) {
+    let dir = Path::new(matches.get_one::<String>("dir").unwrap());
+    let source = matches.get_one::<String>("source").map(Path::new);
+    let dry_run = matches.get_flag("dry-run");
+
+    let links = match clean::scan(dir, source) {
+        Ok(links) => links,
+        Err(err) => {
+            eprintln!("Error: {}: {}", dir.display(), err);
+            process::exit(1);
+        }
+    };
+    let verb = if dry_run { "Would remove" } else { "Removed" };
+    if !dry_run && let Err(err) = clean::remove(&links) {
+        eprintln!("Error: {}", err);
+        process::exit(1);
+    }
+    for link in &links {
+        println!("{} {}", verb, link);
+    }
+    println!(
+        "{} {} broken symlink{}",
+        verb,
+        links.len(),
+        if links.len() == 1 { "" } else { "s" }
+    );
+}
+
+#[cfg(unix)]
+fn run_dedup(matches: &ArgMatches) {
+    let dir = Path::new(matches.get_one::<String>("dir").unwrap());
+    let block = matches.get_flag("block-dedupe");
+    let dry_run = matches.get_flag("dry-run");
+
+    let duplicates = match dedupe::scan(dir) {
+        Ok(duplicates) => duplicates,
+        Err(err) => {
+            eprintln!("Error: {}: {}", dir.display(), err);
+            process::exit(1);
+        }
+    };
+    let (verb, saved) = match (dry_run, block) {
+        (true, true) => ("Would share", Ok(0)),
+        (true, false) => (
+            "Would link",
+            Ok(duplicates.iter().map(|set| set.freed).sum()),
+        ),
+        (false, true) => ("Shared", dedupe::share_copies(&duplicates)),
+        (false, false) => ("Linked", dedupe::link_copies(&duplicates)),
     };
+    let saved = saved.unwrap_or_else(|err| {
+        eprintln!("Error: {}", err);
+        process::exit(1);
+    });
+    for set in &duplicates {
+        for copy in &set.copies {
+            println!("{} {} to {}", verb, copy.display(), set.keep.display());
+        }
+    }
+    let count: usize = duplicates.iter().map(|set| set.copies.len()).sum();
+    if dry_run && block {
+        println!(
+            "{} {} duplicate{}",
+            verb,
+            count,
+            if count == 1 { "" } else { "s" }
+        );
+    } else {
+        println!(
+            "{} {} duplicate{}, {} bytes {}",
+            verb,
+            count,
+            if count == 1 { "" } else { "s" },
+            saved,
+            if block { "shared" } else { "saved" }
+        );
+    }
+}
 
+fn run_undo(matches: &ArgMatches) {
+    let Some(dir) = manifest::state_dir() else {
+        eprintln!("Error: cannot locate the state directory; set HOME or XDG_STATE_HOME");
+        process::exit(1);
+    };
+    let id = matches.get_one::<String>("id").map(String::as_str);
+    let (run, reversals) = match manifest::undo(&dir, id) {
+        Ok(undone) => undone,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            process::exit(1);
+        }
+    };
+    let mut kept = 0;
+    for reversal in &reversals {
+        if matches!(reversal, Reversal::Kept(..)) {
+            kept += 1;
+            eprintln!("Warning: {}", reversal);
+        } else {
+            println!("{}", reversal);
+        }
+    }
+    println!(
+        "Undid run {} into {} ({} change{} reversed, {} kept)",
+        run.id,
+        run.dest.display(),
+        reversals.len() - kept,
+        if reversals.len() - kept == 1 { "" } else { "s" },
+        kept
+    );
+}
+
+#[cfg(unix)]
+fn run_inspect(matches: &ArgMatches) {
+    let path = Path::new(matches.get_one::<String>("path").unwrap());
+    match inspect::inspect(path) {
+        Ok(inspection) => println!("{}", inspection),
+        Err(err) => {
+            eprintln!("Error: {}: {}", path.display(), err);
+            process::exit(1);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn run_verify(matches: &ArgMatches) {
+    let source = Path::new(matches.get_one::<String>("source").unwrap());
+    let dest = Path::new(matches.get_one::<String>("dest").unwrap());
+    let verification = match verify::verify(source, dest) {
+        Ok(verification) => verification,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            process::exit(1);
+        }
+    };
+    for mismatch in &verification.mismatches {
+        println!("{}", mismatch);
+    }
+    println!(
+        "Checked {} link{}, {} mismatch{}",
+        verification.checked,
+        if verification.checked == 1 { "" } else { "s" },
+        verification.mismatches.len(),
+        if verification.mismatches.len() == 1 {
+            ""
+        } else {
+            "es"
+        }
+    );
+    if !verification.mismatches.is_empty() {
+        process::exit(1);
+    }
+}
+
+fn run_status(matches: &ArgMatches) {
+    let source = matches.get_one::<String>("source").unwrap();
+    let dest = matches.get_one::<String>("dest").unwrap();
+    let term = Terminal::detect(
+        matches.get_one::<String>("color").unwrap().parse().unwrap(),
+        term::When::Never,
+    );
+    let format: OutputFormat = matches
+        .get_one::<String>("output")
+        .unwrap()
+        .parse()
+        .unwrap();
+    let opts = link_options(matches, &term, format);
+    let status = match link_files::status(&[source.as_str()], dest, Some(&opts)) {
+        Ok(status) => status,
+        Err(err) => {
+            eprintln!("{} {}", term.error(), err);
+            process::exit(1);
+        }
+    };
+    match format {
+        OutputFormat::Text => {
+            for entry in &status.entries {
+                println!("{}", entry);
+            }
+            println!(
+                "{} linked, {} not linked, {} without a source, {} differing",
+                status.linked,
+                status.count(State::Unlinked),
+                status.count(State::Extraneous),
+                status.count(State::Differs)
+            );
+        }
+        OutputFormat::Json => println!("{}", status.to_json()),
+        OutputFormat::JsonLines => {
+            for entry in &status.entries {
+                println!("{}", entry.to_json());
+            }
+        }
+    }
+    if !status.entries.is_empty() {
+        process::exit(1);
+    }
+}
+
+#[cfg(unix)]
+fn run_serve(matches: &ArgMatches) {
+    let socket = matches.get_one::<String>("socket").unwrap();
+    let opts = serve::ServeOptions {
+        on_source_gone: matches.get_one::<String>("on-source-gone").cloned(),
+    };
+    let result = if matches.get_flag("install-systemd") {
+        std::path::absolute(socket).and_then(|socket| {
+            let mut args = vec![
+                String::from("serve"),
+                String::from("--socket"),
+                socket.to_string_lossy().into_owned(),
+            ];
+            if let Some(hook) = &opts.on_source_gone {
+                args.extend([String::from("--on-source-gone"), hook.clone()]);
+            }
+            install_systemd("flnk-serve", &args, Some(&socket))
+        })
+    } else {
+        #[cfg(not(target_os = "linux"))]
+        if opts.on_source_gone.is_some() {
+            eprintln!("Warning: sources are not watched on this platform; --on-source-gone is ignored");
+        }
+        serve::serve(Path::new(socket), &opts)
+    };
     if let Err(err) = result {
         eprintln!("Error: {}", err);
         process::exit(1);
     }
 }
 
-fn handle_link_files(target: &str, link_name: &str, opts: &LinkOptions) -> Result<(), String> {
-    match link_files(target, link_name, Some(opts)) {
-        Ok(linked_files) => {
-            for file in linked_files {
+#[cfg(target_os = "linux")]
+fn run_watch(matches: &ArgMatches) {
+    let source = matches.get_one::<String>("source").unwrap();
+    let dest = matches.get_one::<String>("dest").unwrap();
+    let watch_opts = watch::WatchOptions {
+        on_source_gone: matches.get_one::<String>("on-source-gone").cloned(),
+    };
+    if matches.get_flag("install-systemd") {
+        // The service runs the same watch, with the operands made absolute.
+        let result = std::path::absolute(source)
+            .and_then(|source| Ok((source, std::path::absolute(dest)?)))
+            .and_then(|(abs_source, abs_dest)| {
+                let args: Vec<String> = env::args()
+                    .skip(1)
+                    .filter(|arg| arg != "--install-systemd")
+                    .map(|arg| match arg {
+                        arg if arg == *source => abs_source.to_string_lossy().into_owned(),
+                        arg if arg == *dest => abs_dest.to_string_lossy().into_owned(),
+                        arg => arg,
+                    })
+                    .collect();
+                install_systemd("flnk-watch", &args, None)
+            });
+        if let Err(err) = result {
+            eprintln!("Error: {}", err);
+            process::exit(1);
+        }
+        return;
+    }
+
+    let term = Terminal::detect(
+        matches.get_one::<String>("color").unwrap().parse().unwrap(),
+        term::When::Never,
+    );
+    let format: OutputFormat = matches
+        .get_one::<String>("output")
+        .unwrap()
+        .parse()
+        .unwrap();
+    let opts = link_options(matches, &term, format);
+    if (opts.interactive || opts.on_error == ErrorPolicy::Ask) && !term.prompts {
+        eprintln!(
+            "{} -i and --on-error ask need a terminal on stdin and stderr",
+            term.error()
+        );
+        process::exit(1);
+    }
+    let (verbose, quiet) = (matches.get_flag("verbose"), matches.get_flag("quiet"));
+
+    let result = watch::watch(
+        Path::new(source),
+        Path::new(dest),
+        &opts,
+        &watch_opts,
+        |result| match result {
+            Ok(report) if format != OutputFormat::Text => println!("{}", report.to_json()),
+            Ok(report) => {
+                render::problems(&term, &report);
+                render::watch_report(&term, &report, verbose, quiet);
+            }
+            Err(err) => render::error(&term, &err),
+        },
+    );
+    if let Err(err) = result {
+        eprintln!("{} {}", term.error(), err);
+        process::exit(1);
+    }
+}
+
+fn run_batch(matches: &ArgMatches) {
+    let file = matches.get_one::<String>("file").unwrap();
+    let text = if file == "-" {
+        io::read_to_string(io::stdin())
+    } else {
+        std::fs::read_to_string(file)
+    };
+
+    match text.and_then(|text| batch::parse(&text)) {
+        Ok(ops) => {
+            let report = batch::run(&ops);
+            println!("{}", report.to_json());
+            if report.failed() > 0 {
+                process::exit(1);
+            }
+        }
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            process::exit(1);
+        }
+    }
+}
+
+fn run_farm(matches: &ArgMatches) {
+    // Farms are always made of symlinks.
+    let opts = LinkOptions::builder()
+        .symbolic(true)
+        .relative(matches.get_flag("relative"))
+        .force(matches.get_flag("force"))
+        .backup(matches.get_flag("backup"))
+        .build()
+        .unwrap_or_else(|err| {
+            eprintln!("Error: {}", err);
+            process::exit(1);
+        });
+    let target = matches.get_one::<String>("target").unwrap();
+    let packages: Vec<farm::Package> = matches
+        .get_many::<String>("packages")
+        .unwrap()
+        .map(|arg| farm::Package::parse(arg))
+        .collect();
+
+    match farm::apply(Path::new(target), &packages, &opts) {
+        Ok(report) => {
+            for file in &report.linked {
                 println!("Created link: {}", file.display());
             }
-            Ok(())
+            for conflict in &report.conflicts {
+                let losers: Vec<String> = conflict
+                    .losers
+                    .iter()
+                    .map(|p| format!("{} ({})", p.path.display(), p.priority))
+                    .collect();
+                println!(
+                    "Conflict: {} from {} ({}) wins over {}",
+                    conflict.path.display(),
+                    conflict.winner.path.display(),
+                    conflict.winner.priority,
+                    losers.join(", ")
+                );
+            }
         }
-        Err(e) => Err(e.to_string()),
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            process::exit(1);
+        }
+    }
+}
+
+fn run_stow(matches: &ArgMatches) {
+    let target = Path::new(matches.get_one::<String>("target").unwrap());
+    let packages: Vec<PathBuf> = matches
+        .get_many::<String>("packages")
+        .unwrap()
+        .map(PathBuf::from)
+        .collect();
+    let opts = stow::StowOptions {
+        dry_run: matches.get_flag("dry-run"),
+        adopt: matches.get_flag("adopt"),
+    };
+
+    let result = if matches.get_flag("unstow") {
+        stow::unstow(target, &packages, opts)
+    } else {
+        stow::stow(target, &packages, opts)
+    };
+    let report = result.unwrap_or_else(|err| {
+        eprintln!("Error: {}", err);
+        process::exit(1);
+    });
+    for conflict in &report.conflicts {
+        eprintln!("Conflict: {}", conflict);
+    }
+    if !report.conflicts.is_empty() {
+        eprintln!(
+            "Error: {} conflict{}, nothing was changed",
+            report.conflicts.len(),
+            if report.conflicts.len() == 1 { "" } else { "s" }
+        );
+        process::exit(1);
+    }
+    let prefix = if opts.dry_run { "Would " } else { "" };
+    for action in &report.actions {
+        println!("{}{}", prefix, action);
+    }
+}
+
+fn run_import_hook(matches: &ArgMatches) {
+    let base = match matches.get_one::<String>("profile") {
+        Some(name) => profile_options(name).unwrap_or_else(|err| {
+            eprintln!("Error: {}", err);
+            process::exit(1);
+        }),
+        None => LinkOptions::default(),
+    };
+    let opts = LinkOptionsBuilder::from(base.clone())
+        .symbolic(base.symbolic || matches.get_flag("symbolic"))
+        .relative(base.relative || matches.get_flag("relative"))
+        .force(base.force || matches.get_flag("force"))
+        .backup(base.backup || matches.get_flag("backup"))
+        .build()
+        .unwrap_or_else(|err| {
+            eprintln!("Error: {}", err);
+            process::exit(1);
+        });
+    let library = matches.get_one::<String>("library").map(Path::new);
+    let log = matches.get_one::<String>("log").map(Path::new);
+
+    let result = import_hook::ImportEvent::from_env(|name| std::env::var(name).ok())
+        .and_then(|event| import_hook::run(&event, library, &opts, log));
+    if let Err(err) = result {
+        eprintln!("Error: {}", err);
+        process::exit(1);
     }
 }
 
-fn link_multiple_to_directory(
+/// Reads the link options of a profile in the default config file.
+#[cfg(unix)]
+fn profile_options(name: &str) -> Result<LinkOptions, String> {
+    let config = config::load(None).map_err(|err| err.to_string())?;
+    config
+        .profile(name)
+        .and_then(|profile| profile.link_options())
+        .map_err(|err| match &config.path {
+            Some(path) => format!("{}: {}", path.display(), err),
+            None => err,
+        })
+}
+
+#[cfg(not(unix))]
+fn profile_options(_name: &str) -> Result<LinkOptions, String> {
+    Err(String::from(
+        "config profiles are not available on this platform",
+    ))
+}
+
+#[cfg(unix)]
+fn install_systemd(name: &str, args: &[String], socket: Option<&Path>) -> io::Result<()> {
+    let dir = systemd::unit_dir()?;
+    let user = !dir.starts_with("/etc");
+    let units = systemd::units(name, args, socket, user)?;
+    for path in systemd::install(&units, &dir)? {
+        println!("Wrote {}", path.display());
+    }
+
+    let unit = match &units.socket {
+        Some((socket_name, _)) => socket_name,
+        None => &units.service_name,
+    };
+    let scope = if user { " --user" } else { "" };
+    println!(
+        "Enable with: systemctl{} daemon-reload && systemctl{} enable --now {}",
+        scope, scope, unit
+    );
+    Ok(())
+}
+
+/// What the main link run has printed and collected so far.
+struct RunOutput {
+    /// Print every created link instead of a summary
+    verbose: bool,
+    /// Print neither the created links nor the summary
+    quiet: bool,
+    /// Print the summary alone
+    summary_only: bool,
+    /// How the outcome is reported on stdout
+    format: OutputFormat,
+    /// The terminal the run is reported on
+    term: Terminal,
+    /// The progress line on stderr
+    progress: Progress,
+    /// With `--print`, which paths of each created link are written on
+    /// stdout instead of the report
+    print: Option<PrintPaths>,
+    /// The byte ending each path written by `--print`
+    terminator: u8,
+}
+
+fn link_targets(
     targets: &[&String],
-    dir: &str,
+    dest: &str,
+    opts: &LinkOptions,
+    out: &mut RunOutput,
+) -> io::Result<LinkReport> {
+    let targets: Vec<&str> = targets.iter().map(|t| t.as_str()).collect();
+    let (verbose, format, print) = (out.verbose, out.format, out.print);
+    let (terminator, term) = (out.terminator, out.term);
+    let progress = &mut out.progress;
+    link_many_progress(&targets, dest, Some(opts), |event| match event {
+        LinkEvent::Linked { path, .. } if format == OutputFormat::JsonLines => {
+            println!(
+                "{}",
+                record("linked", Json::object([("path", Json::from(path))]))
+            )
+        }
+        LinkEvent::Linked {
+            path,
+            source,
+            dest,
+            backup,
+        } => {
+            if let Some(which) = print {
+                progress.print_paths(which, source, dest, terminator);
+            } else if verbose {
+                progress.println(&render::linked(&term, source, dest, backup));
+            }
+            progress.tick(path)
+        }
+        LinkEvent::Copying(file, copy) => progress.copying(file, &copy),
+        // The failure is reported after the run; the line is cleared for a prompt.
+        LinkEvent::Failed(_) | LinkEvent::Conflict(_) => progress.finish(),
+        _ => {}
+    })
+}
+
+/// Installs the logger asked for with `--log-level` and `--log-file`.
+fn start_logging(matches: &ArgMatches) {
+    let file = matches.get_one::<String>("log-file").map(Path::new);
+    let level = match matches.get_one::<String>("log-level") {
+        Some(level) => level.parse().unwrap(),
+        None if file.is_some() => log::LevelFilter::Debug,
+        // What goes wrong while they run is only ever reported in the log.
+        None if matches!(matches.subcommand_name(), Some("serve" | "watch")) => {
+            log::LevelFilter::Warn
+        }
+        None => log::LevelFilter::Off,
+    };
+    if let Err(err) = logger::init(level, file) {
+        eprintln!(
+            "Error: cannot open log file {}: {}",
+            file.unwrap().display(),
+            err
+        );
+        process::exit(1);
+    }
+}
+
+/// Tags a JSON object with the kind of record it is, for `--output jsonl`.
+fn record(event: &str, fields: Json) -> Json {
+    let mut pairs = vec![(String::from("event"), Json::from(event))];
+    if let Json::Object(fields) = fields {
+        pairs.extend(fields);
+    }
+    Json::Object(pairs)
+}
+
+/// Prints the outcome of a run as JSON, exiting non-zero if the run failed
+/// or, with `strict`, reported warnings.
+fn print_json(result: Result<LinkReport, String>, format: OutputFormat, strict: bool) {
+    let (report, mut error) = match result {
+        Ok(report) => (report, None),
+        Err(err) => (LinkReport::default(), Some(err)),
+    };
+    let warnings = report.warnings.len();
+    let failed = report.failures.len();
+    if error.is_none() && failed > 0 {
+        error = Some(format!(
+            "{} entr{} could not be linked",
+            failed,
+            if failed == 1 { "y" } else { "ies" }
+        ));
+    }
+    if error.is_none() && strict && warnings > 0 {
+        error = Some(format!(
+            "{} warning{} with --strict",
+            warnings,
+            if warnings == 1 { "" } else { "s" }
+        ));
+    }
+
+    if format == OutputFormat::Json {
+        let mut doc = report.to_json();
+        if let Json::Object(fields) = &mut doc {
+            fields.push((String::from("error"), Json::from(error.clone())));
+        }
+        println!("{}", doc);
+    } else {
+        for (dest, backup) in &report.backups {
+            let fields = Json::object([
+                ("dest", Json::from(dest.as_path())),
+                ("backup", Json::from(backup.as_path())),
+            ]);
+            println!("{}", record("backup", fields));
+        }
+        for deleted in &report.deleted {
+            let fields = Json::object([("path", Json::from(deleted.as_path()))]);
+            println!("{}", record("deleted", fields));
+        }
+        for warning in &report.warnings {
+            println!("{}", record("warning", warning.to_json()));
+        }
+        for skipped in &report.skipped {
+            println!("{}", record("skipped", skipped.to_json()));
+        }
+        for operation in &report.planned {
+            println!("{}", record("planned", operation.to_json()));
+        }
+        for failure in &report.failures {
+            println!("{}", record("failure", failure.to_json()));
+        }
+        if let Some(err) = &error {
+            let fields = Json::object([("message", Json::from(err.as_str()))]);
+            println!("{}", record("error", fields));
+        }
+        let mut summary = report.summary_json();
+        if let Json::Object(fields) = &mut summary {
+            fields.push((String::from("warnings"), Json::from(warnings)));
+            fields.push((String::from("failed"), Json::from(error.is_some())));
+        }
+        println!("{}", record("summary", summary));
+    }
+    if error.is_some() {
+        process::exit(1);
+    }
+}
+
+/// Asks once before a forced run replaces more than `threshold` existing
+/// destination entries, counted by planning the run first. Without a
+/// terminal to ask on, the run is refused instead.
+fn confirm_bulk(
+    sources: &[&String],
+    dest: &str,
     opts: &LinkOptions,
+    threshold: usize,
+    term: &Terminal,
 ) -> Result<(), String> {
-    for target in targets {
-        handle_link_files(target, dir, opts)?;
+    let mut existing = 0;
+    for source in sources {
+        existing += count_existing(source, dest, Some(opts)).map_err(|e| e.to_string())?;
+    }
+    if existing <= threshold {
+        return Ok(());
+    }
+
+    let what = format!(
+        "replace {} existing file{} in {}",
+        existing,
+        if existing == 1 { "" } else { "s" },
+        dest
+    );
+    if !term.prompts {
+        return Err(format!("would {}; -I needs a terminal to confirm", what));
+    }
+    match term::confirm(&format!("About to {} — continue?", what)) {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(String::from("cancelled; nothing was replaced")),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Splits the operands into the sources and the destination the way `ln`
+/// does, returning whether the destination names the link itself.
+///
+/// With two operands and no `-t`, an existing directory LINK_NAME gets the
+/// source under its own name, and the joined path is then the link itself.
+/// A LINK_NAME ending in `/` must be such a directory; with `-T` it never is,
+/// and with `--no-dereference` neither is a symlink to one.
+///
+/// # Arguments
+///
+/// * `targets` - The operands, in order
+/// * `target_dir` - The `-t` directory, if given
+/// * `no_target_dir` - True with `-T`
+/// * `no_dereference` - True with `--no-dereference`
+///
+/// # Returns
+///
+/// * `Result<(&[&String], String, bool), String>` - The sources, the
+///   destination, and true if it is the link itself
+fn operands<'a>(
+    targets: &'a [&'a String],
+    target_dir: Option<&String>,
+    no_target_dir: bool,
+    no_dereference: bool,
+) -> Result<(&'a [&'a String], String, bool), String> {
+    if let Some(target_dir) = target_dir {
+        return Ok((targets, target_dir.clone(), false));
+    }
+    match targets {
+        [_] if no_target_dir => Err(String::from("-T needs a TARGET and a LINK_NAME")),
+        [_] => Ok((targets, String::from("."), false)),
+        [_, link_name] if no_target_dir => Ok((&targets[..1], (*link_name).clone(), true)),
+        [target, link_name] => {
+            let link_path = Path::new(link_name.as_str());
+            if link_name.ends_with('/') && !link_path.is_dir() {
+                let named = Path::new(link_name.trim_end_matches('/'));
+                return Err(if named.symlink_metadata().is_ok() {
+                    format!("target '{}' is not a directory", link_name)
+                } else {
+                    format!("target '{}': No such file or directory", link_name)
+                });
+            }
+            // A pattern's matches each keep their own names.
+            match Path::new(target.as_str()).file_name() {
+                _ if no_dereference && link_path.is_symlink() => {
+                    Ok((&targets[..1], (*link_name).clone(), true))
+                }
+                Some(name) if link_path.is_dir() && !has_glob(target) => {
+                    let new_link = link_path.join(name);
+                    Ok((&targets[..1], new_link.to_string_lossy().into_owned(), true))
+                }
+                _ => Ok((&targets[..1], (*link_name).clone(), false)),
+            }
+        }
+        _ if no_target_dir => Err(format!(
+            "-T takes a single TARGET; extra operand '{}'",
+            targets[2]
+        )),
+        [sources @ .., dest] => Ok((sources, (*dest).clone(), false)),
+        [] => unreachable!("clap requires at least one TARGET"),
+    }
+}
+
+/// Works out whether and how existing files are backed up, like coreutils:
+/// `-b`, `--backup` without a CONTROL, and `-S` alone take the control from
+/// `VERSION_CONTROL`, or use `existing` if it is unset.
+///
+/// # Returns
+///
+/// * `Result<Option<BackupControl>, String>` - The control, `None` if no
+///   backups are made, or an error naming an invalid control
+fn backup_control(matches: &ArgMatches) -> Result<Option<BackupControl>, String> {
+    let (control, from_env) = match matches.get_one::<String>("backup") {
+        Some(control) if !control.is_empty() => (control.clone(), false),
+        Some(_) => (env::var("VERSION_CONTROL").unwrap_or_default(), true),
+        None if matches.contains_id("suffix") => {
+            (env::var("VERSION_CONTROL").unwrap_or_default(), true)
+        }
+        None => return Ok(None),
+    };
+    match control.as_str() {
+        "none" | "off" => Ok(None),
+        "" => Ok(Some(BackupControl::Existing)),
+        name => name.parse().map(Some).map_err(|err| {
+            if from_env {
+                format!("VERSION_CONTROL: {}", err)
+            } else {
+                err
+            }
+        }),
+    }
+}
+
+/// Returns true if a short option is given on the command line, alone or in
+/// a group such as `-sfn`, before any `--`.
+///
+/// # Arguments
+///
+/// * `command` - The command the arguments are for, which tells which
+///   short options take the rest of their group as a value, as in `-Sn`
+/// * `args` - The arguments, without the program name
+/// * `short` - The short option to look for
+fn short_flag_given(command: &Command, args: &[OsString], short: char) -> bool {
+    let valued: Vec<char> = command
+        .get_arguments()
+        .filter(|arg| arg.get_action().takes_values())
+        .filter_map(Arg::get_short)
+        .collect();
+    for arg in args.iter().map(|arg| arg.to_string_lossy()) {
+        if arg == "--" {
+            break;
+        }
+        let Some(group) = arg.strip_prefix('-').filter(|group| !group.starts_with('-')) else {
+            continue;
+        };
+        for c in group.chars() {
+            if c == short {
+                return true;
+            }
+            if valued.contains(&c) {
+                break;
+            }
+        }
+    }
+    false
+}
+
+/// Replaces a lone `--resume` with the arguments of the most recent
+/// interrupted run recorded in its journal, and moves to the directory it
+/// was started in.
+///
+/// # Arguments
+///
+/// * `args` - The arguments, replaced if they ask to resume
+///
+/// # Returns
+///
+/// * `Option<(PathBuf, journal::Lock)>` - The journal being resumed and the
+///   claim on it, if the arguments ask to resume
+fn resume_args(args: &mut Vec<OsString>) -> Option<(PathBuf, journal::Lock)> {
+    if !args.iter().skip(1).any(|arg| arg == "--resume") {
+        return None;
+    }
+    let fail = |err: &dyn std::fmt::Display| -> ! {
+        eprintln!("Error: {}", err);
+        process::exit(1);
+    };
+    if args.len() > 2 {
+        fail(&"--resume takes no other arguments; the run is resumed with its own");
+    }
+    let Some(dir) = manifest::state_dir() else {
+        fail(&"cannot find the state directory: neither XDG_STATE_HOME nor HOME is set");
+    };
+    let (path, lock) = match journal::latest(&dir) {
+        Ok(Some(latest)) => latest,
+        Ok(None) => fail(&"there is no interrupted run to resume"),
+        Err(err) => fail(&format!("{}: {}", dir.join(journal::DIR_NAME).display(), err)),
+    };
+    let interrupted = match journal::read(&path) {
+        Ok(interrupted) if !interrupted.args.is_empty() => interrupted,
+        Ok(_) => fail(&"there is no interrupted run to resume"),
+        Err(err) => fail(&format!("{}: {}", path.display(), err)),
+    };
+    if let Err(err) = env::set_current_dir(&interrupted.cwd) {
+        fail(&format!("{}: {}", interrupted.cwd.display(), err));
+    }
+    args.truncate(1);
+    args.extend(interrupted.args);
+    args.push(OsString::from("--resume"));
+    Some((path, lock))
+}
+
+/// Reads the config file, or the one `--config` names, and puts its options,
+/// or those of the `--profile` chosen, ahead of the command line's own. A
+/// lenient first parse finds which settings the command line leaves alone,
+/// since what the command line needs, such as `-s` for `-L`, may be in the
+/// file. A lone TARGET gets the DEST its `dest` template gives.
+///
+/// # Arguments
+///
+/// * `command` - The command line's definition
+/// * `args` - The arguments, to which the config file's are added
+#[cfg(unix)]
+fn apply_config(command: &Command, args: &mut Vec<OsString>) {
+    let Ok(matches) = command
+        .clone()
+        .ignore_errors(true)
+        .try_get_matches_from(&*args)
+    else {
+        return;
+    };
+    // `status` and `watch` take the options of a link run after their name.
+    let (command, matches, at) = match matches.subcommand() {
+        None => (command, &matches, 1),
+        Some((name @ ("status" | "watch"), sub)) => {
+            (command.find_subcommand(name).unwrap(), sub, 2)
+        }
+        Some(_) => return,
+    };
+    let mut config = config::load(matches.get_one::<String>("config").map(Path::new))
+        .unwrap_or_else(|err| {
+            eprintln!("Error: {}", err);
+            process::exit(1);
+        });
+    let fail = |path: Option<PathBuf>, err: String| -> ! {
+        match path {
+            Some(path) => eprintln!("Error: {}: {}", path.display(), err),
+            None => eprintln!("Error: {}", err),
+        }
+        process::exit(1);
+    };
+    if let Some(name) = matches.get_one::<String>("profile") {
+        config = config
+            .profile(name)
+            .unwrap_or_else(|err| fail(config.path.clone(), err));
+    }
+    let defaults =
+        config_args(&config, command, matches).unwrap_or_else(|err| fail(config.path.clone(), err));
+    args.splice(at..at, defaults.into_iter().map(OsString::from));
+
+    if at > 1 {
+        return;
+    }
+    let targets: Vec<&String> = matches
+        .get_many::<String>("targets")
+        .map(|v| v.collect())
+        .unwrap_or_default();
+    if let (Some(template), [target]) = (config.get("dest"), &targets[..])
+        && !matches.contains_id("target-directory")
+        && !matches.contains_id("files-from")
+    {
+        let dest = template
+            .as_str()
+            .ok_or_else(|| String::from("dest must be a string"))
+            .and_then(|template| config::expand_dest(template, Path::new(target.as_str())))
+            .unwrap_or_else(|err| fail(config.path.clone(), err));
+        args.push(dest.into_os_string());
+    }
+}
+
+/// Turns the settings of a config file into command-line arguments, leaving
+/// out those the command line already sets.
+///
+/// # Arguments
+///
+/// * `config` - The settings read from the config file
+/// * `matches` - The parsed command line
+///
+/// # Returns
+///
+/// * `Result<Vec<String>, String>` - The arguments to parse ahead of the
+///   command line's, or an error naming a key with no option or a bad value
+#[cfg(unix)]
+fn config_args(
+    config: &config::Config,
+    command: &Command,
+    matches: &ArgMatches,
+) -> Result<Vec<String>, String> {
+    let given = |id: &str| {
+        matches.ids().any(|given| given == id)
+            && matches.value_source(id) == Some(ValueSource::CommandLine)
+    };
+    let mut args = Vec::new();
+    for (key, value) in config.settings() {
+        let flag = || {
+            value
+                .as_bool()
+                .ok_or_else(|| format!("{} must be a boolean", key))
+        };
+        let text = || match value {
+            Json::String(s) => Ok(s.clone()),
+            Json::Number(n) if n.fract() == 0.0 && *n >= 0.0 => Ok(format!("{}", n)),
+            _ => Err(format!(
+                "{} must be a string or a non-negative integer",
+                key
+            )),
+        };
+        let id = match key {
+            "symbolic" | "relative" | "lexical" | "force" | "skip_existing" | "update"
+            | "interactive" | "verbose" | "quiet" | "preserve_symlinks" | "reflink" | "explain"
+            | "dry_run" | "delete" | "follow_links" | "respect_gitignore" | "strict"
+            | "check_first" | "atomic" | "no_dereference" | "flatten" => key.replace('_', "-"),
+            "archive" => String::from("archive-link"),
+            "same_file_system" => String::from("one-file-system"),
+            "backup" => {
+                if flag()? && !given("backup") && config.get("backup_control").is_none() {
+                    args.push(String::from("--backup"));
+                }
+                continue;
+            }
+            "hidden" => {
+                if !given("hidden") && !given("no-hidden") {
+                    args.push(String::from(if flag()? {
+                        "--hidden"
+                    } else {
+                        "--no-hidden"
+                    }));
+                }
+                continue;
+            }
+            "source_paths" => {
+                let paths: SourcePaths = text()?.parse()?;
+                if !given("logical") && !given("physical") {
+                    args.push(format!("--{}", paths.as_str()));
+                }
+                continue;
+            }
+            "exclude" | "include" | "filter" | "transform" => {
+                let items = value
+                    .as_array()
+                    .ok_or_else(|| format!("{} must be an array of strings", key))?;
+                if !given(key) {
+                    for item in items {
+                        let item = item
+                            .as_str()
+                            .ok_or_else(|| format!("{} must be an array of strings", key))?;
+                        args.push(format!("--{}={}", key, item));
+                    }
+                }
+                continue;
+            }
+            "backup_control" | "backup_suffix" | "backup_dir" | "backup_generations"
+            | "relative_to" | "home_style" | "max_depth" | "min_depth" | "overlay" | "fallback"
+            | "on_error" | "network_fs" | "selinux" | "mark" | "owner" | "group" | "chmod"
+            | "dest_template" | "on_collision" | "jobs" | "color" | "progress" | "output" => {
+                let id = match key {
+                    "backup_control" => "backup",
+                    "backup_suffix" => "suffix",
+                    _ => key,
+                }
+                .replace('_', "-");
+                if !given(&id) {
+                    args.push(format!("--{}={}", id, text()?));
+                }
+                continue;
+            }
+            "dest" => continue,
+            _ => return Err(format!("unknown option {}", key)),
+        };
+        if flag()? && !given(&id) {
+            args.push(format!("--{}", id));
+        }
+    }
+    // A setting of a link run that ends, such as `strict`, means nothing to `watch`.
+    args.retain(|arg| {
+        let long = arg[2..].split('=').next().unwrap();
+        command
+            .get_arguments()
+            .any(|known| known.get_long() == Some(long))
+    });
+    Ok(args)
+}
+
+/// Resolves an `--owner` or `--group` name, exiting if it is unknown.
+fn lookup_or_exit(
+    name: Option<&String>,
+    lookup: fn(&str) -> io::Result<u32>,
+    term: &Terminal,
+) -> Option<u32> {
+    let name = name?;
+    match lookup(name) {
+        Ok(id) => Some(id),
+        Err(err) => {
+            eprintln!("{} {}", term.error(), err);
+            process::exit(1);
+        }
     }
-    Ok(())
 }
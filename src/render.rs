@@ -0,0 +1,341 @@
+use flnk::error::{FlnkError, Op};
+use flnk::link::dry_run::Operation;
+use flnk::link::failure::Failure;
+use flnk::link::link_files::LinkReport;
+use flnk::link::link_options::LinkOptions;
+use flnk::term::{self, Style, Terminal};
+use std::io;
+use std::path::Path;
+
+/// What is known about an error besides its message.
+struct Details<'a> {
+    source: Option<&'a Path>,
+    dest: Option<&'a Path>,
+    op: Option<Op>,
+    kind: io::ErrorKind,
+    errno: Option<i32>,
+}
+
+impl Details<'_> {
+    /// Prints the details below an error line, one indented `name: value`
+    /// line each, leaving out what is unknown.
+    fn print(&self) {
+        if let Some(source) = self.source {
+            eprintln!("  source: {}", source.display());
+        }
+        if let Some(dest) = self.dest {
+            eprintln!("  destination: {}", dest.display());
+        }
+        if let Some(errno) = self.errno {
+            match errno_name(errno) {
+                Some(name) => eprintln!("  errno: {} ({})", name, errno),
+                None => eprintln!("  errno: {}", errno),
+            }
+        }
+        if let Some(hint) = self.hint() {
+            eprintln!("  hint: {}", hint);
+        }
+    }
+
+    /// Suggests a way around the error, for the errors that have one.
+    fn hint(&self) -> Option<&'static str> {
+        let op = self.op?;
+        match self.kind {
+            // Clone errors already say why the filesystem refused.
+            _ if op == Op::Clone => None,
+            io::ErrorKind::AlreadyExists if matches!(op, Op::Link | Op::Symlink | Op::Copy) => {
+                Some(
+                    "use -f to replace the destination, -b to back it up first, or --skip-existing to keep it",
+                )
+            }
+            io::ErrorKind::CrossesDevices => Some(
+                "the source and destination are on different filesystems; use -s for a symlink, or --fallback copy to copy the file instead",
+            ),
+            io::ErrorKind::TooManyLinks => Some(
+                "the source has as many hard links as its filesystem allows; use -s, or --fallback copy",
+            ),
+            io::ErrorKind::PermissionDenied if op == Op::Link => Some(
+                "hard links to files you do not own may be refused (see fs.protected_hardlinks); use -s instead",
+            ),
+            io::ErrorKind::PermissionDenied => {
+                Some("check that you can write to the destination directory")
+            }
+            io::ErrorKind::ReadOnlyFilesystem => {
+                Some("the destination is on a read-only filesystem")
+            }
+            io::ErrorKind::StorageFull => Some("the destination filesystem is full"),
+            _ => None,
+        }
+    }
+}
+
+/// Prints an error that ended a run, followed by the entry it happened on,
+/// the OS error number, and a suggested fix where there is one.
+///
+/// # Arguments
+///
+/// * `term` - The terminal the error is printed on
+/// * `err` - The error
+pub fn error(term: &Terminal, err: &io::Error) {
+    eprintln!("{} {}", term.error(), err);
+    let details = match FlnkError::find(err) {
+        // The message of a collision names every path and how to link anyway.
+        Some(FlnkError::Collision { .. }) => return,
+        // The message says where the run stopped; the journal has the rest.
+        Some(FlnkError::Interrupted { .. }) => {
+            eprintln!("  hint: flnk --resume links the entries that are left");
+            return;
+        }
+        Some(found) => Details {
+            source: Some(found.source_path()),
+            dest: Some(found.dest()),
+            op: Some(found.op()),
+            kind: found.kind(),
+            errno: found.errno(),
+        },
+        None => Details {
+            source: None,
+            dest: None,
+            op: None,
+            kind: err.kind(),
+            errno: err.raw_os_error(),
+        },
+    };
+    details.print();
+}
+
+/// Prints an entry a run went on without, like `error`.
+///
+/// # Arguments
+///
+/// * `term` - The terminal the failure is printed on
+/// * `failure` - The failure
+pub fn failure(term: &Terminal, failure: &Failure) {
+    eprintln!("{} {}", term.error(), failure);
+    Details {
+        source: Some(&failure.source),
+        dest: Some(&failure.dest),
+        op: failure.op,
+        kind: failure.kind,
+        errno: failure.errno,
+    }
+    .print();
+}
+
+/// Prints the warnings and failures of a run on stderr.
+///
+/// # Arguments
+///
+/// * `term` - The terminal the problems are printed on
+/// * `report` - The outcome of the run
+pub fn problems(term: &Terminal, report: &LinkReport) {
+    for warning in &report.warnings {
+        eprintln!("{} {}", term.warning(), warning);
+    }
+    for found in &report.failures {
+        failure(term, found);
+    }
+}
+
+/// Prints the outcome of a link run on stdout: what a dry run would do,
+/// the skipped entries with `--explain`, and a summary.
+///
+/// # Arguments
+///
+/// * `term` - The terminal the report is printed on
+/// * `report` - The outcome of the run
+/// * `opts` - The options of the run
+/// * `verbose` - Whether the created links were listed during the run, in
+///   place of the `Linked` summary
+/// * `quiet` - Whether to leave out the summary and the deleted entries
+/// * `summary_only` - Whether to print the summary alone
+pub fn report(
+    term: &Terminal,
+    report: &LinkReport,
+    opts: &LinkOptions,
+    verbose: bool,
+    quiet: bool,
+    summary_only: bool,
+) {
+    if summary_only {
+        let line = if opts.dry_run {
+            format!("Would link {}", entries(planned_links(report)))
+        } else {
+            recap(report)
+        };
+        println!("{}", term.paint(Style::Summary, &line));
+        return;
+    }
+    for operation in &report.planned {
+        println!("Would {}", operation);
+    }
+    // Without --explain, only destinations kept by --skip-existing are
+    // recorded, and they are counted instead of listed.
+    if opts.explain {
+        for skipped in &report.skipped {
+            let line = format!("Skipped {}", skipped);
+            println!("{}", term.paint(Style::Skipped, &line));
+        }
+    }
+    if opts.dry_run {
+        let line = format!("Would link {}", entries(planned_links(report)));
+        println!("{}", term.paint(Style::Summary, &line));
+    } else if !verbose && !quiet {
+        println!("{}", term.paint(Style::Summary, &recap(report)));
+    }
+    if !opts.explain && !quiet && !report.skipped.is_empty() {
+        let line = format!("Kept {} existing", entries(report.skipped.len()));
+        println!("{}", term.paint(Style::Skipped, &line));
+    }
+    if !quiet {
+        for deleted in &report.deleted {
+            println!("Deleted {}", deleted.display());
+        }
+    }
+}
+
+/// Prints the outcome of one pass of `flnk watch` on stdout. Passes that
+/// link nothing print no summary.
+///
+/// # Arguments
+///
+/// * `term` - The terminal the report is printed on
+/// * `report` - The outcome of the pass
+/// * `verbose` - Whether to list each created link instead of a summary
+/// * `quiet` - Whether to leave out the summary and the deleted entries
+#[cfg(target_os = "linux")]
+pub fn watch_report(term: &Terminal, report: &LinkReport, verbose: bool, quiet: bool) {
+    for operation in &report.planned {
+        println!("Would {}", operation);
+    }
+    if verbose {
+        for linked in &report.linked {
+            let line = format!("Created link: {}", linked.display());
+            println!("{}", term.paint(Style::Created, &line));
+        }
+    } else if !quiet && !report.linked.is_empty() {
+        let line = format!("Linked {}", entries(report.linked.len()));
+        println!("{}", term.paint(Style::Summary, &line));
+    }
+    if !quiet {
+        for deleted in &report.deleted {
+            println!("Deleted {}", deleted.display());
+        }
+    }
+}
+
+/// Describes a created link as GNU ln's `-v` does: `'dest' -> 'target'` for
+/// a symlink, with the target as it was written, `'dest' => 'source'` for a
+/// hard link or copy, and ` (backup: 'backup')` after either when the entry
+/// it replaced was backed up.
+///
+/// # Arguments
+///
+/// * `term` - The terminal the line is printed on
+/// * `source` - The source the link was made from
+/// * `dest` - The created link
+/// * `backup` - Where the replaced entry was moved, if anywhere
+///
+/// # Returns
+///
+/// * `String` - The line to print, colored when stdout is
+pub fn linked(term: &Terminal, source: &Path, dest: &Path, backup: Option<&Path>) -> String {
+    let line = match std::fs::read_link(dest) {
+        Ok(target) => format!("'{}' -> '{}'", dest.display(), target.display()),
+        Err(_) => format!("'{}' => '{}'", dest.display(), source.display()),
+    };
+    let line = match backup {
+        Some(backup) => format!("{} (backup: '{}')", line, backup.display()),
+        None => line,
+    };
+    term.paint(Style::Created, &line)
+}
+
+/// Returns the one-line recap of a run, such as `Linked 3 entries, created 1
+/// directory, filtered 2, met 1 conflict, made 1 backup, shared 12.0 KiB in
+/// 0.04s`, leaving out the counts that are zero.
+fn recap(report: &LinkReport) -> String {
+    let plural = |count: usize, one: &str, many: &str| {
+        format!("{} {}", count, if count == 1 { one } else { many })
+    };
+    let mut parts = vec![format!("Linked {}", entries(report.linked.len()))];
+    if !report.created_dirs.is_empty() {
+        let dirs = plural(report.created_dirs.len(), "directory", "directories");
+        parts.push(format!("created {}", dirs));
+    }
+    if report.filtered > 0 {
+        parts.push(format!("filtered {}", report.filtered));
+    }
+    if report.conflicts > 0 {
+        parts.push(format!(
+            "met {}",
+            plural(report.conflicts, "conflict", "conflicts")
+        ));
+    }
+    if !report.backups.is_empty() {
+        parts.push(format!(
+            "made {}",
+            plural(report.backups.len(), "backup", "backups")
+        ));
+    }
+    if report.shared_bytes > 0 {
+        parts.push(format!("shared {}", term::human_bytes(report.shared_bytes)));
+    }
+    format!(
+        "{} in {:.2}s",
+        parts.join(", "),
+        report.elapsed.as_secs_f64()
+    )
+}
+
+/// Returns the number of links, copies, and clones a dry run planned.
+fn planned_links(report: &LinkReport) -> usize {
+    report
+        .planned
+        .iter()
+        .filter(|op| {
+            matches!(
+                op,
+                Operation::Link(..) | Operation::Copy(..) | Operation::Clone(..)
+            )
+        })
+        .count()
+}
+
+/// Returns a count of entries, as in `1 entry` or `2 entries`.
+fn entries(count: usize) -> String {
+    format!("{} entr{}", count, if count == 1 { "y" } else { "ies" })
+}
+
+/// The symbolic name of an OS error number, as in `EXDEV`.
+#[cfg(unix)]
+fn errno_name(errno: i32) -> Option<&'static str> {
+    let name = match errno {
+        libc::EPERM => "EPERM",
+        libc::ENOENT => "ENOENT",
+        libc::EIO => "EIO",
+        libc::EACCES => "EACCES",
+        libc::EBUSY => "EBUSY",
+        libc::EEXIST => "EEXIST",
+        libc::EXDEV => "EXDEV",
+        libc::ENOTDIR => "ENOTDIR",
+        libc::EISDIR => "EISDIR",
+        libc::EINVAL => "EINVAL",
+        libc::ENOSPC => "ENOSPC",
+        libc::EROFS => "EROFS",
+        libc::EMLINK => "EMLINK",
+        libc::ENAMETOOLONG => "ENAMETOOLONG",
+        libc::ENOTEMPTY => "ENOTEMPTY",
+        libc::ELOOP => "ELOOP",
+        libc::EDQUOT => "EDQUOT",
+        libc::EOPNOTSUPP => "EOPNOTSUPP",
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// OS error numbers are only named on Unix.
+#[cfg(not(unix))]
+fn errno_name(_errno: i32) -> Option<&'static str> {
+    None
+}
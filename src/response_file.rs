@@ -0,0 +1,57 @@
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// How deeply response files may name further response files.
+const MAX_DEPTH: usize = 16;
+
+/// Expands `@FILE` arguments into the arguments listed in FILE.
+///
+/// A response file holds one argument per line, taken verbatim apart from a
+/// trailing carriage return, so names with spaces need no quoting. Blank
+/// lines and lines starting with `#` are skipped, and lines may themselves
+/// be `@FILE` references. An `@` argument that does not name a readable file
+/// is kept as it is.
+///
+/// # Arguments
+///
+/// * `args` - The arguments to expand, without the program name
+///
+/// # Returns
+///
+/// * `io::Result<Vec<OsString>>` - The expanded arguments, or an error if
+///   response files nest too deeply
+pub fn expand(args: impl IntoIterator<Item = OsString>) -> io::Result<Vec<OsString>> {
+    let mut out = Vec::new();
+    for arg in args {
+        expand_arg(arg, 0, &mut out)?;
+    }
+    Ok(out)
+}
+
+fn expand_arg(arg: OsString, depth: usize, out: &mut Vec<OsString>) -> io::Result<()> {
+    let Some(path) = arg.to_str().and_then(|a| a.strip_prefix('@')) else {
+        out.push(arg);
+        return Ok(());
+    };
+    let Ok(text) = fs::read_to_string(Path::new(path)) else {
+        out.push(arg);
+        return Ok(());
+    };
+    if depth == MAX_DEPTH {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("response file {} nests too deeply", path),
+        ));
+    }
+
+    for line in text.lines() {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+        expand_arg(OsString::from(line), depth + 1, out)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,172 @@
+use crate::inotify::{self, Inotify};
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+
+/// The directory events that can take a tracked source away.
+const WATCH_MASK: u32 =
+    inotify::IN_DELETE | inotify::IN_MOVED_FROM | inotify::IN_DELETE_SELF | inotify::IN_MOVE_SELF;
+
+/// How a tracked source disappeared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceEventKind {
+    Deleted,
+    Moved,
+}
+
+impl SourceEventKind {
+    /// The lowercase name used in log lines, hook environments, and notifications.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SourceEventKind::Deleted => "deleted",
+            SourceEventKind::Moved => "moved",
+        }
+    }
+}
+
+/// A source of previously created links that has gone away.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceEvent {
+    /// Whether the source was deleted or moved
+    pub kind: SourceEventKind,
+    /// The absolute path the source had
+    pub source: PathBuf,
+    /// The links that were created from it
+    pub links: Vec<PathBuf>,
+}
+
+impl SourceEvent {
+    /// Runs a hook command for the event through `sh -c`, with `FLNK_EVENT`,
+    /// `FLNK_SOURCE`, and `FLNK_LINKS` (one link per line) set, logging a
+    /// warning if it cannot be run or fails.
+    pub fn run_hook(&self, hook: &str) {
+        let links: Vec<String> = self
+            .links
+            .iter()
+            .map(|link| link.to_string_lossy().into_owned())
+            .collect();
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg(hook)
+            .env("FLNK_EVENT", self.kind.as_str())
+            .env("FLNK_SOURCE", &self.source)
+            .env("FLNK_LINKS", links.join("\n"))
+            .status();
+        match status {
+            Ok(status) if !status.success() => log::warn!("hook {} failed: {}", hook, status),
+            Err(e) => log::warn!("cannot run hook {}: {}", hook, e),
+            Ok(_) => {}
+        }
+    }
+}
+
+impl fmt::Display for SourceEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "source {} {}", self.kind.as_str(), self.source.display())?;
+        for link in &self.links {
+            write!(f, "\n  linked at {}", link.display())?;
+        }
+        Ok(())
+    }
+}
+
+/// The tracked sources, grouped by the watched directory that contains them.
+#[derive(Default)]
+struct Tracked {
+    dirs: HashMap<i32, PathBuf>,
+    sources: HashMap<PathBuf, Vec<PathBuf>>,
+}
+
+/// Watches the sources of created links and reports when they are deleted or moved.
+///
+/// Each source's parent directory is watched rather than the source itself,
+/// so removals show up as named directory events and hard links, whose
+/// inode outlives the source name, are covered too.
+pub struct SourceSentinel {
+    inotify: Inotify,
+    tracked: Mutex<Tracked>,
+}
+
+impl SourceSentinel {
+    /// Creates a sentinel that tracks nothing yet.
+    pub fn new() -> io::Result<Self> {
+        Ok(SourceSentinel {
+            inotify: Inotify::new()?,
+            tracked: Mutex::new(Tracked::default()),
+        })
+    }
+
+    /// Starts tracking the source of a created link.
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - The source entry the link was created from
+    /// * `link` - The created link
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<()>` - An error if the source's directory cannot be watched
+    pub fn track(&self, source: &Path, link: &Path) -> io::Result<()> {
+        let source = std::path::absolute(source)?;
+        let link = std::path::absolute(link)?;
+        let dir = source.parent().unwrap_or(Path::new("/")).to_path_buf();
+        let wd = self.inotify.add_watch(&dir, WATCH_MASK)?;
+
+        let mut tracked = self.tracked.lock().unwrap();
+        tracked.dirs.insert(wd, dir);
+        let links = tracked.sources.entry(source).or_default();
+        if !links.contains(&link) {
+            links.push(link);
+        }
+        Ok(())
+    }
+
+    /// Blocks until tracked sources disappear and returns them.
+    ///
+    /// Reported sources are no longer tracked. Events for untracked entries
+    /// are consumed silently, so the result may be empty.
+    pub fn next_events(&self) -> io::Result<Vec<SourceEvent>> {
+        let events = self.inotify.read_events()?;
+        let mut tracked = self.tracked.lock().unwrap();
+        let mut gone = Vec::new();
+
+        for event in events {
+            let Some(dir) = tracked.dirs.get(&event.wd).cloned() else {
+                continue;
+            };
+            if event.mask & inotify::IN_IGNORED != 0 {
+                tracked.dirs.remove(&event.wd);
+                continue;
+            }
+
+            let kind = if event.mask & (inotify::IN_MOVED_FROM | inotify::IN_MOVE_SELF) != 0 {
+                SourceEventKind::Moved
+            } else {
+                SourceEventKind::Deleted
+            };
+            let sources: Vec<PathBuf> = match &event.name {
+                Some(name) => vec![dir.join(name)],
+                // The directory itself went away, taking every source in it along.
+                None => tracked
+                    .sources
+                    .keys()
+                    .filter(|source| source.parent() == Some(dir.as_path()))
+                    .cloned()
+                    .collect(),
+            };
+            for source in sources {
+                if let Some(links) = tracked.sources.remove(&source) {
+                    gone.push(SourceEvent {
+                        kind,
+                        source,
+                        links,
+                    });
+                }
+            }
+        }
+        Ok(gone)
+    }
+}
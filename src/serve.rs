@@ -0,0 +1,427 @@
+use crate::json::Json;
+use crate::link::link_files::{CopyProgress, Observer, expand_sources, link_files_with};
+use crate::link::link_options::{LinkOptions, options_from_json};
+use crate::link::manifest::{self, Reversal};
+use crate::link::pipeline::{self, EntryKind, LinkJob};
+#[cfg(target_os = "linux")]
+use crate::sentinel::{SourceEvent, SourceSentinel};
+use crate::systemd;
+use crate::verify::{self, Mismatch};
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+#[cfg(target_os = "linux")]
+use std::sync::Weak;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// JSON-RPC error code for text that is not valid JSON.
+const PARSE_ERROR: i64 = -32700;
+/// JSON-RPC error code for a message that is not a valid request.
+const INVALID_REQUEST: i64 = -32600;
+/// JSON-RPC error code for an unknown method.
+const METHOD_NOT_FOUND: i64 = -32601;
+/// JSON-RPC error code for missing or malformed parameters.
+const INVALID_PARAMS: i64 = -32602;
+/// JSON-RPC error code for a link operation that failed.
+const OPERATION_FAILED: i64 = -32000;
+
+/// Options for the JSON-RPC server.
+#[derive(Debug, Clone, Default)]
+pub struct ServeOptions {
+    /// A shell command run whenever the source of a link created by the server is deleted or moved.
+    /// Sources are only watched on Linux
+    pub on_source_gone: Option<String>,
+}
+
+/// A connected client.
+///
+/// Each message is written under the lock, so notifications sent from other
+/// threads never interleave with a response.
+struct Client {
+    stream: Mutex<UnixStream>,
+}
+
+impl Client {
+    fn send(&self, message: &Json) -> io::Result<()> {
+        let mut stream = self.stream.lock().unwrap();
+        writeln!(stream, "{}", message)
+    }
+}
+
+/// State shared by every connection. Without inotify, there is none.
+struct Server {
+    #[cfg(target_os = "linux")]
+    opts: ServeOptions,
+    #[cfg(target_os = "linux")]
+    sentinel: SourceSentinel,
+    #[cfg(target_os = "linux")]
+    clients: Mutex<Vec<Weak<Client>>>,
+}
+
+impl Server {
+    /// Watches the source of a created link, so its removal is reported.
+    #[cfg(target_os = "linux")]
+    fn track(&self, job: &LinkJob) {
+        if let Err(e) = self.sentinel.track(&job.source, &job.dest) {
+            log::warn!("cannot watch {}: {}", job.source.display(), e);
+        }
+    }
+
+    /// Sources are not watched without inotify.
+    #[cfg(not(target_os = "linux"))]
+    fn track(&self, _job: &LinkJob) {}
+}
+
+/// An error response to a single request.
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        RpcError {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<io::Error> for RpcError {
+    fn from(e: io::Error) -> Self {
+        RpcError::new(OPERATION_FAILED, e.to_string())
+    }
+}
+
+/// The source, destination, and options shared by every operation.
+fn operation_params(params: Option<&Json>) -> Result<(String, String, LinkOptions), RpcError> {
+    let field = |name: &str| {
+        params
+            .and_then(|p| p.get(name))
+            .and_then(Json::as_str)
+            .map(str::to_string)
+            .ok_or_else(|| RpcError::new(INVALID_PARAMS, format!("missing string param {}", name)))
+    };
+    let opts = options_from_json(params.and_then(|p| p.get("options")))
+        .map_err(|e| RpcError::new(INVALID_PARAMS, e))?;
+    Ok((field("source")?, field("dest")?, opts))
+}
+
+/// Lists the links an operation would create and whether each destination already exists.
+fn plan(params: Option<&Json>) -> Result<Json, RpcError> {
+    let (source, dest, opts) = operation_params(params)?;
+    let sources = expand_sources(&source)?;
+    let jobs = pipeline::collect(&sources, Path::new(&dest), &opts)?;
+
+    Ok(Json::Array(
+        jobs.iter()
+            .filter(|job| job.kind.creates_link())
+            .map(|job| {
+                Json::object([
+                    ("source", Json::from(job.source.as_path())),
+                    ("dest", Json::from(job.dest.as_path())),
+                    (
+                        "exists",
+                        Json::from(fs::symlink_metadata(&job.dest).is_ok()),
+                    ),
+                ])
+            })
+            .collect(),
+    ))
+}
+
+/// Builds a JSON-RPC notification.
+fn notification(method: &str, params: Json) -> Json {
+    Json::object([
+        ("jsonrpc", Json::from("2.0")),
+        ("method", Json::from(method)),
+        ("params", params),
+    ])
+}
+
+/// Streams the events of a `link` request to the client that sent it.
+struct Notifier<'a> {
+    id: &'a Json,
+    opts: &'a LinkOptions,
+    server: &'a Server,
+    client: &'a Client,
+}
+
+impl Observer for Notifier<'_> {
+    fn linked(&mut self, job: &LinkJob) {
+        let params = Json::object([
+            ("id", self.id.clone()),
+            ("linked", Json::from(job.rel_path.as_path())),
+        ]);
+        let _ = self.client.send(&notification("progress", params));
+
+        if self.opts.symbolic || job.kind == EntryKind::File {
+            self.server.track(job);
+        }
+    }
+
+    fn copying(&mut self, job: &LinkJob, progress: &CopyProgress) {
+        let params = Json::object([
+            ("id", self.id.clone()),
+            ("copying", Json::from(job.rel_path.as_path())),
+            ("copied", Json::from(progress.copied)),
+            ("total", Json::from(progress.total)),
+            ("bytes_per_sec", Json::from(progress.bytes_per_sec)),
+        ]);
+        let _ = self.client.send(&notification("progress", params));
+    }
+}
+
+/// Performs an operation, streaming a `progress` notification for every created
+/// link and, while large files are copied, for their bytes copied and throughput.
+///
+/// The sources of the created links are tracked so their removal can be
+/// reported, and the changes are recorded so the `undo` method can reverse them.
+fn link(
+    id: &Json,
+    params: Option<&Json>,
+    server: &Server,
+    client: &Client,
+) -> Result<Json, RpcError> {
+    let (source, dest, mut opts) = operation_params(params)?;
+    if !opts.dry_run {
+        opts.undo_manifest =
+            manifest::state_dir().map(|dir| dir.join(format!("{}.json", manifest::new_id())));
+    }
+    let notifier = Notifier {
+        id,
+        opts: &opts,
+        server,
+        client,
+    };
+    let report = link_files_with(&source, &dest, Some(&opts), notifier)?;
+    Ok(report.to_json())
+}
+
+/// Reverses a recorded run, given by an optional `id`, or the most recent one.
+fn undo(params: Option<&Json>) -> Result<Json, RpcError> {
+    let id = match params.and_then(|params| params.get("id")) {
+        None | Some(Json::Null) => None,
+        Some(id) => Some(
+            id.as_str()
+                .ok_or_else(|| RpcError::new(INVALID_PARAMS, "id must be a string"))?,
+        ),
+    };
+    let dir = manifest::state_dir()
+        .ok_or_else(|| RpcError::new(OPERATION_FAILED, "cannot locate the state directory"))?;
+    let (run, reversals) = manifest::undo(&dir, id)?;
+    Ok(Json::object([
+        ("id", Json::from(run.id.as_str())),
+        ("dest", Json::from(run.dest.as_path())),
+        (
+            "reversals",
+            Json::Array(reversals.iter().map(Reversal::to_json).collect()),
+        ),
+    ]))
+}
+
+/// Checks the links in `dest` against `source`, returning the number of links
+/// `checked` and a `mismatches` array.
+fn verify(params: Option<&Json>) -> Result<Json, RpcError> {
+    let (source, dest, _) = operation_params(params)?;
+    let verification = verify::verify(Path::new(&source), Path::new(&dest))?;
+    Ok(Json::object([
+        ("checked", Json::from(verification.checked)),
+        (
+            "mismatches",
+            Json::Array(
+                verification
+                    .mismatches
+                    .iter()
+                    .map(Mismatch::to_json)
+                    .collect(),
+            ),
+        ),
+    ]))
+}
+
+/// Handles a single request line, sending any notifications to `client`.
+///
+/// # Returns
+///
+/// * `Option<Json>` - The response, or `None` if the request was a notification
+fn handle_request(line: &str, server: &Server, client: &Client) -> Option<Json> {
+    let (id, result) = match Json::parse(line) {
+        Err(e) => (Json::Null, Err(RpcError::new(PARSE_ERROR, e.to_string()))),
+        Ok(request) => {
+            let id = request.get("id").cloned();
+            let params = request.get("params");
+            let result = match request.get("method").and_then(Json::as_str) {
+                None => Err(RpcError::new(INVALID_REQUEST, "missing method")),
+                Some("plan") => plan(params),
+                Some("link") => link(id.as_ref().unwrap_or(&Json::Null), params, server, client),
+                Some("undo") => undo(params),
+                Some("verify") => verify(params),
+                Some(other) => Err(RpcError::new(
+                    METHOD_NOT_FOUND,
+                    format!("unknown method {}", other),
+                )),
+            };
+            (id?, result)
+        }
+    };
+
+    let outcome = match result {
+        Ok(value) => ("result", value),
+        Err(e) => (
+            "error",
+            Json::object([
+                ("code", Json::from(e.code)),
+                ("message", Json::from(e.message)),
+            ]),
+        ),
+    };
+    Some(Json::object([
+        ("jsonrpc", Json::from("2.0")),
+        ("id", id),
+        outcome,
+    ]))
+}
+
+/// Serves requests from a single client until it disconnects.
+fn handle_connection(stream: UnixStream, server: &Server) -> io::Result<()> {
+    let reader = BufReader::new(stream.try_clone()?);
+    let client = Arc::new(Client {
+        stream: Mutex::new(stream),
+    });
+    #[cfg(target_os = "linux")]
+    server.clients.lock().unwrap().push(Arc::downgrade(&client));
+
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(response) = handle_request(&line, server, &client) {
+            client.send(&response)?;
+        }
+    }
+    Ok(())
+}
+
+/// Reports a vanished source: logs it, runs the hook command, and sends a
+/// `source_gone` notification to every connected client.
+#[cfg(target_os = "linux")]
+fn alert(server: &Server, event: &SourceEvent) {
+    log::warn!("{}", event);
+
+    let links: Vec<String> = event
+        .links
+        .iter()
+        .map(|link| link.to_string_lossy().into_owned())
+        .collect();
+    if let Some(hook) = &server.opts.on_source_gone {
+        event.run_hook(hook);
+    }
+
+    let message = notification(
+        "source_gone",
+        Json::object([
+            ("event", Json::from(event.kind.as_str())),
+            ("source", Json::from(event.source.as_path())),
+            ("links", Json::from(links)),
+        ]),
+    );
+    let mut clients = server.clients.lock().unwrap();
+    clients.retain(|client| match client.upgrade() {
+        Some(client) => client.send(&message).is_ok(),
+        None => false,
+    });
+}
+
+/// Listens on a Unix socket and serves JSON-RPC 2.0 requests, one per line.
+///
+/// Supported methods are `plan`, `link`, and `verify`, taking `source`,
+/// `dest`, and an optional `options` object, and `undo`, taking an optional
+/// run `id`. `link` sends a `progress` notification for every created link
+/// before its response. When the source of a link created since the server
+/// started is deleted or moved, every client receives a `source_gone`
+/// notification; sources are only watched on Linux. A stale socket left behind by a previous server is
+/// replaced; any other file at the path is an `AlreadyExists` error. Under systemd, a socket passed by socket
+/// activation is used instead of binding, and readiness and watchdog pings
+/// are reported to the service manager.
+///
+/// # Arguments
+///
+/// * `socket` - The path of the socket to listen on
+/// * `opts` - The options controlling how the server reports events
+///
+/// # Returns
+///
+/// * `io::Result<()>` - Only returns on a listener error
+pub fn serve(socket: &Path, opts: &ServeOptions) -> io::Result<()> {
+    let listener = match systemd::activated_listener() {
+        Some(listener) => listener,
+        None => {
+            match fs::symlink_metadata(socket) {
+                Ok(meta) if meta.file_type().is_socket() => {
+                    if UnixStream::connect(socket).is_ok() {
+                        return Err(io::Error::new(
+                            io::ErrorKind::AddrInUse,
+                            format!("{} is already being served", socket.display()),
+                        ));
+                    }
+                    fs::remove_file(socket)?;
+                }
+                // Anything else at the path is the user's, and is never removed.
+                Ok(_) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::AlreadyExists,
+                        format!("{} exists and is not a socket", socket.display()),
+                    ));
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+            UnixListener::bind(socket)?
+        }
+    };
+
+    #[cfg(target_os = "linux")]
+    let server = {
+        let server = Arc::new(Server {
+            opts: opts.clone(),
+            sentinel: SourceSentinel::new()?,
+            clients: Mutex::new(Vec::new()),
+        });
+        let watcher = Arc::clone(&server);
+        thread::spawn(move || {
+            loop {
+                match watcher.sentinel.next_events() {
+                    Ok(events) => events.iter().for_each(|event| alert(&watcher, event)),
+                    Err(e) => {
+                        log::error!("source watch stopped: {}", e);
+                        return;
+                    }
+                }
+            }
+        });
+        server
+    };
+    #[cfg(not(target_os = "linux"))]
+    let server = {
+        // Sources are not watched without inotify, so there is no hook to run.
+        let _ = opts;
+        Arc::new(Server {})
+    };
+
+    systemd::ready();
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let server = Arc::clone(&server);
+        thread::spawn(move || {
+            if let Err(e) = handle_connection(stream, &server) {
+                log::error!("{}", e);
+            }
+        });
+    }
+    Ok(())
+}
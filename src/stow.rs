@@ -0,0 +1,435 @@
+use crate::link::lexical;
+use crate::link::platform;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A change `stow` or `unstow` makes to the target tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// Creates a symlink at the path with the given relative target
+    Link(PathBuf, PathBuf),
+    /// Removes the symlink at the path
+    Unlink(PathBuf),
+    /// Creates the directory at the path, splitting a folded tree
+    MakeDir(PathBuf),
+    /// Removes the empty directory at the path
+    RemoveDir(PathBuf),
+    /// Moves the file at the path over the package entry, the second path
+    Adopt(PathBuf, PathBuf),
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Link(path, target) => {
+                write!(f, "link {} -> {}", path.display(), target.display())
+            }
+            Action::Unlink(path) => write!(f, "unlink {}", path.display()),
+            Action::MakeDir(path) => write!(f, "mkdir {}", path.display()),
+            Action::RemoveDir(path) => write!(f, "rmdir {}", path.display()),
+            Action::Adopt(path, source) => {
+                write!(f, "adopt {} into {}", path.display(), source.display())
+            }
+        }
+    }
+}
+
+/// A target entry that keeps a package from being stowed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    /// The entry in the target tree
+    pub path: PathBuf,
+    /// The package entry that would be linked there
+    pub source: PathBuf,
+    /// What is in the way
+    pub reason: String,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {}, cannot stow {}",
+            self.path.display(),
+            self.reason,
+            self.source.display()
+        )
+    }
+}
+
+/// Options for `stow` and `unstow`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StowOptions {
+    /// Only plans the changes
+    pub dry_run: bool,
+    /// Moves a file in the way of a package file into the package, replacing
+    /// it, and links it back; ignored by `unstow`
+    pub adopt: bool,
+}
+
+/// The outcome of planning `stow` or `unstow`.
+#[derive(Debug, Clone, Default)]
+pub struct StowReport {
+    /// The changes made, or that would be made, in order
+    pub actions: Vec<Action>,
+    /// The entries in the way; when there are any, nothing is changed
+    pub conflicts: Vec<Conflict>,
+}
+
+/// What is at a path of the target tree once the planned changes are made.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Entry {
+    Missing,
+    Dir,
+    File,
+    Other,
+    /// A symlink, with where it points as an absolute path
+    Link(PathBuf),
+}
+
+/// Works out the changes to the target tree one package at a time, seeing
+/// the changes planned for earlier packages.
+struct Planner {
+    /// The directories holding the packages; links into them are owned
+    stow_dirs: Vec<PathBuf>,
+    /// The entries changed by the planned actions
+    planned: BTreeMap<PathBuf, Entry>,
+    /// Directories the plan creates, which hold nothing it did not put there
+    created: HashSet<PathBuf>,
+    /// Whether files in the way of package files are adopted
+    adopt: bool,
+    report: StowReport,
+}
+
+impl Planner {
+    fn new(packages: &[PathBuf], opts: StowOptions) -> Planner {
+        let mut stow_dirs: Vec<PathBuf> = packages
+            .iter()
+            .filter_map(|package| package.parent().map(Path::to_path_buf))
+            .collect();
+        stow_dirs.sort();
+        stow_dirs.dedup();
+        Planner {
+            stow_dirs,
+            planned: BTreeMap::new(),
+            created: HashSet::new(),
+            adopt: opts.adopt,
+            report: StowReport::default(),
+        }
+    }
+
+    /// Returns what will be at `path`.
+    fn entry(&self, path: &Path) -> Entry {
+        if let Some(entry) = self.planned.get(path) {
+            return entry.clone();
+        }
+        if path
+            .ancestors()
+            .skip(1)
+            .any(|dir| self.created.contains(dir) || self.planned.get(dir) == Some(&Entry::Missing))
+        {
+            return Entry::Missing;
+        }
+        match fs::symlink_metadata(path) {
+            Ok(meta) if meta.file_type().is_symlink() => match fs::read_link(path) {
+                Ok(target) => Entry::Link(lexical::normalize(
+                    &path.parent().unwrap_or(Path::new("/")).join(target),
+                )),
+                Err(_) => Entry::Other,
+            },
+            Ok(meta) if meta.is_dir() => Entry::Dir,
+            Ok(meta) if meta.is_file() => Entry::File,
+            Ok(_) => Entry::Other,
+            Err(_) => Entry::Missing,
+        }
+    }
+
+    /// Returns the names of the entries that will be in the directory `dir`.
+    fn children(&self, dir: &Path) -> io::Result<BTreeSet<PathBuf>> {
+        let mut names = BTreeSet::new();
+        if !self.created.contains(dir) {
+            for entry in fs::read_dir(dir)? {
+                names.insert(PathBuf::from(entry?.file_name()));
+            }
+        }
+        for path in self.planned.keys() {
+            if path.parent() == Some(dir) {
+                names.insert(PathBuf::from(path.file_name().unwrap()));
+            }
+        }
+        names.retain(|name| self.entry(&dir.join(name)) != Entry::Missing);
+        Ok(names)
+    }
+
+    /// Returns true if a symlink pointing at `target` was made by stowing.
+    fn owns(&self, target: &Path) -> bool {
+        self.stow_dirs
+            .iter()
+            .any(|dir| lexical::is_within(target, dir) && target != dir)
+    }
+
+    fn link(&mut self, path: &Path, target: &Path) {
+        let parent = path.parent().unwrap_or(Path::new("/"));
+        let relative = pathdiff::diff_paths(target, parent).unwrap_or(target.to_path_buf());
+        self.report
+            .actions
+            .push(Action::Link(path.to_path_buf(), relative));
+        self.planned
+            .insert(path.to_path_buf(), Entry::Link(target.to_path_buf()));
+    }
+
+    fn unlink(&mut self, path: &Path) {
+        self.report.actions.push(Action::Unlink(path.to_path_buf()));
+        self.planned.insert(path.to_path_buf(), Entry::Missing);
+    }
+
+    /// Replaces a symlink to a package directory with a directory of links
+    /// to its entries, so another package can add to it.
+    fn unfold(&mut self, path: &Path, dir: &Path) -> io::Result<()> {
+        self.unlink(path);
+        self.report
+            .actions
+            .push(Action::MakeDir(path.to_path_buf()));
+        self.planned.insert(path.to_path_buf(), Entry::Dir);
+        self.created.insert(path.to_path_buf());
+        let mut entries: Vec<_> = fs::read_dir(dir)?.collect::<io::Result<_>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            self.link(&path.join(entry.file_name()), &entry.path());
+        }
+        Ok(())
+    }
+
+    /// Plans linking the entries of the package directory `source` into `dest`.
+    fn stow(&mut self, source: &Path, dest: &Path) -> io::Result<()> {
+        let mut entries: Vec<_> = fs::read_dir(source)?.collect::<io::Result<_>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let source = entry.path();
+            let path = dest.join(entry.file_name());
+            let is_dir = entry.file_type()?.is_dir();
+            let conflict = |reason: &str| Conflict {
+                path: path.clone(),
+                source: source.clone(),
+                reason: String::from(reason),
+            };
+            match self.entry(&path) {
+                // A whole directory is linked while no other package shares it.
+                Entry::Missing => self.link(&path, &source),
+                Entry::Link(target) if target == source => {}
+                Entry::Link(target) if self.owns(&target) => {
+                    if is_dir && fs::metadata(&target).is_ok_and(|meta| meta.is_dir()) {
+                        self.unfold(&path, &target)?;
+                        self.stow(&source, &path)?;
+                    } else {
+                        let reason = format!("linked from {} already", target.display());
+                        self.report.conflicts.push(conflict(&reason));
+                    }
+                }
+                Entry::Link(_) => {
+                    let reason = "a symlink not made by stow is there";
+                    self.report.conflicts.push(conflict(reason));
+                }
+                Entry::Dir if is_dir => self.stow(&source, &path)?,
+                Entry::Dir => self.report.conflicts.push(conflict("a directory is there")),
+                Entry::File if self.adopt && entry.file_type()?.is_file() => {
+                    self.report
+                        .actions
+                        .push(Action::Adopt(path.clone(), source.clone()));
+                    self.planned.insert(path.clone(), Entry::Missing);
+                    self.link(&path, &source);
+                }
+                Entry::File | Entry::Other => {
+                    self.report.conflicts.push(conflict("a file is there"))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Plans removing the links to the entries of the package directory
+    /// `source` from `dest`, removing directories left empty and folding
+    /// those left holding links into a single package directory.
+    fn unstow(&mut self, source: &Path, dest: &Path) -> io::Result<()> {
+        let mut entries: Vec<_> = fs::read_dir(source)?.collect::<io::Result<_>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let source = entry.path();
+            let path = dest.join(entry.file_name());
+            match self.entry(&path) {
+                Entry::Link(target) if target == source => self.unlink(&path),
+                Entry::Dir if entry.file_type()?.is_dir() => {
+                    let before = self.report.actions.len();
+                    self.unstow(&source, &path)?;
+                    if self.report.actions.len() > before {
+                        self.fold(&path)?;
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the directory `path` once it is empty, or replaces it with a
+    /// symlink when all it holds is links to every entry of one package
+    /// directory.
+    fn fold(&mut self, path: &Path) -> io::Result<()> {
+        let names = self.children(path)?;
+        let mut dirs = BTreeSet::new();
+        for name in &names {
+            match self.entry(&path.join(name)) {
+                Entry::Link(target) if self.owns(&target) && target.ends_with(name) => {
+                    dirs.insert(target.parent().unwrap().to_path_buf());
+                }
+                _ => return Ok(()),
+            }
+        }
+        let folds_into = match dirs.into_iter().collect::<Vec<_>>()[..] {
+            [] => None,
+            // The links must stand for the directory itself, not a package.
+            [ref dir]
+                if dir.file_name() == path.file_name()
+                    && !self
+                        .stow_dirs
+                        .iter()
+                        .any(|stow_dir| dir.parent() == Some(stow_dir)) =>
+            {
+                let mut entries = BTreeSet::new();
+                for entry in fs::read_dir(dir)? {
+                    entries.insert(PathBuf::from(entry?.file_name()));
+                }
+                if entries != names {
+                    return Ok(());
+                }
+                Some(dir.clone())
+            }
+            _ => return Ok(()),
+        };
+        for name in &names {
+            self.unlink(&path.join(name));
+        }
+        self.report
+            .actions
+            .push(Action::RemoveDir(path.to_path_buf()));
+        self.planned.insert(path.to_path_buf(), Entry::Missing);
+        if let Some(dir) = folds_into {
+            self.link(path, &dir);
+        }
+        Ok(())
+    }
+}
+
+/// Resolves the packages and target to absolute paths, checking that each
+/// package is a directory.
+fn resolve(target: &Path, packages: &[PathBuf]) -> io::Result<(PathBuf, Vec<PathBuf>)> {
+    let target = lexical::normalize(&std::path::absolute(target)?);
+    if !target.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("target {} is not a directory", target.display()),
+        ));
+    }
+    let packages = packages
+        .iter()
+        .map(|package| {
+            if !package.is_dir() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("package {} is not a directory", package.display()),
+                ));
+            }
+            Ok(lexical::normalize(&std::path::absolute(package)?))
+        })
+        .collect::<io::Result<_>>()?;
+    Ok((target, packages))
+}
+
+/// Moves a file, copying it when it is on another filesystem.
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            fs::copy(from, to)?;
+            fs::remove_file(from)
+        }
+        result => result,
+    }
+}
+
+/// Makes the planned changes, stopping at the first that fails.
+fn apply(actions: &[Action]) -> io::Result<()> {
+    for action in actions {
+        match action {
+            Action::Link(path, target) => platform::symlink(target, path)?,
+            Action::Unlink(path) => platform::remove_link(path)?,
+            Action::MakeDir(path) => fs::create_dir(path)?,
+            Action::RemoveDir(path) => fs::remove_dir(path)?,
+            Action::Adopt(path, source) => move_file(path, source)?,
+        }
+    }
+    Ok(())
+}
+
+/// Symlinks the contents of package directories into a target tree, like
+/// GNU Stow, for dotfiles.
+///
+/// The entries at the top of each package are linked into the target with
+/// relative symlinks. A directory that does not exist in the target yet is
+/// linked whole, folding the tree below it; once a second package needs to
+/// add to it, the link is split into a real directory of links to the
+/// first package's entries. Symlinks are owned by stow if they point into
+/// a directory holding one of the packages. Anything else in the way is a
+/// conflict, and then nothing is changed, except that with `adopt` a file
+/// in the way of a package file is moved into the package in its place.
+///
+/// # Arguments
+///
+/// * `target` - The directory to link the packages into
+/// * `packages` - The package directories, stowed in order
+/// * `opts` - Whether to only plan the changes and to adopt files in the way
+///
+/// # Returns
+///
+/// * `io::Result<StowReport>` - The changes made and the conflicts found
+pub fn stow(target: &Path, packages: &[PathBuf], opts: StowOptions) -> io::Result<StowReport> {
+    let (target, packages) = resolve(target, packages)?;
+    let mut planner = Planner::new(&packages, opts);
+    for package in &packages {
+        planner.stow(package, &target)?;
+    }
+    if planner.report.conflicts.is_empty() && !opts.dry_run {
+        apply(&planner.report.actions)?;
+    }
+    Ok(planner.report)
+}
+
+/// Removes the symlinks `stow` made for package directories.
+///
+/// Only links pointing at the package's own entries are removed.
+/// Directories left empty are removed, and a directory left holding only
+/// links to every entry of one other package directory is folded back into
+/// a single link to it.
+///
+/// # Arguments
+///
+/// * `target` - The directory the packages were linked into
+/// * `packages` - The package directories to remove
+/// * `opts` - Whether to only plan the changes
+///
+/// # Returns
+///
+/// * `io::Result<StowReport>` - The changes made, with no conflicts
+pub fn unstow(target: &Path, packages: &[PathBuf], opts: StowOptions) -> io::Result<StowReport> {
+    let (target, packages) = resolve(target, packages)?;
+    let mut planner = Planner::new(&packages, opts);
+    for package in &packages {
+        planner.unstow(package, &target)?;
+    }
+    if !opts.dry_run {
+        apply(&planner.report.actions)?;
+    }
+    Ok(planner.report)
+}
@@ -0,0 +1,229 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::os::unix::io::FromRawFd;
+use std::os::unix::net::{UnixDatagram, UnixListener};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+/// The first file descriptor passed by socket activation.
+const SD_LISTEN_FDS_START: i32 = 3;
+
+/// Sends a state string such as `READY=1` to the service manager.
+///
+/// Does nothing when not running under systemd with `Type=notify`.
+///
+/// # Arguments
+///
+/// * `state` - The newline-separated assignments to send
+///
+/// # Returns
+///
+/// * `io::Result<()>` - Success if the message was sent or there was no one to send it to
+pub fn notify(state: &str) -> io::Result<()> {
+    let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+        return Ok(());
+    };
+    let socket = UnixDatagram::unbound()?;
+    let bytes = path.as_encoded_bytes();
+
+    if let Some(name) = bytes.strip_prefix(b"@") {
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(state.as_bytes(), &addr)?;
+        }
+        #[cfg(not(target_os = "linux"))]
+        let _ = name;
+    } else {
+        socket.send_to(state.as_bytes(), Path::new(&path))?;
+    }
+    Ok(())
+}
+
+/// Returns the watchdog interval requested by the service manager, if any.
+pub fn watchdog_interval() -> Option<Duration> {
+    let pid = env::var("WATCHDOG_PID").ok();
+    let usec = env::var("WATCHDOG_USEC").ok();
+    parse_watchdog(pid.as_deref(), usec.as_deref(), std::process::id())
+}
+
+/// Reads the watchdog interval from the values of `WATCHDOG_PID` and
+/// `WATCHDOG_USEC`.
+///
+/// # Arguments
+///
+/// * `pid` - The process the watchdog is meant for, if the variable is set
+/// * `usec` - The interval in microseconds, if the variable is set
+/// * `own` - The ID of this process
+///
+/// # Returns
+///
+/// * `Option<Duration>` - The interval, or `None` if none is set or it is
+///   meant for another process
+pub(crate) fn parse_watchdog(pid: Option<&str>, usec: Option<&str>, own: u32) -> Option<Duration> {
+    if let Some(pid) = pid.and_then(|p| p.parse::<u32>().ok())
+        && pid != own
+    {
+        return None;
+    }
+    usec.and_then(|usec| usec.parse::<u64>().ok())
+        .filter(|usec| *usec > 0)
+        .map(Duration::from_micros)
+}
+
+/// Reports readiness and, if a watchdog is configured, keeps it fed from a background thread.
+///
+/// Pings are sent at half the requested interval, as systemd recommends.
+pub fn ready() {
+    let _ = notify("READY=1");
+    if let Some(interval) = watchdog_interval() {
+        thread::spawn(move || {
+            loop {
+                thread::sleep(interval / 2);
+                if notify("WATCHDOG=1").is_err() {
+                    return;
+                }
+            }
+        });
+    }
+}
+
+/// Takes over the listening socket passed by systemd socket activation, if any.
+///
+/// # Returns
+///
+/// * `Option<UnixListener>` - The first passed socket, when this process was socket activated
+pub fn activated_listener() -> Option<UnixListener> {
+    let pid = env::var("LISTEN_PID").ok()?;
+    let fds = env::var("LISTEN_FDS").ok()?;
+    if !is_activated(&pid, &fds, std::process::id()) {
+        return None;
+    }
+    unsafe {
+        env::remove_var("LISTEN_PID");
+        env::remove_var("LISTEN_FDS");
+        env::remove_var("LISTEN_FDNAMES");
+        libc::fcntl(SD_LISTEN_FDS_START, libc::F_SETFD, libc::FD_CLOEXEC);
+        Some(UnixListener::from_raw_fd(SD_LISTEN_FDS_START))
+    }
+}
+
+/// Tells from the values of `LISTEN_PID` and `LISTEN_FDS` whether sockets
+/// were passed to this process.
+///
+/// # Arguments
+///
+/// * `pid` - The process the sockets are meant for
+/// * `fds` - The number of sockets passed
+/// * `own` - The ID of this process
+///
+/// # Returns
+///
+/// * `bool` - True if at least one socket was passed to this process
+pub(crate) fn is_activated(pid: &str, fds: &str, own: u32) -> bool {
+    pid.parse::<u32>().is_ok_and(|pid| pid == own) && fds.parse::<i32>().is_ok_and(|fds| fds >= 1)
+}
+
+/// Generated unit files for running flnk as a service.
+#[derive(Debug, Clone)]
+pub struct Units {
+    /// The file name of the service unit
+    pub service_name: String,
+    /// The contents of the service unit
+    pub service: String,
+    /// The file name and contents of the socket unit, for socket-activated services
+    pub socket: Option<(String, String)>,
+}
+
+/// Builds a `Type=notify` service unit, plus a socket unit when a socket is given.
+///
+/// # Arguments
+///
+/// * `name` - The unit name without suffix, e.g. `flnk-serve`
+/// * `args` - The flnk arguments the service runs with
+/// * `socket` - The Unix socket to activate the service on, if any
+/// * `user` - True for a user unit, false for a system unit
+///
+/// # Returns
+///
+/// * `io::Result<Units>` - The units, or an error if the flnk binary cannot be located
+pub fn units(name: &str, args: &[String], socket: Option<&Path>, user: bool) -> io::Result<Units> {
+    let exe = env::current_exe()?;
+    let exec = std::iter::once(exe.to_string_lossy().into_owned())
+        .chain(args.iter().cloned())
+        .map(|arg| {
+            // systemd expands specifiers and variables before splitting the line.
+            let arg = arg.replace('%', "%%").replace('$', "$$");
+            if arg.contains([' ', '"', '\\']) {
+                format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+            } else {
+                arg
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    let wanted_by = if user {
+        "default.target"
+    } else {
+        "multi-user.target"
+    };
+
+    let mut service = format!("[Unit]\nDescription={}\n", name);
+    if socket.is_some() {
+        service.push_str(&format!("Requires={name}.socket\nAfter={name}.socket\n"));
+    }
+    service.push_str(&format!(
+        "\n[Service]\nType=notify\nExecStart={}\nWatchdogSec=30\nRestart=on-failure\n\n[Install]\nWantedBy={}\n",
+        exec, wanted_by
+    ));
+
+    let socket = socket.map(|path| {
+        (
+            format!("{}.socket", name),
+            format!(
+                "[Unit]\nDescription={} socket\n\n[Socket]\nListenStream={}\n\n[Install]\nWantedBy=sockets.target\n",
+                name,
+                path.display()
+            ),
+        )
+    });
+
+    Ok(Units {
+        service_name: format!("{}.service", name),
+        service,
+        socket,
+    })
+}
+
+/// Returns the directory unit files are installed into: the system directory
+/// when running as root, the user's systemd directory otherwise.
+pub fn unit_dir() -> io::Result<PathBuf> {
+    if unsafe { libc::geteuid() } == 0 {
+        return Ok(PathBuf::from("/etc/systemd/system"));
+    }
+    env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .map(|config| config.join("systemd/user"))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "cannot locate the home directory"))
+}
+
+/// Writes the unit files into `dir`.
+///
+/// # Returns
+///
+/// * `io::Result<Vec<PathBuf>>` - The paths of the written files
+pub fn install(units: &Units, dir: &Path) -> io::Result<Vec<PathBuf>> {
+    fs::create_dir_all(dir)?;
+    let mut written = vec![dir.join(&units.service_name)];
+    fs::write(&written[0], &units.service)?;
+    if let Some((name, contents)) = &units.socket {
+        let path = dir.join(name);
+        fs::write(&path, contents)?;
+        written.push(path);
+    }
+    Ok(written)
+}
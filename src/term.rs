@@ -0,0 +1,443 @@
+use crate::link::link_files::CopyProgress;
+use std::fmt;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// When an interactive nicety such as color or a progress line is used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum When {
+    /// Only when the stream it is written to is a terminal
+    #[default]
+    Auto,
+    /// Always, even when the stream is redirected
+    Always,
+    /// Never
+    Never,
+}
+
+impl FromStr for When {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(When::Auto),
+            "always" => Ok(When::Always),
+            "never" => Ok(When::Never),
+            other => Err(format!(
+                "unknown value {}, expected auto, always, or never",
+                other
+            )),
+        }
+    }
+}
+
+/// How a run reports what it did on stdout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// Lines for people to read
+    #[default]
+    Text,
+    /// One JSON document once the run is over
+    Json,
+    /// One JSON record per line, each created link as soon as it exists
+    JsonLines,
+}
+
+/// Which paths of each created link `--print` writes on stdout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PrintPaths {
+    /// The link itself
+    #[default]
+    Dest,
+    /// The source it was linked from
+    Src,
+    /// The source, then the link
+    Both,
+}
+
+impl FromStr for PrintPaths {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dest" => Ok(PrintPaths::Dest),
+            "src" => Ok(PrintPaths::Src),
+            "both" => Ok(PrintPaths::Both),
+            other => Err(format!(
+                "unknown value {}, expected dest, src, or both",
+                other
+            )),
+        }
+    }
+}
+
+/// Writes the paths of a created link for `--print`, each followed by
+/// `terminator`, as raw bytes so names holding spaces or newlines come
+/// through unchanged. A closed stdout is ignored, as `head` leaves it.
+///
+/// # Arguments
+///
+/// * `which` - Which of the paths to write
+/// * `source` - The source the link was made from
+/// * `dest` - The created link
+/// * `terminator` - The byte ending each path, `\n` or NUL
+pub fn print_paths(which: PrintPaths, source: &Path, dest: &Path, terminator: u8) {
+    let paths: &[&Path] = match which {
+        PrintPaths::Dest => &[dest],
+        PrintPaths::Src => &[source],
+        PrintPaths::Both => &[source, dest],
+    };
+    let mut stdout = io::stdout().lock();
+    for path in paths {
+        let _ = stdout
+            .write_all(path.as_os_str().as_encoded_bytes())
+            .and_then(|()| stdout.write_all(&[terminator]));
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "jsonl" => Ok(OutputFormat::JsonLines),
+            other => Err(format!(
+                "unknown value {}, expected text, json, or jsonl",
+                other
+            )),
+        }
+    }
+}
+
+impl When {
+    fn resolve(self, tty: bool) -> bool {
+        match self {
+            When::Auto => tty,
+            When::Always => true,
+            When::Never => false,
+        }
+    }
+}
+
+/// What the terminal the process runs in allows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Terminal {
+    /// Whether the `Warning:` and `Error:` labels on stderr are colored
+    pub color: bool,
+    /// Whether the lines a run prints on stdout are colored
+    pub color_stdout: bool,
+    /// Whether a progress line is drawn on stderr during a run
+    pub progress: bool,
+    /// Whether stdout is a terminal, so per-file output is read by a person
+    pub interactive: bool,
+    /// Whether a person can answer prompts on stdin
+    pub prompts: bool,
+}
+
+impl Terminal {
+    /// Detects the terminal from the standard streams and environment.
+    ///
+    /// Color follows the `NO_COLOR` convention and is off for `TERM=dumb`
+    /// unless forced with `When::Always`. The progress line is only drawn
+    /// automatically when both stdout and stderr are terminals, so output
+    /// redirected to a file or pipe stays clean.
+    ///
+    /// # Arguments
+    ///
+    /// * `color` - When to color stderr labels and stdout lines
+    /// * `progress` - When to draw a progress line on stderr
+    ///
+    /// # Returns
+    ///
+    /// * `Terminal` - The resolved settings
+    pub fn detect(color: When, progress: When) -> Self {
+        let stderr_tty = io::stderr().is_terminal();
+        let stdout_tty = io::stdout().is_terminal();
+        let dumb = std::env::var_os("TERM").is_some_and(|term| term == "dumb");
+        let no_color = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+        Terminal {
+            color: color.resolve(stderr_tty && !dumb && !no_color),
+            color_stdout: color.resolve(stdout_tty && !dumb && !no_color),
+            progress: progress.resolve(stderr_tty && stdout_tty && !dumb),
+            interactive: stdout_tty,
+            prompts: io::stdin().is_terminal() && stderr_tty,
+        }
+    }
+
+    /// Returns the label for a warning line, colored when enabled.
+    pub fn warning(&self) -> Label {
+        Label {
+            text: "Warning:",
+            color: self.color.then_some("33"),
+        }
+    }
+
+    /// Returns the label for an error line, colored when enabled.
+    pub fn error(&self) -> Label {
+        Label {
+            text: "Error:",
+            color: self.color.then_some("31"),
+        }
+    }
+
+    /// Returns a line for stdout in a style, colored when enabled.
+    ///
+    /// # Arguments
+    ///
+    /// * `style` - The style of the line
+    /// * `text` - The line, without its newline
+    ///
+    /// # Returns
+    ///
+    /// * `String` - The line, wrapped in ANSI codes if stdout is colored
+    pub fn paint(&self, style: Style, text: &str) -> String {
+        if !self.color_stdout {
+            return text.to_string();
+        }
+        let code = match style {
+            Style::Created => "32",
+            Style::Skipped => "33",
+            Style::Summary => "1",
+        };
+        format!("\x1b[{}m{}\x1b[0m", code, text)
+    }
+}
+
+/// The style of a line a run prints on stdout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    /// A created link, in green
+    Created,
+    /// A skipped or kept entry, in yellow
+    Skipped,
+    /// The summary of a run, in bold
+    Summary,
+}
+
+/// Asks a yes/no question on stderr and reads the answer from stdin.
+///
+/// # Arguments
+///
+/// * `question` - The question, printed followed by ` [y/N] `
+///
+/// # Returns
+///
+/// * `io::Result<bool>` - True only if the answer starts with `y` or `Y`
+pub fn confirm(question: &str) -> io::Result<bool> {
+    let mut stderr = io::stderr().lock();
+    write!(stderr, "{} [y/N] ", question)?;
+    stderr.flush()?;
+
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(answer.trim_start().starts_with(['y', 'Y']))
+}
+
+/// A line prefix such as `Error:`, with an optional ANSI color.
+#[derive(Debug, Clone, Copy)]
+pub struct Label {
+    text: &'static str,
+    color: Option<&'static str>,
+}
+
+impl fmt::Display for Label {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.color {
+            Some(code) => write!(f, "\x1b[1;{}m{}\x1b[0m", code, self.text),
+            None => f.write_str(self.text),
+        }
+    }
+}
+
+/// Formats a byte count with a binary unit, such as `1.5 GiB`.
+pub fn human_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+/// Formats a duration as `m:ss`, or `h:mm:ss` from an hour up.
+fn human_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    } else {
+        format!("{}:{:02}", secs / 60, secs % 60)
+    }
+}
+
+/// How often the progress line is redrawn.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The number of cells in the progress bar.
+const BAR_WIDTH: usize = 20;
+
+/// A single self-overwriting progress line on stderr.
+#[derive(Debug)]
+pub struct Progress {
+    enabled: bool,
+    count: usize,
+    total: Option<usize>,
+    started: Instant,
+    drawn: Option<Instant>,
+    visible: bool,
+}
+
+impl Progress {
+    /// Creates a progress line that draws nothing unless `enabled`.
+    pub fn new(enabled: bool) -> Self {
+        Progress {
+            enabled,
+            count: 0,
+            total: None,
+            started: Instant::now(),
+            drawn: None,
+            visible: false,
+        }
+    }
+
+    /// Returns true if the line is drawn at all.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Sets how many entries the run is expected to link, counted before it
+    /// starts, so the line shows a bar, the link rate, and the time left.
+    ///
+    /// # Arguments
+    ///
+    /// * `total` - The number of links the run will create
+    pub fn set_total(&mut self, total: usize) {
+        self.total = Some(total);
+        self.started = Instant::now();
+    }
+
+    /// Prints a line on stdout, such as a `--verbose` link, without garbling
+    /// the progress line: the line is erased first and drawn again on the
+    /// next tick.
+    ///
+    /// # Arguments
+    ///
+    /// * `line` - The text to print
+    pub fn println(&mut self, line: &str) {
+        self.erase();
+        println!("{}", line);
+    }
+
+    /// Writes the paths of a created link for `--print`, as `print_paths`
+    /// does, after erasing the line.
+    pub fn print_paths(&mut self, which: PrintPaths, source: &Path, dest: &Path, terminator: u8) {
+        self.erase();
+        print_paths(which, source, dest, terminator);
+    }
+
+    /// Counts one more linked entry, redrawing at most every 100ms.
+    ///
+    /// # Arguments
+    ///
+    /// * `current` - The entry just linked, shown after the count
+    pub fn tick(&mut self, current: &Path) {
+        self.count += 1;
+        if !self.enabled || self.drawn.is_some_and(|at| at.elapsed() < REDRAW_INTERVAL) {
+            return;
+        }
+        self.draw(current, "");
+    }
+
+    /// Shows how far the copy of a large file has got, so the line keeps
+    /// moving while a single file takes a long time.
+    ///
+    /// # Arguments
+    ///
+    /// * `current` - The file being copied
+    /// * `progress` - The bytes copied so far, the file size, and the copy rate
+    pub fn copying(&mut self, current: &Path, progress: &CopyProgress) {
+        if !self.enabled {
+            return;
+        }
+        let percent = progress.copied * 100 / progress.total.max(1);
+        let detail = format!(
+            "  {}% of {} at {}/s",
+            percent,
+            human_bytes(progress.total),
+            human_bytes(progress.bytes_per_sec)
+        );
+        self.draw(current, &detail);
+    }
+
+    /// The count of linked entries, as a bar with the rate and time left
+    /// when the total is known.
+    fn status(&self) -> String {
+        let Some(total) = self.total.filter(|total| *total > 0) else {
+            return format!("{} linked", self.count);
+        };
+        // The pre-count can fall short, for example when the source changes.
+        let total = total.max(self.count);
+        let filled = self.count * BAR_WIDTH / total;
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let rate = if elapsed > 0.0 {
+            self.count as f64 / elapsed
+        } else {
+            0.0
+        };
+        let eta = if rate > 0.0 {
+            human_duration(Duration::from_secs_f64((total - self.count) as f64 / rate))
+        } else {
+            String::from("-:--")
+        };
+        format!(
+            "[{}{}] {}/{} linked  {:.0}/s  ETA {}",
+            "#".repeat(filled),
+            "-".repeat(BAR_WIDTH - filled),
+            self.count,
+            total,
+            rate,
+            eta
+        )
+    }
+
+    fn draw(&mut self, current: &Path, detail: &str) {
+        self.drawn = Some(Instant::now());
+        self.visible = true;
+
+        // The bar leaves less room for the name on an 80-column terminal.
+        let room = if self.total.is_some() { 24 } else { 60 };
+        let mut name = current.display().to_string();
+        if name.chars().count() > room {
+            let tail: String = name.chars().rev().take(room - 1).collect();
+            name = format!("…{}", tail.chars().rev().collect::<String>());
+        }
+        let status = self.status();
+        let mut stderr = io::stderr().lock();
+        let _ = write!(stderr, "\r\x1b[2K{}  {}{}", status, name, detail);
+        let _ = stderr.flush();
+    }
+
+    fn erase(&mut self) {
+        if self.visible {
+            self.visible = false;
+            let mut stderr = io::stderr().lock();
+            let _ = write!(stderr, "\r\x1b[2K");
+            let _ = stderr.flush();
+        }
+    }
+
+    /// Erases the progress line so following output starts on a clean line.
+    pub fn finish(&mut self) {
+        self.erase();
+        self.drawn = None;
+    }
+}
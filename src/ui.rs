@@ -1,7 +1,9 @@
-use crate::link::link_files::link_files;
+use crate::interrupt::{self, CancelToken};
+use crate::link::link_files::{LinkReport, link_files};
 use crate::link::link_options::LinkOptions;
+use crate::term;
 use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode},
+    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyModifiers},
     execute,
     terminal::{EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode, enable_raw_mode},
 };
@@ -16,6 +18,8 @@ use std::{
     error::Error,
     fs, io,
     path::{Path, PathBuf},
+    thread::{self, JoinHandle},
+    time::Duration,
 };
 
 pub struct StatefulList<T> {
@@ -58,7 +62,9 @@ enum AppState {
     SelectSource,
     SelectDestination,
     Confirm,
+    Linking,
     Complete,
+    PickDirectory,
 }
 
 struct App {
@@ -68,6 +74,17 @@ struct App {
     source: Option<PathBuf>,
     destination: Option<PathBuf>,
     linked_files: Vec<PathBuf>,
+    summary: Vec<String>,
+    picked: Option<PathBuf>,
+    opts: LinkOptions,
+    run: Option<Run>,
+}
+
+/// A link run going on in the background, so keys are still read while it
+/// goes and quitting can stop it between entries.
+struct Run {
+    cancel: CancelToken,
+    handle: JoinHandle<io::Result<LinkReport>>,
 }
 
 impl App {
@@ -81,9 +98,41 @@ impl App {
             source: None,
             destination: None,
             linked_files: Vec::new(),
+            summary: Vec::new(),
+            picked: None,
+            opts: LinkOptions::default(),
+            run: None,
         }
     }
 
+    /// Shows the outcome of the run once it has ended.
+    fn finish_run(&mut self) {
+        if !self
+            .run
+            .as_ref()
+            .is_some_and(|run| run.handle.is_finished())
+        {
+            return;
+        }
+        let result = match self.run.take().unwrap().handle.join() {
+            Ok(result) => result,
+            Err(_) => Err(io::Error::other("the link thread panicked")),
+        };
+        match result {
+            Ok(report) => {
+                self.summary = summarize(&report);
+                self.linked_files = report.linked;
+            }
+            Err(e) => {
+                self.linked_files.clear();
+                // Store error for display
+                self.linked_files
+                    .push(PathBuf::from(format!("Error: {}", e)));
+            }
+        }
+        self.state = AppState::Complete;
+    }
+
     fn update_directory(&mut self) {
         self.files =
             StatefulList::with_items(list_directory(&self.current_path).unwrap_or_default());
@@ -95,6 +144,31 @@ impl App {
     }
 }
 
+/// Describes a finished run in a few lines shown above the linked files.
+fn summarize(report: &LinkReport) -> Vec<String> {
+    let plural =
+        |n: usize, one: &str, many: &str| format!("{} {}", n, if n == 1 { one } else { many });
+    let mut lines = vec![format!(
+        "Linked {} ({})",
+        plural(report.linked.len(), "entry", "entries"),
+        term::human_bytes(report.bytes)
+    )];
+    if !report.created_dirs.is_empty() {
+        lines.push(format!(
+            "Created {}",
+            plural(report.created_dirs.len(), "directory", "directories")
+        ));
+    }
+    if !report.backups.is_empty() {
+        lines.push(format!(
+            "Backed up {}",
+            plural(report.backups.len(), "existing entry", "existing entries")
+        ));
+    }
+    lines.extend(report.warnings.iter().map(|w| format!("Warning: {}", w)));
+    lines
+}
+
 fn list_directory(path: &Path) -> io::Result<Vec<PathBuf>> {
     let mut entries = vec![];
     if path != Path::new("/") {
@@ -109,14 +183,21 @@ fn list_directory(path: &Path) -> io::Result<Vec<PathBuf>> {
     Ok(entries)
 }
 
-pub fn run_ui(targets: &[String]) -> Result<(), Box<dyn Error>> {
-    enable_raw_mode()?;
-    let mut stdout = io::stdout();
-    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
-    let backend = CrosstermBackend::new(stdout);
-    let mut terminal = Terminal::new(backend)?;
-
+/// Runs the interactive linker, starting at the step the given targets reach.
+///
+/// # Arguments
+///
+/// * `targets` - The source and destination already chosen, if any
+/// * `opts` - The options links are made with, such as those from the config
+///   file; the UI never prompts per entry, so `interactive` is ignored
+///
+/// # Returns
+///
+/// * `Result<(), Box<dyn Error>>` - An error if the terminal could not be used
+pub fn run_ui(targets: &[String], opts: &LinkOptions) -> Result<(), Box<dyn Error>> {
     let mut app = App::new();
+    app.opts = opts.clone();
+    app.opts.interactive = false;
     if !targets.is_empty() {
         app.source = Some(PathBuf::from(&targets[0]));
         app.state = AppState::SelectDestination;
@@ -126,32 +207,108 @@ pub fn run_ui(targets: &[String]) -> Result<(), Box<dyn Error>> {
         app.state = AppState::Confirm;
     }
 
-    let res = run_app(&mut terminal, app);
-
-    disable_raw_mode()?;
-    execute!(
-        terminal.backend_mut(),
-        LeaveAlternateScreen,
-        DisableMouseCapture
-    )?;
-    terminal.show_cursor()?;
-
-    if let Err(err) = res {
+    if let Err(err) = with_terminal(io::stdout(), &mut app)? {
         println!("{:?}", err)
     }
 
     Ok(())
 }
 
-fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> io::Result<()> {
+/// Runs the directory browser alone and returns the directory the user picks.
+///
+/// The browser is drawn on stderr, so stdout stays free for the answer and
+/// `cd "$(flnk -u --pick-dir)"` works from a shell.
+///
+/// # Returns
+///
+/// * `Result<Option<PathBuf>, Box<dyn Error>>` - The absolute path of the
+///   picked directory, or `None` if the user quit without picking one
+pub fn pick_dir() -> Result<Option<PathBuf>, Box<dyn Error>> {
+    let mut app = App::new();
+    app.current_path = fs::canonicalize(&app.current_path)?;
+    app.update_directory();
+    app.state = AppState::PickDirectory;
+
+    with_terminal(io::stderr(), &mut app)??;
+    Ok(app.picked)
+}
+
+/// Leaves raw mode and the alternate screen when dropped, so the terminal
+/// is restored however the app ends, a panic included.
+struct RestoreOnDrop<W: io::Write>(Terminal<CrosstermBackend<W>>);
+
+impl<W: io::Write> Drop for RestoreOnDrop<W> {
+    fn drop(&mut self) {
+        let _ = disable_raw_mode();
+        let _ = execute!(
+            self.0.backend_mut(),
+            LeaveAlternateScreen,
+            DisableMouseCapture
+        );
+        let _ = self.0.show_cursor();
+    }
+}
+
+/// Runs the app on the alternate screen of the terminal behind `out`,
+/// restoring the terminal afterwards even if the app fails, panics, or is
+/// interrupted.
+fn with_terminal<W: io::Write>(out: W, app: &mut App) -> Result<io::Result<()>, Box<dyn Error>> {
+    interrupt::install();
+    let terminal = Terminal::new(CrosstermBackend::new(out))?;
+    enable_raw_mode()?;
+    let mut terminal = RestoreOnDrop(terminal);
+    execute!(
+        terminal.0.backend_mut(),
+        EnterAlternateScreen,
+        EnableMouseCapture
+    )?;
+
+    Ok(run_app(&mut terminal.0, app))
+}
+
+fn run_app<B: Backend>(terminal: &mut Terminal<B>, app: &mut App) -> io::Result<()> {
     loop {
-        terminal.draw(|f| ui(f, &mut app))?;
+        app.finish_run();
+        terminal.draw(|f| ui(f, app))?;
 
+        // A SIGINT sent while waiting for a key leaves the app like `q` does,
+        // once a run it also stops has ended.
+        if interrupt::requested() && app.run.is_none() {
+            return Ok(());
+        }
+        if !event::poll(Duration::from_millis(250))? {
+            continue;
+        }
         if let Event::Key(key) = event::read()? {
+            // Quitting during a run stops it before its next entry; the
+            // app shows what it linked before it can be left.
+            if let Some(run) = &app.run {
+                let ctrl_c =
+                    key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+                if ctrl_c || matches!(key.code, KeyCode::Char('q') | KeyCode::Esc) {
+                    run.cancel.cancel();
+                }
+                continue;
+            }
             match key.code {
+                // In raw mode, Ctrl-C arrives as a key press instead of a signal.
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    return Ok(());
+                }
                 KeyCode::Char('q') => return Ok(()),
                 KeyCode::Down => app.files.next(),
                 KeyCode::Up => app.files.previous(),
+                KeyCode::Char(' ') if matches!(app.state, AppState::PickDirectory) => {
+                    if let Some(selected) = app.files.state.selected() {
+                        let selected = app.current_path.join(&app.files.items[selected]);
+                        app.picked = Some(fs::canonicalize(selected)?);
+                        return Ok(());
+                    }
+                }
+                KeyCode::Char('.') if matches!(app.state, AppState::PickDirectory) => {
+                    app.picked = Some(app.current_path.clone());
+                    return Ok(());
+                }
                 KeyCode::Enter => {
                     if let Some(selected) = app.files.state.selected() {
                         let selected_path = &app.files.items[selected];
@@ -170,33 +327,26 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> io::Result<(
                                     app.destination = Some(selected_path.clone());
                                     app.state = AppState::Confirm;
                                 }
+                                AppState::PickDirectory => {
+                                    app.current_path = selected_path.clone();
+                                    app.update_directory();
+                                }
                                 _ => {}
                             }
                         }
                     }
                 }
                 KeyCode::Char('y') => {
-                    if let AppState::Confirm = app.state {
-                        if let (Some(source), Some(dest)) = (&app.source, &app.destination) {
-                            let opts = LinkOptions::default();
-                            match link_files(
-                                source.to_str().unwrap(),
-                                dest.to_str().unwrap(),
-                                Some(&opts),
-                            ) {
-                                Ok(linked) => {
-                                    app.linked_files = linked;
-                                    app.state = AppState::Complete;
-                                }
-                                Err(e) => {
-                                    app.linked_files.clear();
-                                    app.state = AppState::Complete;
-                                    // Store error for display
-                                    app.linked_files
-                                        .push(PathBuf::from(format!("Error: {}", e)));
-                                }
-                            }
-                        }
+                    if let AppState::Confirm = app.state
+                        && let (Some(source), Some(dest)) = (&app.source, &app.destination)
+                    {
+                        let cancel = CancelToken::new();
+                        let mut opts = app.opts.clone();
+                        opts.cancel = Some(cancel.clone());
+                        let (source, dest) = (source.clone(), dest.clone());
+                        let handle = thread::spawn(move || link_files(source, dest, Some(&opts)));
+                        app.run = Some(Run { cancel, handle });
+                        app.state = AppState::Linking;
                     }
                 }
                 KeyCode::Char('n') => {
@@ -239,8 +389,12 @@ fn ui(f: &mut Frame, app: &mut App) {
                 })
                 .collect::<Vec<_>>(),
         ),
-        AppState::SelectDestination => (
-            "Select destination directory",
+        AppState::SelectDestination | AppState::PickDirectory => (
+            if matches!(app.state, AppState::PickDirectory) {
+                "Pick a directory"
+            } else {
+                "Select destination directory"
+            },
             app.files
                 .items
                 .iter()
@@ -258,11 +412,17 @@ fn ui(f: &mut Frame, app: &mut App) {
             "Confirm Selection",
             vec![ListItem::new("Press 'y' to confirm or 'n' to start over")],
         ),
+        AppState::Linking => ("Linking", vec![ListItem::new("Linking, press 'q' to stop")]),
         AppState::Complete => (
             "Operation Complete",
-            app.linked_files
+            app.summary
                 .iter()
-                .map(|p| ListItem::new(format!("Linked: {}", p.display())))
+                .map(|line| ListItem::new(line.as_str()))
+                .chain(
+                    app.linked_files
+                        .iter()
+                        .map(|p| ListItem::new(format!("Linked: {}", p.display()))),
+                )
                 .collect::<Vec<_>>(),
         ),
     };
@@ -279,7 +439,7 @@ fn ui(f: &mut Frame, app: &mut App) {
 
     if matches!(
         app.state,
-        AppState::SelectSource | AppState::SelectDestination
+        AppState::SelectSource | AppState::SelectDestination | AppState::PickDirectory
     ) {
         f.render_stateful_widget(items, chunks[1], &mut app.files.state);
     } else {
@@ -287,7 +447,11 @@ fn ui(f: &mut Frame, app: &mut App) {
     }
 
     let status = match app.state {
+        AppState::Linking => "Press 'q' to stop after the current entry",
         AppState::Complete => "Press 'q' to quit",
+        AppState::PickDirectory => {
+            "Use ↑↓ to navigate, Enter to open, Space to pick, '.' to pick this directory, 'q' to quit"
+        }
         _ => "Use ↑↓ to navigate, Enter to select, 'q' to quit",
     };
 
@@ -0,0 +1,235 @@
+use crate::inspect::{self, Resolution};
+use crate::json::Json;
+use crate::link::backups::BACKUP_DIR;
+use crate::link::filter::{Filter, IGNORE_FILE};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// What is wrong with a destination entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// A symlink whose target, the given path, does not exist
+    Broken(PathBuf),
+    /// A symlink resolving to the given path instead of its source
+    WrongTarget(PathBuf),
+    /// A symlink chain that loops or cannot be read
+    Unresolvable(String),
+    /// A file whose inode, the first number, is not the source's, the second
+    InodeMismatch(u64, u64),
+    /// A source entry with nothing at its destination
+    Missing,
+}
+
+impl Problem {
+    /// A short stable name for machine-readable output.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Problem::Broken(_) => "broken",
+            Problem::WrongTarget(_) => "wrong_target",
+            Problem::Unresolvable(_) => "unresolvable",
+            Problem::InodeMismatch(..) => "inode_mismatch",
+            Problem::Missing => "missing",
+        }
+    }
+}
+
+/// A destination entry that does not match its source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    /// The destination entry
+    pub dest: PathBuf,
+    /// The source entry it mirrors
+    pub source: PathBuf,
+    /// What is wrong with it
+    pub problem: Problem,
+}
+
+impl Mismatch {
+    /// Renders the mismatch as a JSON object with `problem`, `dest`,
+    /// `source`, and, where the problem has one, `target` or the two inodes.
+    pub fn to_json(&self) -> Json {
+        let mut pairs = vec![
+            ("problem", Json::from(self.problem.as_str())),
+            ("dest", Json::from(self.dest.as_path())),
+            ("source", Json::from(self.source.as_path())),
+        ];
+        match &self.problem {
+            Problem::Broken(target) | Problem::WrongTarget(target) => {
+                pairs.push(("target", Json::from(target.as_path())))
+            }
+            Problem::Unresolvable(err) => pairs.push(("error", Json::from(err.as_str()))),
+            Problem::InodeMismatch(dest, source) => {
+                pairs.push(("dest_inode", Json::from(*dest)));
+                pairs.push(("source_inode", Json::from(*source)));
+            }
+            Problem::Missing => {}
+        }
+        Json::object(pairs)
+    }
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let dest = self.dest.display();
+        match &self.problem {
+            Problem::Broken(target) => {
+                write!(
+                    f,
+                    "{}: broken symlink, {} does not exist",
+                    dest,
+                    target.display()
+                )
+            }
+            Problem::WrongTarget(target) => write!(
+                f,
+                "{}: points to {} instead of {}",
+                dest,
+                target.display(),
+                self.source.display()
+            ),
+            Problem::Unresolvable(err) => write!(f, "{}: cannot be resolved ({})", dest, err),
+            Problem::InodeMismatch(dest_inode, source_inode) => write!(
+                f,
+                "{}: inode {} is not {}'s inode {}",
+                dest,
+                dest_inode,
+                self.source.display(),
+                source_inode
+            ),
+            Problem::Missing => write!(f, "{}: missing link to {}", dest, self.source.display()),
+        }
+    }
+}
+
+/// The outcome of checking a destination tree against its source.
+#[derive(Debug, Clone, Default)]
+pub struct Verification {
+    /// The number of destination links checked against their source
+    pub checked: usize,
+    /// Every entry found not to match
+    pub mismatches: Vec<Mismatch>,
+}
+
+/// Checks a destination symlink against its source counterpart.
+///
+/// A symlink that is itself a copy of, or hard link to, a source symlink
+/// matches; any other symlink must resolve to the source entry.
+fn check_symlink(dest: &Path, source: &Path) -> Option<Problem> {
+    if let Ok(source_meta) = fs::symlink_metadata(source)
+        && source_meta.file_type().is_symlink()
+        && let Ok(dest_meta) = fs::symlink_metadata(dest)
+        && ((source_meta.dev(), source_meta.ino()) == (dest_meta.dev(), dest_meta.ino())
+            || fs::read_link(source).ok() == fs::read_link(dest).ok())
+    {
+        return None;
+    }
+    match inspect::follow(dest).1 {
+        Resolution::Resolved(target, _) => {
+            let resolved = fs::canonicalize(&target).ok();
+            (resolved.is_none() || resolved != fs::canonicalize(source).ok())
+                .then_some(Problem::WrongTarget(target))
+        }
+        Resolution::Dangling(target) => Some(Problem::Broken(target)),
+        Resolution::Loop => Some(Problem::Unresolvable("symlink loop".to_string())),
+        Resolution::Unreadable(path, err) => Some(Problem::Unresolvable(format!(
+            "{}: {}",
+            path.display(),
+            err
+        ))),
+    }
+}
+
+/// Checks the links in `dest` against the tree at `source`.
+///
+/// `dest` is taken to mirror `source`, so `dest/a/b` is checked against
+/// `source/a/b`. Every symlink in `dest` must resolve to its source entry,
+/// every file whose source entry is a file must share its inode, and every
+/// source entry not excluded by a `.flnkignore` must exist in `dest`.
+/// Files in `dest` without a source entry are left alone, as are the
+/// contents of directories linked whole and the backup directory.
+///
+/// # Arguments
+///
+/// * `source` - The tree the links were made from
+/// * `dest` - The tree holding the links
+///
+/// # Returns
+///
+/// * `io::Result<Verification>` - The mismatches found, or an error if either tree cannot be walked
+pub fn verify(source: &Path, dest: &Path) -> io::Result<Verification> {
+    let mut verification = Verification::default();
+
+    let mut walk = WalkDir::new(dest).min_depth(1).into_iter();
+    while let Some(entry) = walk.next() {
+        let entry = entry?;
+        if entry.depth() == 1 && entry.file_name() == BACKUP_DIR {
+            walk.skip_current_dir();
+            continue;
+        }
+        let rel = entry.path().strip_prefix(dest).unwrap_or(entry.path());
+        let source_path = source.join(rel);
+        let problem = if entry.path_is_symlink() {
+            check_symlink(entry.path(), &source_path)
+        } else if entry.file_type().is_file()
+            && let Ok(source_meta) = fs::symlink_metadata(&source_path)
+            && source_meta.is_file()
+        {
+            let dest_meta = entry.metadata()?;
+            ((dest_meta.dev(), dest_meta.ino()) != (source_meta.dev(), source_meta.ino()))
+                .then(|| Problem::InodeMismatch(dest_meta.ino(), source_meta.ino()))
+        } else {
+            continue;
+        };
+        verification.checked += 1;
+        if let Some(problem) = problem {
+            verification.mismatches.push(Mismatch {
+                dest: entry.path().to_path_buf(),
+                source: source_path,
+                problem,
+            });
+        }
+    }
+
+    let mut rules = Filter::default();
+    rules.add_ignore_file(IGNORE_FILE);
+    let mut filter = rules.walker();
+    let mut walk = WalkDir::new(source).into_iter();
+    while let Some(entry) = walk.next() {
+        let entry = entry?;
+        let is_dir = entry.file_type().is_dir();
+        let rel = entry.path().strip_prefix(source).unwrap_or(entry.path());
+        if entry.depth() > 0 && filter.is_excluded(rel, entry.depth(), is_dir) {
+            if is_dir {
+                walk.skip_current_dir();
+            }
+            continue;
+        }
+        if is_dir {
+            filter.enter_dir(entry.path(), rel, entry.depth())?;
+        }
+        if entry.depth() == 0 {
+            continue;
+        }
+        let dest_path = dest.join(rel);
+        match fs::symlink_metadata(&dest_path) {
+            Ok(metadata) if is_dir && metadata.file_type().is_symlink() => walk.skip_current_dir(),
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                if is_dir {
+                    walk.skip_current_dir();
+                }
+                verification.mismatches.push(Mismatch {
+                    dest: dest_path,
+                    source: entry.path().to_path_buf(),
+                    problem: Problem::Missing,
+                });
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(verification)
+}
@@ -0,0 +1,181 @@
+use crate::inotify::{self, Inotify};
+use crate::link::link_files::{LinkReport, link_files_with};
+use crate::link::link_options::LinkOptions;
+use crate::link::manifest;
+use crate::link::pipeline::{EntryKind, LinkJob};
+use crate::sentinel::SourceSentinel;
+use crate::systemd;
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use walkdir::WalkDir;
+
+/// The source tree events that can bring something new to link: a file
+/// written and closed, an entry moved in, or a directory or symlink created.
+const WATCH_MASK: u32 = inotify::IN_CLOSE_WRITE | inotify::IN_MOVED_TO | inotify::IN_CREATE;
+
+/// With `delete`, the removals that leave destination entries without a source.
+const DELETE_MASK: u32 = inotify::IN_DELETE | inotify::IN_MOVED_FROM;
+
+/// Options for watch mode.
+#[derive(Debug, Clone, Default)]
+pub struct WatchOptions {
+    /// A shell command run whenever the source of a link created by the watcher is deleted or moved
+    pub on_source_gone: Option<String>,
+}
+
+/// The directories of the source tree being watched.
+struct Watcher {
+    inotify: Inotify,
+    mask: u32,
+    dirs: HashMap<i32, PathBuf>,
+}
+
+impl Watcher {
+    /// Watches every directory of the tree, including ones created since the
+    /// last call. Watching a directory again keeps its descriptor. Parts of
+    /// the tree that cannot be read are logged and left unwatched, and a
+    /// directory removed during the walk is passed over.
+    fn watch_tree(&mut self, source: &Path, opts: &LinkOptions) -> io::Result<()> {
+        let walker = WalkDir::new(source)
+            .follow_links(opts.follow_links)
+            .same_file_system(opts.same_file_system);
+        for entry in walker {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    log::warn!("cannot watch part of {}: {}", source.display(), e);
+                    continue;
+                }
+            };
+            if entry.file_type().is_dir() {
+                match self.inotify.add_watch(entry.path(), self.mask) {
+                    Ok(wd) => {
+                        self.dirs.insert(wd, entry.into_path());
+                    }
+                    // Removed since the walk found it, so there is nothing to watch.
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Blocks until something in the tree may need linking. Files that were
+    /// only created are still being written and wait for their close.
+    fn wait(&mut self) -> io::Result<()> {
+        loop {
+            let mut changed = false;
+            for event in self.inotify.read_events()? {
+                if event.mask & inotify::IN_IGNORED != 0 {
+                    self.dirs.remove(&event.wd);
+                } else if event.mask & inotify::IN_Q_OVERFLOW != 0
+                    || event.mask & inotify::IN_CREATE == 0
+                    || event.mask & inotify::IN_ISDIR != 0
+                {
+                    changed = true;
+                } else if let (Some(dir), Some(name)) = (self.dirs.get(&event.wd), &event.name) {
+                    changed |= dir.join(name).is_symlink();
+                }
+            }
+            if changed {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Mirrors a source tree into a destination, so `SOURCE/a/b` is linked at
+/// `DEST/a/b`, then keeps linking what appears in it: files once they are written and closed or moved in, and new
+/// directories and symlinks. Each change runs the whole tree again with the
+/// same options and filters, so entries linked before are left alone.
+///
+/// Sources of created links are tracked like `serve` does, and their removal
+/// is logged and runs the `on_source_gone` hook. With `delete`, removals in
+/// the source are mirrored too. Under systemd, readiness and watchdog pings
+/// are reported once the first run is done.
+///
+/// # Arguments
+///
+/// * `source` - The directory to watch
+/// * `dest` - The directory its entries are linked into
+/// * `opts` - The options every run is made with; each run that changes
+///   something gets its own undo manifest next to `undo_manifest`
+/// * `watch_opts` - How vanished sources are reported
+/// * `on_run` - Called with the outcome of every run; a failed run does not
+///   stop the watch
+///
+/// # Returns
+///
+/// * `io::Result<()>` - Only returns on an error watching the source
+pub fn watch(
+    source: &Path,
+    dest: &Path,
+    opts: &LinkOptions,
+    watch_opts: &WatchOptions,
+    mut on_run: impl FnMut(io::Result<LinkReport>),
+) -> io::Result<()> {
+    if !source.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is not a directory to watch", source.display()),
+        ));
+    }
+    let mut watcher = Watcher {
+        inotify: Inotify::new()?,
+        mask: if opts.delete {
+            WATCH_MASK | DELETE_MASK
+        } else {
+            WATCH_MASK
+        },
+        dirs: HashMap::new(),
+    };
+
+    let sentinel = Arc::new(SourceSentinel::new()?);
+    let alerts = Arc::clone(&sentinel);
+    let hook = watch_opts.on_source_gone.clone();
+    thread::spawn(move || {
+        loop {
+            match alerts.next_events() {
+                Ok(events) => {
+                    for event in events {
+                        log::warn!("{}", event);
+                        if let Some(hook) = &hook {
+                            event.run_hook(hook);
+                        }
+                    }
+                }
+                Err(e) => {
+                    log::error!("source watch stopped: {}", e);
+                    return;
+                }
+            }
+        }
+    });
+
+    let manifests = opts.undo_manifest.as_ref().and_then(|path| path.parent());
+    let mut first = true;
+    loop {
+        // Watch first, so nothing created during the run is missed.
+        watcher.watch_tree(source, opts)?;
+        let mut run = opts.clone();
+        run.no_target_directory = true;
+        run.undo_manifest = manifests.map(|dir| dir.join(format!("{}.json", manifest::new_id())));
+        let track = |job: &LinkJob| {
+            if (opts.symbolic || job.kind == EntryKind::File)
+                && let Err(e) = sentinel.track(&job.source, &job.dest)
+            {
+                log::warn!("cannot watch {}: {}", job.source.display(), e);
+            }
+        };
+        on_run(link_files_with(source, dest, Some(&run), track));
+        if first {
+            systemd::ready();
+            first = false;
+        }
+        watcher.wait()?;
+    }
+}